@@ -0,0 +1,266 @@
+//! Derive macros for RetroShare wire items.
+//!
+//! - `RsPacket` implements `retroshare_compat::basics::RsPacket` from `#[rs_packet(service = .., sub_type = ..)]`
+//! - `RsSerialize` / `RsDeserialize` implement serde's traits for the RetroShare wire format,
+//!   fields marked with `#[tlv(tag = ..)]` are wrapped into a TLV on the fly
+//!
+//! ```ignore
+//! #[derive(Debug, RsPacket, RsSerialize, RsDeserialize)]
+//! #[rs_packet(service = ServiceType::Chat, sub_type = 0x04)]
+//! pub struct ChatStatusItem {
+//!     pub flags: u32,
+//!     #[tlv(tag = TLV_TYPE_STR_MSG, sized)]
+//!     pub status_string: String,
+//! }
+//! ```
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Expr, Fields, Ident, Type};
+
+#[proc_macro_derive(RsPacket, attributes(rs_packet))]
+pub fn derive_rs_packet(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    rs_packet(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(RsSerialize, attributes(tlv))]
+pub fn derive_rs_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    rs_serialize(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro_derive(RsDeserialize, attributes(tlv))]
+pub fn derive_rs_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    rs_deserialize(&input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+fn rs_packet(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let mut service = None;
+    let mut sub_type = None;
+
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("rs_packet"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("service") {
+                service = Some(meta.value()?.parse::<Expr>()?);
+                Ok(())
+            } else if meta.path.is_ident("sub_type") {
+                sub_type = Some(meta.value()?.parse::<Expr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `service` or `sub_type`"))
+            }
+        })?;
+    }
+
+    let missing = |what| {
+        syn::Error::new_spanned(&input.ident, format!("missing `#[rs_packet({what} = ..)]`"))
+    };
+    let service = service.ok_or_else(|| missing("service"))?;
+    let sub_type = sub_type.ok_or_else(|| missing("sub_type"))?;
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::retroshare_compat::basics::RsPacket for #name #ty_generics #where_clause {
+            fn get_service(&self) -> u16 {
+                (#service).into()
+            }
+
+            fn get_sub_type(&self) -> u8 {
+                #sub_type
+            }
+        }
+    })
+}
+
+/// How a field is put on the wire.
+enum Wrap {
+    /// serialized as is
+    None,
+    /// `Tlv<TAG, T>`, the inner value has no size of its own
+    Tlv(Expr),
+    /// `Tlv2<TAG, T>`, the inner value's size is replaced by the TLV header (strings, binary data)
+    Sized(Expr),
+}
+
+struct Field<'a> {
+    ident: &'a Ident,
+    ty: &'a Type,
+    wrap: Wrap,
+}
+
+fn parse_fields(input: &DeriveInput) -> syn::Result<Vec<Field<'_>>> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "generic items are not supported",
+        ));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            Fields::Unit => return Ok(vec![]),
+            Fields::Unnamed(_) => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "only structs with named fields are supported",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "only structs are supported",
+            ))
+        }
+    };
+
+    fields
+        .iter()
+        .map(|field| {
+            let mut tag = None;
+            let mut sized = false;
+            for attr in field.attrs.iter().filter(|a| a.path().is_ident("tlv")) {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("tag") {
+                        tag = Some(meta.value()?.parse::<Expr>()?);
+                        Ok(())
+                    } else if meta.path.is_ident("sized") {
+                        sized = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `tag` or `sized`"))
+                    }
+                })?;
+            }
+
+            let wrap = match (tag, sized) {
+                (None, false) => Wrap::None,
+                (None, true) => {
+                    return Err(syn::Error::new_spanned(field, "`sized` requires a `tag`"))
+                }
+                (Some(tag), false) => Wrap::Tlv(tag),
+                (Some(tag), true) => Wrap::Sized(tag),
+            };
+
+            Ok(Field {
+                ident: field.ident.as_ref().unwrap(),
+                ty: &field.ty,
+                wrap,
+            })
+        })
+        .collect()
+}
+
+fn rs_serialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = parse_fields(input)?;
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let len = fields.len();
+
+    let ser_fields = fields.iter().map(|Field { ident, wrap, .. }| {
+        let ident_str = ident.to_string();
+        let value = match wrap {
+            Wrap::None => quote! { &self.#ident },
+            Wrap::Tlv(tag) => quote! {
+                &::retroshare_compat::tlv::Tlv::<{ #tag }, _>::new(&self.#ident)
+            },
+            Wrap::Sized(tag) => quote! {
+                &::retroshare_compat::tlv::Tlv2::<{ #tag }, _>::from(&self.#ident)
+            },
+        };
+        quote! {
+            ::serde::ser::SerializeStruct::serialize_field(&mut state, #ident_str, #value)?;
+        }
+    });
+
+    Ok(quote! {
+        impl ::serde::Serialize for #name {
+            fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                #[allow(unused_mut)]
+                let mut state = serializer.serialize_struct(#name_str, #len)?;
+                #(#ser_fields)*
+                ::serde::ser::SerializeStruct::end(state)
+            }
+        }
+    })
+}
+
+fn rs_deserialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = parse_fields(input)?;
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let visitor = format_ident!("__{}Visitor", name);
+
+    let field_strs = fields.iter().map(|f| f.ident.to_string());
+    let idents = fields.iter().map(|f| f.ident);
+    let de_fields = fields
+        .iter()
+        .enumerate()
+        .map(|(i, Field { ident, ty, wrap })| {
+            let (wire_ty, unwrap) = match wrap {
+                Wrap::None => (quote! { #ty }, quote! {}),
+                Wrap::Tlv(tag) => (
+                    quote! { ::retroshare_compat::tlv::Tlv<{ #tag }, #ty> },
+                    quote! { .0 },
+                ),
+                Wrap::Sized(tag) => (
+                    quote! { ::retroshare_compat::tlv::Tlv2<{ #tag }, #ty> },
+                    quote! { .into_inner() },
+                ),
+            };
+            quote! {
+                let #ident = seq
+                    .next_element::<#wire_ty>()?
+                    .ok_or_else(|| ::serde::de::Error::invalid_length(#i, &self))?
+                    #unwrap;
+            }
+        });
+
+    Ok(quote! {
+        impl<'de> ::serde::Deserialize<'de> for #name {
+            fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                struct #visitor;
+
+                impl<'de> ::serde::de::Visitor<'de> for #visitor {
+                    type Value = #name;
+
+                    fn expecting(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                        write!(f, "struct {}", #name_str)
+                    }
+
+                    #[allow(unused_mut, unused_variables)]
+                    fn visit_seq<A>(self, mut seq: A) -> ::std::result::Result<Self::Value, A::Error>
+                    where
+                        A: ::serde::de::SeqAccess<'de>,
+                    {
+                        #(#de_fields)*
+                        Ok(#name { #(#idents),* })
+                    }
+                }
+
+                deserializer.deserialize_struct(#name_str, &[#(#field_strs),*], #visitor)
+            }
+        }
+    })
+}
@@ -332,6 +332,8 @@ gen_webui_types!(
 );
 gen_webui_types!(GxsGroupId, GxsGroupIdHex, GxsGroupIdWrapped);
 gen_webui_types!(GxsCircleId, GxsCircleIdHex, GxsCircleIdWrapped);
+gen_webui_types!(Sha1CheckSum, Sha1CheckSumHex, Sha1CheckSumWrapped);
+gen_webui_types!(NodeGroupId, NodeGroupIdHex, NodeGroupIdWrapped);
 
 // struct PeerBandwidthLimits : RsSerializable
 // {
@@ -0,0 +1,169 @@
+//! RS's local network discovery: locations announce themselves with UDP broadcasts (using udp-discovery-cpp), the
+//! packet's user data is a serialized `BroadcastDiscoveryPack`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    basics::{PgpFingerprint, SslId},
+    read_u16, read_u32, read_u64, write_u16, write_u32, write_u64,
+};
+
+// constexpr uint16_t port = 36405;
+// constexpr uint32_t appId = 904571;
+pub const BROADCAST_DISCOVERY_PORT: u16 = 36405;
+pub const BROADCAST_DISCOVERY_APP_ID: u32 = 904571;
+
+// udp-discovery-cpp's packet (protocol version 0):
+//  magic 'R' 'N' '6' 'U'
+//  version (u8), packet type (u8), reserved (u16)
+//  application id (u32), peer id (u32), snapshot index (u64)
+//  user data size (u16), user data
+const MAGIC: [u8; 4] = *b"RN6U";
+const PROTOCOL_VERSION: u8 = 0;
+const PACKET_HEADER_SIZE: usize = 4 + 1 + 1 + 2 + 4 + 4 + 8 + 2;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    IAmHere = 0,
+    IAmOutOfHere = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryPacket {
+    pub packet_type: PacketType,
+    pub app_id: u32,
+    /// random, picked on start up
+    pub peer_id: u32,
+    /// increased whenever the user data changes
+    pub snapshot_index: u64,
+    pub user_data: Vec<u8>,
+}
+
+impl DiscoveryPacket {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ser = Vec::with_capacity(PACKET_HEADER_SIZE + self.user_data.len());
+        ser.extend_from_slice(&MAGIC);
+        ser.push(PROTOCOL_VERSION);
+        ser.push(self.packet_type as u8);
+        write_u16(&mut ser, 0);
+        write_u32(&mut ser, self.app_id);
+        write_u32(&mut ser, self.peer_id);
+        write_u64(&mut ser, self.snapshot_index);
+        write_u16(&mut ser, self.user_data.len() as u16);
+        ser.extend_from_slice(&self.user_data);
+        ser
+    }
+
+    /// Returns `None` for anything that isn't a (complete) discovery packet.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_HEADER_SIZE || data[..4] != MAGIC || data[4] != PROTOCOL_VERSION {
+            return None;
+        }
+        let packet_type = match data[5] {
+            0 => PacketType::IAmHere,
+            1 => PacketType::IAmOutOfHere,
+            _ => return None,
+        };
+
+        let mut data = data[8..].to_owned();
+        let app_id = read_u32(&mut data);
+        let peer_id = read_u32(&mut data);
+        let snapshot_index = read_u64(&mut data);
+        let len = read_u16(&mut data) as usize;
+        if data.len() < len {
+            return None;
+        }
+        data.truncate(len);
+
+        Some(DiscoveryPacket {
+            packet_type,
+            app_id,
+            peer_id,
+            snapshot_index,
+            user_data: data,
+        })
+    }
+}
+
+// struct BroadcastDiscoveryPack : RsSerializable
+// {
+// 	RsPgpFingerprint mPgpFingerprint;
+// 	RsPeerId mSslId;
+// 	uint16_t mLocalPort;
+// 	std::string mProfileName;
+// 	...
+// };
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastDiscoveryPack {
+    pub pgp_fingerprint: PgpFingerprint,
+    pub ssl_id: SslId,
+    pub local_port: u16,
+    pub profile_name: String,
+}
+
+// struct RsBroadcastDiscoveryResult : RsSerializable
+// {
+// 	RsPgpFingerprint mPgpFingerprint;
+// 	RsPeerId mSslId;
+// 	std::string mProfileName;
+// 	RsUrl mLocator;
+// 	...
+// };
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BroadcastDiscoveryResult {
+    #[serde(
+        rename(serialize = "mPgpFingerprint", deserialize = "mPgpFingerprint"),
+        with = "hex"
+    )]
+    pub pgp_fingerprint: PgpFingerprint,
+    #[serde(rename(serialize = "mSslId", deserialize = "mSslId"), with = "hex")]
+    pub ssl_id: SslId,
+    #[serde(rename(serialize = "mProfileName", deserialize = "mProfileName"))]
+    pub profile_name: String,
+    /// e.g. `ipv4://192.168.1.2:1234`
+    #[serde(rename(serialize = "mLocator", deserialize = "mLocator"))]
+    pub locator: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basics::{PgpFingerprint, SslId},
+        serde::{from_retroshare_wire_result, to_retroshare_wire},
+    };
+
+    use super::{BroadcastDiscoveryPack, DiscoveryPacket, PacketType, BROADCAST_DISCOVERY_APP_ID};
+
+    #[test]
+    fn packet() {
+        let pack = BroadcastDiscoveryPack {
+            pgp_fingerprint: PgpFingerprint([1; 20]),
+            ssl_id: SslId([2; 16]),
+            local_port: 1234,
+            profile_name: "rusty".into(),
+        };
+        let user_data = to_retroshare_wire(&pack);
+        assert_eq!(user_data.len(), 20 + 16 + 2 + 4 + 5);
+
+        let packet = DiscoveryPacket {
+            packet_type: PacketType::IAmHere,
+            app_id: BROADCAST_DISCOVERY_APP_ID,
+            peer_id: 42,
+            snapshot_index: 1,
+            user_data,
+        };
+        let ser = packet.to_bytes();
+        assert_eq!(&ser[..6], b"RN6U\x00\x00");
+
+        let de = DiscoveryPacket::from_bytes(&ser).unwrap();
+        assert_eq!(de, packet);
+        let mut user_data = de.user_data;
+        let de: BroadcastDiscoveryPack = from_retroshare_wire_result(&mut user_data).unwrap();
+        assert_eq!(de, pack);
+
+        // truncated
+        assert_eq!(DiscoveryPacket::from_bytes(&ser[..ser.len() - 1]), None);
+        assert_eq!(DiscoveryPacket::from_bytes(b"RN6U"), None);
+    }
+}
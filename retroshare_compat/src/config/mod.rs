@@ -103,6 +103,7 @@ pub struct PeerNetItem {
 }
 
 bitflags! {
+    #[derive(Default)]
     pub struct ServicePermissionFlags: u32 {
         const DIRECT_DL  = 0x00000008;  // Accept to directly DL from this peer (breaks anonymity)
         const ALLOW_PUSH = 0x00000010;  // Auto-DL files recommended by this peer
@@ -153,14 +154,15 @@ pub struct PeerServicePermissionItem {
 //          std::map<RsPgpId,PeerBandwidthLimits> peers ;
 //  };
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+/// Rates in kB/s, 0 means unlimited.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct PeerBandwidthLimits {
-    max_up_rate_kbs: u32,
-    max_dl_rate_kbs: u32,
+    pub max_up_rate_kbs: u32,
+    pub max_dl_rate_kbs: u32,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PeerBandwidthLimitsItem(HashMap<PgpId, PeerBandwidthLimits>);
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PeerBandwidthLimitsItem(pub HashMap<PgpId, PeerBandwidthLimits>);
 
 bitflags! {
     #[derive(Default)]
@@ -182,14 +184,14 @@ impl_serde_for_bitflags!(NodeGroupFlags);
 //      RsTlvPgpIdSet pgpList;
 //  };
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct NodeGroupItem {
     _dummy: u32,
-    id: NodeGroupId,
-    name: StringTagged<TLV_TYPE_STR_NAME>,
-    flag: NodeGroupFlags,
+    pub id: NodeGroupId,
+    pub name: StringTagged<TLV_TYPE_STR_NAME>,
+    pub flag: NodeGroupFlags,
 
-    pgp_list: TlvPgpIdSet,
+    pub pgp_list: TlvPgpIdSet,
 }
 
 //  class RsPeerStunItem: public RsItem
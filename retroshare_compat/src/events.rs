@@ -5,16 +5,33 @@ use serde_json::{json, Value};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::{
-    basics::{GxsIdHex, PeerIdHex, SslId},
+    basics::{GxsIdHex, PeerIdHex, PgpFingerprint, SslId},
+    broadcast_discovery::BroadcastDiscoveryResult,
     services::chat::{ChatId, ChatLobbyMsgItem},
+    webui::mail::MailMessageId,
 };
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum EventType {
     None,
-    BroadcastDiscovery,
-    GossipDiscovery,
+    BroadcastDiscovery {
+        #[serde(rename(serialize = "mDiscoveryEventType", deserialize = "mDiscoveryEventType"))]
+        code: BroadcastDiscoveryEventCode,
+        #[serde(rename(serialize = "mData", deserialize = "mData"))]
+        data: BroadcastDiscoveryResult,
+    },
+    GossipDiscovery {
+        #[serde(rename(
+            serialize = "mGossipDiscoveryEventType",
+            deserialize = "mGossipDiscoveryEventType"
+        ))]
+        code: GossipDiscoveryEventCode,
+        #[serde(rename(serialize = "mFromId", deserialize = "mFromId"), with = "hex")]
+        from_id: SslId,
+        #[serde(rename(serialize = "mAboutId", deserialize = "mAboutId"), with = "hex")]
+        about_id: SslId,
+    },
     AuthsslConnectionAutentication,
     PeerConnection,
     GxsChanges,
@@ -23,7 +40,15 @@ pub enum EventType {
         #[serde(with = "hex")]
         ssl_id: SslId,
     },
-    MailStatus,
+    MailStatus {
+        #[serde(rename(
+            serialize = "mMailStatusEventCode",
+            deserialize = "mMailStatusEventCode"
+        ))]
+        code: MailStatusEventCode,
+        #[serde(rename(serialize = "mChangedMsgIds", deserialize = "mChangedMsgIds"))]
+        msg_ids: Vec<MailMessageId>,
+    },
     GxsCircles,
     GxsChannels,
     GxsForums,
@@ -37,10 +62,30 @@ pub enum EventType {
         msg: ChatMessage,
     },
     Network,
-    MailTag,
+    MailTag {
+        #[serde(rename(serialize = "mMailTagEventCode", deserialize = "mMailTagEventCode"))]
+        code: MailTagEventCode,
+        #[serde(rename(serialize = "mChangedMsgTagIds", deserialize = "mChangedMsgTagIds"))]
+        tag_ids: Vec<String>,
+    },
     /** Emitted to update library clients about file hashing being completed */
     FileHashingCompleted,
-    TorManager,
+    TorManager {
+        #[serde(rename(
+            serialize = "mTorManagerEventType",
+            deserialize = "mTorManagerEventType"
+        ))]
+        code: TorManagerEventCode,
+        #[serde(rename(
+            serialize = "mTorConnectivityStatus",
+            deserialize = "mTorConnectivityStatus"
+        ))]
+        connectivity: TorConnectivityStatus,
+        #[serde(rename(serialize = "mTorStatus", deserialize = "mTorStatus"))]
+        status: TorStatus,
+        #[serde(rename(serialize = "mErrorMessage", deserialize = "mErrorMessage"))]
+        error_message: String,
+    },
 }
 
 impl Default for EventType {
@@ -55,13 +100,13 @@ impl EventType {
 
         match self {
             None => 0,
-            BroadcastDiscovery => 1,
-            GossipDiscovery => 2,
+            BroadcastDiscovery { .. } => 1,
+            GossipDiscovery { .. } => 2,
             AuthsslConnectionAutentication => 3,
             PeerConnection => 4,
             GxsChanges => 5,
             PeerStateChanged { .. } => 6,
-            MailStatus => 7,
+            MailStatus { .. } => 7,
             GxsCircles => 8,
             GxsChannels => 9,
             GxsForums => 10,
@@ -72,9 +117,9 @@ impl EventType {
             FileTransfer => 14,
             ChatMessage { .. } => 15,
             Network => 16,
-            MailTag => 17,
+            MailTag { .. } => 17,
             FileHashingCompleted => 20,
-            TorManager => 21,
+            TorManager { .. } => 21,
         }
     }
 }
@@ -89,8 +134,20 @@ impl From<u32> for EventType {
     fn from(x: u32) -> Self {
         match x {
             0 => EventType::None,
-            1 => EventType::BroadcastDiscovery,
-            2 => EventType::GossipDiscovery,
+            1 => EventType::BroadcastDiscovery {
+                code: BroadcastDiscoveryEventCode::Unknown,
+                data: BroadcastDiscoveryResult {
+                    pgp_fingerprint: PgpFingerprint::default(),
+                    ssl_id: SslId::default(),
+                    profile_name: String::new(),
+                    locator: String::new(),
+                },
+            },
+            2 => EventType::GossipDiscovery {
+                code: GossipDiscoveryEventCode::Unknown,
+                from_id: SslId::default(),
+                about_id: SslId::default(),
+            },
             3 => EventType::AuthsslConnectionAutentication,
             4 => EventType::PeerConnection,
             5 => EventType::GxsChanges,
@@ -98,7 +155,10 @@ impl From<u32> for EventType {
                 // TODO
                 ssl_id: SslId::default(),
             },
-            7 => EventType::MailStatus,
+            7 => EventType::MailStatus {
+                code: MailStatusEventCode::NewMessage,
+                msg_ids: vec![],
+            },
             8 => EventType::GxsCircles,
             9 => EventType::GxsChannels,
             10 => EventType::GxsForums,
@@ -110,9 +170,17 @@ impl From<u32> for EventType {
                 msg: ChatMessage::default(),
             },
             16 => EventType::Network,
-            17 => EventType::MailTag,
+            17 => EventType::MailTag {
+                code: MailTagEventCode::TagAdded,
+                tag_ids: vec![],
+            },
             20 => EventType::FileHashingCompleted,
-            21 => EventType::TorManager,
+            21 => EventType::TorManager {
+                code: TorManagerEventCode::Unknown,
+                connectivity: TorConnectivityStatus::Unknown,
+                status: TorStatus::Unknown,
+                error_message: String::new(),
+            },
             m @ _ => unreachable!("invalid value {m}"),
         }
     }
@@ -159,6 +227,128 @@ impl From<EventType> for Value {
     }
 }
 
+// enum class RsBroadcastDiscoveryEventType: uint32_t {
+// 	UNKNOWN     = 0x00,
+// 	PEER_FOUND  = 0x01
+// };
+
+#[repr(u32)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum BroadcastDiscoveryEventCode {
+    Unknown = 0x00,
+    PeerFound = 0x01,
+}
+
+// enum class RsGossipDiscoveryEventType: uint32_t {
+// 	UNKNOWN                   = 0x00,
+// 	FRIEND_PEER_INFO_RECEIVED = 0x01,
+// };
+
+#[repr(u32)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum GossipDiscoveryEventCode {
+    Unknown = 0x00,
+    FriendPeerInfoReceived = 0x01,
+}
+
+// enum class RsMailStatusEventCode: uint8_t
+// {
+// 	NEW_MESSAGE                     = 0x00,
+// 	MESSAGE_REMOVED                 = 0x01,
+// 	MESSAGE_SENT                    = 0x02,
+// 	/// means the peer received the message
+// 	MESSAGE_RECEIVED_ACK            = 0x03,
+// 	/// An error occurred attempting to sign the message
+// 	SIGNATURE_FAILED   = 0x04,
+// 	MESSAGE_CHANGED                 = 0x05,
+// 	TAG_CHANGED                     = 0x06,
+// };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum MailStatusEventCode {
+    NewMessage = 0x00,
+    MessageRemoved = 0x01,
+    MessageSent = 0x02,
+    MessageReceivedAck = 0x03,
+    SignatureFailed = 0x04,
+    MessageChanged = 0x05,
+    TagChanged = 0x06,
+}
+
+// enum class MailTagEventCode: uint8_t {
+// 	TAG_ADDED   = 0x00,
+// 	TAG_CHANGED = 0x01,
+// 	TAG_REMOVED = 0x02,
+// };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum MailTagEventCode {
+    TagAdded = 0x00,
+    TagChanged = 0x01,
+    TagRemoved = 0x02,
+}
+
+// enum class RsTorManagerEventCode: uint8_t
+// {
+//     UNKNOWN                   = 0x00,
+//     TOR_STATUS_CHANGED        = 0x01,
+//     BOOTSTRAP_STATUS_CHANGED  = 0x02,
+//     TOR_CONNECTIVITY_CHANGED  = 0x03,
+//     TOR_MANAGER_ERROR         = 0x04,
+//     CONFIGURATION_NEEDED      = 0x05,
+//     TOR_MANAGER_STOPPED       = 0x06,
+// };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum TorManagerEventCode {
+    Unknown = 0x00,
+    TorStatusChanged = 0x01,
+    BootstrapStatusChanged = 0x02,
+    TorConnectivityChanged = 0x03,
+    TorManagerError = 0x04,
+    ConfigurationNeeded = 0x05,
+    TorManagerStopped = 0x06,
+}
+
+// enum class RsTorConnectivityStatus: uint8_t {
+//     UNKNOWN              = 0x00,
+//     CONNECTING           = 0x01,
+//     SOCKET_CONNECTED     = 0x02,
+//     AUTHENTICATING       = 0x03,
+//     AUTHENTICATION_ERROR = 0x04,
+//     HIDDEN_SERVICE_READY = 0x05,
+//     NOT_CONNECTED        = 0x06,
+// };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum TorConnectivityStatus {
+    Unknown = 0x00,
+    Connecting = 0x01,
+    SocketConnected = 0x02,
+    Authenticating = 0x03,
+    AuthenticationError = 0x04,
+    HiddenServiceReady = 0x05,
+    NotConnected = 0x06,
+}
+
+// enum class RsTorStatus: uint8_t {
+//     UNKNOWN = 0x00,
+//     OFFLINE = 0x01,
+//     READY   = 0x02,
+// };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum TorStatus {
+    Unknown = 0x00,
+    Offline = 0x01,
+    Ready = 0x02,
+}
+
 // struct ChatMessage : RsSerializable
 // {
 //     ChatId chat_id; // id of chat endpoint
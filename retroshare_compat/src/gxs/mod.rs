@@ -325,6 +325,12 @@ pub struct GxsReputation {
     peer_opinion: i32,
 }
 
+impl GxsReputation {
+    pub fn overall_score(&self) -> i32 {
+        self.overall_score
+    }
+}
+
 #[cfg(test)]
 mod test_nxs_transaction {
     use crate::serde::to_retroshare_wire;
@@ -55,6 +55,12 @@ pub struct SSGxsIdGroup {
     score: SSGxsIdReputation,
 }
 
+impl SSGxsIdGroup {
+    pub fn reputation(&self) -> &GxsReputation {
+        &self.score.rep
+    }
+}
+
 fn read(part: &str, identifier: char) -> String {
     let mut txt = part.to_string();
     let expected = identifier.to_string() + ":";
@@ -2,7 +2,14 @@
 
 use byteorder::{ByteOrder, NetworkEndian};
 
+// lets the derive macros refer to `::retroshare_compat` from within this crate, too
+extern crate self as retroshare_compat;
+
+pub use basics::RsPacket;
+pub use retroshare_compat_derive::{RsDeserialize, RsPacket, RsSerialize};
+
 pub mod basics;
+pub mod broadcast_discovery;
 pub mod config;
 pub mod events;
 pub mod gxs;
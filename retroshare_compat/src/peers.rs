@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::basics::{PgpFingerprint, PgpId, SslId, PgpIdHex};
+use crate::{
+    basics::{PgpFingerprint, PgpId, PgpIdHex, SslId},
+    config::ServicePermissionFlags,
+};
 
 // struct RsPeerDetails : RsSerializable
 // {
@@ -146,6 +149,8 @@ pub struct PeerDetails {
     #[serde(with = "hex")]
     pub fpr: PgpFingerprint,
     pub gpg_signers: Vec<PgpIdHex>,
+
+    pub service_perm_flags: ServicePermissionFlags,
 }
 
 impl PeerDetails {
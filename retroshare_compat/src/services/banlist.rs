@@ -0,0 +1,181 @@
+use ::serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{
+    basics::PeerId,
+    tlv::{tags::*, tlv_ip_addr::TlvIpAddress, tlv_set::TlvSet, tlv_string::StringTagged, Tlv},
+};
+
+// const uint8_t RS_PKT_SUBTYPE_BANLIST_ITEM_deprecated        = 0x01;
+// const uint8_t RS_PKT_SUBTYPE_BANLIST_CONFIG_ITEM_deprecated = 0x02;
+// const uint8_t RS_PKT_SUBTYPE_BANLIST_ITEM                   = 0x03;
+// const uint8_t RS_PKT_SUBTYPE_BANLIST_CONFIG_ITEM            = 0x04;
+pub const BANLIST_SUB_TYPE_ITEM: u8 = 0x03;
+pub const BANLIST_SUB_TYPE_CONFIG_ITEM: u8 = 0x04;
+pub const BANLIST_SUB_TYPE_OWN_ENTRY: u8 = 0x80;
+pub const BANLIST_SUB_TYPE_SETTINGS: u8 = 0x81;
+
+// #define RSBANLIST_ORIGIN_UNKNOWN	0
+// #define RSBANLIST_ORIGIN_SELF	1
+// #define RSBANLIST_ORIGIN_FRIEND	2
+// #define RSBANLIST_ORIGIN_FOF		3
+pub const BANLIST_ORIGIN_UNKNOWN: u32 = 0;
+pub const BANLIST_ORIGIN_SELF: u32 = 1;
+pub const BANLIST_ORIGIN_FRIEND: u32 = 2;
+pub const BANLIST_ORIGIN_FOF: u32 = 3;
+
+// #define RSBANLIST_REASON_UNKNOWN	   0
+// #define RSBANLIST_REASON_USER	   1
+// #define RSBANLIST_REASON_DHT	   2
+// #define RSBANLIST_REASON_AUTO_RANGE 3
+pub const BANLIST_REASON_UNKNOWN: u32 = 0;
+pub const BANLIST_REASON_USER: u32 = 1;
+pub const BANLIST_REASON_DHT: u32 = 2;
+pub const BANLIST_REASON_AUTO_RANGE: u32 = 3;
+
+// #define RSBANLIST_TYPE_PEERLIST	1
+// #define RSBANLIST_TYPE_BLACKLIST	2
+// #define RSBANLIST_TYPE_WHITELIST	3
+pub const BANLIST_TYPE_PEERLIST: u32 = 1;
+pub const BANLIST_TYPE_BLACKLIST: u32 = 2;
+pub const BANLIST_TYPE_WHITELIST: u32 = 3;
+
+// class RsTlvBanListEntry: public RsTlvItem
+// {
+// 	RsTlvIpAddress addr;
+// 	uint32_t level;
+// 	uint32_t reason;
+// 	uint32_t age;
+// 	uint8_t  masked_bytes;
+// 	std::string comment;
+// };
+
+#[derive(Debug, PartialEq, Eq, Default, Clone, Serialize, Deserialize)]
+pub struct TlvBanListEntryInner {
+    pub addr: TlvIpAddress,
+    /// origin of the entry (`BANLIST_ORIGIN_*`)
+    pub level: u32,
+    /// `BANLIST_REASON_*`
+    pub reason: u32,
+    /// seconds since the entry was added
+    pub age: u32,
+    /// number of (IPv4) bytes that are masked, 0 bans a single address, 1 a /24 and 2 a /16 range
+    pub masked_bytes: u8,
+    pub comment: StringTagged<TLV_TYPE_STR_COMMENT>,
+}
+pub type TlvBanListEntry = Tlv<TLV_TYPE_BAN_ENTRY, TlvBanListEntryInner>;
+
+impl Hash for TlvBanListEntryInner {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr.hash(state);
+        self.masked_bytes.hash(state);
+    }
+}
+
+impl fmt::Display for TlvBanListEntryInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TlvBanListEntry: [addr: {}, masked_bytes: {}, level: {}, reason: {}, age: {}]",
+            self.addr.0.ip(),
+            self.masked_bytes,
+            self.level,
+            self.reason,
+            self.age
+        )
+    }
+}
+
+pub type TlvBanList = TlvSet<TLV_TYPE_BAN_LIST, TlvBanListEntry>;
+
+// class RsBanListItem: public RsItem
+// {
+// 	RsTlvBanList peerList;
+// };
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BanListItem {
+    pub peer_list: TlvBanList,
+}
+
+// class RsBanListConfigItem: public RsItem
+// {
+// 	uint32_t type;
+// 	RsPeerId peerId;
+// 	rstime_t update_time;
+// 	RsTlvBanList banned_peers;
+// };
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BanListConfigItem {
+    /// `BANLIST_TYPE_*`
+    pub type_: u32,
+    pub peer_id: PeerId,
+    pub update_time: i64,
+    pub banned_peers: TlvBanList,
+}
+
+/// One of our own bans, only used for rustyshare's own config file.
+///
+/// RS keeps its own bans in a `RsBanListConfigItem`, which has no room for an expiry.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BanListOwnEntryItem {
+    pub added: u64,
+    /// 0 for permanent entries
+    pub expires: u64,
+    pub entry: TlvBanListEntry,
+}
+
+pub const BANLIST_SETTINGS_ENABLED: u32 = 0x1;
+pub const BANLIST_SETTINGS_USE_FRIENDS: u32 = 0x2;
+pub const BANLIST_SETTINGS_SHARE: u32 = 0x4;
+
+/// Ban list settings (`BANLIST_SETTINGS_*`), only used for rustyshare's own config file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BanListSettingsItem {
+    pub flags: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::{
+        serde::{from_retroshare_wire, to_retroshare_wire},
+        tlv::TLV_HEADER_SIZE,
+    };
+
+    use super::{BanListItem, TlvBanListEntry, TlvBanListEntryInner, BANLIST_REASON_USER};
+
+    #[test]
+    fn banlist_item() {
+        let entry = TlvBanListEntry::new(TlvBanListEntryInner {
+            addr: "192.168.1.0:0".parse::<SocketAddr>().unwrap().into(),
+            level: 1,
+            reason: BANLIST_REASON_USER,
+            age: 42,
+            masked_bytes: 1,
+            comment: "test".into(),
+        });
+        let item = BanListItem {
+            peer_list: [entry.to_owned()].into_iter().collect(),
+        };
+
+        let mut ser = to_retroshare_wire(&item);
+        // set header + entry header + ip address (ipv4) + level, reason, age + masked_bytes + comment
+        let expected = TLV_HEADER_SIZE
+            + TLV_HEADER_SIZE
+            + (TLV_HEADER_SIZE * 2 + 4 + 2)
+            + 3 * 4
+            + 1
+            + (TLV_HEADER_SIZE + 4);
+        assert_eq!(ser.len(), expected);
+
+        let de: BanListItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.peer_list.0.len(), 1);
+        assert_eq!(de.peer_list.0.into_iter().next().unwrap().0, entry.0);
+    }
+}
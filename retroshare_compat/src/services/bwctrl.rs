@@ -1,5 +1,12 @@
 use crate::tlv::Tlv;
 
+// const uint8_t RS_PKT_SUBTYPE_BWCTRL_ALLOWED_ITEM = 0x01;
+pub const BWCTRL_SUB_TYPE_ALLOWED: u8 = 0x01;
+// rustyshare's own config items
+pub const BWCTRL_SUB_TYPE_MAX_RATES: u8 = 0x80;
+pub const BWCTRL_SUB_TYPE_PEER_LIMITS: u8 = 0x81;
+
 const BWCTRL_ITEM_TAG: u16 = 0x0035;
 
+/// Rate (in bytes/s) the peer is allowed to send us.
 pub type BwCtrlAllowedItem = Tlv<BWCTRL_ITEM_TAG, u32>;
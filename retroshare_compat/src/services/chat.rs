@@ -4,10 +4,12 @@ use bitflags_serde_shim::impl_serde_for_bitflags;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::{
-    basics::{DistantChatPeerId, DistantChatPeerIdHex, PeerId, PeerIdHex},
+    basics::{DistantChatPeerId, DistantChatPeerIdHex, GxsId, PeerId, PeerIdHex},
     serde::Toggleable,
+    services::ServiceType,
     tlv::{tags::*, tlv_keys::TlvKeySignature, tlv_string::StringTagged},
     webui::XInt64,
+    RsDeserialize, RsPacket, RsSerialize,
 };
 
 pub type ChatLobbyId = u64;
@@ -43,6 +45,16 @@ impl_serde_for_bitflags!(ChatLobbyFlags);
 // const ChatLobbyFlags RS_CHAT_LOBBY_FLAGS_CHALLENGE     ( 0x00000008 ) ;
 // const ChatLobbyFlags RS_CHAT_LOBBY_FLAGS_PGP_SIGNED    ( 0x00000010 ) ; // requires the signing ID to be PGP-linked. Avoids anonymous crap.
 
+// RS uses the same type for lobby flags and chat (message) flags
+impl ChatLobbyFlags {
+    pub const LOBBY_AUTO_SUBSCRIBE: Self = Self { bits: 0x0001 };
+    pub const LOBBY_PUBLIC: Self = Self { bits: 0x0004 };
+    pub const LOBBY_CHALLENGE: Self = Self { bits: 0x0008 };
+    pub const LOBBY_PGP_SIGNED: Self = Self { bits: 0x0010 };
+    /// Not part of RS, reuses `RS_CHAT_LOBBY_FLAGS_deprecated`, only used in rustyshare's own config.
+    pub const LOBBY_NOTIFY: Self = Self { bits: 0x0002 };
+}
+
 // class RsChatMsgItem: public RsChatItem
 // {
 // public:
@@ -280,10 +292,144 @@ pub struct ChatLobbyInviteItem {
 // 		std::string status_string;
 // };
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone, RsPacket, RsSerialize, RsDeserialize)]
+#[rs_packet(service = ServiceType::Chat, sub_type = 0x04)]
 pub struct ChatStatusItem {
     pub flags: u32,
-    pub status_string: StringTagged<TLV_TYPE_STR_MSG>,
+    #[tlv(tag = TLV_TYPE_STR_MSG, sized)]
+    pub status_string: String,
+}
+
+// // This class is used to store the outgoing (queued) private messages
+// //
+// class RsPrivateChatMsgConfigItem: public RsChatItem
+// {
+// 	public:
+// 		RsPrivateChatMsgConfigItem() :RsChatItem(RS_PKT_SUBTYPE_PRIVATECHATMSG_CONFIG) {}
+// 		RsPrivateChatMsgConfigItem(void *data,uint32_t size) ; // deserialization
+
+// 		virtual ~RsPrivateChatMsgConfigItem() {}
+// 		virtual void clear() {}
+
+// 		virtual void serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx);
+
+// 		/* set data from RsChatMsgItem to RsPrivateChatMsgConfigItem */
+// 		void set(RsChatMsgItem *ci, const RsPeerId &peerId, uint32_t confFlags);
+// 		/* get data from RsPrivateChatMsgConfigItem to RsChatMsgItem */
+// 		void get(RsChatMsgItem *ci);
+
+// 		RsPeerId configPeerId;
+// 		uint32_t chatFlags;
+// 		uint32_t configFlags;
+// 		uint32_t sendTime;
+// 		std::string message;
+// 		uint32_t recvTime;
+// };
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PrivateChatMsgConfigItem {
+    pub config_peer_id: PeerId,
+    pub chat_flags: ChatLobbyFlags,
+    pub config_flags: u32,
+    pub send_time: u32,
+    pub message: StringTagged<TLV_TYPE_STR_MSG>,
+    pub recv_time: u32,
+}
+
+impl PrivateChatMsgConfigItem {
+    pub fn new(msg: ChatMsgItem, peer_id: PeerId) -> Self {
+        Self {
+            config_peer_id: peer_id,
+            chat_flags: msg.chat_flags,
+            config_flags: 0,
+            send_time: msg.send_time,
+            message: msg.message,
+            recv_time: msg.recv_time,
+        }
+    }
+}
+
+impl From<PrivateChatMsgConfigItem> for (PeerId, ChatMsgItem) {
+    fn from(item: PrivateChatMsgConfigItem) -> Self {
+        (
+            item.config_peer_id,
+            ChatMsgItem {
+                chat_flags: item.chat_flags,
+                send_time: item.send_time,
+                message: item.message,
+                recv_time: item.recv_time,
+            },
+        )
+    }
+}
+
+// class RsChatLobbyConfigItem: public RsChatItem
+// {
+// public:
+//     RsChatLobbyConfigItem() :RsChatItem(RS_PKT_SUBTYPE_CHAT_LOBBY_CONFIG) { lobby_Id = 0; }
+
+//     virtual ~RsChatLobbyConfigItem() {}
+
+//     virtual void clear() { lobby_Id = 0; }
+
+// 	void serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx);
+
+//     uint64_t lobby_Id;
+//     uint32_t flags ;
+// };
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyConfigItem {
+    pub lobby_id: ChatLobbyId,
+    pub flags: ChatLobbyFlags,
+}
+
+// class RsSubscribedChatLobbyConfigItem: public RsChatItem
+// {
+// public:
+//     RsSubscribedChatLobbyConfigItem() :RsChatItem(RS_PKT_SUBTYPE_SUBSCRIBED_CHAT_LOBBY_CONFIG) {}
+//     virtual ~RsSubscribedChatLobbyConfigItem() {}
+
+//     virtual void clear() { RsChatItem::clear(); info.clear(); }
+
+// 	void serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx);
+
+//     ChatLobbyInfo info;
+// };
+
+// void RsSubscribedChatLobbyConfigItem::serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx)
+// {
+//     RsTypeSerializer::serial_process<uint64_t>(j,ctx,info.lobby_id,"info.lobby_id") ;
+//     RsTypeSerializer::serial_process(j,ctx,TLV_TYPE_STR_NAME,info.lobby_name,"info.lobby_name") ;
+//     RsTypeSerializer::serial_process(j,ctx,TLV_TYPE_STR_NAME,info.lobby_topic,"info.lobby_topic") ;
+//     RsTypeSerializer::serial_process(j,ctx,info.participating_friends,"info.participating_friends") ;
+//     RsTypeSerializer::serial_process(j,ctx,info.gxs_id,"info.gxs_id") ;
+//     RsTypeSerializer::serial_process(j,ctx,info.lobby_flags,"info.lobby_flags") ;
+// }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SubscribedChatLobbyConfigItem {
+    pub lobby_id: ChatLobbyId,
+    pub lobby_name: StringTagged<TLV_TYPE_STR_NAME>,
+    pub lobby_topic: StringTagged<TLV_TYPE_STR_NAME>,
+    pub participating_friends: Vec<PeerId>,
+    pub gxs_id: GxsId,
+    pub lobby_flags: ChatLobbyFlags,
+}
+
+// not part of RS, holds the global lobby moderation settings
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatModerationConfigItem {
+    pub min_reputation: i32,
+    pub banned: Vec<GxsId>,
+    pub muted: Vec<GxsId>,
+}
+
+// not part of RS, holds the identities banned from a single lobby
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyBanConfigItem {
+    pub lobby_id: ChatLobbyId,
+    pub banned: Vec<GxsId>,
 }
 
 // // This class contains avatar images in Qt format.
@@ -306,10 +452,9 @@ pub struct ChatStatusItem {
 // 	unsigned char* image_data ; /// image data
 // };
 
-// FIXME: add support for RawMemoryWrapper
+// `RawMemoryWrapper` is serialized as size (u32) followed by the data, just like a `Vec<u8>`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatAvatarItem {
-    pub uint32_t: u32,
     pub image_data: Vec<u8>,
 }
 
@@ -466,3 +611,73 @@ impl From<DistantChatPeerId> for ChatId {
 //         ChatId::TypeBroadcast
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basics::{GxsId, PeerId},
+        serde::{from_retroshare_wire, to_retroshare_wire},
+    };
+
+    use crate::{services::ServiceType, tlv::tlv_string::StringTagged, RsPacket};
+
+    use super::{ChatAvatarItem, ChatLobbyFlags, ChatStatusItem, SubscribedChatLobbyConfigItem};
+
+    #[test]
+    fn chat_status_item() {
+        let item = ChatStatusItem {
+            flags: ChatLobbyFlags::PRIVATE.bits(),
+            status_string: "is typing...".into(),
+        };
+        assert_eq!(item.get_service(), u16::from(ServiceType::Chat));
+        assert_eq!(item.get_sub_type(), 0x04);
+
+        // must match the previous hand written layout
+        let mut expected = to_retroshare_wire(&item.flags);
+        expected.extend(to_retroshare_wire(&StringTagged::<
+            { crate::tlv::tags::TLV_TYPE_STR_MSG },
+        >::from("is typing...")));
+
+        let mut ser = to_retroshare_wire(&item);
+        assert_eq!(ser, expected);
+
+        let de: ChatStatusItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.flags, item.flags);
+        assert_eq!(de.status_string, item.status_string);
+    }
+
+    #[test]
+    fn subscribed_lobby_config_item() {
+        let item = SubscribedChatLobbyConfigItem {
+            lobby_id: 0x0102030405060708,
+            lobby_name: "lobby".to_owned().into(),
+            lobby_topic: "".to_owned().into(),
+            participating_friends: vec![PeerId::from("65d33bc7bee18b713364b0301dbed896")],
+            gxs_id: GxsId::from("c59df722f56f2f886ac301acc5572e03"),
+            lobby_flags: ChatLobbyFlags::LOBBY_PUBLIC,
+        };
+
+        let mut ser = to_retroshare_wire(&item);
+        // id + name (tlv) + topic (tlv) + friends + gxs id + flags
+        assert_eq!(ser.len(), 8 + (6 + 5) + 6 + (4 + 16) + 16 + 4);
+
+        let de: SubscribedChatLobbyConfigItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.lobby_id, item.lobby_id);
+        assert_eq!(de.participating_friends, item.participating_friends);
+        assert_eq!(de.gxs_id, item.gxs_id);
+        assert_eq!(de.lobby_flags, item.lobby_flags);
+    }
+
+    #[test]
+    fn avatar_item() {
+        let item = ChatAvatarItem {
+            image_data: vec![0xff, 0xd8, 0xff, 0xe0],
+        };
+
+        let mut ser = to_retroshare_wire(&item);
+        assert_eq!(ser, [0, 0, 0, 4, 0xff, 0xd8, 0xff, 0xe0]);
+
+        let de: ChatAvatarItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.image_data, item.image_data);
+    }
+}
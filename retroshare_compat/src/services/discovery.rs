@@ -51,13 +51,45 @@ pub enum GossipDiscoveryItemType {
 // };
 
 #[repr(u32)]
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum GossipDiscoveryPgpListMode {
     None = 0x00000000,
     Friends = 0x00000001,
     Getcert = 0x00000002,
 }
 
+// our serde implementation doesn't support enums, serialize the plain u32
+impl Serialize for GossipDiscoveryPgpListMode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(*self as u32)
+    }
+}
+
+impl<'de> Deserialize<'de> for GossipDiscoveryPgpListMode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match u32::deserialize(deserializer)? {
+            0x00000001 => Ok(GossipDiscoveryPgpListMode::Friends),
+            0x00000002 => Ok(GossipDiscoveryPgpListMode::Getcert),
+            _ => Ok(GossipDiscoveryPgpListMode::None),
+        }
+    }
+}
+
+// const uint32_t RS_NET_MODE_UNKNOWN = 0x0000;
+// const uint32_t RS_NET_MODE_EXT     = 0x0001;
+// const uint32_t RS_NET_MODE_UPNP    = 0x0002;
+// const uint32_t RS_NET_MODE_UDP     = 0x0004;
+// const uint32_t RS_NET_MODE_HIDDEN  = 0x0008;
+// const uint32_t RS_NET_MODE_UNREACHABLE = 0x0010;
+pub const NET_MODE_UDP: u32 = 0x0004;
+pub const NET_MODE_HIDDEN: u32 = 0x0008;
+
 // const uint32_t RS_VS_DISC_OFF		= 0x0000;
 // const uint32_t RS_VS_DISC_MINIMAL	= 0x0001;
 // const uint32_t RS_VS_DISC_FULL		= 0x0002;
@@ -137,6 +169,13 @@ pub struct DiscPgpListItem {
 // 	uint32_t bin_len;
 // };
 
+/// Binary (public) pgp key of a friend, `bin_data` is serialized like a `RsTlvMemBlock_proxy`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscPgpKeyItem {
+    pub pgp_key_id: PgpId,
+    pub bin_data: Vec<u8>,
+}
+
 // class RS_DEPRECATED_FOR(RsDiscPgpKeyItem) RsDiscPgpCertItem: public RsDiscItem
 // {
 // public:
@@ -158,6 +197,13 @@ pub struct DiscPgpListItem {
 //     std::string pgpCert;
 // };
 
+/// Armored pgp key, still sent by older RS versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiscPgpCertItem {
+    pub pgp_id: PgpId,
+    pub pgp_cert: StringTagged<TLV_TYPE_STR_PGPCERT>,
+}
+
 // class RsDiscContactItem: public RsDiscItem
 // {
 // public:
@@ -202,7 +248,7 @@ pub struct DiscPgpListItem {
 // };
 
 // #[derive(Serialize, Deserialize, Debug)]
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct DiscContactItem {
     pub pgp_id: PgpId,
     pub ssl_id: SslId,
@@ -510,11 +556,46 @@ impl fmt::Display for DiscIdentityListItem {
 #[cfg(test)]
 mod test_discovery {
     use crate::{
+        basics::PgpId,
         serde::{from_retroshare_wire, to_retroshare_wire},
         services::discovery::read_rs_disc_contact_item,
+        tlv::tlv_set::TlvPgpIdSet,
     };
 
-    use super::{write_rs_disc_contact_item, DiscContactItem};
+    use super::{
+        write_rs_disc_contact_item, DiscContactItem, DiscPgpKeyItem, DiscPgpListItem,
+        GossipDiscoveryPgpListMode,
+    };
+
+    #[test]
+    fn test_disc_pgp_list_item() {
+        let orig = DiscPgpListItem {
+            mode: GossipDiscoveryPgpListMode::Getcert,
+            pgp_id_set: TlvPgpIdSet::from_iter([PgpId([1; 8])]),
+        };
+
+        let mut ser = to_retroshare_wire(&orig);
+        assert_eq!(&ser[..4], &[0, 0, 0, 2]);
+
+        let de: DiscPgpListItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.mode, orig.mode);
+        assert_eq!(de.pgp_id_set.0, orig.pgp_id_set.0);
+    }
+
+    #[test]
+    fn test_disc_pgp_key_item() {
+        let orig = DiscPgpKeyItem {
+            pgp_key_id: PgpId([1; 8]),
+            bin_data: vec![2; 5],
+        };
+
+        let mut ser = to_retroshare_wire(&orig);
+        assert_eq!(ser.len(), 8 + 4 + 5);
+        assert_eq!(&ser[8..12], &[0, 0, 0, 5]);
+
+        let de: DiscPgpKeyItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de, orig);
+    }
 
     #[test]
     #[should_panic]
@@ -0,0 +1,219 @@
+use ::serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::{
+    basics::{GxsId, Sha1CheckSum},
+    serde::to_retroshare_wire,
+    tlv::tlv_keys::TlvKeySignature,
+};
+
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_PUBLISH_KEY             = 0x01 ;	// used to publish a key
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_ACK_deprecated          = 0x03 ;	// don't use!
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_SIGNED_RECEIPT          = 0x04 ;	// long-distance acknowledgement of data received
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_DATA_deprecated         = 0x05 ;	// used to send data to a destination (Signed by source)
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_DATA                    = 0x06 ;	// used to send data to a destination (Signed by source)
+//
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_MATRIX_CLUES            = 0x80 ;	// item to save matrix clues
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_FRIENDS_LIST            = 0x82 ;	// item to save friend lists
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_ROUTING_INFO_deprecated = 0x87 ;	// deprecated. Don't use.
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_TRANSACTION_CHUNK       = 0x88 ;	// chunk of data. Used internally.
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_TRANSACTION_ACKN        = 0x89 ;	// acknowledge for finished transaction. Not necessary, but increases fiability.
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_MATRIX_TRACK            = 0x90 ;	// item to save matrix track info
+// const uint8_t RS_PKT_SUBTYPE_GROUTER_ROUTING_INFO            = 0x93 ;	// item to save routing info
+pub const GROUTER_SUB_TYPE_SIGNED_RECEIPT: u8 = 0x04;
+pub const GROUTER_SUB_TYPE_DATA: u8 = 0x06;
+pub const GROUTER_SUB_TYPE_MATRIX_CLUES: u8 = 0x80;
+pub const GROUTER_SUB_TYPE_TRANSACTION_CHUNK: u8 = 0x88;
+pub const GROUTER_SUB_TYPE_TRANSACTION_ACKN: u8 = 0x89;
+pub const GROUTER_SUB_TYPE_ROUTING_INFO: u8 = 0x93;
+
+// static const uint32_t RS_GROUTER_DATA_FLAGS_ENCRYPTED = 0x0001 ;
+pub const GROUTER_DATA_FLAGS_ENCRYPTED: u32 = 0x0001;
+
+// static const GRouterServiceId GROUTER_CLIENT_ID_MESSAGES     = 0x1001 ;
+pub const GROUTER_CLIENT_ID_MESSAGES: u32 = 0x1001;
+
+// class RsGRouterGenericDataItem: public RsGRouterAbstractMsgItem, public RsGRouterNonCopyableObject
+// {
+// 	GRouterMsgPropagationId routing_id ;
+// 	GRouterKeyId destination_key ;
+// 	GRouterServiceId service_id ;
+// 	uint32_t data_size ;
+// 	uint8_t *data_bytes;
+//
+// 	RsTlvKeySignature signature ;		// signature of the data, without the signature
+// 	uint32_t duplication_factor ;
+// 	uint32_t flags ;
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GRouterGenericDataItem {
+    pub routing_id: u64,
+    pub destination_key: GxsId,
+    pub service_id: u32,
+    // pub data_size: u32, // part of data
+    pub data: Vec<u8>,
+
+    pub signature: TlvKeySignature,
+    pub duplication_factor: u32,
+    pub flags: u32,
+}
+
+impl GRouterGenericDataItem {
+    /// Returns the (serialized) part of the item that is covered by the signature.
+    pub fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = to_retroshare_wire(&self.routing_id);
+        payload.extend(to_retroshare_wire(&self.destination_key));
+        payload.extend(to_retroshare_wire(&self.service_id));
+        payload.extend(to_retroshare_wire(&self.data));
+        payload
+    }
+}
+
+impl fmt::Display for GRouterGenericDataItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GRouterGenericDataItem [routing_id: {:016x}, destination: {}, service_id: {:08x}, size: {}]",
+            self.routing_id,
+            self.destination_key,
+            self.service_id,
+            self.data.len(),
+        )
+    }
+}
+
+// class RsGRouterSignedReceiptItem: public RsGRouterAbstractMsgItem
+// {
+// 	GRouterMsgPropagationId routing_id ;
+// 	uint32_t flags ;
+// 	GRouterKeyId destination_key ;
+// 	GRouterServiceId service_id ;
+// 	Sha1CheckSum data_hash ;	// avoids an attacker to re-use a given signed receipt. This is the hash of the decrypted data.
+//
+// 	RsTlvKeySignature signature ;		// signs mid+destination_key+state
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GRouterSignedReceiptItem {
+    pub routing_id: u64,
+    pub flags: u32,
+    pub destination_key: GxsId,
+    pub service_id: u32,
+    pub data_hash: Sha1CheckSum,
+
+    pub signature: TlvKeySignature,
+}
+
+impl GRouterSignedReceiptItem {
+    /// Returns the (serialized) part of the item that is covered by the signature.
+    pub fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = to_retroshare_wire(&self.routing_id);
+        payload.extend(to_retroshare_wire(&self.flags));
+        payload.extend(to_retroshare_wire(&self.destination_key));
+        payload.extend(to_retroshare_wire(&self.service_id));
+        payload.extend(to_retroshare_wire(&self.data_hash));
+        payload
+    }
+}
+
+// class RsGRouterTransactionChunkItem: public RsGRouterTransactionItem, public RsGRouterNonCopyableObject
+// {
+// 	GRouterMsgPropagationId propagation_id ;
+// 	uint32_t chunk_start ;
+// 	uint32_t chunk_size ;
+// 	uint32_t total_size ;
+// 	uint8_t *chunk_data ;
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GRouterTransactionChunkItem {
+    pub propagation_id: u64,
+    pub chunk_start: u32,
+    pub total_size: u32,
+    // pub chunk_size: u32, // part of chunk_data
+    pub chunk_data: Vec<u8>,
+}
+
+impl fmt::Display for GRouterTransactionChunkItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GRouterTransactionChunkItem [propagation_id: {:016x}, {}..{} of {}]",
+            self.propagation_id,
+            self.chunk_start,
+            self.chunk_start as usize + self.chunk_data.len(),
+            self.total_size,
+        )
+    }
+}
+
+// class RsGRouterTransactionAcknItem: public RsGRouterTransactionItem
+// {
+// 	GRouterMsgPropagationId propagation_id ;	// id of the transaction (i.e. chunk)
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GRouterTransactionAcknItem {
+    pub propagation_id: u64,
+}
+
+/// Pending outgoing item, only used for rustyshare's own config file.
+///
+/// This is a stripped down version of RS's `RsGRouterRoutingInfoItem`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GRouterRoutingInfoItem {
+    pub data_status: u32,
+    pub received_time: u64,
+    pub last_sent: u64,
+    pub sending_attempts: u32,
+    pub data_item: GRouterGenericDataItem,
+}
+
+/// What we learned about reaching a key, only used for rustyshare's own config file.
+///
+/// RS stores friends that are likely to reach a key (`RsGRouterMatrixCluesItem`), we only deal with tunnels and remember how
+/// well a key was reachable in the past.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GRouterMatrixCluesItem {
+    pub destination_key: GxsId,
+    pub last_receipt: u64,
+    pub receipts: u32,
+    pub failures: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basics::GxsId,
+        serde::{from_retroshare_wire, to_retroshare_wire},
+        tlv::tlv_keys::{TlvKeySignature, TlvKeySignatureInner},
+    };
+
+    use super::GRouterGenericDataItem;
+
+    #[test]
+    fn data_item() {
+        let signer: GxsId = "c59df722f56f2f886ac301acc5572e03".into();
+        let mut inner = TlvKeySignatureInner::new(signer.into());
+        inner.sign_data = vec![0x11, 0x22].into();
+        let item = GRouterGenericDataItem {
+            routing_id: 0x0102030405060708,
+            destination_key: "0d2a6c2e3b1e4d3c8a6e5d6f7a8b9c0d".into(),
+            service_id: 0x1001,
+            data: vec![0xaa, 0xbb],
+            signature: TlvKeySignature::new(inner),
+            duplication_factor: 1,
+            flags: 1,
+        };
+
+        let signed = item.signed_payload();
+        assert_eq!(signed.len(), 8 + 16 + 4 + 4 + 2);
+
+        let mut ser = to_retroshare_wire(&item);
+        assert!(ser.starts_with(&signed));
+
+        let de: GRouterGenericDataItem = from_retroshare_wire(&mut ser);
+        assert_eq!(item, de);
+    }
+}
@@ -0,0 +1,161 @@
+use ::serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::tlv::tlv_keys::{TlvKeySignature, TlvPublicRSAKey};
+
+// /*******************************************************************************
+//  * libretroshare/src/gxstunnel: rsgxstunnelitems.h                             *
+//  *                                                                             *
+//  * libretroshare: retroshare core library                                      *
+//  *                                                                             *
+//  * Copyright 2015 by Cyril Soler <csoler@users.sourceforge.net>                *
+//  *                                                                             *
+//  * This program is free software: you can redistribute it and/or modify        *
+//  * it under the terms of the GNU Lesser General Public License as              *
+//  * published by the Free Software Foundation, either version 3 of the          *
+//  * License, or (at your option) any later version.                             *
+//  *                                                                             *
+//  * This program is distributed in the hope that it will be useful,             *
+//  * but WITHOUT ANY WARRANTY; without even the implied warranty of              *
+//  * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the                *
+//  * GNU Lesser General Public License for more details.                         *
+//  *                                                                             *
+//  * You should have received a copy of the GNU Lesser General Public License    *
+//  * along with this program. If not, see <https://www.gnu.org/licenses/>.       *
+//  *                                                                             *
+//  *******************************************************************************/
+// const uint8_t RS_PKT_SUBTYPE_GXS_TUNNEL_DATA           = 0x01 ;
+// const uint8_t RS_PKT_SUBTYPE_GXS_TUNNEL_DH_PUBLIC_KEY  = 0x02 ;
+// const uint8_t RS_PKT_SUBTYPE_GXS_TUNNEL_STATUS         = 0x03 ;
+// const uint8_t RS_PKT_SUBTYPE_GXS_TUNNEL_DATA_ACK       = 0x04 ;
+pub const GXS_TUNNEL_SUB_TYPE_DATA: u8 = 0x01;
+pub const GXS_TUNNEL_SUB_TYPE_DH_PUBLIC_KEY: u8 = 0x02;
+pub const GXS_TUNNEL_SUB_TYPE_STATUS: u8 = 0x03;
+pub const GXS_TUNNEL_SUB_TYPE_DATA_ACK: u8 = 0x04;
+
+// const uint32_t RS_GXS_TUNNEL_FLAG_CLOSING_DISTANT_CONNECTION = 0x0400 ;
+// const uint32_t RS_GXS_TUNNEL_FLAG_ACK_DISTANT_CONNECTION     = 0x0800 ;
+// const uint32_t RS_GXS_TUNNEL_FLAG_KEEP_ALIVE                 = 0x1000 ;
+pub const GXS_TUNNEL_FLAG_CLOSING_DISTANT_CONNECTION: u32 = 0x0400;
+pub const GXS_TUNNEL_FLAG_ACK_DISTANT_CONNECTION: u32 = 0x0800;
+pub const GXS_TUNNEL_FLAG_KEEP_ALIVE: u32 = 0x1000;
+
+// class RsGxsTunnelDataItem: public RsGxsTunnelItem
+// {
+// public:
+//     uint64_t unique_item_counter; // this allows to make the item unique
+//     uint32_t flags;               // mainly NEEDS_HACK?
+//     uint32_t service_id ;
+//     uint32_t data_size ;          // encrypted data size
+//     unsigned char *data ;         // encrypted data
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GxsTunnelDataItem {
+    pub unique_item_counter: u64,
+    pub flags: u32,
+    pub service_id: u32,
+    // pub data_size: u32, // part of data
+    pub data: Vec<u8>,
+}
+
+impl fmt::Display for GxsTunnelDataItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GxsTunnelDataItem [counter: {:016x}, service_id: {:08x}, size: {}]",
+            self.unique_item_counter,
+            self.service_id,
+            self.data.len(),
+        )
+    }
+}
+
+// class RsGxsTunnelStatusItem: public RsGxsTunnelItem
+// {
+// public:
+//     uint32_t status ;
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GxsTunnelStatusItem {
+    pub status: u32,
+}
+
+// class RsGxsTunnelDataAckItem: public RsGxsTunnelItem
+// {
+// public:
+//     uint64_t unique_item_counter ; // unique identifier for that item
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GxsTunnelDataAckItem {
+    pub unique_item_counter: u64,
+}
+
+// // This class contains the public Diffie-Hellman parameters to be sent
+// // when performing a DH agreement over a distant chat connection.
+// //
+// class RsGxsTunnelDHPublicKeyItem: public RsGxsTunnelItem
+// {
+// public:
+//     // Private data to DH public key item
+//     //
+//     BIGNUM *public_key ;
+//
+//     RsTlvKeySignature signature ; // signs the public key in a row.
+//     RsTlvPublicRSAKey gxs_key ;   // public key of the signer
+// };
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GxsTunnelDHPublicKeyItem {
+    /// big endian encoded `BIGNUM`
+    pub public_key: Vec<u8>,
+
+    pub signature: TlvKeySignature,
+    pub gxs_key: TlvPublicRSAKey,
+}
+
+impl fmt::Display for GxsTunnelDHPublicKeyItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GxsTunnelDHPublicKeyItem [key_id: {}, public_key: {} bytes]",
+            self.signature.key_id,
+            self.public_key.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serde::{from_retroshare_wire, to_retroshare_wire};
+
+    use super::GxsTunnelDataItem;
+
+    #[test]
+    fn data_item() {
+        let item = GxsTunnelDataItem {
+            unique_item_counter: 0x0102030405060708,
+            flags: 0,
+            service_id: 0xa0001,
+            data: vec![0xaa, 0xbb],
+        };
+
+        let mut ser = to_retroshare_wire(&item);
+        assert_eq!(
+            ser,
+            vec![
+                1, 2, 3, 4, 5, 6, 7, 8, // counter
+                0, 0, 0, 0, // flags
+                0, 0x0a, 0, 1, // service id
+                0, 0, 0, 2, 0xaa, 0xbb // data
+            ]
+        );
+
+        let de: GxsTunnelDataItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.unique_item_counter, item.unique_item_counter);
+        assert_eq!(de.service_id, item.service_id);
+        assert_eq!(de.data, item.data);
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{services::ServiceType, RsPacket};
+
+// class RsHeartbeatItem: public RsItem
+// {
+// public:
+// 	RsHeartbeatItem() :RsItem(RS_PKT_VERSION_SERVICE, RS_SERVICE_TYPE_HEARTBEAT, RS_PKT_SUBTYPE_HEARTBEAT_PULSE)
+// 	{
+// 		setPriorityLevel(QOS_PRIORITY_RS_HEARTBEAT_PULSE) ;
+// 	}
+// 	virtual ~RsHeartbeatItem() {}
+//
+// 	virtual void clear(){}
+// };
+
+/// The heartbeat has no payload.
+#[derive(Debug, Default, Serialize, Deserialize, RsPacket)]
+#[rs_packet(service = ServiceType::Heartbeat, sub_type = 0x01)]
+pub struct HeartbeatItem {}
+
+#[cfg(test)]
+mod tests {
+    use crate::serde::{from_retroshare_wire_result, to_retroshare_wire};
+
+    use super::HeartbeatItem;
+
+    #[test]
+    fn heartbeat_item() {
+        let mut ser = to_retroshare_wire(&HeartbeatItem {});
+        assert!(ser.is_empty());
+
+        let _: HeartbeatItem = from_retroshare_wire_result(&mut ser).unwrap();
+    }
+}
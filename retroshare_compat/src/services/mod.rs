@@ -1,9 +1,15 @@
 use log::warn;
 
+pub mod banlist;
 pub mod bwctrl;
 pub mod chat;
 pub mod discovery;
+pub mod grouter;
+pub mod gxs_tunnel;
+pub mod heartbeat;
+pub mod msg;
 pub mod rtt;
+pub mod service_control;
 pub mod service_info;
 pub mod status;
 pub mod turtle;
@@ -11,12 +17,12 @@ pub mod turtle;
 // const SERVICE_FILE_INDEX: u16 = 0x0001;
 const SERVICE_DISCOVERY: u16 = 0x0011;
 const SERVICE_CHAT: u16 = 0x0012;
-// const SERVICE_MSG: u16 = 0x0013;
+const SERVICE_MSG: u16 = 0x0013;
 const SERVICE_TURTLE: u16 = 0x0014;
 // const SERVICE_TUNNEL: u16 = 0x0015;
 const SERVICE_HEARTBEAT: u16 = 0x0016;
 // const SERVICE_FILE_TRANSFER: u16 = 0x0017;
-// const SERVICE_GROUTER: u16 = 0x0018;
+const SERVICE_GROUTER: u16 = 0x0018;
 // const SERVICE_FILE_DATABASE: u16 = 0x0019;
 const SERVICE_SERVICE_INFO: u16 = 0x0020;
 const SERVICE_BWCTRL: u16 = 0x0021;
@@ -24,10 +30,10 @@ const SERVICE_BWCTRL: u16 = 0x0021;
 // const SERVICE_DIRECT_MAIL: u16 = 0x0023;
 // const SERVICE_DISTANT_MAIL: u16 = 0x0024;
 // const SERVICE_GWEMAIL_MAIL: u16 = 0x0025;
-// const SERVICE_SERVICE_CONTROL: u16 = 0x0026;
+const SERVICE_SERVICE_CONTROL: u16 = 0x0026;
 // const SERVICE_DISTANT_CHAT: u16 = 0x0027;
-// const SERVICE_GXS_TUNNEL: u16 = 0x0028;
-// const SERVICE_BANLIST: u16 = 0x0101;
+const SERVICE_GXS_TUNNEL: u16 = 0x0028;
+const SERVICE_BANLIST: u16 = 0x0101;
 const SERVICE_STATUS: u16 = 0x0102;
 // const SERVICE_FRIEND_SERVER: u16 = 0x0103;
 
@@ -52,11 +58,16 @@ const SLICE_PROBE: u16 = 0xaabb;
 pub enum ServiceType {
     Unknown = 0xffff,
 
+    BanList = SERVICE_BANLIST,
     BwCtrl = SERVICE_BWCTRL,
     Chat = SERVICE_CHAT,
     Discovery = SERVICE_DISCOVERY,
+    GRouter = SERVICE_GROUTER,
+    GxsTunnel = SERVICE_GXS_TUNNEL,
     Heartbeat = SERVICE_HEARTBEAT,
+    Msg = SERVICE_MSG,
     Rtt = SERVICE_RTT,
+    ServiceControl = SERVICE_SERVICE_CONTROL,
     ServiceInfo = SERVICE_SERVICE_INFO,
     Status = SERVICE_STATUS,
     Turtle = SERVICE_TURTLE,
@@ -78,11 +89,16 @@ impl From<&u16> for ServiceType {
         use ServiceType::*;
 
         match *t {
+            SERVICE_BANLIST => BanList,
             SERVICE_BWCTRL => BwCtrl,
             SERVICE_CHAT => Chat,
             SERVICE_DISCOVERY => Discovery,
+            SERVICE_GROUTER => GRouter,
+            SERVICE_GXS_TUNNEL => GxsTunnel,
             SERVICE_HEARTBEAT => Heartbeat,
+            SERVICE_MSG => Msg,
             SERVICE_RTT => Rtt,
+            SERVICE_SERVICE_CONTROL => ServiceControl,
             SERVICE_SERVICE_INFO => ServiceInfo,
             SERVICE_STATUS => Status,
             SERVICE_TURTLE => Turtle,
@@ -119,11 +135,16 @@ impl From<ServiceType> for u16 {
         match s {
             Unknown => panic!("service type 'unknown' cannot be converted"),
 
+            BanList => SERVICE_BANLIST,
             BwCtrl => SERVICE_BWCTRL,
             Chat => SERVICE_CHAT,
             Discovery => SERVICE_DISCOVERY,
+            GRouter => SERVICE_GROUTER,
+            GxsTunnel => SERVICE_GXS_TUNNEL,
             Heartbeat => SERVICE_HEARTBEAT,
+            Msg => SERVICE_MSG,
             Rtt => SERVICE_RTT,
+            ServiceControl => SERVICE_SERVICE_CONTROL,
             ServiceInfo => SERVICE_SERVICE_INFO,
             Status => SERVICE_STATUS,
             Turtle => SERVICE_TURTLE,
@@ -0,0 +1,250 @@
+use ::serde::{Deserialize, Serialize};
+use bitflags::bitflags;
+use bitflags_serde_shim::impl_serde_for_bitflags;
+
+use crate::tlv::{
+    tags::*,
+    tlv_file::TlvFileSet,
+    tlv_set::{TlvGxsIdSet, TlvPeerIdSet},
+    tlv_string::StringTagged,
+};
+
+// const uint32_t RS_MSG_FLAGS_OUTGOING        = 0x00000001;
+// const uint32_t RS_MSG_FLAGS_PENDING         = 0x00000002;
+// const uint32_t RS_MSG_FLAGS_DRAFT           = 0x00000004;
+// const uint32_t RS_MSG_FLAGS_NEW             = 0x00000010;
+// const uint32_t RS_MSG_FLAGS_TRASH           = 0x00000020;
+// const uint32_t RS_MSG_FLAGS_UNREAD_BY_USER  = 0x00000040;
+// const uint32_t RS_MSG_FLAGS_REPLIED         = 0x00000080;
+// const uint32_t RS_MSG_FLAGS_FORWARDED       = 0x00000100;
+// const uint32_t RS_MSG_FLAGS_STAR            = 0x00000200;
+// const uint32_t RS_MSG_FLAGS_PARTIAL         = 0x00000400;
+// const uint32_t RS_MSG_FLAGS_USER_REQUEST    = 0x00000800;
+// const uint32_t RS_MSG_FLAGS_FRIEND_RECOMMENDATION = 0x00001000;
+// const uint32_t RS_MSG_FLAGS_RETURN_RECEPT   = 0x00002000;
+// const uint32_t RS_MSG_FLAGS_ENCRYPTED       = 0x00004000;
+// const uint32_t RS_MSG_FLAGS_DISTANT         = 0x00008000;
+// const uint32_t RS_MSG_FLAGS_SIGNATURE_CHECKS = 0x00010000;
+// const uint32_t RS_MSG_FLAGS_SIGNED          = 0x00020000;
+// const uint32_t RS_MSG_FLAGS_LOAD_EMBEDDED_IMAGES = 0x00040000;
+// const uint32_t RS_MSG_FLAGS_DECRYPTED       = 0x00080000;
+// const uint32_t RS_MSG_FLAGS_ROUTED          = 0x00100000;
+// const uint32_t RS_MSG_FLAGS_PUBLISH_KEY     = 0x00200000;
+// const uint32_t RS_MSG_FLAGS_SPAM            = 0x00400000;
+
+bitflags! {
+    /// Flags used by the (stored) mail items, these differ from the ones used by the API!
+    pub struct MsgItemFlags: u32 {
+        const OUTGOING             = 0x00000001;
+        const PENDING              = 0x00000002;
+        const DRAFT                = 0x00000004;
+        const NEW                  = 0x00000010;
+        const TRASH                = 0x00000020;
+        const UNREAD_BY_USER       = 0x00000040;
+        const REPLIED              = 0x00000080;
+        const FORWARDED            = 0x00000100;
+        const STAR                 = 0x00000200;
+        const PARTIAL              = 0x00000400;
+        const USER_REQUEST         = 0x00000800;
+        const FRIEND_RECOMMENDATION = 0x00001000;
+        const RETURN_RECEPT        = 0x00002000;
+        const ENCRYPTED            = 0x00004000;
+        const DISTANT              = 0x00008000;
+        const SIGNATURE_CHECKS     = 0x00010000;
+        const SIGNED               = 0x00020000;
+        const LOAD_EMBEDDED_IMAGES = 0x00040000;
+        const DECRYPTED            = 0x00080000;
+        const ROUTED               = 0x00100000;
+        const PUBLISH_KEY          = 0x00200000;
+        const SPAM                 = 0x00400000;
+    }
+}
+
+impl_serde_for_bitflags!(MsgItemFlags);
+
+// #define RS_MSG_OUTGOING        0x0001   /* !Inbox */
+// #define RS_MSG_PENDING         0x0002   /* OutBox */
+// #define RS_MSG_DRAFT           0x0004   /* Draft  */
+// #define RS_MSG_NEW             0x0010   /* New */
+// #define RS_MSG_TRASH           0x0020   /* Trash */
+// #define RS_MSG_UNREAD_BY_USER  0x0040   /* Unread by user */
+// #define RS_MSG_REPLIED         0x0080   /* Message is replied */
+// #define RS_MSG_FORWARDED       0x0100   /* Message is forwarded */
+// #define RS_MSG_STAR            0x0200   /* Message is marked with a star */
+// // system message
+// #define RS_MSG_USER_REQUEST    0x0400   /* user request */
+// #define RS_MSG_FRIEND_RECOMMENDATION 0x0800 /* friend recommendation */
+// #define RS_MSG_DISTANT         0x1000   /* message is distant */
+// #define RS_MSG_SIGNATURE_CHECKS 0x2000  /* message was signed, and signature checked */
+// #define RS_MSG_SIGNED          0x4000   /* message was signed and signature didn't check */
+// #define RS_MSG_LOAD_EMBEDDED_IMAGES 0x8000 /* load embedded images */
+// #define RS_MSG_PUBLISH_KEY     0x020000 /* publish key */
+// #define RS_MSG_SPAM            0x040000 /* Message is marked as spam */
+bitflags! {
+    /// Flags used by the API (`MessageInfo` and `MsgInfoSummary`)
+    pub struct MsgFlags: u32 {
+        const OUTGOING             = 0x0001;
+        const PENDING              = 0x0002;
+        const DRAFT                = 0x0004;
+        const NEW                  = 0x0010;
+        const TRASH                = 0x0020;
+        const UNREAD_BY_USER       = 0x0040;
+        const REPLIED              = 0x0080;
+        const FORWARDED            = 0x0100;
+        const STAR                 = 0x0200;
+        const USER_REQUEST         = 0x0400;
+        const FRIEND_RECOMMENDATION = 0x0800;
+        const DISTANT              = 0x1000;
+        const SIGNATURE_CHECKS     = 0x2000;
+        const SIGNED               = 0x4000;
+        const LOAD_EMBEDDED_IMAGES = 0x8000;
+        const PUBLISH_KEY          = 0x020000;
+        const SPAM                 = 0x040000;
+
+        // #define RS_MSG_INBOX           0x00     /* Inbox */
+        // #define RS_MSG_SENTBOX         0x01     /* Sentbox  = OUTGOING           */
+        // #define RS_MSG_OUTBOX          0x03     /* Outbox   = OUTGOING + PENDING */
+        // #define RS_MSG_DRAFTBOX        0x05     /* Draftbox = OUTGOING + DRAFT   */
+        const BOXMASK              = 0x000f;
+    }
+}
+
+impl_serde_for_bitflags!(MsgFlags);
+
+impl From<MsgItemFlags> for MsgFlags {
+    fn from(flags: MsgItemFlags) -> Self {
+        // the lower bits are identical
+        let mut ret = MsgFlags::from_bits_truncate(flags.bits() & 0x03ff);
+
+        for (from, to) in [
+            (MsgItemFlags::USER_REQUEST, MsgFlags::USER_REQUEST),
+            (
+                MsgItemFlags::FRIEND_RECOMMENDATION,
+                MsgFlags::FRIEND_RECOMMENDATION,
+            ),
+            (MsgItemFlags::DISTANT, MsgFlags::DISTANT),
+            (MsgItemFlags::SIGNATURE_CHECKS, MsgFlags::SIGNATURE_CHECKS),
+            (MsgItemFlags::SIGNED, MsgFlags::SIGNED),
+            (
+                MsgItemFlags::LOAD_EMBEDDED_IMAGES,
+                MsgFlags::LOAD_EMBEDDED_IMAGES,
+            ),
+            (MsgItemFlags::PUBLISH_KEY, MsgFlags::PUBLISH_KEY),
+            (MsgItemFlags::SPAM, MsgFlags::SPAM),
+        ] {
+            ret.set(to, flags.contains(from));
+        }
+        ret
+    }
+}
+
+// class RsMsgItem: public RsMessageItem
+// {
+// 	...
+// 	// ----------- Specific fields ------------- //
+// 	uint32_t msgFlags;
+// 	uint32_t msgId;
+// 	uint32_t sendTime;
+// 	uint32_t recvTime;
+// 	std::string subject;
+// 	std::string message;
+// 	RsTlvPeerIdSet rspeerid_msgto;
+// 	RsTlvPeerIdSet rspeerid_msgcc;
+// 	RsTlvPeerIdSet rspeerid_msgbcc;
+// 	RsTlvGxsIdSet rsgxsid_msgto;
+// 	RsTlvGxsIdSet rsgxsid_msgcc;
+// 	RsTlvGxsIdSet rsgxsid_msgbcc;
+// 	RsTlvFileSet attachment;
+// };
+
+// void RsMsgItem::serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx)
+// {
+//     RsTypeSerializer::serial_process<uint32_t>(j,ctx,msgFlags,"msgFlags");
+//     RsTypeSerializer::serial_process<uint32_t>(j,ctx,sendTime,"sendTime");
+//     RsTypeSerializer::serial_process<uint32_t>(j,ctx,recvTime,"recvTime");
+//     RsTypeSerializer::serial_process        (j,ctx,TLV_TYPE_STR_SUBJECT,subject,"subject");
+//     RsTypeSerializer::serial_process        (j,ctx,TLV_TYPE_STR_MSG,message,"message");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rspeerid_msgto,"rspeerid_msgto");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rspeerid_msgcc,"rspeerid_msgcc");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rspeerid_msgbcc,"rspeerid_msgbcc");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rsgxsid_msgto,"rsgxsid_msgto");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rsgxsid_msgcc,"rsgxsid_msgcc");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,rsgxsid_msgbcc,"rsgxsid_msgbcc");
+//     RsTypeSerializer::serial_process<RsTlvItem>(j,ctx,attachment,"attachment");
+//     if(ctx.mFlags & RsServiceSerializer::SERIALIZATION_FLAG_CONFIG)
+//         RsTypeSerializer::serial_process<uint32_t>(j,ctx,msgId,"msgId");
+// }
+
+/// The message id is only part of RS's config items, it is not included here.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MsgItem {
+    pub msg_flags: MsgItemFlags,
+    pub send_time: u32,
+    pub recv_time: u32,
+    pub subject: StringTagged<TLV_TYPE_STR_SUBJECT>,
+    pub message: StringTagged<TLV_TYPE_STR_MSG>,
+    pub rspeerid_msgto: TlvPeerIdSet,
+    pub rspeerid_msgcc: TlvPeerIdSet,
+    pub rspeerid_msgbcc: TlvPeerIdSet,
+    pub rsgxsid_msgto: TlvGxsIdSet,
+    pub rsgxsid_msgcc: TlvGxsIdSet,
+    pub rsgxsid_msgbcc: TlvGxsIdSet,
+    pub attachment: TlvFileSet,
+}
+
+impl Default for MsgItemFlags {
+    fn default() -> Self {
+        MsgItemFlags::empty()
+    }
+}
+
+impl Default for MsgFlags {
+    fn default() -> Self {
+        MsgFlags::empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basics::PeerId,
+        serde::{from_retroshare_wire, to_retroshare_wire},
+    };
+
+    use super::{MsgFlags, MsgItem, MsgItemFlags};
+
+    #[test]
+    fn msg_item() {
+        let mut item = MsgItem {
+            msg_flags: MsgItemFlags::NEW,
+            send_time: 1337,
+            subject: "subject".into(),
+            message: "hello".into(),
+            ..Default::default()
+        };
+        item.rspeerid_msgto
+            .0
+            .insert(PeerId::from("65d33bc7bee18b713364b0301dbed896"));
+
+        let mut ser = to_retroshare_wire(&item);
+        // flags + times + subject + message + peer sets + gxs sets + file set
+        assert_eq!(
+            ser.len(),
+            3 * 4 + (6 + 7) + (6 + 5) + (6 + 16) + 2 * 6 + 3 * 6 + 6
+        );
+
+        let de: MsgItem = from_retroshare_wire(&mut ser);
+        assert_eq!(de.msg_flags, item.msg_flags);
+        assert_eq!(de.subject, item.subject);
+        assert_eq!(de.rspeerid_msgto, item.rspeerid_msgto);
+    }
+
+    #[test]
+    fn msg_flags() {
+        let flags = MsgItemFlags::OUTGOING | MsgItemFlags::STAR | MsgItemFlags::DISTANT;
+        assert_eq!(
+            MsgFlags::from(flags),
+            MsgFlags::OUTGOING | MsgFlags::STAR | MsgFlags::DISTANT
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{services::ServiceType, RsPacket};
+
 // class RsRttPingItem: public RsRttItem
 // {
 // 	public:
@@ -17,7 +19,8 @@ use serde::{Deserialize, Serialize};
 // 		uint64_t mPingTS;
 // };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, RsPacket)]
+#[rs_packet(service = ServiceType::Rtt, sub_type = 0x01)]
 pub struct RttPingItem {
     #[serde(rename(serialize = "mSeqNo", deserialize = "mSeqNo"))]
     pub seq_no: u32,
@@ -43,7 +46,8 @@ pub struct RttPingItem {
 // 		uint64_t mPongTS;
 // };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, RsPacket)]
+#[rs_packet(service = ServiceType::Rtt, sub_type = 0x02)]
 pub struct RttPongItem {
     pub ping: RttPingItem,
     #[serde(rename(serialize = "mPongTS", deserialize = "mPongTS"))]
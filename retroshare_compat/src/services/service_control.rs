@@ -0,0 +1,41 @@
+use ::serde::{Deserialize, Serialize};
+
+use crate::tlv::{
+    tags::*,
+    tlv_set::{TlvNodeGroupIdSet, TlvPeerIdSet},
+    tlv_string::StringTagged,
+};
+
+// const uint8_t RS_PKT_SUBTYPE_SERVICE_CONTROL_SERVICE_PERMISSIONS = 0x01 ;
+pub const SERVICE_CONTROL_SUB_TYPE_PERMISSIONS: u8 = 0x01;
+pub const SERVICE_CONTROL_SUB_TYPE_GROUP_PERMISSIONS: u8 = 0x80;
+
+// class RsServicePermissionItem: public RsItem, public RsServicePermissions
+// {
+// 	uint32_t mServiceId;
+// 	std::string mServiceName;
+// 	bool mDefaultAllowed;
+// 	std::set<RsPeerId> mPeersAllowed;
+// 	std::set<RsPeerId> mPeersDenied;
+// };
+
+/// Only used for config files, RS doesn't send service control items over the wire.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ServicePermissionItem {
+    pub service_id: u32,
+    pub service_name: StringTagged<TLV_TYPE_STR_NAME>,
+    /// bool
+    pub default_allowed: u8,
+    pub peers_allowed: TlvPeerIdSet,
+    pub peers_denied: TlvPeerIdSet,
+}
+
+/// Node groups that are allowed or denied a service, only used for rustyshare's own config file.
+///
+/// RS only supports per location permissions.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ServiceGroupPermissionItem {
+    pub service_id: u32,
+    pub groups_allowed: TlvNodeGroupIdSet,
+    pub groups_denied: TlvNodeGroupIdSet,
+}
@@ -36,6 +36,7 @@ use crate::tlv::{tags::*, tlv_map::TlvMapWithPair, Tlv};
 // };
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
 pub struct RsServiceInfo {
     pub m_service_name: String,
     pub m_service_type: u32,
@@ -61,6 +62,30 @@ impl RsServiceInfo {
             m_min_version_minor: 0,
         }
     }
+
+    /// Whether both sides meet each others minimum version (RS's `ServiceInfoCompatible`).
+    ///
+    /// Unlike RS the service names are not compared, ours don't match RS's.
+    pub fn is_compatible(&self, other: &RsServiceInfo) -> bool {
+        // bool versionOkay(uint16_t version_major, uint16_t version_minor, uint16_t min_version_major, uint16_t min_version_minor)
+        fn version_okay(major: u16, minor: u16, min_major: u16, min_minor: u16) -> bool {
+            (major, minor) >= (min_major, min_minor)
+        }
+
+        self.m_service_type == other.m_service_type
+            && version_okay(
+                self.m_version_major,
+                self.m_version_minor,
+                other.m_min_version_major,
+                other.m_min_version_minor,
+            )
+            && version_okay(
+                other.m_version_major,
+                other.m_version_minor,
+                self.m_min_version_major,
+                self.m_min_version_minor,
+            )
+    }
 }
 
 // class RsServiceInfoListItem: public RsItem
@@ -244,4 +269,23 @@ mod test_tlv_map {
                 .collect()
         );
     }
+
+    #[test]
+    fn test_compatible() {
+        let ours = RsServiceInfo::new(0x0012, "chat");
+        let mut theirs = ours.to_owned();
+        assert!(ours.is_compatible(&theirs));
+
+        // newer peer that still talks to 1.0
+        theirs.m_version_major = 2;
+        assert!(ours.is_compatible(&theirs));
+
+        // newer peer that requires 1.1
+        theirs.m_min_version_minor = 1;
+        assert!(!ours.is_compatible(&theirs));
+        assert!(!theirs.is_compatible(&ours));
+
+        // different service
+        assert!(!ours.is_compatible(&RsServiceInfo::new(0x0013, "chat")));
+    }
 }
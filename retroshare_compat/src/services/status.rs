@@ -3,7 +3,7 @@ use std::fmt::Display;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-use crate::basics::RsPacket;
+use crate::{services::ServiceType, RsPacket};
 
 // const uint32_t RS_STATUS_OFFLINE  = 0x0000;
 // const uint32_t RS_STATUS_AWAY     = 0x0001;
@@ -81,19 +81,11 @@ impl Display for StatusValue {
 // 	/* not serialised */
 // 	uint32_t recvTime;
 // };
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, RsPacket)]
+#[rs_packet(service = ServiceType::Status, sub_type = 0x01)]
 pub struct StatusItem {
     #[serde(rename(serialize = "sendTime", deserialize = "sendTime"))]
     pub send_time: u32,
     pub status: StatusValue,
 }
 
-impl RsPacket for StatusItem {
-    fn get_service(&self) -> u16 {
-        0x0102
-    }
-    
-    fn get_sub_type(&self) -> u8 {
-        0x01
-    }
-}
@@ -2,9 +2,8 @@ use ::serde::{Deserialize, Serialize};
 use std::fmt;
 
 use crate::{
-    basics::*,
-    tlv::{tags::*, tlv_string::StringTagged},
-    utils::RsRegularExpression::LinearizedExpression,
+    basics::*, services::ServiceType, tlv::tags::*,
+    utils::RsRegularExpression::LinearizedExpression, RsDeserialize, RsPacket, RsSerialize,
 };
 
 // typedef Sha1CheckSum  RsFileHash ;
@@ -126,8 +125,8 @@ pub type TurtleSearchRequestId = u32;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TurtleSearchRequestItem {
-    request_id: u32,
-    depth: u16,
+    pub request_id: u32,
+    pub depth: u16,
 }
 //  class RsTurtleFileSearchRequestItem: public RsTurtleSearchRequestItem
 //  {
@@ -158,9 +157,11 @@ pub struct TurtleSearchRequestItem {
 //          void serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx);
 //  };
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, RsPacket, RsSerialize, RsDeserialize)]
+#[rs_packet(service = ServiceType::Turtle, sub_type = 0x01)]
 pub struct TurtleStringSearchRequestItem {
-    match_string: StringTagged<TLV_TYPE_STR_VALUE>,
+    #[tlv(tag = TLV_TYPE_STR_VALUE, sized)]
+    match_string: String,
 
     // Base is serialized at last!
     base: TurtleSearchRequestItem,
@@ -232,13 +233,26 @@ pub struct TurtleRegExpSearchRequestItem {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TurtleGenericSearchRequestItem {
-    base: TurtleSearchRequestItem,
+    pub base: TurtleSearchRequestItem,
 
-    service_id: u16, // service to search
+    pub service_id: u16, // service to search
     #[serde(skip)]
-    _search_data_len: u32, // used by rs for serialization
-    request_type: u8, // type of request. This is used to limit the number of responses.
-    search_data: Vec<u8>,
+    pub _search_data_len: u32, // used by rs for serialization
+    pub request_type: u8, // type of request. This is used to limit the number of responses.
+    pub search_data: Vec<u8>,
+}
+
+impl fmt::Display for TurtleGenericSearchRequestItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TurtleGenericSearchRequestItem [req_id: {:08x}, service: {:04x}, type: {}, size: {}]",
+            self.base.request_id,
+            self.service_id,
+            self.request_type,
+            self.search_data.len(),
+        )
+    }
 }
 
 //  class RsTurtleSearchResultItem: public RsTurtleItem
@@ -257,7 +271,7 @@ pub struct TurtleGenericSearchRequestItem {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TurtleSearchResultItem {
-    request_id: TurtleSearchRequestId,
+    pub request_id: TurtleSearchRequestId,
 }
 
 //  class RsTurtleFTSearchResultItem: public RsTurtleSearchResultItem
@@ -297,6 +311,25 @@ pub struct TurtleSearchResultItem {
 //          void serial_process(RsGenericSerializer::SerializeJob j,RsGenericSerializer::SerializeContext& ctx);
 //  };
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TurtleGenericSearchResultItem {
+    pub base: TurtleSearchResultItem,
+
+    // pub result_data_len: u32, // part of result_data
+    pub result_data: Vec<u8>,
+}
+
+impl fmt::Display for TurtleGenericSearchResultItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TurtleGenericSearchResultItem [req_id: {:08x}, size: {}]",
+            self.base.request_id,
+            self.result_data.len(),
+        )
+    }
+}
+
 //  /***********************************************************************************/
 //  /*                           Turtle Tunnel Item classes                            */
 //  /***********************************************************************************/
@@ -1,5 +1,6 @@
 pub mod tags;
 pub mod tlv_base;
+pub mod tlv_file;
 pub mod tlv_ip_addr;
 pub mod tlv_keys;
 pub mod tlv_map;
@@ -138,6 +138,12 @@ impl<const TAG: u16, T> From<T> for Tlv<TAG, T> {
 #[derive(Default, Clone, PartialEq, Eq, Hash)]
 pub struct Tlv2<const TAG: u16, T>(T);
 
+impl<const TAG: u16, T> Tlv2<TAG, T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 impl<const TAG: u16, T: Serialize> Serialize for Tlv2<TAG, T> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -234,7 +240,7 @@ mod test_tlv {
         basics::SslId,
         serde::{from_retroshare_wire_result, to_retroshare_wire, to_retroshare_wire_result},
         tlv::{tlv_set::TlvPeerIdSet, Tlv, Tlv2, TLV_HEADER_SIZE},
-        write_u16, write_u32,
+        write_u16, write_u32, RsDeserialize, RsSerialize,
     };
     use serde::{Deserialize, Serialize};
 
@@ -369,4 +375,41 @@ mod test_tlv {
 
         assert_eq!(orig, de);
     }
+
+    #[test]
+    fn test_tlv_derive() {
+        #[derive(Debug, PartialEq, RsSerialize, RsDeserialize)]
+        struct Dummy {
+            a: u16,
+            #[tlv(tag = 0x1337)]
+            b: u32,
+            #[tlv(tag = 0x5c, sized)]
+            c: String,
+            #[tlv(tag = 0x1338, sized)]
+            d: Vec<u8>,
+        }
+
+        let orig = Dummy {
+            a: 0x4242,
+            b: 0x12345678,
+            c: "laptop".into(),
+            d: vec![1, 2, 3],
+        };
+
+        let expected = hex::decode(concat!(
+            "4242",
+            "13370000000a12345678",
+            "005c0000000c6c6170746f70",
+            "133800000009010203"
+        ))
+        .unwrap();
+
+        let mut ser = to_retroshare_wire_result(&orig).unwrap();
+
+        assert_eq!(ser, expected);
+
+        let de: Dummy = from_retroshare_wire_result(&mut ser).unwrap();
+
+        assert_eq!(orig, de);
+    }
 }
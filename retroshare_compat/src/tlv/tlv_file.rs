@@ -0,0 +1,315 @@
+use std::fmt;
+
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    basics::Sha1CheckSum,
+    read_u16, read_u32,
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    tlv::{tags::*, tlv_set::TlvHashSet, tlv_string::StringTagged, Tlv, TLV_HEADER_SIZE},
+    write_u16, write_u32,
+};
+
+// class RsTlvFileItem: public RsTlvItem
+// {
+// 	public:
+// 	 RsTlvFileItem();
+// 	 ...
+// 	uint64_t filesize; /// Mandatory: size of file to be downloaded
+// 	RsFileHash hash;   /// Mandatory: to find file
+// 	std::string name;  /// Optional: name of file
+// 	std::string path;  /// Optional: path on host computer
+// 	uint32_t    pop;   /// Optional: Popularity of file
+// 	uint32_t    age;   /// Optional: age of file
+// 	// For chunk hashing.
+// 	uint32_t piecesize; /// Optional: bytes/piece for hashset.
+// 	RsTlvHashSet hashset; /// Optional: chunk hashes.
+// };
+
+/// Reference to a file, e.g. used for mail attachments.
+///
+/// All optional fields are only serialized when they are set (not empty / not zero).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlvFileItem {
+    pub file_size: u64,
+    pub hash: Sha1CheckSum,
+    pub name: String,
+    pub path: String,
+    pub pop: u32,
+    pub age: u32,
+    pub piece_size: u32,
+    pub hash_set: TlvHashSet,
+}
+
+// class RsTlvFileSet: public RsTlvItem
+// {
+// 	public:
+// 	 ...
+// 	std::list<RsTlvFileItem> items; /// Mandatory
+// 	std::string title;   	        /// Optional: title of file set
+// 	std::string comment;	        /// Optional: comments for file
+// };
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TlvFileSet {
+    pub items: Vec<TlvFileItem>,
+    pub title: String,
+    pub comment: String,
+}
+
+fn wrap_tlv(tag: u16, payload: &[u8]) -> Vec<u8> {
+    let mut ser = vec![];
+    write_u16(&mut ser, tag);
+    write_u32(&mut ser, (payload.len() + TLV_HEADER_SIZE) as u32);
+    ser.extend_from_slice(payload);
+    ser
+}
+
+/// Returns the tag and length of the next TLV without consuming it.
+fn peek_tlv(bytes: &[u8]) -> Option<(u16, usize)> {
+    if bytes.len() < TLV_HEADER_SIZE {
+        return None;
+    }
+    let tag = read_u16(&mut bytes[0..2].to_owned());
+    let len = read_u32(&mut bytes[2..6].to_owned()) as usize;
+    if len < TLV_HEADER_SIZE || len > bytes.len() {
+        return None;
+    }
+    Some((tag, len))
+}
+
+impl TlvFileItem {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = to_retroshare_wire(&self.file_size);
+        payload.extend(to_retroshare_wire(&self.hash));
+        if !self.name.is_empty() {
+            let name: StringTagged<TLV_TYPE_STR_NAME> = self.name.to_owned().into();
+            payload.extend(to_retroshare_wire(&name));
+        }
+        if !self.path.is_empty() {
+            let path: StringTagged<TLV_TYPE_STR_PATH> = self.path.to_owned().into();
+            payload.extend(to_retroshare_wire(&path));
+        }
+        if self.pop != 0 {
+            payload.extend(to_retroshare_wire(&Tlv::<TLV_TYPE_UINT32_POP, _>(self.pop)));
+        }
+        if self.age != 0 {
+            payload.extend(to_retroshare_wire(&Tlv::<TLV_TYPE_UINT32_AGE, _>(self.age)));
+        }
+        if self.piece_size != 0 {
+            payload.extend(to_retroshare_wire(&Tlv::<TLV_TYPE_UINT32_SIZE, _>(
+                self.piece_size,
+            )));
+        }
+        if !self.hash_set.0.is_empty() {
+            payload.extend(to_retroshare_wire(&self.hash_set));
+        }
+
+        wrap_tlv(TLV_TYPE_FILEITEM, &payload)
+    }
+
+    fn from_bytes(v: &[u8]) -> Result<Self, crate::serde::Error> {
+        match peek_tlv(v) {
+            Some((TLV_TYPE_FILEITEM, len)) if len == v.len() => (),
+            _ => return Err(crate::serde::Error::WrongTag),
+        }
+        let mut bytes = v[TLV_HEADER_SIZE..].to_vec();
+
+        let mut item = TlvFileItem {
+            file_size: from_retroshare_wire_result(&mut bytes)?,
+            hash: from_retroshare_wire_result(&mut bytes)?,
+            ..Default::default()
+        };
+
+        while let Some((tag, len)) = peek_tlv(&bytes) {
+            match tag {
+                TLV_TYPE_STR_NAME => {
+                    let name: StringTagged<TLV_TYPE_STR_NAME> =
+                        from_retroshare_wire_result(&mut bytes)?;
+                    item.name = name.into();
+                }
+                TLV_TYPE_STR_PATH => {
+                    let path: StringTagged<TLV_TYPE_STR_PATH> =
+                        from_retroshare_wire_result(&mut bytes)?;
+                    item.path = path.into();
+                }
+                TLV_TYPE_UINT32_POP => {
+                    let pop: Tlv<TLV_TYPE_UINT32_POP, u32> =
+                        from_retroshare_wire_result(&mut bytes)?;
+                    item.pop = pop.0;
+                }
+                TLV_TYPE_UINT32_AGE => {
+                    let age: Tlv<TLV_TYPE_UINT32_AGE, u32> =
+                        from_retroshare_wire_result(&mut bytes)?;
+                    item.age = age.0;
+                }
+                TLV_TYPE_UINT32_SIZE => {
+                    let piece_size: Tlv<TLV_TYPE_UINT32_SIZE, u32> =
+                        from_retroshare_wire_result(&mut bytes)?;
+                    item.piece_size = piece_size.0;
+                }
+                TLV_TYPE_HASHSET => item.hash_set = from_retroshare_wire_result(&mut bytes)?,
+                tag => {
+                    log::warn!("skipping unknown tlv {tag:04x} in file item");
+                    bytes.drain(..len);
+                }
+            }
+        }
+
+        Ok(item)
+    }
+}
+
+impl Serialize for TlvFileItem {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.to_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for TlvFileItem {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TlvFileItemVisitor;
+
+        impl<'de> Visitor<'de> for TlvFileItemVisitor {
+            type Value = TlvFileItem;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a TlvFileItem")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                TlvFileItem::from_bytes(v).map_err(::serde::de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(TlvFileItemVisitor)
+    }
+}
+
+impl Serialize for TlvFileSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut payload = vec![];
+        for item in &self.items {
+            payload.extend(item.to_bytes());
+        }
+        if !self.title.is_empty() {
+            let title: StringTagged<TLV_TYPE_STR_TITLE> = self.title.to_owned().into();
+            payload.extend(to_retroshare_wire(&title));
+        }
+        if !self.comment.is_empty() {
+            let comment: StringTagged<TLV_TYPE_STR_COMMENT> = self.comment.to_owned().into();
+            payload.extend(to_retroshare_wire(&comment));
+        }
+
+        serializer.serialize_bytes(&wrap_tlv(TLV_TYPE_FILESET, &payload))
+    }
+}
+
+impl<'de> Deserialize<'de> for TlvFileSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TlvFileSetVisitor;
+
+        impl<'de> Visitor<'de> for TlvFileSetVisitor {
+            type Value = TlvFileSet;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a TlvFileSet")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match peek_tlv(v) {
+                    Some((TLV_TYPE_FILESET, len)) if len == v.len() => (),
+                    _ => return Err(::serde::de::Error::custom(crate::serde::Error::WrongTag)),
+                }
+                let mut bytes = v[TLV_HEADER_SIZE..].to_vec();
+                let mut set = TlvFileSet::default();
+
+                while let Some((tag, len)) = peek_tlv(&bytes) {
+                    let mut tlv: Vec<_> = bytes.drain(..len).collect();
+                    match tag {
+                        TLV_TYPE_FILEITEM => set.items.push(
+                            TlvFileItem::from_bytes(&tlv).map_err(::serde::de::Error::custom)?,
+                        ),
+                        TLV_TYPE_STR_TITLE => {
+                            let title: StringTagged<TLV_TYPE_STR_TITLE> =
+                                from_retroshare_wire_result(&mut tlv)
+                                    .map_err(::serde::de::Error::custom)?;
+                            set.title = title.into();
+                        }
+                        TLV_TYPE_STR_COMMENT => {
+                            let comment: StringTagged<TLV_TYPE_STR_COMMENT> =
+                                from_retroshare_wire_result(&mut tlv)
+                                    .map_err(::serde::de::Error::custom)?;
+                            set.comment = comment.into();
+                        }
+                        tag => log::warn!("skipping unknown tlv {tag:04x} in file set"),
+                    }
+                }
+
+                Ok(set)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(TlvFileSetVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        basics::Sha1CheckSum,
+        serde::{from_retroshare_wire, to_retroshare_wire},
+    };
+
+    use super::{TlvFileItem, TlvFileSet};
+
+    #[test]
+    fn file_set() {
+        let set = TlvFileSet {
+            items: vec![
+                TlvFileItem {
+                    file_size: 1337,
+                    hash: Sha1CheckSum::from("0123456789abcdef0123456789abcdef01234567"),
+                    name: String::from("rustyshare.txt"),
+                    ..Default::default()
+                },
+                TlvFileItem {
+                    file_size: 42,
+                    age: 7,
+                    ..Default::default()
+                },
+            ],
+            title: String::new(),
+            comment: String::from("attachments"),
+        };
+
+        let mut ser = to_retroshare_wire(&set);
+        // header + 2 * (header + size + hash) + name + age + comment
+        assert_eq!(
+            ser.len(),
+            6 + 2 * (6 + 8 + 20) + (6 + 14) + (6 + 4) + (6 + 11)
+        );
+
+        let de: TlvFileSet = from_retroshare_wire(&mut ser);
+        assert_eq!(de, set);
+        assert!(ser.is_empty());
+    }
+}
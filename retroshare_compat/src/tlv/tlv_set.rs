@@ -17,6 +17,15 @@ pub struct TlvSet<const TAG: u16, T>(pub HashSet<T>)
 where
     T: Eq + PartialEq + Hash;
 
+impl<const TAG: u16, T> FromIterator<T> for TlvSet<TAG, T>
+where
+    T: Eq + PartialEq + Hash,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
 impl<const TAG: u16, T> Serialize for TlvSet<TAG, T>
 where
     T: Serialize + Eq + PartialEq + Hash,
@@ -3,7 +3,7 @@ use std::collections::HashSet;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    basics::{GxsIdHex, SslIdHex},
+    basics::{DistantChatPeerIdHex, GxsIdHex, SslIdHex},
     gen_type_wrapped,
     services::chat::{ChatLobbyFlags, ChatLobbyId},
 };
@@ -84,6 +84,9 @@ pub struct ChatLobbyInfo {
     pub lobby_flags: ChatLobbyFlags, // see RS_CHAT_LOBBY_PRIVACY_LEVEL_PUBLIC / RS_CHAT_LOBBY_PRIVACY_LEVEL_PRIVATE
     pub gxs_ids: Vec<ChatLobbyInfoGxsIds>, // list of non direct friend who participate. Used to display only.
     pub last_activity: XInt64<i64>, // last recorded activity. Useful for removing dead lobbies.
+
+    // not part of RS
+    pub participants: Vec<ChatLobbyParticipant>,
 }
 
 // TODO emulate RS's HashMap to json behavior
@@ -92,3 +95,82 @@ pub struct ChatLobbyInfoGxsIds {
     pub key: GxsIdHex,
     pub value: XInt64<i64>,
 }
+
+// not part of RS, nickname and status are taken from the participant's lobby events
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyParticipant {
+    pub gxs_id: GxsIdHex,
+    pub nickname: String,
+    pub status: String,
+    pub last_activity: XInt64<i64>,
+}
+
+// struct ChatLobbyInvite : RsSerializable
+// {
+// 	ChatLobbyId lobby_id ;
+// 	RsPeerId peer_id ;
+// 	std::string lobby_name ;
+// 	std::string lobby_topic ;
+// 	ChatLobbyFlags lobby_flags ;
+// };
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyInvite {
+    pub lobby_id: XInt64<u64>,
+    pub peer_id: SslIdHex,
+    pub lobby_name: String,
+    pub lobby_topic: String,
+    pub lobby_flags: ChatLobbyFlags,
+}
+
+// not part of RS, RS only knows about the auto subscribe flag
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbySubscription {
+    pub lobby_id: XInt64<u64>,
+    pub gxs_id: GxsIdHex,
+    pub auto_subscribe: bool,
+    pub notify: bool,
+}
+
+// not part of RS
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyBans {
+    pub lobby_id: XInt64<u64>,
+    pub gxs_ids: Vec<GxsIdHex>,
+}
+
+// not part of RS, RS only knows about (global) reputations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChatLobbyModeration {
+    pub min_reputation: i32,
+    pub banned: Vec<GxsIdHex>,
+    pub lobby_banned: Vec<ChatLobbyBans>,
+    pub muted: Vec<GxsIdHex>,
+}
+
+// struct DistantChatPeerInfo : RsSerializable
+// {
+// 	RsGxsId to_id ;
+// 	RsGxsId own_id ;
+// 	DistantChatPeerId peer_id ;	// this is the tunnel id actually
+// 	uint32_t status ;			// see the values in rsmsgs.h
+// 	uint32_t pending_items;	// items not sent, waiting for a tunnel
+// };
+
+// #define RS_DISTANT_CHAT_STATUS_UNKNOWN			0x0000
+// #define RS_DISTANT_CHAT_STATUS_TUNNEL_DN   		0x0001
+// #define RS_DISTANT_CHAT_STATUS_CAN_TALK		0x0002
+// #define RS_DISTANT_CHAT_STATUS_REMOTELY_CLOSED 	0x0003
+pub const DISTANT_CHAT_STATUS_UNKNOWN: u32 = 0x0000;
+pub const DISTANT_CHAT_STATUS_TUNNEL_DN: u32 = 0x0001;
+pub const DISTANT_CHAT_STATUS_CAN_TALK: u32 = 0x0002;
+pub const DISTANT_CHAT_STATUS_REMOTELY_CLOSED: u32 = 0x0003;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DistantChatPeerInfo {
+    pub to_id: GxsIdHex,
+    pub own_id: GxsIdHex,
+    pub peer_id: DistantChatPeerIdHex, // this is the tunnel id actually
+    pub status: u32,                   // see the values above
+    pub pending_items: u32,            // items not sent, waiting for a tunnel
+}
@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+
+use crate::{
+    basics::{GxsId, GxsIdHex, PeerId, Sha1CheckSumHex, SslIdHex},
+    services::msg::{MsgFlags, MsgItem, MsgItemFlags},
+    tlv::tlv_file::{TlvFileItem, TlvFileSet},
+};
+
+use super::XInt64;
+
+pub type MailMessageId = String;
+
+/// Converts between (sets of) ids and their hex (web ui) representation
+fn convert_ids<T, U, C>(ids: impl IntoIterator<Item = T>) -> C
+where
+    U: From<T>,
+    C: FromIterator<U>,
+{
+    ids.into_iter().map(U::from).collect()
+}
+
+// enum class BoxName:uint8_t {
+//         BOX_NONE = 0x00, BOX_INBOX = 0x01, BOX_OUTBOX = 0x02, BOX_DRAFTS = 0x03, BOX_SENT = 0x04, BOX_TRASH = 0x05, BOX_ALL = 0x06
+//     };
+
+#[repr(u8)]
+#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
+pub enum BoxName {
+    None = 0x00,
+    Inbox = 0x01,
+    Outbox = 0x02,
+    Drafts = 0x03,
+    Sent = 0x04,
+    Trash = 0x05,
+    All = 0x06,
+}
+
+impl Default for BoxName {
+    fn default() -> Self {
+        BoxName::All
+    }
+}
+
+impl BoxName {
+    /// Checks whether a message with the given (API) flags belongs into this box
+    pub fn contains(&self, flags: MsgFlags) -> bool {
+        let trash = flags.contains(MsgFlags::TRASH);
+        let boxed = flags & MsgFlags::BOXMASK;
+
+        match self {
+            BoxName::None => false,
+            BoxName::All => true,
+            BoxName::Trash => trash,
+            _ if trash => false,
+            BoxName::Inbox => !boxed.contains(MsgFlags::OUTGOING),
+            BoxName::Outbox => boxed == MsgFlags::OUTGOING | MsgFlags::PENDING,
+            BoxName::Drafts => boxed == MsgFlags::OUTGOING | MsgFlags::DRAFT,
+            BoxName::Sent => boxed == MsgFlags::OUTGOING,
+        }
+    }
+}
+
+// struct FileInfo : RsSerializable
+// {
+// 	...
+// 	std::string fname;
+// 	RsFileHash hash;
+// 	uint64_t size;
+// 	...
+// };
+
+/// Only the subset of RS's `FileInfo` that is actually used by mail attachments.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct FileInfo {
+    pub fname: String,
+    pub hash: Sha1CheckSumHex,
+    pub size: XInt64<u64>,
+}
+
+impl From<TlvFileItem> for FileInfo {
+    fn from(item: TlvFileItem) -> Self {
+        FileInfo {
+            fname: item.name,
+            hash: item.hash.into(),
+            size: item.file_size.into(),
+        }
+    }
+}
+
+impl From<FileInfo> for TlvFileItem {
+    fn from(info: FileInfo) -> Self {
+        TlvFileItem {
+            file_size: info.size.into(),
+            hash: info.hash.into(),
+            name: info.fname,
+            ..Default::default()
+        }
+    }
+}
+
+// struct MessageInfo : RsSerializable
+// {
+// 	std::string msgId;
+// 	RsPeerId rspeerid_srcId;
+// 	RsGxsId rsgxsid_srcId;
+// 	unsigned int msgflags;
+// 	std::set<RsPeerId> rspeerid_msgto;
+// 	std::set<RsPeerId> rspeerid_msgcc;
+// 	std::set<RsPeerId> rspeerid_msgbcc;
+// 	std::set<RsGxsId> rsgxsid_msgto;
+// 	std::set<RsGxsId> rsgxsid_msgcc;
+// 	std::set<RsGxsId> rsgxsid_msgbcc;
+// 	std::string title;
+// 	std::string msg;
+// 	std::string attach_title;
+// 	std::string attach_comment;
+// 	std::list<FileInfo> files;
+// 	int size;  /* total of files */
+// 	int count; /* file count     */
+// 	int ts;
+// };
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MessageInfo {
+    pub msgId: MailMessageId,
+    pub rspeerid_srcId: SslIdHex,
+    pub rsgxsid_srcId: GxsIdHex,
+    pub msgflags: MsgFlags,
+    pub rspeerid_msgto: HashSet<SslIdHex>,
+    pub rspeerid_msgcc: HashSet<SslIdHex>,
+    pub rspeerid_msgbcc: HashSet<SslIdHex>,
+    pub rsgxsid_msgto: HashSet<GxsIdHex>,
+    pub rsgxsid_msgcc: HashSet<GxsIdHex>,
+    pub rsgxsid_msgbcc: HashSet<GxsIdHex>,
+    pub title: String,
+    pub msg: String,
+    pub attach_title: String,
+    pub attach_comment: String,
+    pub files: Vec<FileInfo>,
+    pub size: i32,
+    pub count: i32,
+    pub ts: i32,
+}
+
+impl MessageInfo {
+    pub fn new(msg_id: i64, src: PeerId, item: MsgItem) -> Self {
+        let files: Vec<FileInfo> = item
+            .attachment
+            .items
+            .into_iter()
+            .map(|file| file.into())
+            .collect();
+
+        // distant messages are stored with the gxs id of their sender
+        let (peer_src, gxs_src) = if item.msg_flags.contains(MsgItemFlags::DISTANT) {
+            (SslIdHex::default(), GxsId::from(src.to_vec()).into())
+        } else {
+            (src.into(), GxsIdHex::default())
+        };
+
+        MessageInfo {
+            msgId: msg_id.to_string(),
+            rspeerid_srcId: peer_src,
+            rsgxsid_srcId: gxs_src,
+            msgflags: item.msg_flags.into(),
+            rspeerid_msgto: convert_ids(item.rspeerid_msgto.0),
+            rspeerid_msgcc: convert_ids(item.rspeerid_msgcc.0),
+            rspeerid_msgbcc: convert_ids(item.rspeerid_msgbcc.0),
+            rsgxsid_msgto: convert_ids(item.rsgxsid_msgto.0),
+            rsgxsid_msgcc: convert_ids(item.rsgxsid_msgcc.0),
+            rsgxsid_msgbcc: convert_ids(item.rsgxsid_msgbcc.0),
+            title: item.subject.into(),
+            msg: item.message.into(),
+            attach_title: item.attachment.title,
+            attach_comment: item.attachment.comment,
+            size: files
+                .iter()
+                .map(|file| u64::from(file.size.to_owned()))
+                .sum::<u64>() as i32,
+            count: files.len() as i32,
+            files,
+            ts: item.send_time as i32,
+        }
+    }
+}
+
+impl From<MessageInfo> for MsgItem {
+    fn from(info: MessageInfo) -> Self {
+        MsgItem {
+            subject: info.title.into(),
+            message: info.msg.into(),
+            rspeerid_msgto: convert_ids(info.rspeerid_msgto),
+            rspeerid_msgcc: convert_ids(info.rspeerid_msgcc),
+            rspeerid_msgbcc: convert_ids(info.rspeerid_msgbcc),
+            rsgxsid_msgto: convert_ids(info.rsgxsid_msgto),
+            rsgxsid_msgcc: convert_ids(info.rsgxsid_msgcc),
+            rsgxsid_msgbcc: convert_ids(info.rsgxsid_msgbcc),
+            attachment: TlvFileSet {
+                items: info.files.into_iter().map(|file| file.into()).collect(),
+                title: info.attach_title,
+                comment: info.attach_comment,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+// struct MsgInfoSummary : RsSerializable
+// {
+// 	RsMailMessageId msgId;
+// 	RsPeerId srcId;
+// 	uint32_t msgflags;
+// 	std::list<uint32_t> msgtags;
+// 	std::string title;
+// 	int count; /** file count     */
+// 	rstime_t ts;
+// };
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MsgInfoSummary {
+    pub msgId: MailMessageId,
+    pub srcId: SslIdHex,
+    pub msgflags: MsgFlags,
+    pub msgtags: Vec<u32>,
+    pub title: String,
+    pub count: i32,
+    pub ts: XInt64<i64>,
+}
+
+impl MsgInfoSummary {
+    pub fn new(msg_id: i64, src: PeerId, item: &MsgItem, tags: Vec<u32>) -> Self {
+        MsgInfoSummary {
+            msgId: msg_id.to_string(),
+            srcId: src.into(),
+            msgflags: item.msg_flags.into(),
+            msgtags: tags,
+            title: item.subject.to_owned().into(),
+            count: item.attachment.items.len() as i32,
+            ts: (item.send_time as i64).into(),
+        }
+    }
+}
+
+// struct MsgTagInfo : RsSerializable
+// {
+// 	std::string msgId;
+// 	std::list<uint32_t> tagIds;
+// };
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MsgTagInfo {
+    pub msgId: MailMessageId,
+    pub tagIds: Vec<u32>,
+}
+
+// struct MsgTagType : RsSerializable
+// {
+// 	/* map containing tagId -> pair (text, rgb color) */
+// 	std::map<uint32_t, std::pair<std::string, uint32_t> > types;
+// };
+
+// TODO emulate RS's HashMap to json behavior
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MsgTagType {
+    pub types: Vec<MsgTagTypeEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MsgTagTypeEntry {
+    pub key: u32,
+    pub value: MsgTagTypeValue,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MsgTagTypeValue {
+    pub first: String,
+    pub second: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::msg::MsgFlags;
+
+    use super::BoxName;
+
+    #[test]
+    fn box_name() {
+        let inbox = MsgFlags::NEW;
+        let outbox = MsgFlags::OUTGOING | MsgFlags::PENDING;
+        let draft = MsgFlags::OUTGOING | MsgFlags::DRAFT;
+        let sent = MsgFlags::OUTGOING | MsgFlags::STAR;
+        let trash = MsgFlags::TRASH | MsgFlags::OUTGOING;
+
+        assert!(BoxName::Inbox.contains(inbox));
+        assert!(BoxName::Outbox.contains(outbox));
+        assert!(BoxName::Drafts.contains(draft));
+        assert!(BoxName::Sent.contains(sent));
+        assert!(BoxName::Trash.contains(trash));
+        assert!(!BoxName::Sent.contains(trash));
+        assert!(!BoxName::Sent.contains(outbox));
+        assert!(BoxName::All.contains(trash));
+    }
+}
@@ -4,6 +4,7 @@ use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
 
 pub mod chat;
 pub mod identity;
+pub mod mail;
 
 // Yay JavaScript and stuff...
 #[derive(Debug, Clone, Serialize, Default, PartialEq)]
@@ -0,0 +1,355 @@
+//! Bounded channels used for the intercom.
+//!
+//! Every channel has a capacity and a policy deciding what happens when it is full. All channels register
+//! themselves so that their queue depths can be inspected (see [`stats`]).
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::poll_fn,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use tokio::sync::Notify;
+
+/// What a full channel does with new messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// The sender waits until there is room again.
+    Block,
+    /// The oldest queued message is dropped.
+    DropOldest,
+    /// The channel is closed (and its queue dropped), the receiver sees the channel as closed.
+    Disconnect,
+}
+
+/// Snapshot of a channel's queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStats {
+    pub name: String,
+    pub policy: Policy,
+    pub capacity: usize,
+    /// currently queued messages
+    pub len: usize,
+    /// highest number of queued messages so far
+    pub max_len: usize,
+    pub sent: u64,
+    pub dropped: u64,
+    pub closed: bool,
+}
+
+/// The channel is closed, returns the message.
+pub struct SendError<T>(pub T);
+
+pub enum TrySendError<T> {
+    /// Only returned by blocking channels.
+    Full(T),
+    Closed(T),
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(closed)")
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("TrySendError(full)"),
+            TrySendError::Closed(_) => f.write_str("TrySendError(closed)"),
+        }
+    }
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    closed: bool,
+    waker: Option<Waker>,
+
+    max_len: usize,
+    sent: u64,
+    dropped: u64,
+}
+
+struct Shared<T> {
+    name: String,
+    policy: Policy,
+    capacity: usize,
+
+    state: Mutex<State<T>>,
+    senders: AtomicUsize,
+    /// wakes blocked senders
+    space: Notify,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state
+            .lock()
+            .expect("failed to get channel state, lock poisoned!")
+    }
+
+    fn close(&self) {
+        let waker = {
+            let mut state = self.lock();
+            state.closed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        self.space.notify_waiters();
+    }
+}
+
+trait ChannelInfo: Send + Sync {
+    fn stats(&self) -> ChannelStats;
+}
+
+impl<T: Send> ChannelInfo for Shared<T> {
+    fn stats(&self) -> ChannelStats {
+        let state = self.lock();
+        ChannelStats {
+            name: self.name.to_owned(),
+            policy: self.policy,
+            capacity: self.capacity,
+            len: state.queue.len(),
+            max_len: state.max_len,
+            sent: state.sent,
+            dropped: state.dropped,
+            closed: state.closed,
+        }
+    }
+}
+
+static CHANNELS: Mutex<Vec<Weak<dyn ChannelInfo>>> = Mutex::new(Vec::new());
+
+fn channels() -> MutexGuard<'static, Vec<Weak<dyn ChannelInfo>>> {
+    CHANNELS
+        .lock()
+        .expect("failed to get channels, lock poisoned!")
+}
+
+/// Returns the queue stats of all living channels.
+pub fn stats() -> Vec<ChannelStats> {
+    let mut channels = channels();
+    channels.retain(|channel| channel.strong_count() > 0);
+    channels
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|channel| channel.stats())
+        .collect()
+}
+
+/// Creates a bounded channel, `name` identifies it in the stats.
+pub fn channel<T: Send + 'static>(
+    name: impl Into<String>,
+    capacity: usize,
+    policy: Policy,
+) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channels need a capacity");
+
+    let shared = Arc::new(Shared {
+        name: name.into(),
+        policy,
+        capacity,
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            closed: false,
+            waker: None,
+            max_len: 0,
+            sent: 0,
+            dropped: 0,
+        }),
+        senders: AtomicUsize::new(1),
+        space: Notify::new(),
+    });
+
+    let info: Arc<dyn ChannelInfo> = shared.to_owned();
+    let mut channels = channels();
+    channels.retain(|channel| channel.strong_count() > 0);
+    channels.push(Arc::downgrade(&info));
+
+    (
+        Sender {
+            shared: shared.to_owned(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a message, waits for room when the channel blocks.
+    pub async fn send(&self, mut msg: T) -> Result<(), SendError<T>> {
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            // register before checking, otherwise a wake up might get lost
+            space.as_mut().enable();
+
+            match self.try_send(msg) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Closed(msg)) => return Err(SendError(msg)),
+                Err(TrySendError::Full(m)) => {
+                    msg = m;
+                    space.await;
+                }
+            }
+        }
+    }
+
+    /// Sends a message without waiting, a full blocking channel returns [`TrySendError::Full`].
+    pub fn try_send(&self, msg: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.lock();
+        if state.closed {
+            return Err(TrySendError::Closed(msg));
+        }
+
+        if state.queue.len() >= self.shared.capacity {
+            match self.shared.policy {
+                Policy::Block => return Err(TrySendError::Full(msg)),
+                Policy::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                Policy::Disconnect => {
+                    state.dropped += state.queue.len() as u64 + 1;
+                    let queue = std::mem::take(&mut state.queue);
+                    drop(state);
+                    drop(queue);
+
+                    self.shared.close();
+                    return Err(TrySendError::Closed(msg));
+                }
+            }
+        }
+
+        state.queue.push_back(msg);
+        state.sent += 1;
+        state.max_len = state.max_len.max(state.queue.len());
+        let waker = state.waker.take();
+        drop(state);
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.to_owned(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // last sender, wake up the receiver
+            if let Some(waker) = self.shared.lock().waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender")
+            .field("name", &self.shared.name)
+            .finish()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next message, `None` once the channel is closed (or all senders are gone).
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.shared.lock();
+        if let Some(msg) = state.queue.pop_front() {
+            drop(state);
+            self.shared.space.notify_waiters();
+            return Poll::Ready(Some(msg));
+        }
+
+        if state.closed || self.shared.senders.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().to_owned());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{channel, stats, Policy, TrySendError};
+
+    #[tokio::test]
+    async fn policies() {
+        let (tx, mut rx) = channel("test drop oldest", 2, Policy::DropOldest);
+        for i in 0..3 {
+            tx.send(i).await.unwrap();
+        }
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.recv().await, Some(2));
+
+        let stats = stats()
+            .into_iter()
+            .find(|stats| stats.name == "test drop oldest")
+            .unwrap();
+        assert_eq!((stats.len, stats.max_len, stats.dropped), (0, 2, 1));
+
+        let (tx, mut rx) = channel("test disconnect", 1, Policy::Disconnect);
+        tx.try_send(1).unwrap();
+        assert!(matches!(tx.try_send(2), Err(TrySendError::Closed(2))));
+        assert!(tx.is_closed());
+        assert_eq!(rx.recv().await, None);
+
+        let (tx, mut rx) = channel("test block", 1, Policy::Block);
+        tx.send(1).await.unwrap();
+        assert!(matches!(tx.try_send(2), Err(TrySendError::Full(2))));
+
+        // the sender continues once there is room
+        let sender = tokio::spawn(async move { tx.send(2).await.is_ok() });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv().await, Some(1));
+        assert!(sender.await.unwrap());
+        assert_eq!(rx.recv().await, Some(2));
+
+        // all senders are gone
+        assert_eq!(rx.recv().await, None);
+    }
+}
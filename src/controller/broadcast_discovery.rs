@@ -0,0 +1,228 @@
+//! Finds friends in the local network (and announces ourself), see `retroshare_compat::broadcast_discovery`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, warn};
+use nanorand::{Rng, WyRand};
+use retroshare_compat::{
+    basics::{PgpFingerprint, SslId},
+    broadcast_discovery::*,
+    events::{BroadcastDiscoveryEventCode, EventType},
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    tlv::tlv_ip_addr::TlvIpAddressInfoInner,
+};
+use tokio::{net::UdpSocket, select, time::interval};
+
+use crate::{
+    channel::Sender,
+    model::{
+        intercom::{Intercom, PeerUpdate},
+        DataCore,
+    },
+};
+
+/// RS announces itself every few seconds, too
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+const BROADCAST_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(
+    Ipv4Addr::BROADCAST,
+    BROADCAST_DISCOVERY_PORT,
+));
+
+/// Hidden nodes never reveal themselves.
+fn is_enabled(core: &DataCore) -> bool {
+    let net = core.get_net();
+    net.is_broadcast_discovery() && !net.is_hidden() && !net.is_hidden_only()
+}
+
+fn own_pack(core: &DataCore) -> Option<BroadcastDiscoveryPack> {
+    let person = core.get_own_person();
+    let fingerprint: [u8; 20] = person.get_pgp().fingerprint().as_bytes().try_into().ok()?;
+
+    Some(BroadcastDiscoveryPack {
+        pgp_fingerprint: PgpFingerprint(fingerprint),
+        ssl_id: *core.get_own_location().get_location_id(),
+        local_port: core.get_net().get_port(),
+        profile_name: person.get_name().to_owned(),
+    })
+}
+
+pub async fn run(core: Arc<DataCore>, core_tx: Sender<Intercom>) {
+    // the port might be taken (e.g. by RS), we can still announce ourself then
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, BROADCAST_DISCOVERY_PORT)).await {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("[broadcast discovery] failed to bind port {BROADCAST_DISCOVERY_PORT}, friends won't be found: {err}");
+            match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    warn!("[broadcast discovery] failed to bind: {err}");
+                    return;
+                }
+            }
+        }
+    };
+    if let Err(err) = socket.set_broadcast(true) {
+        warn!("[broadcast discovery] failed to enable broadcasts: {err}");
+        return;
+    }
+
+    let peer_id = WyRand::new().generate::<u32>();
+    let mut snapshot_index = 0;
+    let mut last_pack = None;
+    let mut announced = false;
+    // found locations by their (udp-discovery) peer id
+    let mut found: HashMap<u32, (SslId, SocketAddr)> = HashMap::new();
+
+    let mut timer = interval(ANNOUNCE_INTERVAL);
+    let mut buf = vec![0; u16::MAX as usize];
+
+    loop {
+        select! {
+            _ = timer.tick() => {
+                let (packet_type, pack) = match (is_enabled(&core), announced) {
+                    (true, _) => match own_pack(&core) {
+                        Some(pack) => (PacketType::IAmHere, pack),
+                        None => {
+                            warn!("[broadcast discovery] unsupported pgp fingerprint");
+                            return;
+                        }
+                    },
+                    // tell everyone that we are gone
+                    (false, true) => match last_pack.to_owned() {
+                        Some(pack) => (PacketType::IAmOutOfHere, pack),
+                        None => continue,
+                    },
+                    (false, false) => continue,
+                };
+                announced = packet_type == PacketType::IAmHere;
+                if last_pack.as_ref() != Some(&pack) {
+                    snapshot_index += 1;
+                    last_pack = Some(pack.to_owned());
+                }
+
+                let packet = DiscoveryPacket {
+                    packet_type,
+                    app_id: BROADCAST_DISCOVERY_APP_ID,
+                    peer_id,
+                    snapshot_index,
+                    user_data: to_retroshare_wire(&pack),
+                };
+                if let Err(err) = socket.send_to(&packet.to_bytes(), BROADCAST_ADDR).await {
+                    debug!("[broadcast discovery] failed to send: {err}");
+                }
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (len, from) = match res {
+                    Ok(res) => res,
+                    Err(err) => {
+                        debug!("[broadcast discovery] failed to receive: {err}");
+                        continue;
+                    }
+                };
+                if !is_enabled(&core) {
+                    continue;
+                }
+                let packet = match DiscoveryPacket::from_bytes(&buf[..len]) {
+                    Some(packet) if packet.app_id == BROADCAST_DISCOVERY_APP_ID && packet.peer_id != peer_id => packet,
+                    _ => continue,
+                };
+                if packet.packet_type == PacketType::IAmOutOfHere {
+                    found.remove(&packet.peer_id);
+                    continue;
+                }
+
+                let mut user_data = packet.user_data;
+                let pack: BroadcastDiscoveryPack = match from_retroshare_wire_result(&mut user_data) {
+                    Ok(pack) => pack,
+                    Err(err) => {
+                        debug!("[broadcast discovery] invalid packet from {from}: {err:?}");
+                        continue;
+                    }
+                };
+                let addr = SocketAddr::new(from.ip(), pack.local_port);
+                if pack.ssl_id == *core.get_own_location().get_location_id()
+                    || found.get(&packet.peer_id) == Some(&(pack.ssl_id, addr))
+                {
+                    continue;
+                }
+                found.insert(packet.peer_id, (pack.ssl_id, addr));
+
+                handle_peer_found(&core, &core_tx, pack, addr).await;
+            }
+        }
+    }
+}
+
+async fn handle_peer_found(
+    core: &DataCore,
+    core_tx: &Sender<Intercom>,
+    pack: BroadcastDiscoveryPack,
+    addr: SocketAddr,
+) {
+    debug!(
+        "[broadcast discovery] found {} ({}) at {addr}",
+        pack.profile_name, pack.ssl_id
+    );
+
+    // RS reports every location it finds
+    let locator = match addr {
+        SocketAddr::V4(_) => format!("ipv4://{addr}"),
+        SocketAddr::V6(_) => format!("ipv6://{addr}"),
+    };
+    core_tx
+        .send(Intercom::Event(EventType::BroadcastDiscovery {
+            code: BroadcastDiscoveryEventCode::PeerFound,
+            data: BroadcastDiscoveryResult {
+                pgp_fingerprint: pack.pgp_fingerprint,
+                ssl_id: pack.ssl_id,
+                profile_name: pack.profile_name.to_owned(),
+                locator,
+            },
+        }))
+        .await
+        .expect("failed to communicate with core");
+
+    // friends get their local address updated
+    let loc = match core.get_location_by_id(Arc::new(pack.ssl_id)) {
+        Some(loc) => loc,
+        None => return,
+    };
+    if loc.get_person().get_pgp().fingerprint().as_bytes() != pack.pgp_fingerprint.as_ref() {
+        warn!(
+            "[broadcast discovery] {} at {addr} announced a different pgp fingerprint, ignoring",
+            pack.ssl_id
+        );
+        return;
+    }
+    if loc.get_ips().0.iter().any(|ip| ip.addr.0 == addr) {
+        return;
+    }
+
+    info!(
+        "[broadcast discovery] found {} {} at {addr}",
+        loc.get_person().get_name(),
+        loc.get_name()
+    );
+    let seen_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs();
+    core_tx
+        .send(Intercom::PeerUpdate(PeerUpdate::Address(
+            loc.get_location_id(),
+            HashSet::from([TlvIpAddressInfoInner {
+                addr: addr.into(),
+                seen_time,
+                source: 0,
+            }
+            .into()]),
+            HashSet::new(),
+        )))
+        .await
+        .expect("failed to communicate with core");
+}
@@ -7,11 +7,12 @@ use log::{debug, info, trace, warn};
 use retroshare_compat::services::service_info::RsServiceInfo;
 use tokio::{
     io::{self, split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     task::JoinHandle,
+    time::sleep,
 };
 
 use crate::{
+    channel::{channel, Policy, Receiver, Sender},
     error::RsError,
     low_level_parsing::{
         headers::{Header, HEADER_SIZE},
@@ -19,30 +20,69 @@ use crate::{
         Packet,
     },
     model::{
-        intercom::{Intercom, PeerState, PeerUpdate},
+        intercom::{Intercom, PeerState, PeerThreadCommand, PeerUpdate},
         location::Location,
+        net::HiddenType,
+        DataCore,
     },
     retroshare_compat::ssl_key::SslKey,
     services::{service_info, Services},
     transport_ng::ConnectionType,
+    utils::rate_limiter::RateLimiter,
 };
 
-use super::CoreController;
+use super::{
+    qos::{self, QosQueue, QOS_BATCH_SIZE, QOS_MAX_QUEUED_BYTES},
+    CoreController,
+};
+
+/// Packets read from the peer but not yet handled, the reader waits when full.
+const NET_QUEUE_SIZE: usize = 64;
+/// Messages for the peer worker, a peer that doesn't take its packets is disconnected when full.
+const PEER_QUEUE_SIZE: usize = 1024;
 
 pub struct ConnectedPeer {}
 
 impl ConnectedPeer {
-    pub async fn run<T: AsyncRead + AsyncWrite>(
-        mut rx: UnboundedReceiver<Intercom>,
-        peer_tx: UnboundedSender<Intercom>, // requires own tx for services
-        core_tx: UnboundedSender<Intercom>,
-        tls_stream: T,
+    pub async fn run<T: AsyncRead + AsyncWrite + Send + 'static>(
+        mut rx: Receiver<Intercom>,
+        peer_tx: Sender<Intercom>, // requires own tx for services
+        core_tx: Sender<Intercom>,
+        core: Arc<DataCore>,
+        (tls_stream, addr): (T, SocketAddr), // the peer's address is unspecified for hidden nodes
         location: Arc<Location>,
         mut global_services: Vec<RsServiceInfo>,
     ) {
         let (mut stream_read, mut stream_write) = split(tls_stream);
+        let turtle = core.get_service_data().turtle();
+
+        let service_control = core.get_service_data().service_control();
+        let bwctrl = core.get_service_data().bwctrl();
+        let location_id = location.get_location_id();
+        let traffic = bwctrl.traffic_counter(&location_id);
+
+        // reading is not cancel safe, do it in its own task
+        // a full queue stops the reading, which slows down a flooding peer
+        let (net_tx, mut net_rx) = channel(
+            format!("net {}", location.get_name()),
+            NET_QUEUE_SIZE,
+            Policy::Block,
+        );
+        let reader = tokio::spawn(async move {
+            loop {
+                let res = ConnectedPeer::receive_packet(&mut stream_read).await;
+                let failed = res.is_err();
+                if net_tx.send(res).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        // packets waiting for bandwidth
+        let mut queue = QosQueue::new();
+        let mut limiter = RateLimiter::new(bwctrl.get_send_rate(&location_id));
 
-        let mut services = Services::get_peer_services(core_tx.to_owned(), peer_tx).await;
+        let mut services = Services::get_peer_services(&core, core_tx.to_owned(), peer_tx).await;
         let mut parser = Parser::new(location.get_location_id());
 
         let mut service_infos = services.get_service_infos();
@@ -50,18 +90,18 @@ impl ConnectedPeer {
 
         // boot up
         // send through parser
-        let packet = service_info::ServiceInfo::gen_service_info(&service_infos);
-        ConnectedPeer::send_packet(&mut stream_write, &mut parser, packet)
-            .await
-            .expect("failed to send");
+        let packet = service_info::ServiceInfo::gen_service_info(
+            &service_infos,
+            service_control,
+            &location.get_location_id(),
+        );
+        ConnectedPeer::queue_packet(&mut queue, &mut parser, packet);
 
         core_tx
             .send(Intercom::PeerUpdate(PeerUpdate::Status(
-                PeerState::Connected(
-                    location.get_location_id(),
-                    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0),
-                ),
+                PeerState::Connected(location.get_location_id(), addr),
             )))
+            .await
             .expect("failed to send");
 
         if log::log_enabled!(log::Level::Info) {
@@ -74,45 +114,115 @@ impl ConnectedPeer {
 
         // enter main loop
         loop {
-            let net_fut = ConnectedPeer::receive_packet(&mut stream_read);
-            let queue_fut = rx.recv();
-
             tokio::select! {
-                res = net_fut => {
+                res = net_rx.recv() => {
                     trace!("net");
 
                     match res {
-                        Ok((header, payload)) => {
+                        Some(Ok((header, payload))) => {
                             if let Some(packet) = parser.handle_incoming_packet(header, payload) {
                                 trace!("handling packet {packet:?}");
 
-                                // if there is no fitting peer service, the packet will be forwarded to the core
-                                services.handle_packet(packet).await;
+                                if let Header::Service { service, .. } = packet.header {
+                                    traffic.record_in(service, HEADER_SIZE + packet.payload.len());
+                                }
+
+                                // turtle fast path, tunnel data of known tunnels is directly sent to the target peer
+                                if let Err(packet) = turtle.try_forward(packet) {
+                                    // if there is no fitting peer service, the packet will be forwarded to the core
+                                    services.handle_packet(packet).await;
+                                }
                             }
                         }
-                        Err(err) => {
-
-                        warn!("[peer] failed to read packet: {err:?}");
+                        Some(Err(err)) => {
+                            warn!("[peer] failed to read packet: {err:?}");
                             return;
                         }
+                        None => return,
                     }
                 }
-                res = queue_fut => {
+                // stop taking packets while the peer is behind, eventually it gets disconnected
+                res = rx.recv(), if queue.bytes() < QOS_MAX_QUEUED_BYTES => {
                     trace!("queue");
 
                     match res {
                         Some(msg) =>   match msg {
-                            Intercom::Send(packet) => ConnectedPeer::send_packet(&mut stream_write, &mut parser, packet).await.expect("failed to send"),
+                            Intercom::Send(packet) => {
+                                if let Header::Service { service, .. } = packet.header {
+                                    if !service_control.is_allowed(service, &location.get_location_id()) {
+                                        debug!("not sending packet of service {service:?} to {}, service is not allowed", location.get_name());
+                                        continue;
+                                    }
+                                }
+                                ConnectedPeer::queue_packet(&mut queue, &mut parser, packet);
+                            }
+                            Intercom::ServiceInfoUpdate(list) => {
+                                debug!("{} announced {} services", location.get_name(), list.len());
+                                service_control.set_peer_services(location.get_location_id().as_ref().to_owned(), &service_infos, list);
+                            }
+                            Intercom::Thread(PeerThreadCommand::UpdateServiceInfo) => {
+                                let packet = service_info::ServiceInfo::gen_service_info(
+                                    &service_infos,
+                                    service_control,
+                                    &location.get_location_id(),
+                                );
+                                ConnectedPeer::queue_packet(&mut queue, &mut parser, packet);
+                            }
                             msg => panic!("not implemented, received {msg:?}"),
                         },
-                        None => {}
+                        None => {
+                            warn!("[peer] {} is not taking its packets, disconnecting", location.get_name());
+                            reader.abort();
+                            return;
+                        }
                     }
 
                 }
+                _ = sleep(limiter.delay()), if !queue.is_empty() => {
+                    trace!("send ({} packets, {} bytes queued)", queue.len(), queue.bytes());
+
+                    // batch as much as the peer's rate allows
+                    limiter.set_rate(bwctrl.get_send_rate(&location_id));
+                    let mut batch = vec![];
+                    while batch.len() < QOS_BATCH_SIZE && limiter.delay().is_zero() {
+                        let slice = match queue.pop() {
+                            Some(slice) => slice,
+                            None => break,
+                        };
+
+                        limiter.consume(slice.data.len());
+                        if let Some(service) = slice.service {
+                            traffic.record_out(service, slice.data.len(), slice.last);
+                        }
+                        batch.extend(slice.data);
+                    }
+
+                    if let Err(err) = ConnectedPeer::write_batch(&mut stream_write, &batch).await {
+                        warn!("[peer] failed to send: {err:?}");
+                        reader.abort();
+                        return;
+                    }
+                }
             }
         }
     }
 
+    /// Slices a packet and queues it with its priority.
+    fn queue_packet(queue: &mut QosQueue, parser: &mut Parser, packet: Packet) {
+        debug!("<<< queue_packet to {}", packet.peer_id);
+        debug!("<<< header {:?}", packet.header);
+        trace!("<<< payload {:02X?}", packet.payload);
+
+        let priority = packet
+            .priority
+            .unwrap_or_else(|| qos::priority(&packet.header));
+        let service = match packet.header {
+            Header::Service { service, .. } => Some(service),
+            _ => None,
+        };
+        queue.push(priority, service, parser.handle_outgoign_packet(packet));
+    }
+
     async fn receive_packet<T: AsyncRead + std::marker::Unpin>(
         stream: &mut T,
     ) -> Result<(Header, Vec<u8>), RsError> {
@@ -147,18 +257,13 @@ impl ConnectedPeer {
         }
     }
 
-    async fn send_packet<T: AsyncWrite + std::marker::Unpin>(
+    async fn write_batch<T: AsyncWrite + std::marker::Unpin>(
         stream: &mut T,
-        parser: &mut Parser,
-        packet: Packet,
+        batch: &[u8],
     ) -> io::Result<()> {
-        debug!("<<< send_packet to {}", packet.peer_id);
-        debug!("<<< header {:?}", packet.header);
-        trace!("<<< payload {:02X?}", packet.payload);
+        trace!("<<< writing {} bytes", batch.len());
 
-        for packet in parser.handle_outgoign_packet(packet) {
-            stream.write_all(&packet).await?;
-        }
+        stream.write_all(batch).await?;
         stream.flush().await
     }
 }
@@ -168,23 +273,26 @@ pub(super) struct ConnectionBuilder {
     // own_peer_id: Arc<PeerId>,
     own_key_pair: SslKey,
 
-    core_tx: UnboundedSender<Intercom>,
+    core_tx: Sender<Intercom>,
+    core: Arc<DataCore>,
 
-    peer_rx: UnboundedReceiver<Intercom>,
-    peer_tx: UnboundedSender<Intercom>,
+    peer_rx: Receiver<Intercom>,
+    peer_tx: Sender<Intercom>,
 
     global_services: Vec<RsServiceInfo>,
 }
 
 impl ConnectionBuilder {
-    pub fn new(
-        cc: &CoreController,
-        peer_location: Arc<Location>,
-    ) -> (Self, UnboundedSender<Intercom>) {
+    pub fn new(cc: &CoreController, peer_location: Arc<Location>) -> (Self, Sender<Intercom>) {
         let own_peer_id = cc.data_core.get_own_location().get_location_id();
         let own_key_pair = cc.data_core.get_own_keypair().to_owned();
         let core_tx = cc.core_tx.clone();
-        let (peer_tx, peer_rx) = unbounded_channel();
+        let core = cc.data_core.clone();
+        let (peer_tx, peer_rx) = channel(
+            format!("peer {}", peer_location.get_name()),
+            PEER_QUEUE_SIZE,
+            Policy::Disconnect,
+        );
         let global_services = cc.services.get_service_infos();
 
         assert_ne!(peer_location.get_location_id(), own_peer_id);
@@ -196,6 +304,7 @@ impl ConnectionBuilder {
                 own_key_pair,
 
                 core_tx,
+                core,
 
                 peer_rx,
                 peer_tx: peer_tx.to_owned(),
@@ -208,22 +317,55 @@ impl ConnectionBuilder {
 
     pub(super) async fn connect(self) -> Option<JoinHandle<()>> {
         trace!("trying to connect to {}", self.peer_location.get_name());
-        // turn IPs into ConnectionType::Tcp
-        let ips = {
+        // hidden nodes are reached through their proxy, in hidden only mode clear-net addresses are never used
+        let net = self.core.get_net();
+        let mut candidates = vec![];
+        if let Some((host, port)) = self.peer_location.get_hidden_addr() {
+            if let Some(ty) = HiddenType::from_host(&host) {
+                let proxy = net.get_proxy(ty);
+                candidates.push(ConnectionType::Hidden { host, port, proxy });
+            }
+        }
+
+        // turn IPs into ConnectionType::Tcp, skipping banned ones, external ones are tried over UDP (TOU) last
+        // (only when enabled, it is experimental, when the peer runs its DHT and thus has a UDP stack, and never through
+        // a proxy, UDP can't be proxied)
+        let proxy = net.outgoing_proxy_for(self.peer_location.get_person().get_pgp_id());
+        if net.is_hidden_only() {
+            debug!(
+                "hidden only, not connecting to {} over clear-net",
+                self.peer_location.get_name()
+            );
+        } else {
+            let banlist = self.core.get_service_data().banlist();
+            let allowed = |addr: &SocketAddr| match banlist.is_banned(&addr.ip()) {
+                Some(ban) => {
+                    debug!(
+                        "not connecting to {} with banned ip {addr} ({})",
+                        self.peer_location.get_name(),
+                        ban.comment
+                    );
+                    false
+                }
+                None => true,
+            };
+
             let ips = self.peer_location.get_ips();
             let mut local: Vec<ConnectionType> = ips
                 .0
                 .iter()
-                .map(|val| ConnectionType::Tcp(val.addr.0))
+                .map(|val| val.addr.0)
+                .filter(allowed)
+                .map(ConnectionType::Tcp)
                 .collect();
-            let mut external: Vec<ConnectionType> = ips
-                .1
-                .iter()
-                .map(|val| ConnectionType::Tcp(val.addr.0))
-                .collect();
-            local.append(&mut external);
-            local
-        };
+            let external: Vec<SocketAddr> =
+                ips.1.iter().map(|val| val.addr.0).filter(allowed).collect();
+            local.extend(external.iter().cloned().map(ConnectionType::Tcp));
+            if net.is_experimental_udp() && proxy.is_none() && self.peer_location.has_dht() {
+                local.extend(external.into_iter().map(ConnectionType::Udp));
+            }
+            candidates.extend(local);
+        }
 
         let _loc_id = self.peer_location.get_location_id().to_owned();
         let loc_key = self.peer_location.get_person().get_pgp().to_owned();
@@ -234,34 +376,21 @@ impl ConnectionBuilder {
             loc_key,
             self.peer_location.get_name(),
         ) {
-            for ip in ips {
+            let con = con.with_proxy(proxy);
+            for ip in candidates {
                 trace!(
                     "trying to connect to {} with ip {:?}",
                     self.peer_location.get_name(),
                     ip
                 );
 
+                let addr = ip
+                    .peer_addr()
+                    .unwrap_or_else(|| SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0));
                 if let Ok(tls_stream) = con.connect(ip).await {
                     trace!("connected to {}!", self.peer_location.get_name());
 
-                    return Some(tokio::spawn(async move {
-                        ConnectedPeer::run(
-                            self.peer_rx,
-                            self.peer_tx,
-                            self.core_tx.to_owned(),
-                            tls_stream,
-                            self.peer_location.clone(),
-                            self.global_services,
-                        )
-                        .await;
-
-                        // disconnected
-                        self.core_tx
-                            .send(Intercom::PeerUpdate(PeerUpdate::Status(
-                                PeerState::NotConnected(self.peer_location.get_location_id()),
-                            )))
-                            .expect("failed to send");
-                    }));
+                    return Some(self.spawn_peer(tls_stream, addr));
                 }
             }
         } else {
@@ -272,8 +401,50 @@ impl ConnectionBuilder {
                 .send(Intercom::PeerUpdate(PeerUpdate::Status(
                     PeerState::NotConnected(self.peer_location.get_location_id()),
                 )))
+                .await
                 .expect("failed to send");
         }
         None
     }
+
+    /// Takes over an incoming connection, the peer was identified by the listener.
+    pub(super) async fn accept<T: AsyncRead + AsyncWrite + Send + 'static>(
+        self,
+        tls_stream: T,
+        addr: SocketAddr,
+    ) -> Option<JoinHandle<()>> {
+        trace!("accepted {} from {addr}", self.peer_location.get_name());
+        Some(self.spawn_peer(tls_stream, addr))
+    }
+
+    fn spawn_peer<T: AsyncRead + AsyncWrite + Send + 'static>(
+        self,
+        tls_stream: T,
+        addr: SocketAddr,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let core = self.core.to_owned();
+            ConnectedPeer::run(
+                self.peer_rx,
+                self.peer_tx,
+                self.core_tx.to_owned(),
+                self.core,
+                (tls_stream, addr),
+                self.peer_location.clone(),
+                self.global_services,
+            )
+            .await;
+
+            // disconnected
+            core.get_service_data()
+                .service_control()
+                .remove_peer_services(&self.peer_location.get_location_id());
+            self.core_tx
+                .send(Intercom::PeerUpdate(PeerUpdate::Status(
+                    PeerState::NotConnected(self.peer_location.get_location_id()),
+                )))
+                .await
+                .expect("failed to send");
+        })
+    }
 }
@@ -0,0 +1,120 @@
+//! Accepts incoming connections on our port, peers are identified by their certificate (see `transport_ng::Acceptor`).
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{sleep, timeout},
+};
+
+use crate::{
+    channel::Sender,
+    model::{
+        intercom::{Intercom, PeerThreadCommand},
+        DataCore,
+    },
+    transport_ng::Acceptor,
+};
+
+/// How long a peer may take for the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time to wait after a failed accept (e.g. too many open files).
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+pub async fn run(core: Arc<DataCore>, core_tx: Sender<Intercom>) {
+    let acceptor = match Acceptor::new(core.get_own_keypair()) {
+        Ok(acceptor) => acceptor,
+        Err(err) => {
+            warn!("[listener] failed to set up tls: {err}");
+            return;
+        }
+    };
+
+    // hidden nodes are reached through their Tor / I2P service, which connects locally
+    let net = core.get_net();
+    let ip = if net.is_hidden() || net.is_hidden_only() {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    };
+    let addr = SocketAddr::new(ip, net.get_port());
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("[listener] failed to bind {addr}, friends can't connect to us: {err}");
+            return;
+        }
+    };
+    info!("[listener] listening on {addr}");
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(con) => con,
+            Err(err) => {
+                warn!("[listener] failed to accept: {err}");
+                sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+
+        // no handshake with banned addresses
+        if let Some(ban) = core.get_service_data().banlist().is_banned(&addr.ip()) {
+            debug!(
+                "[listener] dropping connection from banned ip {addr} ({})",
+                ban.comment
+            );
+            continue;
+        }
+
+        tokio::spawn(handle_incoming(
+            core.clone(),
+            core_tx.clone(),
+            acceptor.clone(),
+            stream,
+            addr,
+        ));
+    }
+}
+
+async fn handle_incoming(
+    core: Arc<DataCore>,
+    core_tx: Sender<Intercom>,
+    acceptor: Acceptor,
+    stream: TcpStream,
+    addr: SocketAddr,
+) {
+    let (ssl_id, stream) = match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(con)) => con,
+        Ok(Err(err)) => {
+            debug!("[listener] handshake with {addr} failed: {err}");
+            return;
+        }
+        Err(_) => {
+            debug!("[listener] handshake with {addr} timed out");
+            return;
+        }
+    };
+
+    // only friends are let in, the core sorts out duplicate connections
+    let ssl_id = Arc::new(ssl_id);
+    if ssl_id == core.get_own_location().get_location_id()
+        || core.get_location_by_id(ssl_id.to_owned()).is_none()
+    {
+        debug!("[listener] dropping connection from unknown location {ssl_id} ({addr})");
+        return;
+    }
+
+    core_tx
+        .send(Intercom::Thread(PeerThreadCommand::Incoming(
+            ssl_id,
+            addr,
+            Box::new(stream),
+        )))
+        .await
+        .expect("failed to communicate with core");
+}
@@ -1,41 +1,59 @@
 use log::{debug, info, trace, warn};
 use std::{
     collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
     sync::Arc,
     time::{Duration, Instant},
 };
 use tokio::{
+    net::TcpStream,
     select,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
     task::JoinHandle,
-    time::interval,
+    time::{interval, timeout},
 };
 
-use retroshare_compat::{basics::SslId, gxs::sqlite::database::GxsDatabase};
+use retroshare_compat::{
+    basics::SslId,
+    config::NodeGroupItem,
+    events::{EventType, TorConnectivityStatus, TorManagerEventCode, TorStatus},
+    gxs::sqlite::database::GxsDatabase,
+};
 
 use crate::{
+    channel::{self, channel, Policy, Receiver, Sender},
     gxs::gxs_backend::GxsShared,
     model::{
         intercom::{Intercom, PeerState, PeerThreadCommand, PeerUpdate},
         location::Location,
+        net::{self, HiddenType, NetStore},
         person::Peer,
         ConnectedPeerEntries, DataCore,
     },
     retroshare_compat::ssl_key::SslKey,
     services::Services,
+    transport_ng::IncomingStream,
     utils::{self, simple_stats::StatsCollection},
 };
 
 use self::connected_peer::ConnectionBuilder;
 
+pub mod broadcast_discovery;
 pub mod connected_peer;
+pub mod listener;
+pub mod qos;
+
+/// Messages for the core, senders wait when full.
+const CORE_QUEUE_SIZE: usize = 4096;
+/// How long the Tor proxy may take to accept a connection.
+const TOR_PROXY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct CoreController {
     data_core: Arc<DataCore>,
     services: Services,
 
-    core_tx: UnboundedSender<Intercom>,
-    core_rx: UnboundedReceiver<Intercom>,
+    core_tx: Sender<Intercom>,
+    core_rx: Receiver<Intercom>,
 
     pending_connection_attempts: ConnectedPeerEntries<Option<JoinHandle<()>>>,
 }
@@ -43,15 +61,26 @@ pub struct CoreController {
 impl CoreController {
     pub async fn new(
         keys: SslKey,
-        friends: (Vec<Arc<Peer>>, Vec<Arc<Location>>),
+        friends: (Vec<Arc<Peer>>, Vec<Arc<Location>>, Vec<NodeGroupItem>),
         own_id: Arc<SslId>,
+        net: NetStore,
         gxs_id_db: GxsDatabase,
+        config_dir: PathBuf,
     ) -> (Self, Arc<DataCore>) {
-        let (core_tx, core_rx) = unbounded_channel();
+        // the core itself only waits (bounded) on full channels, so waiting for the core can't dead lock
+        let (core_tx, core_rx) = channel("core", CORE_QUEUE_SIZE, Policy::Block);
 
         let gxs_shared_id = Arc::new(GxsShared::new(core_tx.clone(), own_id.clone()));
 
-        let data_core = DataCore::new(keys, friends, own_id, gxs_shared_id.to_owned()).await;
+        let data_core = DataCore::new(
+            keys,
+            friends,
+            own_id,
+            net,
+            gxs_shared_id.to_owned(),
+            config_dir,
+        )
+        .await;
 
         let services =
             Services::get_core_services(&data_core, core_tx.clone(), (gxs_id_db, gxs_shared_id))
@@ -65,6 +94,9 @@ impl CoreController {
             }
         }
 
+        tokio::spawn(broadcast_discovery::run(data_core.clone(), core_tx.clone()));
+        tokio::spawn(listener::run(data_core.clone(), core_tx.clone()));
+
         let dc = data_core.clone();
         (
             CoreController {
@@ -82,6 +114,7 @@ impl CoreController {
 
     pub async fn run(&mut self) -> ! {
         let mut timer_slow_5s = interval(Duration::from_secs(5));
+        let mut timer_slow_60s = interval(Duration::from_secs(60));
         let mut stats: StatsCollection = (Instant::now(), HashMap::new());
 
         loop {
@@ -99,15 +132,29 @@ impl CoreController {
                     // reconnects
                     self.check_reconnects().await;
 
+                    // queues
+                    for stats in channel::stats() {
+                        if stats.len > stats.capacity / 2 {
+                            warn!("[core] {} is lagging behind, {}/{} queued ({} dropped)", stats.name, stats.len, stats.capacity, stats.dropped);
+                        }
+                    }
+
                     // // FIXME
                     // self.data_core.webui_send(
                     //     EventType::PeerStateChanged { ssl_id: "d6fb6c0f53d18303dcc9043111490e40".into() }
                     // ).await;
                 }
+                _ = timer_slow_60s.tick() => {
+                    self.check_tor_proxy();
+                    self.check_own_addresses().await;
+                }
                 msg = self.core_rx.recv() => {
                     trace!("queue");
 
                     match msg {
+                        Some(Intercom::Thread(PeerThreadCommand::Incoming(loc, addr, stream))) => {
+                            self.handle_incoming(loc, addr, *stream).await
+                        }
                         Some(msg) => self.handle_message(&msg).await,
                         None => {}
                     }
@@ -219,15 +266,29 @@ impl CoreController {
                             }
                         }
                     }
+                    PeerUpdate::HiddenAddress(ssl_id, host, port) => {
+                        match self.data_core.get_location_by_id(ssl_id.to_owned()) {
+                            Some(peer) if HiddenType::from_host(host).is_some() => {
+                                if peer.get_hidden_addr().as_ref()
+                                    != Some(&(host.to_owned(), *port))
+                                {
+                                    info!(
+                                        "[core] updating hidden address {host}:{port} of peer {} {}",
+                                        peer.get_person().get_name(),
+                                        peer.get_name()
+                                    );
+                                    peer.set_hidden_addr(Some((host.to_owned(), *port)));
+                                }
+                            }
+                            Some(_) => {
+                                warn!("[core] ignoring invalid hidden address {host} of {ssl_id}")
+                            }
+                            None => warn!("[core] got an update for an unknown location! {ssl_id}"),
+                        }
+                    }
                 }
             }
 
-            Intercom::Thread(PeerThreadCommand::Incoming(con)) => {
-                let _ = con.peer_addr().unwrap();
-
-                unimplemented!();
-            }
-
             Intercom::Send(packet) => {
                 self.data_core.try_send_to_peer(packet.to_owned()).await;
             }
@@ -269,28 +330,113 @@ impl CoreController {
         match msg {
             Intercom::PeerUpdate(state) => {
                 // handle event listener
-                for subscriber in self.data_core.get_subscribers().await.iter() {
-                    debug!("{subscriber:?}, closed: {}", subscriber.is_closed());
-                    subscriber
-                        .send(Intercom::PeerUpdate(state.clone()))
-                        .expect("failed to communicate with service");
-                }
+                self.data_core
+                    .notify_subscribers(|| Intercom::PeerUpdate(state.clone()))
+                    .await;
             }
             Intercom::Event(event) => {
                 // handle webui
                 self.data_core.webui_send(event.to_owned()).await;
 
                 // handle event listener
-                for subscriber in self.data_core.get_subscribers().await.iter() {
-                    subscriber
-                        .send(Intercom::Event(event.clone()))
-                        .expect("failed to communicate with service");
-                }
+                self.data_core
+                    .notify_subscribers(|| Intercom::Event(event.clone()))
+                    .await;
             }
             _ => (),
         }
     }
 
+    /// Starts a peer worker for an incoming connection, unless the location is connected already (or we are connecting).
+    async fn handle_incoming(&mut self, loc: Arc<SslId>, addr: SocketAddr, stream: IncomingStream) {
+        let location = match self.data_core.get_location_by_id(loc.to_owned()) {
+            Some(location) => location,
+            None => return,
+        };
+        if self.pending_connection_attempts.0.contains_key(&loc)
+            || self
+                .data_core
+                .get_connected_peers()
+                .lock()
+                .await
+                .0
+                .contains_key(&loc)
+        {
+            debug!(
+                "[core] {} is already connected, dropping incoming connection from {addr}",
+                location.get_name()
+            );
+            return;
+        }
+
+        info!(
+            "[core] incoming connection from {} ({addr})",
+            location.get_name()
+        );
+        let (builder, peer_tx) = ConnectionBuilder::new(self, location);
+        let handler = tokio::spawn(builder.accept(stream, addr));
+        self.pending_connection_attempts
+            .0
+            .insert(loc, (peer_tx, handler));
+    }
+
+    /// Probes the Tor proxy (when there is any use for it), changes are reported to the webui.
+    fn check_tor_proxy(&self) {
+        let is_onion = |loc: &Arc<Location>| {
+            loc.get_hidden_addr()
+                .is_some_and(|(host, _)| HiddenType::from_host(&host) == Some(HiddenType::Tor))
+        };
+        if !self.data_core.get_net().is_hidden()
+            && !self.data_core.get_locations().iter().any(is_onion)
+        {
+            return;
+        }
+
+        let data_core = self.data_core.clone();
+        tokio::spawn(async move {
+            let proxy = data_core.get_net().get_proxy(HiddenType::Tor);
+            let error = match timeout(TOR_PROXY_PROBE_TIMEOUT, TcpStream::connect(proxy)).await {
+                Ok(Ok(_)) => None,
+                Ok(Err(err)) => Some(err.to_string()),
+                Err(_) => Some(String::from("timed out")),
+            };
+            if !data_core.get_net().set_tor_reachable(error.is_none()) {
+                return;
+            }
+
+            let event = match error {
+                None => {
+                    info!("[core] tor proxy {proxy} is reachable");
+                    EventType::TorManager {
+                        code: TorManagerEventCode::TorConnectivityChanged,
+                        connectivity: TorConnectivityStatus::SocketConnected,
+                        status: TorStatus::Ready,
+                        error_message: String::new(),
+                    }
+                }
+                Some(err) => {
+                    warn!("[core] tor proxy {proxy} is not reachable: {err}");
+                    EventType::TorManager {
+                        code: TorManagerEventCode::TorManagerError,
+                        connectivity: TorConnectivityStatus::NotConnected,
+                        status: TorStatus::Offline,
+                        error_message: format!("proxy {proxy} is not reachable: {err}"),
+                    }
+                }
+            };
+            data_core.webui_send(event).await;
+        });
+    }
+
+    /// Picks up changes of our network interfaces.
+    async fn check_own_addresses(&self) {
+        let net = self.data_core.get_net();
+        if net.set_local_ips(net::detect_local_ips()) {
+            info!("[core] local addresses: {:?}", net.get_local_addrs());
+        }
+        self.data_core.update_own_addresses().await;
+    }
+
     async fn check_reconnects(&mut self) {
         let mut candidates: Vec<_> = self
             .data_core
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+
+use retroshare_compat::services::ServiceType;
+
+use crate::low_level_parsing::headers::Header;
+
+// RS's `QOS_PRIORITY_*` range (rsitems.h)
+pub const QOS_PRIORITY_TOP: u8 = 9;
+pub const QOS_PRIORITY_DEFAULT: u8 = 3;
+
+/// Writes are batched up to this size before flushing.
+pub const QOS_BATCH_SIZE: usize = 16 * 1024;
+/// The peer worker stops taking new packets once this much is queued.
+pub const QOS_MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Returns the priority of an outgoing item, roughly following the priorities RS assigns to its items.
+pub fn priority(header: &Header) -> u8 {
+    match header {
+        Header::Service {
+            service, sub_type, ..
+        } => match service {
+            ServiceType::Rtt | ServiceType::BwCtrl => QOS_PRIORITY_TOP,
+            ServiceType::Heartbeat => 8,
+            ServiceType::ServiceInfo => 7,
+            // RS_PKT_SUBTYPE_CHAT_AVATAR
+            ServiceType::Chat if *sub_type == 0x03 => 2,
+            ServiceType::Chat => 7,
+            ServiceType::Status | ServiceType::Discovery => 5,
+            ServiceType::Turtle | ServiceType::GxsTunnel | ServiceType::GRouter => 4,
+            ServiceType::Msg => 2,
+            _ => QOS_PRIORITY_DEFAULT,
+        },
+        _ => QOS_PRIORITY_DEFAULT,
+    }
+}
+
+#[derive(Debug)]
+struct QueuedPacket {
+    service: Option<ServiceType>,
+    slices: VecDeque<Vec<u8>>,
+}
+
+/// A slice (or whole packet) ready to be written.
+#[derive(Debug)]
+pub struct QueuedSlice {
+    pub service: Option<ServiceType>,
+    pub data: Vec<u8>,
+    /// whether this is the packet's last slice
+    pub last: bool,
+}
+
+/// Per peer outgoing queue, the equivalent of RS's pqiQoS.
+///
+/// The highest priority is always sent first, slices of packets with the same priority are interleaved
+/// so that a large packet doesn't hold back smaller ones.
+#[derive(Debug)]
+pub struct QosQueue {
+    /// indexed by priority
+    levels: Vec<VecDeque<QueuedPacket>>,
+    packets: usize,
+    bytes: usize,
+}
+
+impl Default for QosQueue {
+    fn default() -> Self {
+        QosQueue {
+            levels: (0..=QOS_PRIORITY_TOP).map(|_| VecDeque::new()).collect(),
+            packets: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl QosQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the slices of one packet.
+    pub fn push(&mut self, priority: u8, service: Option<ServiceType>, slices: Vec<Vec<u8>>) {
+        if slices.is_empty() {
+            return;
+        }
+
+        self.packets += 1;
+        self.bytes += slices.iter().map(Vec::len).sum::<usize>();
+        self.levels[priority.min(QOS_PRIORITY_TOP) as usize].push_back(QueuedPacket {
+            service,
+            slices: slices.into(),
+        });
+    }
+
+    /// Takes the next slice, packets of the same priority take turns.
+    pub fn pop(&mut self) -> Option<QueuedSlice> {
+        let level = self
+            .levels
+            .iter_mut()
+            .rev()
+            .find(|level| !level.is_empty())?;
+        let mut packet = level.pop_front()?;
+        let data = packet.slices.pop_front()?;
+        let service = packet.service;
+
+        let last = packet.slices.is_empty();
+        if last {
+            self.packets -= 1;
+        } else {
+            level.push_back(packet);
+        }
+        self.bytes -= data.len();
+
+        Some(QueuedSlice {
+            service,
+            data,
+            last,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets == 0
+    }
+
+    /// Number of (partly) queued packets.
+    pub fn len(&self) -> usize {
+        self.packets
+    }
+
+    /// Number of queued bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retroshare_compat::services::ServiceType;
+
+    use super::{QosQueue, QOS_PRIORITY_DEFAULT, QOS_PRIORITY_TOP};
+
+    #[test]
+    fn qos_queue() {
+        let mut queue = QosQueue::new();
+        let turtle = Some(ServiceType::Turtle);
+        queue.push(
+            QOS_PRIORITY_DEFAULT,
+            turtle,
+            vec![vec![1], vec![2], vec![3]],
+        );
+        queue.push(QOS_PRIORITY_DEFAULT, turtle, vec![vec![4], vec![5]]);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.bytes(), 5);
+
+        // slices of the same priority are interleaved
+        assert_eq!(queue.pop().unwrap().data, vec![1]);
+        assert_eq!(queue.pop().unwrap().data, vec![4]);
+
+        // higher priorities go first
+        queue.push(QOS_PRIORITY_TOP, Some(ServiceType::Rtt), vec![vec![9]]);
+        let slice = queue.pop().unwrap();
+        assert_eq!(slice.data, vec![9]);
+        assert_eq!(slice.service, Some(ServiceType::Rtt));
+        assert!(slice.last);
+
+        let data: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|slice| (slice.data[0], slice.last))
+            .collect();
+        assert_eq!(data, vec![(2, false), (5, true), (3, true)]);
+        assert!(queue.is_empty());
+        assert_eq!(queue.bytes(), 0);
+    }
+}
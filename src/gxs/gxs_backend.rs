@@ -13,16 +13,14 @@ use retroshare_compat::{
 };
 use tokio::{
     select,
-    sync::{
-        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        Mutex, RwLock,
-    },
+    sync::{Mutex, RwLock},
     time::{interval, Interval},
 };
 #[cfg(feature = "tracing")]
 use tracing::event;
 
 use crate::{
+    channel::{channel, Policy, Receiver, Sender},
     gxs::transaction::StoredNxsItem,
     low_level_parsing::Packet,
     model::{
@@ -39,6 +37,8 @@ use super::{nxs::NxsTransactionController, transaction::TransactionId};
 pub enum GxsItemsWrapper {
     GxsGroupIdsAll,
     GxsGroupIds(Vec<GxsGroupId>),
+    /// groups to fetch from a peer (when unknown)
+    GxsGroupIdsFrom(Vec<GxsGroupId>, Arc<PeerId>),
     GxsGroups(Vec<GxsGroup>),
     // GxsMessage(GxsMessageId),
 }
@@ -87,30 +87,31 @@ impl GxsTask {
 // +++++++++++++++++++++++++++++++++++++++++
 // +++++++++++++++++++++++++++++++++++++++++
 
+/// Pending requests, callers wait when full.
+const GXS_REQUESTS_QUEUE_SIZE: usize = 64;
+
+type GxsRequestPair<IN, OUT> = (Sender<AppRequest<IN, OUT>>, Receiver<AppRequest<IN, OUT>>);
+
 enum GxsRequestChannelInner<IN, OUT> {
-    Initialized(
-        Option<(
-            UnboundedSender<AppRequest<IN, OUT>>,
-            UnboundedReceiver<AppRequest<IN, OUT>>,
-        )>,
-    ),
-    Ready(UnboundedSender<AppRequest<IN, OUT>>),
+    Initialized(Option<GxsRequestPair<IN, OUT>>),
+    Ready(Sender<AppRequest<IN, OUT>>),
 }
 
 pub struct GxsRequestChannel<IN, OUT>(RwLock<GxsRequestChannelInner<IN, OUT>>);
 
-impl<IN, OUT> GxsRequestChannel<IN, OUT> {
+impl<IN, OUT> GxsRequestChannel<IN, OUT>
+where
+    IN: Send + 'static,
+    OUT: Send + 'static,
+{
     pub fn new() -> Self {
-        let (tx, rx): (
-            UnboundedSender<AppRequest<IN, OUT>>,
-            UnboundedReceiver<AppRequest<IN, OUT>>,
-        ) = unbounded_channel();
+        let (tx, rx) = channel("gxs requests", GXS_REQUESTS_QUEUE_SIZE, Policy::Block);
         GxsRequestChannel(RwLock::new(GxsRequestChannelInner::Initialized(Some((
             tx, rx,
         )))))
     }
 
-    pub fn take_receiver(&self) -> UnboundedReceiver<AppRequest<IN, OUT>> {
+    pub fn take_receiver(&self) -> Receiver<AppRequest<IN, OUT>> {
         let mut lock = self.0.try_write().unwrap(); // when this is called, there should only be one writer (the caller)
         let (tx, rx) = match *lock {
             GxsRequestChannelInner::Initialized(ref mut inner) => inner.take().unwrap(),
@@ -121,26 +122,27 @@ impl<IN, OUT> GxsRequestChannel<IN, OUT> {
         rx
     }
 
-    pub fn add_request(&self, request: AppRequest<IN, OUT>)
+    pub async fn add_request(&self, request: AppRequest<IN, OUT>)
     where
         IN: Debug,
         OUT: Debug,
     {
-        match *self.0.try_read().unwrap() {
+        let tx = match *self.0.try_read().unwrap() {
             // when this is called there should only be readers but not a single writer anymore
-            GxsRequestChannelInner::Ready(ref tx) => tx.send(request).unwrap(),
+            GxsRequestChannelInner::Ready(ref tx) => tx.to_owned(),
             GxsRequestChannelInner::Initialized(Some(ref inner)) => {
                 log::error!("add_request was called before take_receiver!");
-                inner.0.send(request).unwrap();
+                inner.0.to_owned()
             }
             _ => panic!(),
-        }
+        };
+        tx.send(request).await.unwrap();
     }
 }
 
 // Object shared between various gxs/nxs components
 pub struct GxsShared {
-    pub(super) core_tx: UnboundedSender<Intercom>,
+    pub(super) core_tx: Sender<Intercom>,
 
     pub(super) own_id: Arc<SslId>,
 
@@ -152,7 +154,7 @@ pub struct GxsShared {
 }
 
 impl GxsShared {
-    pub fn new(core_tx: UnboundedSender<Intercom>, own_id: Arc<SslId>) -> Self {
+    pub fn new(core_tx: Sender<Intercom>, own_id: Arc<SslId>) -> Self {
         GxsShared {
             core_tx,
             own_id,
@@ -169,7 +171,7 @@ pub struct GxsBackend<const TYPE: u16> {
     core: Arc<DataCore>,
 
     shared: Arc<GxsShared>,
-    requests: UnboundedReceiver<AppRequest<GxsItemsWrapper, GxsItemsWrapper>>,
+    requests: Receiver<AppRequest<GxsItemsWrapper, GxsItemsWrapper>>,
 
     database: Mutex<GxsDatabase>,
     mem_cache: Mutex<GxsDatabase>,
@@ -612,6 +614,26 @@ impl<const TYPE: u16> GxsBackend<TYPE> {
                 }
                 groups
             }
+            GxsItemsWrapper::GxsGroupIdsFrom(group_ids, peer_id) => {
+                let mut missing = self.missing_groups.lock().await;
+                for group_id in group_ids {
+                    // the database is not Send, don't hold it across an `await`
+                    let cached = matches!(
+                        self.mem_cache.lock().await.get_grp_meta(&group_id),
+                        Ok(Some(_))
+                    );
+                    let stored = cached
+                        || matches!(
+                            self.database.lock().await.get_grp_meta(&group_id),
+                            Ok(Some(_))
+                        );
+
+                    if !stored && !missing.iter().any(|(id, _)| id == &group_id) {
+                        missing.push((group_id, peer_id.to_owned()));
+                    }
+                }
+                vec![]
+            }
             GxsItemsWrapper::GxsGroups(_) => {
                 log::error!("this makes no sense: request = {request:?}");
                 vec![]
@@ -1,9 +1,12 @@
 use log::trace;
 use openssl::{
+    envelope::{Open, Seal},
+    error::ErrorStack,
     hash::MessageDigest,
     pkey::PKey,
     rsa::Rsa,
     sign::{Signer, Verifier},
+    symm::Cipher,
 };
 use retroshare_compat::tlv::tlv_keys::{TlvKeyFlags, TlvPrivateRSAKey, TlvPublicRSAKey};
 
@@ -49,3 +52,97 @@ pub fn generate_signature(
     s.update(data_to_sign)?;
     s.sign_to_vec()
 }
+
+/// RS's `GxsSecurity::encrypt` (single key format): `[encrypted key length (u32 BE)][encrypted key][iv][AES-128-CBC encrypted data]`
+pub fn encrypt_data(key: &TlvPublicRSAKey, data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    assert!(!key.key_flags.contains(TlvKeyFlags::TYPE_FULL));
+
+    let rsa = Rsa::public_key_from_der_pkcs1(key.key_data.as_slice())?;
+    let pkey = PKey::from_rsa(rsa)?;
+
+    let cipher = Cipher::aes_128_cbc();
+    let mut seal = Seal::new(cipher, &[pkey])?;
+    let mut encrypted = vec![0; data.len() + cipher.block_size()];
+    let mut len = seal.update(data, &mut encrypted)?;
+    len += seal.finalize(&mut encrypted[len..])?;
+    encrypted.truncate(len);
+
+    let ek = &seal.encrypted_keys()[0];
+    let mut out = (ek.len() as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(ek);
+    out.extend_from_slice(seal.iv().unwrap_or_default());
+    out.extend(encrypted);
+    Ok(out)
+}
+
+/// RS's `GxsSecurity::decrypt`, counterpart of `encrypt_data`. Returns `None` for malformed data.
+pub fn decrypt_data(key: &TlvPrivateRSAKey, data: &[u8]) -> Result<Option<Vec<u8>>, ErrorStack> {
+    assert!(key.key_flags.contains(TlvKeyFlags::TYPE_FULL));
+
+    let rsa = Rsa::private_key_from_der(key.key_data.as_slice())?;
+    let pkey = PKey::from_rsa(rsa)?;
+
+    let cipher = Cipher::aes_128_cbc();
+    let iv_len = cipher.iv_len().unwrap_or_default();
+    if data.len() < 4 {
+        return Ok(None);
+    }
+    let ek_len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+    if data.len() < 4 + ek_len + iv_len {
+        return Ok(None);
+    }
+    let (ek, rest) = data[4..].split_at(ek_len);
+    let (iv, encrypted) = rest.split_at(iv_len);
+
+    let mut open = Open::new(cipher, &pkey, Some(iv), ek)?;
+    let mut decrypted = vec![0; encrypted.len() + cipher.block_size()];
+    let mut len = open.update(encrypted, &mut decrypted)?;
+    len += open.finalize(&mut decrypted[len..])?;
+    decrypted.truncate(len);
+    Ok(Some(decrypted))
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::rsa::Rsa;
+    use retroshare_compat::{
+        basics::GxsId,
+        tlv::{
+            tlv_keys::{TlvKeyFlags, TlvPrivateRSAKey, TlvPublicRSAKey, TlvRSAKeyInner},
+            Tlv,
+        },
+    };
+
+    use super::{decrypt_data, encrypt_data};
+
+    #[test]
+    fn encryption() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let key_id: GxsId = "c59df722f56f2f886ac301acc5572e03".into();
+        let build = |key_flags, key_data: Vec<u8>| TlvRSAKeyInner {
+            key_id: key_id.into(),
+            key_flags,
+            start_ts: 0,
+            end_ts: 0,
+            key_data: key_data.into(),
+        };
+        let pub_key: TlvPublicRSAKey = Tlv::new(build(
+            TlvKeyFlags::TYPE_PUBLIC_ONLY,
+            rsa.public_key_to_der_pkcs1().unwrap(),
+        ))
+        .into();
+        let priv_key: TlvPrivateRSAKey = Tlv::new(build(
+            TlvKeyFlags::TYPE_FULL,
+            rsa.private_key_to_der().unwrap(),
+        ))
+        .into();
+
+        let data = b"some secret data".to_vec();
+        let encrypted = encrypt_data(&pub_key, &data).unwrap();
+        // key length + 256 bytes key + iv + one block
+        assert_eq!(encrypted.len(), 4 + 256 + 16 + 32);
+
+        assert_eq!(decrypt_data(&priv_key, &encrypted).unwrap(), Some(data));
+        assert_eq!(decrypt_data(&priv_key, &encrypted[..10]).unwrap(), None);
+    }
+}
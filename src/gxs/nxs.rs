@@ -81,7 +81,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
         }
     }
 
-    fn send_packet<T>(&self, sub_type: u8, item: &T, receiving_peer: Arc<PeerId>)
+    async fn send_packet<T>(&self, sub_type: u8, item: &T, receiving_peer: Arc<PeerId>)
     where
         T: Serialize,
     {
@@ -92,6 +92,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
         self.shared
             .core_tx
             .send(Intercom::Send(packet))
+            .await
             .expect("failed to send to core");
     }
 
@@ -432,8 +433,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
                                             SUBTYPE_NXS_GRP_ITEM,
                                             &item,
                                             transaction.peer_id.to_owned(),
-                                        )
-                                        ;
+                                        ).await;
                                     }
                                     StoredNxsItem::NxsSyncGrpItem(item) => {
                                         // (to_retroshare_wire(&item), SUBTYPE_NXS_SYNC_GRP_ITEM)
@@ -441,8 +441,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
                                             SUBTYPE_NXS_SYNC_GRP_ITEM,
                                             &item,
                                             transaction.peer_id.to_owned(),
-                                        )
-                                        ;
+                                        ).await;
                                     }
                                 }
                             }
@@ -489,8 +488,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
                                 SUBTYPE_NXS_TRANSACTION_ITEM,
                                 &item,
                                 peer_id.to_owned(),
-                            )
-                            ;
+                            ).await;
 
                             info!("Starting -> Receiving {id}");
                             transaction.state = NxsTransactionState::Receiving;
@@ -535,8 +533,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
                                 SUBTYPE_NXS_TRANSACTION_ITEM,
                                 &item,
                                 peer_id.to_owned(),
-                            )
-                            ;
+                            ).await;
 
                             if self.process_transaction_for_decryption(transaction).await {
                                 to_remove.push(*id);
@@ -779,8 +776,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
             SUBTYPE_NXS_TRANSACTION_ITEM,
             &initial_packet,
             peer_id.to_owned(),
-        )
-        ;
+        ).await;
 
         Some(transaction_id)
     }
@@ -839,8 +835,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
             SUBTYPE_NXS_TRANSACTION_ITEM,
             &initial_packet,
             peer_id.to_owned(),
-        )
-        ;
+        ).await;
 
         Some(transaction_id)
     }
@@ -922,7 +917,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
             .insert(transaction_id, transaction_new);
 
         // send item
-        self.send_packet(SUBTYPE_NXS_TRANSACTION_ITEM, &initial_packet, peer_id);
+        self.send_packet(SUBTYPE_NXS_TRANSACTION_ITEM, &initial_packet, peer_id).await;
 
         transaction_id
     }
@@ -948,8 +943,7 @@ impl<const TYPE: u16> NxsTransactionController<TYPE> {
                     flag: 0,
                     sync_hash: "".into(),
                 };
-                self.send_packet(SUBTYPE_NXS_SYNC_GRP_REQ_ITEM, &item, peer_id)
-                    ;
+                self.send_packet(SUBTYPE_NXS_SYNC_GRP_REQ_ITEM, &item, peer_id).await;
 
                 result.push(transaction_id);
             }
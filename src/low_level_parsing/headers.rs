@@ -2,6 +2,7 @@ use std::fmt::Display;
 
 use crate::error::*;
 use byteorder::{BigEndian, ByteOrder, NetworkEndian};
+use log::warn;
 use retroshare_compat::services::ServiceType;
 
 pub const HEADER_SIZE: usize = 8;
@@ -149,6 +150,32 @@ impl Header {
     }
 }
 
+/// Splits a config file (a plain list of service or class items) into its records.
+///
+/// Parsing stops at the first invalid record, `name` is only used for logging.
+pub fn read_config_records(mut data: Vec<u8>, name: &str) -> Vec<(Header, Vec<u8>)> {
+    let mut records = vec![];
+
+    while data.len() >= HEADER_SIZE {
+        let header: [u8; HEADER_SIZE] = data[..HEADER_SIZE].try_into().unwrap();
+        let (header, size) = match Header::try_parse(&header) {
+            Ok(header @ (Header::Service { size, .. } | Header::Class { size, .. }))
+                if (HEADER_SIZE..=data.len()).contains(&(size as usize)) =>
+            {
+                (header, size as usize)
+            }
+            _ => {
+                warn!("failed to parse {name}");
+                break;
+            }
+        };
+        let payload = data.drain(..size).skip(HEADER_SIZE).collect();
+        records.push((header, payload));
+    }
+
+    records
+}
+
 impl From<SliceHeader> for Header {
     fn from(header: SliceHeader) -> Self {
         Header::Slice {
@@ -318,7 +345,7 @@ mod tests {
 
     use ::retroshare_compat::services::ServiceType;
 
-    use super::Header;
+    use super::{read_config_records, Header};
 
     fn gen_slice_probe() -> Vec<u8> {
         let header = Header::Service {
@@ -357,4 +384,32 @@ mod tests {
         let c = [0x02, 0xaa, 0xbb, 0xcc, 0x00, 0x00, 0x00, 0x08];
         assert_eq!(a, c);
     }
+
+    #[test]
+    fn config_records() {
+        let header = Header::Service {
+            service: ServiceType::Chat,
+            sub_type: 0x01,
+            size: 8 + 2,
+        };
+        let mut data = header.to_bytes().to_vec();
+        data.extend([1, 2]);
+
+        let records = read_config_records(data.to_owned(), "test");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].1, vec![1, 2]);
+
+        // a record must at least hold its header and fit into the data
+        for size in [0, 7, 11] {
+            let header = Header::Service {
+                service: ServiceType::Chat,
+                sub_type: 0x01,
+                size,
+            };
+            let mut broken = data.to_owned();
+            broken.extend(header.to_bytes());
+            broken.extend([1, 2]);
+            assert_eq!(read_config_records(broken, "test").len(), 1);
+        }
+    }
 }
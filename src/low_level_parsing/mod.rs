@@ -14,6 +14,8 @@ pub struct PacketInner {
     pub header: Header,
     pub payload: Vec<u8>,
     pub peer_id: Arc<SslId>,
+    /// QoS priority declared by the sending service, derived from the header otherwise
+    pub priority: Option<u8>,
 }
 
 /// Wraps a `PacketInner` in a `Box` to ensure heap usage
@@ -40,9 +42,15 @@ impl Packet {
             header,
             payload,
             peer_id: loc,
+            priority: None,
         }))
     }
 
+    pub fn with_priority(mut self, priority: u8) -> Packet {
+        self.priority = Some(priority);
+        self
+    }
+
     pub fn new_without_location(header: Header, payload: Vec<u8>) -> Packet {
         Packet::new(header, payload, Arc::new(SslId::default()))
     }
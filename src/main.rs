@@ -18,7 +18,8 @@
 // )]
 
 use controller::CoreController;
-use log::warn;
+use model::net::NetStore;
+use log::{info, warn};
 use std::{
     convert::TryInto,
     fs::File,
@@ -32,6 +33,7 @@ use tokio::select;
 
 use ::retroshare_compat::basics::*;
 
+mod channel;
 mod controller;
 mod error;
 mod gxs;
@@ -63,10 +65,11 @@ fn read_location_cert(path: &Path) -> Result<X509, io::Error> {
     Ok(openssl::x509::X509::from_pem(&user_cert)?)
 }
 
+const LOC_FOLDER_PREFIX: &str = "LOC06_";
+const LOC_FOLDER_PREFIX_HIDDEN: &str = "HID06_";
+
 #[allow(unused_braces)]
 fn select_location(base_dir: &Path, keys: &Keyring) -> Option<(String, X509, openpgp::cert::Cert)> {
-    const LOC_FOLDER_PREFIX: &str = "LOC06_";
-    const LOC_FOLDER_PREFIX_HIDDEN: &str = "HID06_";
 
     // build list with valid options
     let mut locations = vec![];
@@ -213,37 +216,50 @@ async fn main() {
     .expect("failed to load peers.cfg");
 
     // ... and peer infos
-    let friends = serial_stuff::load_peers(&mut peers_cfg, &keys);
+    let (friends, key_values) = serial_stuff::load_peers(&mut peers_cfg, &keys);
 
     // build own id
     let hex = hex::decode(&loc.0[6..]).expect("Decoding failed");
     let peer_id: [u8; 16] = hex.try_into().expect("failed to convert!"); // SSL_ID
     let peer_id = Arc::new(SslId(peer_id));
 
+    // ... and network settings
+    let net = NetStore::new(loc.0.starts_with(LOC_FOLDER_PREFIX_HIDDEN));
+    net.apply_key_values(&key_values);
+
+    // keep the port we had before (RS stores it with our addresses), otherwise stick to the random one
+    let own_port = friends
+        .1
+        .iter()
+        .find(|loc| loc.get_location_id() == peer_id)
+        .and_then(|loc| {
+            let ips = loc.get_ips();
+            ips.0
+                .iter()
+                .chain(ips.1.iter())
+                .map(|ip| ip.addr.0.port())
+                .find(|port| *port != 0)
+        });
+    if let Some(port) = own_port {
+        net.set_port(port);
+    }
+    info!("using port {}", net.get_port());
+
     // init data core
     // let data_core = model::DataCore::new(ssl_key, friends, peer_id).await;
 
     // enter main loop
-    let (mut core, data_core) = CoreController::new(ssl_key, friends, peer_id, gxs_id_db).await;
+    let (mut core, data_core) = CoreController::new(
+        ssl_key,
+        friends,
+        peer_id,
+        net,
+        gxs_id_db,
+        location_path.join("config"),
+    )
+    .await;
     let fut = core.run();
 
-    // let own_ips = data_core.get_own_location().get_ips().0.clone(); // TODO
-
-    // setup listener
-    // let port = own_ips
-    //     .iter()
-    //     .find(|ip| {
-    //         if let SocketAddr::V4(v4) = ip.addr.0 {
-    //             return v4.ip().is_private();
-    //         }
-    //         false
-    //     })
-    //     .expect("can't find local address!")
-    //     .addr
-    //     .0
-    //     .port();
-    // let _addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
-
     // setup webui
     let web = webui::actix::run_actix(data_core.clone());
 
@@ -1,9 +1,8 @@
 use std::{collections::HashSet, net::SocketAddr, sync::Arc};
 
 use retroshare_compat::{basics::SslId, events::EventType, services::service_info::RsServiceInfo, tlv::tlv_ip_addr::TlvIpAddressInfo};
-use tokio::net::TcpStream;
 
-use crate::low_level_parsing::Packet;
+use crate::{low_level_parsing::Packet, transport_ng::IncomingStream};
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -19,10 +18,13 @@ pub enum Intercom {
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum PeerThreadCommand {
-    Incoming(TcpStream),
+    /// an identified (but unverified) peer connected to us
+    Incoming(Arc<SslId>, SocketAddr, Box<IncomingStream>),
     Start,
     Stop,
     TryConnect,
+    /// (service) permissions changed, the peer must be sent an updated service list
+    UpdateServiceInfo,
 }
 
 #[derive(Clone, Debug)]
@@ -34,6 +36,8 @@ pub enum PeerUpdate {
         HashSet<TlvIpAddressInfo>,
         HashSet<TlvIpAddressInfo>,
     ),
+    /// Tor or I2P address (host, port)
+    HiddenAddress(Arc<SslId>, String, u16),
 }
 
 #[derive(Clone, Debug)]
@@ -20,6 +20,10 @@ pub struct Location {
 
     ips_local: RwLock<Vec<TlvIpAddressInfo>>,
     ips_external: RwLock<Vec<TlvIpAddressInfo>>,
+    /// Tor or I2P address (host, port)
+    hidden_addr: RwLock<Option<(String, u16)>>,
+    /// whether the location runs RS's DHT, only then it has a UDP stack to connect to
+    dht: RwLock<bool>,
 
     ip_connected: RwLock<Option<SocketAddr>>,
     last_connection_attempt: RwLock<Instant>,
@@ -42,6 +46,8 @@ impl Location {
 
             ips_local: RwLock::new(ips.0),
             ips_external: RwLock::new(ips.1),
+            hidden_addr: RwLock::new(None),
+            dht: RwLock::new(false),
 
             ip_connected: RwLock::new(None),
             last_connection_attempt: RwLock::new(
@@ -83,6 +89,35 @@ impl Location {
         (local, external)
     }
 
+    pub fn get_hidden_addr(&self) -> Option<(String, u16)> {
+        self.hidden_addr
+            .read()
+            .expect("failed to get hidden address, lock poisoned!")
+            .to_owned()
+    }
+
+    pub fn set_hidden_addr(&self, addr: Option<(String, u16)>) {
+        let mut hidden_addr = self
+            .hidden_addr
+            .write()
+            .expect("failed to get hidden address, lock poisoned!");
+        if *hidden_addr != addr {
+            // like a new ip address, trigger a reconnect
+            *self.last_connection_attempt.write().unwrap() = Instant::now()
+                .checked_sub(PEER_CONNECTION_TRY_DURATION)
+                .unwrap();
+            *hidden_addr = addr;
+        }
+    }
+
+    pub fn has_dht(&self) -> bool {
+        *self.dht.read().expect("failed to get dht, lock poisoned!")
+    }
+
+    pub fn set_dht(&self, dht: bool) {
+        *self.dht.write().expect("failed to get dht, lock poisoned!") = dht;
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -164,6 +199,7 @@ impl Location {
             issuer: *self.peer.to_owned(),
             fpr: peer.get_pgp().fingerprint().as_bytes().to_owned().into(),
             // gpg_signers: Vec<PgpId>,
+            service_perm_flags: peer.get_service_perm_flags(),
             ..Default::default()
         }
     }
@@ -1,30 +1,63 @@
 use getset::Getters;
 use log::{debug, trace, warn};
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{mpsc::UnboundedSender, Mutex, MutexGuard};
-
-use retroshare_compat::{basics::SslId, events::EventType};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tokio::sync::Mutex;
+
+use retroshare_compat::{
+    basics::SslId,
+    config::NodeGroupItem,
+    events::EventType,
+    services::ServiceType,
+    tlv::tlv_ip_addr::{TlvIpAddressInfo, TlvIpAddressInfoInner},
+};
 
 use crate::{
-    gxs::gxs_backend::GxsShared, low_level_parsing::Packet, retroshare_compat::ssl_key::SslKey,
+    channel::{channel, Policy, Receiver, Sender, TrySendError},
+    gxs::gxs_backend::GxsShared,
+    low_level_parsing::{headers::Header, Packet},
+    retroshare_compat::{config_store, ssl_key::SslKey},
 };
 
 use self::{
-    intercom::Intercom,
+    intercom::{Intercom, PeerThreadCommand, PeerUpdate},
     location::Location,
+    net::{NetStore, NET_CONFIG_FILE},
     person::Peer,
-    services::{chat::ChatStore, gxs_id::GxsIdStore},
+    services::{
+        banlist::BanListStore,
+        bwctrl::BwCtrlStore,
+        chat::ChatStore,
+        discovery::DiscoveryStore,
+        grouter::GRouterStore,
+        gxs_id::GxsIdStore,
+        gxs_tunnel::GxsTunnelStore,
+        mail::MailStore,
+        service_control::{ServiceControlStore, ServicePermissions, SERVICE_CONTROL_CONFIG_FILE},
+        turtle::TurtleStore,
+    },
 };
 
 pub mod gxs_timestamps;
 pub mod intercom;
 pub mod location;
+pub mod net;
 pub mod person;
 pub mod services;
 
+/// Queue size of each event subscriber, old events are dropped.
+const EVENTS_QUEUE_SIZE: usize = 256;
+/// Queue size of each webui client, old events are dropped.
+const WEBUI_CLIENT_QUEUE_SIZE: usize = 256;
+
 pub struct ConnectedPeerEntries<TASK>(
-    pub HashMap<Arc<SslId>, (UnboundedSender<Intercom>, tokio::task::JoinHandle<TASK>)>,
+    pub HashMap<Arc<SslId>, (Sender<Intercom>, tokio::task::JoinHandle<TASK>)>,
 );
 
 impl<TASK> Default for ConnectedPeerEntries<TASK> {
@@ -35,17 +68,41 @@ impl<TASK> Default for ConnectedPeerEntries<TASK> {
 
 #[derive(Getters)]
 pub struct DataCoreServiceStore {
+    #[getset(get = "pub")]
+    banlist: BanListStore,
+    #[getset(get = "pub")]
+    bwctrl: BwCtrlStore,
     #[getset(get = "pub")]
     chat: ChatStore,
     #[getset(get = "pub")]
+    discovery: DiscoveryStore,
+    #[getset(get = "pub")]
+    grouter: GRouterStore,
+    #[getset(get = "pub")]
     gxs_id: GxsIdStore,
+    #[getset(get = "pub")]
+    gxs_tunnel: GxsTunnelStore,
+    #[getset(get = "pub")]
+    mail: MailStore,
+    #[getset(get = "pub")]
+    service_control: ServiceControlStore,
+    #[getset(get = "pub")]
+    turtle: TurtleStore,
 }
 
 impl DataCoreServiceStore {
     pub fn new(gxs_shared_id: Arc<GxsShared>) -> Self {
         DataCoreServiceStore {
+            banlist: BanListStore::new(),
+            bwctrl: BwCtrlStore::new(),
             chat: ChatStore::new(),
+            discovery: DiscoveryStore::new(),
+            grouter: GRouterStore::new(),
             gxs_id: GxsIdStore::new(gxs_shared_id),
+            gxs_tunnel: GxsTunnelStore::new(),
+            mail: MailStore::new(),
+            service_control: ServiceControlStore::new(),
+            turtle: TurtleStore::new(),
         }
     }
 }
@@ -53,12 +110,16 @@ impl DataCoreServiceStore {
 pub struct DataCore {
     own_key_pair: SslKey,
     own_location: Arc<Location>,
+    /// location's config directory
+    config_dir: PathBuf,
 
-    event_listener: Mutex<Vec<UnboundedSender<Intercom>>>,
-    webui_clients: Mutex<Vec<UnboundedSender<Value>>>,
+    event_listener: Mutex<Vec<Sender<Intercom>>>,
+    webui_clients: Mutex<Vec<Sender<Value>>>,
 
     peers: Vec<Arc<Peer>>,
-    locations: Vec<Arc<Location>>,
+    /// grows when discovery finds new locations of our friends
+    locations: RwLock<Vec<Arc<Location>>>,
+    net: NetStore,
 
     // gxs_dbs: Vec<Mutex<GxsDatabase>>,
     // gxs_ids: HashMap<GxsId, TlvSecurityKeySet>,
@@ -70,9 +131,11 @@ pub struct DataCore {
 impl DataCore {
     pub async fn new(
         keys: SslKey,
-        friends: (Vec<Arc<Peer>>, Vec<Arc<Location>>),
+        friends: (Vec<Arc<Peer>>, Vec<Arc<Location>>, Vec<NodeGroupItem>),
         peer_id: Arc<SslId>,
+        net: NetStore,
         gxs_shared_id: Arc<GxsShared>,
+        config_dir: PathBuf,
     ) -> Arc<DataCore> {
         let me = friends
             .1
@@ -84,9 +147,11 @@ impl DataCore {
             let mut dc = DataCore {
                 own_key_pair: keys,
                 own_location: me.clone(),
+                config_dir,
 
                 peers: friends.0,
-                locations: friends.1,
+                locations: RwLock::new(friends.1),
+                net,
 
                 event_listener: Mutex::new(vec![]),
                 webui_clients: Mutex::new(vec![]),
@@ -98,12 +163,27 @@ impl DataCore {
                 // services: RwLock::new(DataCoreServiceStore::default()),
                 services: DataCoreServiceStore::new(gxs_shared_id),
             };
-            dc.init().await;
+            dc.init(friends.2).await;
             dc
         })
     }
 
-    async fn init(&mut self) {
+    async fn init(&mut self, groups: Vec<NodeGroupItem>) {
+        let service_control = self.services.service_control();
+        service_control.set_peers(
+            self.get_locations()
+                .iter()
+                .map(|loc| (*loc.get_location_id(), *loc.get_person().get_pgp_id()))
+                .collect(),
+            groups,
+        );
+        if let Some(data) = self.load_config(SERVICE_CONTROL_CONFIG_FILE) {
+            service_control.load_config(data);
+        }
+        if let Some(data) = self.load_config(NET_CONFIG_FILE) {
+            self.net.load_config(data);
+        }
+
         // use retroshare_compat::gxs::GxsType::*;
 
         // for gxs in &self.gxs_dbs {
@@ -137,8 +217,62 @@ impl DataCore {
         &self.own_key_pair
     }
 
+    pub fn get_config_dir(&self) -> &PathBuf {
+        &self.config_dir
+    }
+
+    /// Loads (and decrypts) a config file from the location's config directory.
+    pub fn load_config(&self, name: &str) -> Option<Vec<u8>> {
+        let path = self.config_dir.join(name);
+        if !path.exists() {
+            return None;
+        }
+        config_store::decrypt_file(&path, self.own_key_pair.to_owned()).ok()
+    }
+
+    /// Stores (and encrypts) a config file in the location's config directory.
+    ///
+    /// RS signs its config files, so do not overwrite any of RS's files!
+    pub fn save_config(&self, name: &str, data: &[u8]) -> bool {
+        match config_store::encrypt_file(
+            &self.config_dir.join(name),
+            self.own_key_pair.to_owned(),
+            data,
+        ) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("failed to save config {name}: {err}");
+                false
+            }
+        }
+    }
+
     pub fn get_locations(&self) -> Vec<Arc<Location>> {
-        self.locations.clone()
+        self.locations
+            .read()
+            .expect("failed to get locations, lock poisoned!")
+            .clone()
+    }
+
+    /// Adds a new location of a known friend, returns `false` when the location is already known.
+    pub fn add_location(&self, loc: Arc<Location>) -> bool {
+        let mut locations = self
+            .locations
+            .write()
+            .expect("failed to get locations, lock poisoned!");
+        if locations
+            .iter()
+            .any(|known| known.get_location_id() == loc.get_location_id())
+        {
+            return false;
+        }
+
+        self.services
+            .service_control()
+            .add_location(*loc.get_location_id(), *loc.get_person().get_pgp_id());
+        loc.get_person().add_location(loc.to_owned());
+        locations.push(loc);
+        true
     }
 
     pub fn get_location_by_id(&self, ssl_id: Arc<SslId>) -> Option<Arc<Location>> {
@@ -151,12 +285,31 @@ impl DataCore {
         self.peers.clone()
     }
 
-    pub async fn events_subscribe(&self, receiver: UnboundedSender<Intercom>) {
-        self.event_listener.lock().await.push(receiver);
+    pub fn get_net(&self) -> &NetStore {
+        &self.net
     }
 
-    pub async fn get_subscribers(&self) -> MutexGuard<'_, Vec<UnboundedSender<Intercom>>> {
-        self.event_listener.lock().await
+    /// Subscribes to peer updates and events, `name` identifies the subscriber's queue.
+    pub async fn events_subscribe(&self, name: &str) -> Receiver<Intercom> {
+        let (tx, rx) = channel(
+            format!("events {name}"),
+            EVENTS_QUEUE_SIZE,
+            Policy::DropOldest,
+        );
+        self.event_listener.lock().await.push(tx);
+        rx
+    }
+
+    /// Sends a message (built by `msg` for each subscriber) to all subscribers, ended subscribers are removed.
+    pub async fn notify_subscribers(&self, msg: impl Fn() -> Intercom) {
+        let mut subscribers = self.event_listener.lock().await;
+        subscribers.retain(|subscriber| !subscriber.is_closed());
+        for subscriber in subscribers.iter() {
+            // subscribers ending right now are removed next time
+            if let Err(TrySendError::Full(_)) = subscriber.try_send(msg()) {
+                warn!("[core] subscriber queue full, dropping message");
+            }
+        }
     }
 
     pub async fn is_online(&self, peer_id: Arc<SslId>) -> bool {
@@ -167,18 +320,117 @@ impl DataCore {
         &self.connected_peers
     }
 
+    /// Updates (and stores) a service's permissions, connected peers are sent an updated service list.
+    pub async fn update_service_permissions(
+        &self,
+        service: ServiceType,
+        permissions: ServicePermissions,
+    ) -> bool {
+        let service_control = self.services.service_control();
+        service_control.set_permissions(service, permissions);
+        let saved = self.save_config(SERVICE_CONTROL_CONFIG_FILE, &service_control.save_config());
+
+        for (tx, _) in self.connected_peers.lock().await.0.values() {
+            tx.try_send(Intercom::Thread(PeerThreadCommand::UpdateServiceInfo))
+                .unwrap_or_else(|_| {
+                    warn!("[core] failed to send to peer worker");
+                });
+        }
+
+        saved
+    }
+
+    /// Updates our own location with our current addresses (see `NetStore`), returns `true` when they changed.
+    ///
+    /// Changes are announced, see `announce_own_addresses`.
+    pub async fn update_own_addresses(&self) -> bool {
+        let seen_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("time went backwards")
+            .as_secs();
+        let to_info = |addr: SocketAddr| -> TlvIpAddressInfo {
+            TlvIpAddressInfoInner {
+                addr: addr.into(),
+                seen_time,
+                source: 0,
+            }
+            .into()
+        };
+        let local = self.net.get_local_addrs();
+
+        let external = {
+            let ips = self.own_location.get_ips();
+            let addrs = |ips: &Vec<TlvIpAddressInfo>| -> Vec<SocketAddr> {
+                ips.iter().map(|ip| ip.addr.0).collect()
+            };
+            // keep the known ones (e.g. from peers.cfg) until friends tell us otherwise
+            let external = match self.net.get_ext_addr() {
+                Some(addr) => vec![addr],
+                None => addrs(&ips.1),
+            };
+            if addrs(&ips.0) == local && addrs(&ips.1) == external {
+                return false;
+            }
+            external
+        };
+
+        {
+            let mut ips = self.own_location.get_ips_mut();
+            *ips.0 = local.into_iter().map(to_info).collect();
+            *ips.1 = external.into_iter().map(to_info).collect();
+        }
+        self.announce_own_addresses().await;
+
+        true
+    }
+
+    /// Announces our own location's addresses to subscribers as `PeerUpdate::Address`, e.g. to republish them.
+    pub async fn announce_own_addresses(&self) {
+        let update = {
+            let ips = self.own_location.get_ips();
+            PeerUpdate::Address(
+                self.own_location.get_location_id(),
+                ips.0.iter().cloned().collect(),
+                ips.1.iter().cloned().collect(),
+            )
+        };
+        self.notify_subscribers(|| Intercom::PeerUpdate(update.clone()))
+            .await;
+    }
+
+    /// Sends a packet to its location or, without location, to all connected peers.
+    ///
+    /// Peers that didn't announce the packet's service are skipped. Peers that don't take their packets are
+    /// disconnected (see `ConnectionBuilder`).
     pub async fn try_send_to_peer(&self, packet: Packet) {
         // lock peers once
 
+        let service_control = self.services.service_control();
+        let is_active = |peer_id: &SslId| match packet.header {
+            Header::Service { service, .. } => service_control.is_active(service, peer_id),
+            _ => true,
+        };
+
         if packet.has_location() {
             debug!("sending to peer {}", packet.peer_id());
+            if !is_active(&packet.peer_id) {
+                debug!(
+                    "not sending {:?} to {}, peer doesn't run the service",
+                    packet.header,
+                    packet.peer_id()
+                );
+                return;
+            }
             self.send_to_peer(packet).await;
         } else {
             debug!("sending to <all>");
             for peer in &self.connected_peers.lock().await.0 {
+                if !is_active(peer.0) {
+                    continue;
+                }
                 let mut item = packet.to_owned();
                 item.peer_id = peer.0.to_owned();
-                peer.1 .0.send(Intercom::Send(item)).unwrap_or_else(|_| {
+                peer.1 .0.try_send(Intercom::Send(item)).unwrap_or_else(|_| {
                     warn!("[core] failed to send to peer worker");
                 });
             }
@@ -196,16 +448,17 @@ impl DataCore {
             .0
             .entry(to)
             .and_modify(|(tx, _)| {
-                tx.send(Intercom::Send(packet)).unwrap_or_else(|_| {
+                tx.try_send(Intercom::Send(packet)).unwrap_or_else(|_| {
                     warn!("[core] failed to send to peer worker");
                 })
             });
     }
 
-    pub async fn webui_add_client(&self, tx: UnboundedSender<Value>) {
+    pub async fn webui_add_client(&self) -> Receiver<Value> {
         trace!("webui_add_client");
 
-        tx.send(json!({
+        let (tx, rx) = channel("webui client", WEBUI_CLIENT_QUEUE_SIZE, Policy::DropOldest);
+        tx.try_send(json!({
             "retval":{
                 "errorNumber": 0,
                 "errorCategory": "generic",
@@ -214,6 +467,7 @@ impl DataCore {
         }))
         .expect("failed to send");
         self.webui_clients.lock().await.push(tx);
+        rx
     }
 
     pub async fn webui_send(&self, event: EventType) {
@@ -223,7 +477,7 @@ impl DataCore {
 
         let mut ok_clients = Vec::new();
         for client in self.webui_clients.lock().await.iter() {
-            let result = client.send(msg.to_owned());
+            let result = client.try_send(msg.to_owned());
 
             if let Ok(()) = result {
                 ok_clients.push(client.to_owned());
@@ -2,7 +2,10 @@
 use std::string::String;
 use std::sync::{RwLock, RwLockReadGuard, Arc};
 
-use retroshare_compat::basics::*;
+use retroshare_compat::{
+    basics::*,
+    config::{PeerBandwidthLimits, ServicePermissionFlags},
+};
 use sequoia_openpgp as openpgp;
 
 use crate::model::location::Location;
@@ -11,6 +14,8 @@ pub struct Peer {
     name: String,
     pgp_cert: openpgp::Cert,
     pgp_id: PgpId,
+    service_perm_flags: RwLock<ServicePermissionFlags>,
+    bandwidth_limits: RwLock<PeerBandwidthLimits>,
 
     locations: RwLock<Vec<Arc<Location>>>,
 }
@@ -21,6 +26,8 @@ impl Peer {
             name,
             pgp_cert: cert,
             pgp_id,
+            service_perm_flags: RwLock::new(ServicePermissionFlags::DEFAULT),
+            bandwidth_limits: RwLock::new(PeerBandwidthLimits::default()),
             locations: RwLock::new(vec![]),
         }
     }
@@ -37,6 +44,22 @@ impl Peer {
         &self.pgp_cert
     }
 
+    pub fn get_service_perm_flags(&self) -> ServicePermissionFlags {
+        *self.service_perm_flags.read().unwrap()
+    }
+
+    pub fn set_service_perm_flags(&self, flags: ServicePermissionFlags) {
+        *self.service_perm_flags.write().unwrap() = flags;
+    }
+
+    pub fn get_bandwidth_limits(&self) -> PeerBandwidthLimits {
+        *self.bandwidth_limits.read().unwrap()
+    }
+
+    pub fn set_bandwidth_limits(&self, limits: PeerBandwidthLimits) {
+        *self.bandwidth_limits.write().unwrap() = limits;
+    }
+
     pub fn get_locations(&self) -> RwLockReadGuard<Vec<Arc<Location>>> {
         self.locations.read().unwrap()
     }
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, RwLock},
+    time::SystemTime,
+};
+
+use retroshare_compat::{
+    basics::SslId,
+    services::banlist::{TlvBanListEntry, TlvBanListEntryInner},
+};
+
+use crate::channel::Sender;
+
+/// A banned address (or IPv4 range).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BanEntry {
+    pub addr: IpAddr,
+    /// number of (IPv4) bytes that are masked, 0 bans a single address, 1 a /24 and 2 a /16 range
+    pub masked_bytes: u8,
+    /// `BANLIST_REASON_*`
+    pub reason: u32,
+    /// `BANLIST_ORIGIN_*`
+    pub origin: u32,
+    pub comment: String,
+    /// unix time the entry was added
+    pub added: u64,
+    /// unix time the entry expires, `None` for permanent entries
+    pub expires: Option<u64>,
+}
+
+impl BanEntry {
+    /// Returns the address with the masked bytes cleared.
+    pub fn masked(addr: &IpAddr, masked_bytes: u8) -> IpAddr {
+        match addr.to_canonical() {
+            IpAddr::V4(addr) => {
+                let mut octets = addr.octets();
+                for octet in octets.iter_mut().rev().take(masked_bytes.min(2) as usize) {
+                    *octet = 0;
+                }
+                IpAddr::from(octets)
+            }
+            addr => addr,
+        }
+    }
+
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        Self::masked(addr, self.masked_bytes) == Self::masked(&self.addr, self.masked_bytes)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Converts an entry received from a friend, `age` is relative to `now`.
+    pub fn from_tlv(entry: TlvBanListEntryInner, origin: u32, now: u64) -> Self {
+        BanEntry {
+            addr: entry.addr.0.ip(),
+            masked_bytes: entry.masked_bytes,
+            reason: entry.reason,
+            origin,
+            comment: entry.comment.into(),
+            added: now.saturating_sub(entry.age as u64),
+            expires: None,
+        }
+    }
+
+    pub fn to_tlv(&self, now: u64) -> TlvBanListEntry {
+        TlvBanListEntry::new(TlvBanListEntryInner {
+            addr: SocketAddr::new(self.addr, 0).into(),
+            level: self.origin,
+            reason: self.reason,
+            age: now.saturating_sub(self.added) as u32,
+            masked_bytes: self.masked_bytes,
+            comment: self.comment.to_owned().into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BanListSettings {
+    /// refuse connections to and from banned addresses
+    pub enabled: bool,
+    /// also apply the lists our friends are sharing with us
+    pub use_friends: bool,
+    /// share our own list with our friends
+    pub share: bool,
+}
+
+impl Default for BanListSettings {
+    fn default() -> Self {
+        BanListSettings {
+            enabled: true,
+            use_friends: false,
+            share: true,
+        }
+    }
+}
+
+/// Shared ban list state.
+///
+/// Connections check addresses with `is_banned`, changes go through the BanList service with `BanListCmd`.
+#[derive(Debug, Default)]
+pub struct BanListStore {
+    cmd: Mutex<Option<Sender<BanListCmd>>>,
+
+    settings: RwLock<BanListSettings>,
+    /// our own bans
+    own: RwLock<Vec<BanEntry>>,
+    /// bans our friends shared with us
+    friends: RwLock<HashMap<SslId, Vec<BanEntry>>>,
+}
+
+impl BanListStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_cmd(&self, tx: Sender<BanListCmd>) {
+        *self.cmd.lock().expect("failed to get cmd, lock poisoned!") = Some(tx);
+    }
+
+    /// Sends a command to the BanList service, returns `false` when the BanList service is not running or its queue is full.
+    pub fn send_cmd(&self, cmd: BanListCmd) -> bool {
+        match &*self.cmd.lock().expect("failed to get cmd, lock poisoned!") {
+            Some(tx) => tx.try_send(cmd).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn get_settings(&self) -> BanListSettings {
+        *self
+            .settings
+            .read()
+            .expect("failed to get settings, lock poisoned!")
+    }
+
+    pub fn set_settings(&self, settings: BanListSettings) {
+        *self
+            .settings
+            .write()
+            .expect("failed to get settings, lock poisoned!") = settings;
+    }
+
+    pub fn get_own(&self) -> Vec<BanEntry> {
+        self.own
+            .read()
+            .expect("failed to get own bans, lock poisoned!")
+            .to_owned()
+    }
+
+    /// Adds (or replaces) one of our own bans.
+    pub fn add_own(&self, entry: BanEntry) {
+        let mut own = self
+            .own
+            .write()
+            .expect("failed to get own bans, lock poisoned!");
+        own.retain(|e| !(e.addr == entry.addr && e.masked_bytes == entry.masked_bytes));
+        own.push(entry);
+    }
+
+    /// Removes one of our own bans, returns `false` when there was no such ban.
+    pub fn remove_own(&self, addr: &IpAddr, masked_bytes: u8) -> bool {
+        let mut own = self
+            .own
+            .write()
+            .expect("failed to get own bans, lock poisoned!");
+        let len = own.len();
+        own.retain(|e| !(e.addr == *addr && e.masked_bytes == masked_bytes));
+        own.len() != len
+    }
+
+    pub fn get_friends(&self) -> HashMap<SslId, Vec<BanEntry>> {
+        self.friends
+            .read()
+            .expect("failed to get friend bans, lock poisoned!")
+            .to_owned()
+    }
+
+    pub fn set_friend(&self, peer_id: SslId, entries: Vec<BanEntry>) {
+        self.friends
+            .write()
+            .expect("failed to get friend bans, lock poisoned!")
+            .insert(peer_id, entries);
+    }
+
+    /// Drops expired own bans and friend bans added before `friends_before`, returns `true` when something was removed.
+    pub fn expire(&self, now: u64, friends_before: u64) -> bool {
+        let mut removed = false;
+
+        let mut own = self
+            .own
+            .write()
+            .expect("failed to get own bans, lock poisoned!");
+        let len = own.len();
+        own.retain(|e| !e.is_expired(now));
+        removed |= own.len() != len;
+
+        let mut friends = self
+            .friends
+            .write()
+            .expect("failed to get friend bans, lock poisoned!");
+        for entries in friends.values_mut() {
+            let len = entries.len();
+            entries.retain(|e| e.added >= friends_before);
+            removed |= entries.len() != len;
+        }
+        friends.retain(|_, entries| !entries.is_empty());
+
+        removed
+    }
+
+    /// Returns the ban that covers `addr` (if any), always `None` when filtering is disabled.
+    pub fn is_banned(&self, addr: &IpAddr) -> Option<BanEntry> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.is_banned_at(addr, now)
+    }
+
+    fn is_banned_at(&self, addr: &IpAddr, now: u64) -> Option<BanEntry> {
+        let settings = self.get_settings();
+        if !settings.enabled {
+            return None;
+        }
+
+        if let Some(entry) = self
+            .own
+            .read()
+            .expect("failed to get own bans, lock poisoned!")
+            .iter()
+            .find(|e| !e.is_expired(now) && e.contains(addr))
+        {
+            return Some(entry.to_owned());
+        }
+
+        if settings.use_friends {
+            return self
+                .friends
+                .read()
+                .expect("failed to get friend bans, lock poisoned!")
+                .values()
+                .flatten()
+                .find(|e| e.contains(addr))
+                .cloned();
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum BanListCmd {
+    Ban(BanEntry),
+    Unban { addr: IpAddr, masked_bytes: u8 },
+    SetSettings(BanListSettings),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use retroshare_compat::services::banlist::{BANLIST_ORIGIN_SELF, BANLIST_REASON_USER};
+
+    use super::{BanEntry, BanListSettings, BanListStore};
+
+    fn entry(addr: &str, masked_bytes: u8, expires: Option<u64>) -> BanEntry {
+        BanEntry {
+            addr: addr.parse().unwrap(),
+            masked_bytes,
+            reason: BANLIST_REASON_USER,
+            origin: BANLIST_ORIGIN_SELF,
+            comment: String::new(),
+            added: 0,
+            expires,
+        }
+    }
+
+    #[test]
+    fn is_banned() {
+        let store = BanListStore::new();
+        store.add_own(entry("10.1.2.3", 1, None));
+        store.add_own(entry("192.168.0.1", 0, Some(100)));
+        store.set_friend(vec![1; 16].into(), vec![entry("172.16.5.5", 2, None)]);
+
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(store.is_banned_at(&ip("10.1.2.200"), 0).is_some());
+        assert!(store.is_banned_at(&ip("::ffff:10.1.2.200"), 0).is_some());
+        assert!(store.is_banned_at(&ip("10.1.3.1"), 0).is_none());
+        assert!(store.is_banned_at(&ip("192.168.0.1"), 50).is_some());
+        assert!(store.is_banned_at(&ip("192.168.0.1"), 100).is_none());
+
+        // friend lists are only used when enabled
+        assert!(store.is_banned_at(&ip("172.16.1.1"), 0).is_none());
+        store.set_settings(BanListSettings {
+            use_friends: true,
+            ..Default::default()
+        });
+        assert!(store.is_banned_at(&ip("172.16.1.1"), 0).is_some());
+
+        assert!(store.remove_own(&ip("10.1.2.3"), 1));
+        assert!(store.is_banned_at(&ip("10.1.2.200"), 0).is_none());
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    time::Duration,
+};
+
+use retroshare_compat::{
+    basics::{PgpId, SslId},
+    config::PeerBandwidthLimits,
+    services::ServiceType,
+};
+
+use crate::channel::Sender;
+
+pub const BWCTRL_CONFIG_FILE: &str = "rustyshare_bwctrl.cfg";
+
+// RS's defaults (in kB/s)
+const DEFAULT_MAX_UP_RATE_KBS: u32 = 10_000;
+const DEFAULT_MAX_DL_RATE_KBS: u32 = 10_000;
+
+/// Splits `total_kbs` evenly between `peers`, capped by the friend's own limit.
+///
+/// Both limits are in kB/s where 0 means unlimited, returns bytes/s or `None` for unlimited.
+pub fn share_rate(total_kbs: u32, peers: usize, friend_kbs: u32) -> Option<u32> {
+    let total = (total_kbs != 0).then(|| total_kbs.saturating_mul(1024) / peers.max(1) as u32);
+    let friend = (friend_kbs != 0).then(|| friend_kbs.saturating_mul(1024));
+
+    match (total, friend) {
+        (Some(total), Some(friend)) => Some(total.min(friend)),
+        (total, friend) => total.or(friend),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+impl TrafficStats {
+    fn add(&mut self, other: &TrafficStats) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.packets_in += other.packets_in;
+        self.packets_out += other.packets_out;
+    }
+}
+
+/// Traffic of a peer that is not merged into the statistics yet.
+///
+/// Each peer task counts into its own counter, `BwCtrlStore::update` merges them. The lock is only shared with the
+/// merging.
+#[derive(Debug, Default)]
+pub struct TrafficCounter {
+    services: Mutex<HashMap<ServiceType, TrafficStats>>,
+}
+
+impl TrafficCounter {
+    pub fn record_in(&self, service: ServiceType, bytes: usize) {
+        let mut services = self.lock();
+        let stats = services.entry(service).or_default();
+        stats.bytes_in += bytes as u64;
+        stats.packets_in += 1;
+    }
+
+    /// Records a sent slice, `last` marks the final slice of a packet.
+    pub fn record_out(&self, service: ServiceType, bytes: usize, last: bool) {
+        let mut services = self.lock();
+        let stats = services.entry(service).or_default();
+        stats.bytes_out += bytes as u64;
+        stats.packets_out += last as u64;
+    }
+
+    fn take(&self) -> HashMap<ServiceType, TrafficStats> {
+        mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ServiceType, TrafficStats>> {
+        self.services
+            .lock()
+            .expect("failed to get traffic, lock poisoned!")
+    }
+}
+
+/// Bandwidth state of a connected peer, rates are in bytes/s.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PeerBandwidth {
+    /// what the peer allows us to send, `None` until it told us
+    pub allowed_out: Option<u32>,
+    /// what we allow the peer to send
+    pub allowed_in: Option<u32>,
+    /// what we actually send at most, `None` for unlimited
+    pub send_rate: Option<u32>,
+
+    pub stats: TrafficStats,
+    /// measured over the last update interval
+    pub rate_in: f32,
+    pub rate_out: f32,
+    last: TrafficStats,
+}
+
+/// Bandwidth limits and traffic statistics.
+///
+/// Peer workers record their traffic and limit their sending by `get_send_rate`, the BwCtrl service updates the rates.
+#[derive(Debug)]
+pub struct BwCtrlStore {
+    cmd: Mutex<Option<Sender<BwCtrlCmd>>>,
+
+    /// our total limits
+    max_rates: RwLock<PeerBandwidthLimits>,
+    peers: RwLock<HashMap<SslId, PeerBandwidth>>,
+    services: RwLock<HashMap<ServiceType, TrafficStats>>,
+    /// counters of the running peer tasks
+    counters: Mutex<HashMap<SslId, Arc<TrafficCounter>>>,
+}
+
+impl Default for BwCtrlStore {
+    fn default() -> Self {
+        BwCtrlStore {
+            cmd: Mutex::new(None),
+            max_rates: RwLock::new(PeerBandwidthLimits {
+                max_up_rate_kbs: DEFAULT_MAX_UP_RATE_KBS,
+                max_dl_rate_kbs: DEFAULT_MAX_DL_RATE_KBS,
+            }),
+            peers: RwLock::new(HashMap::new()),
+            services: RwLock::new(HashMap::new()),
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl BwCtrlStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_cmd(&self, tx: Sender<BwCtrlCmd>) {
+        *self.cmd.lock().expect("failed to get cmd, lock poisoned!") = Some(tx);
+    }
+
+    /// Sends a command to the BwCtrl service, returns `false` when the BwCtrl service is not running or its queue is full.
+    pub fn send_cmd(&self, cmd: BwCtrlCmd) -> bool {
+        match &*self.cmd.lock().expect("failed to get cmd, lock poisoned!") {
+            Some(tx) => tx.try_send(cmd).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn get_max_rates(&self) -> PeerBandwidthLimits {
+        *self
+            .max_rates
+            .read()
+            .expect("failed to get max rates, lock poisoned!")
+    }
+
+    pub fn set_max_rates(&self, rates: PeerBandwidthLimits) {
+        *self
+            .max_rates
+            .write()
+            .expect("failed to get max rates, lock poisoned!") = rates;
+    }
+
+    pub fn get_peers(&self) -> HashMap<SslId, PeerBandwidth> {
+        self.peers
+            .read()
+            .expect("failed to get peers, lock poisoned!")
+            .to_owned()
+    }
+
+    pub fn get_services(&self) -> HashMap<ServiceType, TrafficStats> {
+        self.services
+            .read()
+            .expect("failed to get services, lock poisoned!")
+            .to_owned()
+    }
+
+    pub fn remove_peer(&self, peer_id: &SslId) {
+        self.peers
+            .write()
+            .expect("failed to get peers, lock poisoned!")
+            .remove(peer_id);
+    }
+
+    /// Rate (in bytes/s) we may send to `peer_id`, `None` for unlimited.
+    pub fn get_send_rate(&self, peer_id: &SslId) -> Option<u32> {
+        self.peers
+            .read()
+            .expect("failed to get peers, lock poisoned!")
+            .get(peer_id)
+            .and_then(|peer| peer.send_rate)
+    }
+
+    /// Stores the rate the peer allows us to send, 0 is taken as unlimited.
+    pub fn set_allowed_out(&self, peer_id: SslId, rate: u32) {
+        self.peers
+            .write()
+            .expect("failed to get peers, lock poisoned!")
+            .entry(peer_id)
+            .or_default()
+            .allowed_out = (rate != 0).then_some(rate);
+    }
+
+    /// Returns the counter a peer task records its traffic with.
+    pub fn traffic_counter(&self, peer_id: &SslId) -> Arc<TrafficCounter> {
+        self.counters
+            .lock()
+            .expect("failed to get counters, lock poisoned!")
+            .entry(peer_id.to_owned())
+            .or_default()
+            .to_owned()
+    }
+
+    /// Merges the recorded traffic, recomputes the rates of all connected peers (with their friend limits) and measures
+    /// their traffic over `elapsed`.
+    ///
+    /// Returns the rate each peer is allowed to send us.
+    pub fn update(
+        &self,
+        connected: &[(SslId, PeerBandwidthLimits)],
+        elapsed: Duration,
+    ) -> Vec<(SslId, Option<u32>)> {
+        let max_rates = self.get_max_rates();
+        let mut peers = self
+            .peers
+            .write()
+            .expect("failed to get peers, lock poisoned!");
+
+        {
+            let mut services = self
+                .services
+                .write()
+                .expect("failed to get services, lock poisoned!");
+            // counters of ended peer tasks are dropped once merged
+            self.counters
+                .lock()
+                .expect("failed to get counters, lock poisoned!")
+                .retain(|peer_id, counter| {
+                    for (service, traffic) in counter.take() {
+                        peers
+                            .entry(peer_id.to_owned())
+                            .or_default()
+                            .stats
+                            .add(&traffic);
+                        services.entry(service).or_default().add(&traffic);
+                    }
+                    Arc::strong_count(counter) > 1
+                });
+        }
+
+        peers.retain(|peer_id, _| connected.iter().any(|(id, _)| id == peer_id));
+
+        let secs = elapsed.as_secs_f32().max(f32::EPSILON);
+        connected
+            .iter()
+            .map(|(peer_id, limits)| {
+                let peer = peers.entry(peer_id.to_owned()).or_default();
+
+                peer.allowed_in = share_rate(
+                    max_rates.max_dl_rate_kbs,
+                    connected.len(),
+                    limits.max_dl_rate_kbs,
+                );
+                let up = share_rate(
+                    max_rates.max_up_rate_kbs,
+                    connected.len(),
+                    limits.max_up_rate_kbs,
+                );
+                peer.send_rate = match (up, peer.allowed_out) {
+                    (Some(up), Some(allowed)) => Some(up.min(allowed)),
+                    (up, allowed) => up.or(allowed),
+                };
+
+                peer.rate_in = (peer.stats.bytes_in - peer.last.bytes_in) as f32 / secs;
+                peer.rate_out = (peer.stats.bytes_out - peer.last.bytes_out) as f32 / secs;
+                peer.last = peer.stats;
+
+                (peer_id.to_owned(), peer.allowed_in)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum BwCtrlCmd {
+    /// our total limits
+    SetMaxRates(PeerBandwidthLimits),
+    /// a friend's limits
+    SetPeerMaxRates(PgpId, PeerBandwidthLimits),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use retroshare_compat::{basics::SslId, config::PeerBandwidthLimits, services::ServiceType};
+
+    use super::{share_rate, BwCtrlStore};
+
+    #[test]
+    fn rates() {
+        assert_eq!(share_rate(0, 3, 0), None);
+        assert_eq!(share_rate(30, 3, 0), Some(10 * 1024));
+        assert_eq!(share_rate(30, 3, 5), Some(5 * 1024));
+        assert_eq!(share_rate(0, 3, 5), Some(5 * 1024));
+
+        let alice: SslId = vec![1; 16].into();
+        let bob: SslId = vec![2; 16].into();
+        let store = BwCtrlStore::new();
+        store.set_max_rates(PeerBandwidthLimits {
+            max_up_rate_kbs: 100,
+            max_dl_rate_kbs: 0,
+        });
+        store.set_allowed_out(alice, 1024);
+        // 0 doesn't stop sending
+        store.set_allowed_out(bob, 0);
+        let alice_traffic = store.traffic_counter(&alice);
+        alice_traffic.record_out(ServiceType::Chat, 1024, false);
+        alice_traffic.record_out(ServiceType::Chat, 1024, true);
+        let bob_traffic = store.traffic_counter(&bob);
+        bob_traffic.record_in(ServiceType::Chat, 100);
+
+        let connected = [
+            (alice, PeerBandwidthLimits::default()),
+            (
+                bob,
+                PeerBandwidthLimits {
+                    max_up_rate_kbs: 0,
+                    max_dl_rate_kbs: 8,
+                },
+            ),
+        ];
+        let allowed = store.update(&connected, Duration::from_secs(2));
+        assert_eq!(allowed, vec![(alice, None), (bob, Some(8 * 1024))]);
+
+        // the peer's allowed rate wins
+        assert_eq!(store.get_send_rate(&alice), Some(1024));
+        assert_eq!(store.get_send_rate(&bob), Some(50 * 1024));
+
+        let peers = store.get_peers();
+        assert_eq!(peers[&alice].rate_out, 1024.0);
+        assert_eq!(peers[&bob].stats.packets_in, 1);
+        assert_eq!(peers[&alice].stats.packets_out, 1);
+        assert_eq!(store.get_services()[&ServiceType::Chat].bytes_out, 2048);
+
+        // disconnected peers are dropped, their last traffic is still counted
+        bob_traffic.record_in(ServiceType::Chat, 100);
+        drop(bob_traffic);
+        store.update(&connected[..1], Duration::from_secs(2));
+        assert_eq!(store.get_send_rate(&bob), None);
+        assert_eq!(store.get_peers()[&alice].rate_out, 0.0);
+        assert_eq!(store.get_services()[&ServiceType::Chat].bytes_in, 200);
+        assert_eq!(store.counters.lock().unwrap().len(), 1);
+    }
+}
@@ -1,18 +1,25 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, VecDeque},
     sync::Arc,
     time::{Duration, SystemTime},
 };
 
 use retroshare_compat::{
-    basics::{GxsId, PeerId},
+    basics::{DistantChatPeerId, GxsId, PeerId},
     services::chat::{
-        ChatId, ChatLobbyFlags, ChatLobbyId, ChatLobbyInviteItem, ChatLobbyMsgId,
-        VisibleChatLobbyInfo,
+        ChatId, ChatLobbyFlags, ChatLobbyId, ChatLobbyInviteItem, ChatLobbyMsgId, ChatMsgItem,
+        SubscribedChatLobbyConfigItem, VisibleChatLobbyInfo,
+    },
+    webui::chat::{
+        ChatLobbyBans, ChatLobbyInfo, ChatLobbyInfoGxsIds, ChatLobbyInvite, ChatLobbyModeration,
+        ChatLobbyParticipant, ChatLobbySubscription, VisibleChatLobbyRecord,
     },
-    webui::chat::{ChatLobbyInfo, ChatLobbyInfoGxsIds, VisibleChatLobbyRecord},
 };
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use tokio::sync::{oneshot, Mutex, RwLock};
+
+use crate::channel::Sender;
+
+use super::chat_history::ChatHistory;
 
 // use crate::services::chat::CHAT_MAX_KEEP_MSG_RECORD;
 
@@ -27,7 +34,23 @@ TODO refactor this code to:
 #[derive(Debug)]
 pub struct ChatStore {
     pub lobbies: RwLock<HashMap<ChatLobbyId, Lobby>>,
-    pub cmd: RwLock<Option<UnboundedSender<ChatCmd>>>,
+    pub cmd: RwLock<Option<Sender<ChatCmd>>>,
+    /// private messages waiting for their peer to come online
+    pub pending_private: RwLock<Vec<(Arc<PeerId>, ChatMsgItem)>>,
+    /// received parts of split up private messages
+    pub partial_private: RwLock<HashMap<Arc<PeerId>, String>>,
+    /// lobby invites waiting for the user to accept or deny them
+    pub invites: RwLock<HashMap<ChatLobbyId, (Arc<PeerId>, ChatLobbyInviteItem)>>,
+    /// lobbies we subscribed to, these are stored and restored on startup
+    pub subscriptions: RwLock<HashMap<ChatLobbyId, LobbySubscription>>,
+    /// ban and mute lists, reputation threshold and flood protection
+    pub moderation: RwLock<LobbyModeration>,
+    /// our own avatar (jpeg), empty when not set
+    pub own_avatar: RwLock<Vec<u8>>,
+    /// avatars of our friends
+    pub avatars: RwLock<HashMap<Arc<PeerId>, PeerAvatar>>,
+    /// persistent chat history, `None` when the database couldn't be opened
+    pub history: Mutex<Option<ChatHistory>>,
     // shared: Mutex<Vec<AppRequest<ChatLobbyId, Lobby>>>, // TODO
 }
 
@@ -36,11 +59,197 @@ impl ChatStore {
         Self {
             lobbies: RwLock::new(HashMap::new()),
             cmd: RwLock::new(None),
+            pending_private: RwLock::new(vec![]),
+            partial_private: RwLock::new(HashMap::new()),
+            invites: RwLock::new(HashMap::new()),
+            subscriptions: RwLock::new(HashMap::new()),
+            moderation: RwLock::new(LobbyModeration::default()),
+            own_avatar: RwLock::new(vec![]),
+            avatars: RwLock::new(HashMap::new()),
+            history: Mutex::new(None),
             // shared: Mutex::new(vec![]),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LobbySubscription {
+    /// identity used in the lobby
+    pub gxs_id: GxsId,
+    /// (re)join the lobby on startup or once it becomes visible
+    pub auto_join: bool,
+    /// whether the user wants to be notified about new messages, this is up to the web ui
+    pub notify: bool,
+}
+
+impl LobbySubscription {
+    pub fn new(gxs_id: GxsId) -> Self {
+        Self {
+            gxs_id,
+            auto_join: true,
+            notify: true,
+        }
+    }
+
+    pub fn flags(&self) -> ChatLobbyFlags {
+        let mut flags = ChatLobbyFlags::empty();
+        flags.set(ChatLobbyFlags::LOBBY_AUTO_SUBSCRIBE, self.auto_join);
+        flags.set(ChatLobbyFlags::LOBBY_NOTIFY, self.notify);
+        flags
+    }
+
+    pub fn to_chat_lobby_subscription(self, lobby_id: ChatLobbyId) -> ChatLobbySubscription {
+        ChatLobbySubscription {
+            lobby_id: lobby_id.into(),
+            gxs_id: self.gxs_id.into(),
+            auto_subscribe: self.auto_join,
+            notify: self.notify,
+        }
+    }
+}
+
+impl From<(GxsId, ChatLobbyFlags)> for LobbySubscription {
+    fn from((gxs_id, flags): (GxsId, ChatLobbyFlags)) -> Self {
+        Self {
+            gxs_id,
+            auto_join: flags.contains(ChatLobbyFlags::LOBBY_AUTO_SUBSCRIBE),
+            notify: flags.contains(ChatLobbyFlags::LOBBY_NOTIFY),
+        }
+    }
+}
+
+/// Maximum size of an avatar in bytes (same as RS).
+pub const MAX_AVATAR_SIZE: usize = 32767;
+/// Minimum time between two avatar requests to the same friend.
+const AVATAR_REQUEST_INTERVAL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+pub struct PeerAvatar {
+    /// jpeg data, empty until received
+    pub data: Vec<u8>,
+    pub last_update: Option<SystemTime>,
+    /// last time we asked for the avatar
+    pub last_request: Option<SystemTime>,
+    /// whether the friend has received our current avatar
+    pub own_sent: bool,
+}
+
+impl PeerAvatar {
+    /// Returns `true` (and notes the request) when the avatar is missing and wasn't requested recently.
+    pub fn should_request(&mut self) -> bool {
+        if !self.data.is_empty() {
+            return false;
+        }
+
+        let now = SystemTime::now();
+        match self.last_request {
+            Some(ts) if now.duration_since(ts).unwrap_or_default() < AVATAR_REQUEST_INTERVAL => {
+                false
+            }
+            _ => {
+                self.last_request = Some(now);
+                true
+            }
+        }
+    }
+}
+
+/// Number of messages a single identity may send within `FLOOD_PERIOD` (same as RS).
+const FLOOD_MAX_MESSAGES: usize = 5;
+const FLOOD_PERIOD: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct LobbyModeration {
+    /// identities with a lower overall reputation score are dropped
+    pub min_reputation: i32,
+    /// identities banned from all lobbies
+    pub banned: HashSet<GxsId>,
+    /// identities banned from a single lobby
+    pub lobby_banned: HashMap<ChatLobbyId, HashSet<GxsId>>,
+    /// identities whose messages are still relayed but not shown (local only)
+    pub muted: HashSet<GxsId>,
+
+    /// recent message timestamps per sender
+    flood: HashMap<GxsId, VecDeque<SystemTime>>,
+}
+
+impl LobbyModeration {
+    pub fn is_banned(&self, lobby_id: &ChatLobbyId, gxs_id: &GxsId) -> bool {
+        self.banned.contains(gxs_id)
+            || self
+                .lobby_banned
+                .get(lobby_id)
+                .is_some_and(|banned| banned.contains(gxs_id))
+    }
+
+    pub fn is_muted(&self, gxs_id: &GxsId) -> bool {
+        self.muted.contains(gxs_id)
+    }
+
+    /// Bans (or unbans) an identity, globally when `lobby_id` is `None`.
+    pub fn set_banned(&mut self, lobby_id: Option<ChatLobbyId>, gxs_id: GxsId, banned: bool) {
+        let set = match lobby_id {
+            Some(lobby_id) => self.lobby_banned.entry(lobby_id).or_default(),
+            None => &mut self.banned,
+        };
+        if banned {
+            set.insert(gxs_id);
+        } else {
+            set.remove(&gxs_id);
+        }
+        self.lobby_banned.retain(|_, banned| !banned.is_empty());
+    }
+
+    pub fn set_muted(&mut self, gxs_id: GxsId, muted: bool) {
+        if muted {
+            self.muted.insert(gxs_id);
+        } else {
+            self.muted.remove(&gxs_id);
+        }
+    }
+
+    /// Records a message from `gxs_id`, returns `false` when the sender exceeds the flood limit.
+    pub fn check_flood(&mut self, gxs_id: &GxsId, now: SystemTime) -> bool {
+        let history = self.flood.entry(*gxs_id).or_default();
+        while let Some(ts) = history.front() {
+            match now.duration_since(*ts) {
+                Ok(age) if age >= FLOOD_PERIOD => _ = history.pop_front(),
+                _ => break,
+            }
+        }
+        if history.len() >= FLOOD_MAX_MESSAGES {
+            return false;
+        }
+        history.push_back(now);
+        true
+    }
+
+    pub fn maintain(&mut self) {
+        let now = SystemTime::now();
+        self.flood.retain(|_, history| {
+            history
+                .back()
+                .is_some_and(|ts| now.duration_since(*ts).unwrap_or_default() < FLOOD_PERIOD)
+        });
+    }
+
+    pub fn to_chat_lobby_moderation(&self) -> ChatLobbyModeration {
+        ChatLobbyModeration {
+            min_reputation: self.min_reputation,
+            banned: self.banned.iter().map(|id| (*id).into()).collect(),
+            lobby_banned: self
+                .lobby_banned
+                .iter()
+                .map(|(lobby_id, banned)| ChatLobbyBans {
+                    lobby_id: (*lobby_id).into(),
+                    gxs_ids: banned.iter().map(|id| (*id).into()).collect(),
+                })
+                .collect(),
+            muted: self.muted.iter().map(|id| (*id).into()).collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Lobby {
     pub lobby_id: ChatLobbyId,
@@ -51,7 +260,7 @@ pub struct Lobby {
     /// tracks our own friends/peers
     pub participating_friends: HashMap<Arc<PeerId>, SystemTime>,
     /// tracks lobby participants
-    pub participants: HashMap<Arc<GxsId>, SystemTime>,
+    pub participants: HashMap<Arc<GxsId>, LobbyParticipant>,
 
     pub total_number_of_peers: u32,
     /// maps to both `last_report_time` and `last_activity`
@@ -63,7 +272,39 @@ pub struct Lobby {
     pub msg_cache: HashMap<ChatLobbyMsgId, SystemTime>,
 }
 
+#[derive(Debug, Clone)]
+pub struct LobbyParticipant {
+    pub nickname: String,
+    /// last status string, e.g. "is typing..."
+    pub status: String,
+    pub last_activity: SystemTime,
+}
+
+impl LobbyParticipant {
+    pub fn new(nickname: String) -> Self {
+        Self {
+            nickname,
+            status: String::new(),
+            last_activity: SystemTime::now(),
+        }
+    }
+}
+
 impl Lobby {
+    /// Marks a lobby participant as active, adding it when unknown.
+    pub fn touch_participant(
+        &mut self,
+        gxs_id: Arc<GxsId>,
+        nickname: &str,
+    ) -> &mut LobbyParticipant {
+        let participant = self
+            .participants
+            .entry(gxs_id)
+            .or_insert_with(|| LobbyParticipant::new(nickname.to_owned()));
+        participant.last_activity = SystemTime::now();
+        participant
+    }
+
     pub fn update_participant(&mut self, peer: Arc<PeerId>) {
         *self
             .participating_friends
@@ -109,6 +350,60 @@ impl From<VisibleChatLobbyInfo> for Lobby {
     }
 }
 
+impl From<ChatLobbyInviteItem> for Lobby {
+    fn from(x: ChatLobbyInviteItem) -> Self {
+        VisibleChatLobbyInfo {
+            id: x.lobby_id,
+            name: x.lobby_name,
+            topic: x.lobby_topic,
+            count: 0,
+            flags: x.lobby_flags,
+        }
+        .into()
+    }
+}
+
+impl From<SubscribedChatLobbyConfigItem> for Lobby {
+    fn from(x: SubscribedChatLobbyConfigItem) -> Self {
+        let now = SystemTime::now();
+
+        Self {
+            participating_friends: x
+                .participating_friends
+                .into_iter()
+                .map(|peer| (Arc::new(peer), now))
+                .collect(),
+            participants: [(Arc::new(x.gxs_id), LobbyParticipant::new(String::new()))].into(),
+            joined: true,
+            gxs_id: Some(x.gxs_id),
+            ..Lobby::from(VisibleChatLobbyInfo {
+                id: x.lobby_id,
+                name: x.lobby_name,
+                topic: x.lobby_topic,
+                count: 0,
+                flags: x.lobby_flags,
+            })
+        }
+    }
+}
+
+impl From<&Lobby> for SubscribedChatLobbyConfigItem {
+    fn from(lobby: &Lobby) -> Self {
+        Self {
+            lobby_id: lobby.lobby_id,
+            lobby_name: lobby.lobby_name.to_owned().into(),
+            lobby_topic: lobby.lobby_topic.to_owned().into(),
+            participating_friends: lobby
+                .participating_friends
+                .keys()
+                .map(|peer| **peer)
+                .collect(),
+            gxs_id: lobby.gxs_id.unwrap_or_default(),
+            lobby_flags: lobby.lobby_flags,
+        }
+    }
+}
+
 impl From<&Lobby> for ChatLobbyInviteItem {
     fn from(lobby: &Lobby) -> Self {
         Self {
@@ -163,10 +458,14 @@ impl From<&Lobby> for ChatLobbyInfo {
             gxs_ids: lobby
                 .participants
                 .iter()
-                .map(|(peer, time)| {
+                .map(|(peer, participant)| {
                     (
                         *peer.to_owned(),
-                        time.duration_since(SystemTime::UNIX_EPOCH).unwrap().into(),
+                        participant
+                            .last_activity
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .into(),
                     )
                 })
                 .map(|(peer, time)| ChatLobbyInfoGxsIds {
@@ -179,6 +478,20 @@ impl From<&Lobby> for ChatLobbyInfo {
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
                 .into(),
+            participants: lobby
+                .participants
+                .iter()
+                .map(|(gxs_id, participant)| ChatLobbyParticipant {
+                    gxs_id: (**gxs_id).into(),
+                    nickname: participant.nickname.to_owned(),
+                    status: participant.status.to_owned(),
+                    last_activity: participant
+                        .last_activity
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .into(),
+                })
+                .collect(),
         }
     }
 }
@@ -195,9 +508,133 @@ impl From<&Lobby> for VisibleChatLobbyInfo {
     }
 }
 
+/// Converts a pending invite (and the inviting peer) for the web ui.
+pub fn to_chat_lobby_invite(peer_id: &PeerId, invite: &ChatLobbyInviteItem) -> ChatLobbyInvite {
+    ChatLobbyInvite {
+        lobby_id: invite.lobby_id.into(),
+        peer_id: (*peer_id).into(),
+        lobby_name: invite.lobby_name.to_owned().into(),
+        lobby_topic: invite.lobby_topic.to_owned().into(),
+        lobby_flags: invite.lobby_flags,
+    }
+}
+
 #[allow(dead_code)]
 pub enum ChatCmd {
     SendMessage(ChatId, String),
+    SendStatus(ChatId, String),
+    /// Joins a lobby with the given id, keeps the subscription's settings when there is one.
     JoinLobby(ChatLobbyId, GxsId),
+    /// Leaves a lobby and removes the subscription.
     LeaveLobby(ChatLobbyId),
+    /// Changes a subscription's settings, `None` keeps the current value.
+    SetLobbySubscription {
+        lobby_id: ChatLobbyId,
+        auto_join: Option<bool>,
+        notify: Option<bool>,
+    },
+    /// Creates a new lobby, joins it with the given id and invites the given friends.
+    CreateLobby {
+        name: String,
+        topic: String,
+        flags: ChatLobbyFlags,
+        gxs_id: GxsId,
+        invited: Vec<PeerId>,
+        tx: oneshot::Sender<ChatLobbyId>,
+    },
+    /// Bans (or unbans) an identity from a lobby or, when no lobby is given, from all lobbies.
+    SetBanned {
+        lobby_id: Option<ChatLobbyId>,
+        gxs_id: GxsId,
+        banned: bool,
+    },
+    SetMuted(GxsId, bool),
+    SetMinReputation(i32),
+    /// Sets our own avatar (jpeg), an empty one removes it.
+    SetOwnAvatar(Vec<u8>),
+    /// Asks a friend for their avatar.
+    RequestAvatar(PeerId),
+    InviteToLobby(ChatLobbyId, PeerId),
+    AcceptLobbyInvite(ChatLobbyId, GxsId),
+    DenyLobbyInvite(ChatLobbyId),
+    /// Opens a distant chat to the first id, using the second (own) id.
+    InitiateDistantChat(GxsId, GxsId),
+    CloseDistantChat(DistantChatPeerId),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use retroshare_compat::{
+        basics::GxsId,
+        serde::{from_retroshare_wire, to_retroshare_wire},
+        services::chat::{
+            ChatLobbyConfigItem, ChatLobbyFlags, SubscribedChatLobbyConfigItem,
+            VisibleChatLobbyInfo,
+        },
+    };
+
+    use super::{Lobby, LobbyModeration, LobbySubscription, FLOOD_MAX_MESSAGES, FLOOD_PERIOD};
+
+    #[test]
+    fn moderation() {
+        let mut moderation = LobbyModeration::default();
+        let gxs_id = GxsId::default();
+
+        moderation.set_banned(Some(1), gxs_id, true);
+        assert!(moderation.is_banned(&1, &gxs_id));
+        assert!(!moderation.is_banned(&2, &gxs_id));
+        moderation.set_banned(None, gxs_id, true);
+        assert!(moderation.is_banned(&2, &gxs_id));
+        moderation.set_banned(None, gxs_id, false);
+        moderation.set_banned(Some(1), gxs_id, false);
+        assert!(!moderation.is_banned(&1, &gxs_id));
+        assert!(moderation.lobby_banned.is_empty());
+
+        let now = SystemTime::now();
+        for _ in 0..FLOOD_MAX_MESSAGES {
+            assert!(moderation.check_flood(&gxs_id, now));
+        }
+        assert!(!moderation.check_flood(&gxs_id, now));
+        let later = now + FLOOD_PERIOD + Duration::from_secs(1);
+        assert!(moderation.check_flood(&gxs_id, later));
+    }
+
+    #[test]
+    fn subscription_config() {
+        let gxs_id: GxsId = "c59df722f56f2f886ac301acc5572e03".into();
+        let lobby = Lobby::from(VisibleChatLobbyInfo {
+            id: 42,
+            name: "lobby".to_string().into(),
+            topic: "topic".to_string().into(),
+            count: 0,
+            flags: ChatLobbyFlags::LOBBY_PUBLIC,
+        });
+
+        for (auto_join, notify) in [(true, true), (true, false), (false, true), (false, false)] {
+            let subscription = LobbySubscription {
+                gxs_id,
+                auto_join,
+                notify,
+            };
+
+            // same items as the chat service saves
+            let mut flags = to_retroshare_wire(&ChatLobbyConfigItem {
+                lobby_id: lobby.lobby_id,
+                flags: subscription.flags(),
+            });
+            let mut item: SubscribedChatLobbyConfigItem = (&lobby).into();
+            item.gxs_id = subscription.gxs_id;
+            let mut item = to_retroshare_wire(&item);
+
+            let flags: ChatLobbyConfigItem = from_retroshare_wire(&mut flags);
+            let item: SubscribedChatLobbyConfigItem = from_retroshare_wire(&mut item);
+            assert_eq!(flags.lobby_id, item.lobby_id);
+            assert_eq!(
+                LobbySubscription::from((item.gxs_id, flags.flags)),
+                subscription
+            );
+        }
+    }
 }
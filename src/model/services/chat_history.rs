@@ -0,0 +1,477 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use log::debug;
+use retroshare_compat::{
+    basics::{DistantChatPeerId, PeerId},
+    events::ChatMessage,
+    services::chat::{ChatId, ChatIdType, ChatLobbyId},
+};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use serde::Serialize;
+
+const TABLE_MESSAGES: &str = "messages";
+const TABLE_MESSAGES_FTS: &str = "messages_fts";
+const TABLE_RETENTION: &str = "retention";
+
+/// Used when no limits were set for a chat, 0 means unlimited.
+pub const DEFAULT_MAX_MESSAGES: u32 = 1000;
+/// Used when no limits were set for a chat, 0 means unlimited. (same as RS)
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(10 * 24 * 60 * 60);
+
+// class HistoryMsg
+// {
+// public:
+//     uint32_t msgId;
+//     RsPeerId chatPeerId;
+//     bool incoming;
+//     RsPeerId peerId;
+//     std::string peerName;
+//     uint32_t sendTime;
+//     uint32_t recvTime;
+//     std::string message;
+// };
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryMsg {
+    pub msg_id: i64,
+    pub chat_peer_id: ChatId,
+    pub incoming: bool,
+    /// author, either a gxs id (lobbies, distant chat) or a peer id
+    pub peer_id: String,
+    pub peer_name: String,
+    pub send_time: u32,
+    pub recv_time: u32,
+    pub message: String,
+}
+
+impl HistoryMsg {
+    fn from_row(row: &Row) -> Result<Option<Self>> {
+        let chat_id: String = row.get(1)?;
+        Ok(chat_id_from_key(&chat_id).map(|chat_peer_id| HistoryMsg {
+            msg_id: row.get(0).unwrap_or_default(),
+            chat_peer_id,
+            incoming: row.get(2).unwrap_or_default(),
+            peer_id: row.get(3).unwrap_or_default(),
+            peer_name: row.get(4).unwrap_or_default(),
+            send_time: row.get(5).unwrap_or_default(),
+            recv_time: row.get(6).unwrap_or_default(),
+            message: row.get(7).unwrap_or_default(),
+        }))
+    }
+}
+
+impl From<&ChatMessage> for HistoryMsg {
+    fn from(msg: &ChatMessage) -> Self {
+        let peer_id = match msg.chat_id.ty {
+            ChatIdType::TypeLobby | ChatIdType::TypePrivateDistant => {
+                msg.lobby_peer_gxs_id.to_string()
+            }
+            ChatIdType::TypeBroadcast => msg.broadcast_peer_id.to_string(),
+            _ => msg.chat_id.peer_id.to_string(),
+        };
+
+        HistoryMsg {
+            msg_id: 0,
+            chat_peer_id: msg.chat_id.to_owned(),
+            incoming: msg.incoming,
+            peer_id,
+            peer_name: msg.peer_alternate_nickname.to_owned(),
+            send_time: msg.send_time,
+            recv_time: msg.recv_time,
+            message: msg.msg.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionLimits {
+    /// number of messages to keep, 0 means unlimited
+    pub max_messages: u32,
+    /// 0 means unlimited
+    pub max_age: Duration,
+}
+
+impl Default for RetentionLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: DEFAULT_MAX_MESSAGES,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+}
+
+/// Maps a `ChatId` to the key used in the database, `None` for invalid ids.
+pub fn chat_id_to_key(chat_id: &ChatId) -> Option<String> {
+    match chat_id.ty {
+        ChatIdType::TypeLobby => {
+            let lobby_id: ChatLobbyId = chat_id.lobby_id.to_owned().into();
+            Some(format!("lobby:{lobby_id}"))
+        }
+        ChatIdType::TypePrivate => Some(format!("peer:{}", *chat_id.peer_id)),
+        ChatIdType::TypePrivateDistant => Some(format!("distant:{}", *chat_id.distant_chat_id)),
+        ChatIdType::TypeBroadcast => Some(String::from("broadcast")),
+        ChatIdType::TypeNotSet => None,
+    }
+}
+
+fn chat_id_from_key(key: &str) -> Option<ChatId> {
+    let (ty, id) = key.split_once(':').unwrap_or((key, ""));
+    match ty {
+        "lobby" => id.parse::<ChatLobbyId>().ok().map(|id| id.into()),
+        "peer" if id.len() == 32 => Some(PeerId::from(id).into()),
+        "distant" if id.len() == 32 => Some(DistantChatPeerId::from(id).into()),
+        "broadcast" => Some(ChatId {
+            ty: ChatIdType::TypeBroadcast,
+            ..Default::default()
+        }),
+        _ => None,
+    }
+}
+
+/// Turns user input into a FTS query, every word must match (as a prefix).
+fn to_fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+/// Stores lobby, direct and distant chat messages.
+#[derive(Debug)]
+pub struct ChatHistory {
+    db: Connection,
+}
+
+impl ChatHistory {
+    pub fn new_file(path: PathBuf, passwd: &str) -> Result<Self> {
+        let db = Connection::open(path)?;
+        if !passwd.is_empty() {
+            db.pragma_update(None, "key", passwd)?;
+        }
+
+        let db = ChatHistory { db };
+        db.create_tables()?;
+        Ok(db)
+    }
+
+    #[allow(dead_code)]
+    pub fn new_mem() -> Result<Self> {
+        let db = ChatHistory {
+            db: Connection::open_in_memory()?,
+        };
+        db.create_tables()?;
+        Ok(db)
+    }
+
+    fn create_tables(&self) -> Result<()> {
+        self.db.execute_batch(&format!(
+            "BEGIN;
+            CREATE TABLE IF NOT EXISTS {TABLE_MESSAGES} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                chat_id TEXT NOT NULL,
+                incoming INT,
+                peer_id TEXT,
+                peer_name TEXT,
+                send_time INT,
+                recv_time INT,
+                message TEXT
+            );
+            CREATE INDEX IF NOT EXISTS {TABLE_MESSAGES}_chat_id ON {TABLE_MESSAGES} (chat_id, recv_time);
+
+            CREATE VIRTUAL TABLE IF NOT EXISTS {TABLE_MESSAGES_FTS} USING fts5(
+                message, content='{TABLE_MESSAGES}', content_rowid='id'
+            );
+            CREATE TRIGGER IF NOT EXISTS {TABLE_MESSAGES}_insert AFTER INSERT ON {TABLE_MESSAGES} BEGIN
+                INSERT INTO {TABLE_MESSAGES_FTS} (rowid, message) VALUES (new.id, new.message);
+            END;
+            CREATE TRIGGER IF NOT EXISTS {TABLE_MESSAGES}_delete AFTER DELETE ON {TABLE_MESSAGES} BEGIN
+                INSERT INTO {TABLE_MESSAGES_FTS} ({TABLE_MESSAGES_FTS}, rowid, message) VALUES ('delete', old.id, old.message);
+            END;
+
+            CREATE TABLE IF NOT EXISTS {TABLE_RETENTION} (
+                chat_id TEXT PRIMARY KEY,
+                max_messages INT,
+                max_age INT
+            );
+            COMMIT;"
+        ))
+    }
+
+    /// Adds a message and enforces the chat's message limit, returns the message id.
+    pub fn add_message(&self, msg: &HistoryMsg) -> Result<Option<i64>> {
+        let chat_id = match chat_id_to_key(&msg.chat_peer_id) {
+            Some(chat_id) => chat_id,
+            None => return Ok(None),
+        };
+
+        self.db.execute(
+            &format!(
+                "INSERT INTO {TABLE_MESSAGES} (chat_id, incoming, peer_id, peer_name, send_time, recv_time, message)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+            ),
+            params![
+                chat_id,
+                msg.incoming,
+                msg.peer_id,
+                msg.peer_name,
+                msg.send_time,
+                msg.recv_time,
+                msg.message
+            ],
+        )?;
+        let id = self.db.last_insert_rowid();
+
+        let limits = self.get_retention(&msg.chat_peer_id)?;
+        if limits.max_messages > 0 {
+            let removed = self.db.execute(
+                &format!(
+                    "DELETE FROM {TABLE_MESSAGES} WHERE chat_id = ?1 AND id NOT IN (
+                        SELECT id FROM {TABLE_MESSAGES} WHERE chat_id = ?1 ORDER BY recv_time DESC, id DESC LIMIT ?2
+                    )"
+                ),
+                params![chat_id, limits.max_messages],
+            )?;
+            if removed > 0 {
+                debug!("removed {removed} old message(s) from {chat_id}");
+            }
+        }
+
+        Ok(Some(id))
+    }
+
+    /// Returns up to `count` messages, skipping the `offset` most recent ones, oldest first.
+    pub fn get_messages(
+        &self,
+        chat_id: &ChatId,
+        count: u32,
+        offset: u32,
+    ) -> Result<Vec<HistoryMsg>> {
+        let chat_id = match chat_id_to_key(chat_id) {
+            Some(chat_id) => chat_id,
+            None => return Ok(vec![]),
+        };
+
+        let mut stm = self.db.prepare(&format!(
+            "SELECT id, chat_id, incoming, peer_id, peer_name, send_time, recv_time, message FROM {TABLE_MESSAGES}
+            WHERE chat_id = ?1 ORDER BY recv_time DESC, id DESC LIMIT ?2 OFFSET ?3"
+        ))?;
+        let mut msgs: Vec<_> = stm
+            .query_map(params![chat_id, count, offset], HistoryMsg::from_row)?
+            .filter_map(|msg| msg.ok().flatten())
+            .collect();
+        msgs.reverse();
+        Ok(msgs)
+    }
+
+    /// Full text search, optionally limited to one chat, most recent first.
+    pub fn search(
+        &self,
+        chat_id: Option<&ChatId>,
+        query: &str,
+        count: u32,
+        offset: u32,
+    ) -> Result<Vec<HistoryMsg>> {
+        let query = to_fts_query(query);
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        // empty matches all chats
+        let chat_id = chat_id.and_then(chat_id_to_key).unwrap_or_default();
+
+        let mut stm = self.db.prepare(&format!(
+            "SELECT m.id, m.chat_id, m.incoming, m.peer_id, m.peer_name, m.send_time, m.recv_time, m.message
+            FROM {TABLE_MESSAGES_FTS} f JOIN {TABLE_MESSAGES} m ON m.id = f.rowid
+            WHERE {TABLE_MESSAGES_FTS} MATCH ?1 AND (?2 = '' OR m.chat_id = ?2)
+            ORDER BY m.recv_time DESC, m.id DESC LIMIT ?3 OFFSET ?4"
+        ))?;
+        let msgs = stm
+            .query_map(params![query, chat_id, count, offset], HistoryMsg::from_row)?
+            .filter_map(|msg| msg.ok().flatten())
+            .collect();
+        Ok(msgs)
+    }
+
+    pub fn remove_messages(&self, msg_ids: &[i64]) -> Result<()> {
+        let mut stm = self
+            .db
+            .prepare(&format!("DELETE FROM {TABLE_MESSAGES} WHERE id = ?1"))?;
+        for msg_id in msg_ids {
+            stm.execute([msg_id])?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&self, chat_id: &ChatId) -> Result<()> {
+        if let Some(chat_id) = chat_id_to_key(chat_id) {
+            self.db.execute(
+                &format!("DELETE FROM {TABLE_MESSAGES} WHERE chat_id = ?1"),
+                [chat_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn get_retention(&self, chat_id: &ChatId) -> Result<RetentionLimits> {
+        let chat_id = match chat_id_to_key(chat_id) {
+            Some(chat_id) => chat_id,
+            None => return Ok(RetentionLimits::default()),
+        };
+
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT max_messages, max_age FROM {TABLE_RETENTION} WHERE chat_id = ?1"),
+                [chat_id],
+                |row| {
+                    Ok(RetentionLimits {
+                        max_messages: row.get(0)?,
+                        max_age: Duration::from_secs(row.get(1)?),
+                    })
+                },
+            )
+            .optional()?
+            .unwrap_or_default())
+    }
+
+    /// Sets the chat's limits and applies them.
+    pub fn set_retention(&self, chat_id: &ChatId, limits: RetentionLimits) -> Result<()> {
+        let key = match chat_id_to_key(chat_id) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        self.db.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {TABLE_RETENTION} (chat_id, max_messages, max_age) VALUES (?1, ?2, ?3)"
+            ),
+            params![key, limits.max_messages, limits.max_age.as_secs()],
+        )?;
+
+        if limits.max_messages > 0 {
+            self.db.execute(
+                &format!(
+                    "DELETE FROM {TABLE_MESSAGES} WHERE chat_id = ?1 AND id NOT IN (
+                        SELECT id FROM {TABLE_MESSAGES} WHERE chat_id = ?1 ORDER BY recv_time DESC, id DESC LIMIT ?2
+                    )"
+                ),
+                params![key, limits.max_messages],
+            )?;
+        }
+        self.remove_expired()
+    }
+
+    /// Removes all messages that are older than their chat's age limit.
+    pub fn remove_expired(&self) -> Result<()> {
+        let removed = self.db.execute(
+            &format!(
+                "DELETE FROM {TABLE_MESSAGES} WHERE id IN (
+                    SELECT m.id FROM {TABLE_MESSAGES} m LEFT JOIN {TABLE_RETENTION} r ON r.chat_id = m.chat_id
+                    WHERE COALESCE(r.max_age, ?1) > 0 AND m.recv_time < ?2 - COALESCE(r.max_age, ?1)
+                )"
+            ),
+            params![DEFAULT_MAX_AGE.as_secs(), now()],
+        )?;
+        if removed > 0 {
+            debug!("removed {removed} expired message(s)");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use retroshare_compat::{basics::PeerId, services::chat::ChatId};
+
+    use super::{chat_id_from_key, chat_id_to_key, now, ChatHistory, HistoryMsg, RetentionLimits};
+
+    fn msg(chat_id: &ChatId, message: &str, recv_time: u32) -> HistoryMsg {
+        HistoryMsg {
+            msg_id: 0,
+            chat_peer_id: chat_id.to_owned(),
+            incoming: true,
+            peer_id: String::new(),
+            peer_name: String::from("tester"),
+            send_time: recv_time,
+            recv_time,
+            message: message.into(),
+        }
+    }
+
+    #[test]
+    fn chat_id_key() {
+        let lobby: ChatId = 4347301314802127616u64.into();
+        let peer: ChatId = PeerId::from("65d33bc7bee18b713364b0301dbed896").into();
+
+        for id in [lobby, peer] {
+            let key = chat_id_to_key(&id).unwrap();
+            assert_eq!(
+                chat_id_to_key(&chat_id_from_key(&key).unwrap()).unwrap(),
+                key
+            );
+        }
+        assert!(chat_id_to_key(&ChatId::default()).is_none());
+    }
+
+    #[test]
+    fn history() {
+        let db = ChatHistory::new_mem().unwrap();
+        let lobby: ChatId = 4347301314802127616u64.into();
+        let peer: ChatId = PeerId::from("65d33bc7bee18b713364b0301dbed896").into();
+        let now = now();
+
+        for i in 0..10 {
+            db.add_message(&msg(&lobby, &format!("lobby message {i}"), now + i))
+                .unwrap();
+        }
+        db.add_message(&msg(&peer, "hello rustyshare", now))
+            .unwrap();
+
+        // paging
+        let page = db.get_messages(&lobby, 3, 0).unwrap();
+        assert_eq!(
+            page.iter().map(|m| m.message.as_str()).collect::<Vec<_>>(),
+            ["lobby message 7", "lobby message 8", "lobby message 9"]
+        );
+        let page = db.get_messages(&lobby, 3, 9).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].message, "lobby message 0");
+
+        // search
+        assert_eq!(db.search(None, "rusty", 10, 0).unwrap().len(), 1);
+        assert_eq!(db.search(Some(&lobby), "rusty", 10, 0).unwrap().len(), 0);
+        assert_eq!(db.search(Some(&lobby), "message", 10, 0).unwrap().len(), 10);
+        assert_eq!(db.search(None, "\"", 10, 0).unwrap().len(), 0);
+
+        // retention
+        db.set_retention(
+            &lobby,
+            RetentionLimits {
+                max_messages: 5,
+                max_age: Duration::ZERO,
+            },
+        )
+        .unwrap();
+        assert_eq!(db.get_messages(&lobby, 100, 0).unwrap().len(), 5);
+        assert_eq!(db.search(Some(&lobby), "message", 10, 0).unwrap().len(), 5);
+
+        db.add_message(&msg(&peer, "ancient", 1000)).unwrap();
+        db.remove_expired().unwrap();
+        assert_eq!(db.get_messages(&peer, 100, 0).unwrap().len(), 1);
+
+        db.clear(&peer).unwrap();
+        assert!(db.get_messages(&peer, 100, 0).unwrap().is_empty());
+    }
+}
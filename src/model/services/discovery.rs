@@ -0,0 +1,68 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use retroshare_compat::basics::PgpId;
+use sequoia_openpgp::Cert;
+
+/// Keys of friends of friends, received through discovery.
+///
+/// RS keeps them in its (public) keyring, they are needed to add a friend of a friend and to verify the locations
+/// discovery tells us about.
+#[derive(Debug, Default)]
+pub struct DiscoveryStore {
+    keys: RwLock<HashMap<PgpId, Cert>>,
+}
+
+impl DiscoveryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `false` when the key was already known (it is replaced anyway).
+    pub fn add_key(&self, pgp_id: PgpId, cert: Cert) -> bool {
+        self.keys
+            .write()
+            .expect("failed to get keys, lock poisoned!")
+            .insert(pgp_id, cert)
+            .is_none()
+    }
+
+    pub fn has_key(&self, pgp_id: &PgpId) -> bool {
+        self.keys
+            .read()
+            .expect("failed to get keys, lock poisoned!")
+            .contains_key(pgp_id)
+    }
+
+    pub fn get_key_ids(&self) -> Vec<PgpId> {
+        self.keys
+            .read()
+            .expect("failed to get keys, lock poisoned!")
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retroshare_compat::basics::PgpId;
+    use sequoia_openpgp::cert::CertBuilder;
+
+    use super::DiscoveryStore;
+
+    #[test]
+    fn keys() {
+        let (cert, _) = CertBuilder::general_purpose(None, Some("alice"))
+            .generate()
+            .unwrap();
+        let pgp_id: PgpId = cert.keyid().as_bytes().to_vec().into();
+
+        let store = DiscoveryStore::new();
+        assert!(!store.has_key(&pgp_id));
+        assert!(store.add_key(pgp_id, cert.to_owned()));
+        assert!(!store.add_key(pgp_id, cert));
+
+        assert!(store.has_key(&pgp_id));
+        assert_eq!(store.get_key_ids(), vec![pgp_id]);
+    }
+}
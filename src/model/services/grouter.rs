@@ -0,0 +1,104 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, RwLock},
+};
+
+use log::warn;
+use retroshare_compat::basics::GxsId;
+use tokio::sync::oneshot;
+
+use crate::channel::Sender;
+
+/// Shared global router state.
+///
+/// Services that send data to (distant) gxs ids (e.g. mail) register a channel for their GRouter client id
+/// and control the GRouter service with `GRouterCmd`.
+#[derive(Debug, Default)]
+pub struct GRouterStore {
+    clients: RwLock<HashMap<u32, Sender<GRouterEvent>>>,
+    cmd: Mutex<Option<Sender<GRouterCmd>>>,
+
+    /// our own ids, tunnels to these are accepted
+    own_ids: RwLock<HashSet<GxsId>>,
+}
+
+impl GRouterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a client service, data received for `service_id` is passed to `tx`.
+    pub fn register_client(&self, service_id: u32, tx: Sender<GRouterEvent>) {
+        if self
+            .clients
+            .write()
+            .expect("failed to get clients, lock poisoned!")
+            .insert(service_id, tx)
+            .is_some()
+        {
+            warn!("replacing already registered GRouter client {service_id:08x}");
+        }
+    }
+
+    pub fn get_client(&self, service_id: &u32) -> Option<Sender<GRouterEvent>> {
+        self.clients
+            .read()
+            .expect("failed to get clients, lock poisoned!")
+            .get(service_id)
+            .cloned()
+    }
+
+    pub fn set_cmd(&self, tx: Sender<GRouterCmd>) {
+        *self.cmd.lock().expect("failed to get cmd, lock poisoned!") = Some(tx);
+    }
+
+    /// Sends a command to the GRouter service, returns `false` when the GRouter service is not running or its queue is full.
+    pub fn send_cmd(&self, cmd: GRouterCmd) -> bool {
+        match &*self.cmd.lock().expect("failed to get cmd, lock poisoned!") {
+            Some(tx) => tx.try_send(cmd).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn set_own_ids(&self, ids: HashSet<GxsId>) {
+        *self
+            .own_ids
+            .write()
+            .expect("failed to get own ids, lock poisoned!") = ids;
+    }
+
+    pub fn is_own_id(&self, id: &GxsId) -> bool {
+        self.own_ids
+            .read()
+            .expect("failed to get own ids, lock poisoned!")
+            .contains(id)
+    }
+}
+
+#[derive(Debug)]
+pub enum GRouterCmd {
+    /// Signs (with `signing_id`) and encrypts (for `destination`) data and routes it to `destination`.
+    ///
+    /// Returns the routing id, which is used by later `GRouterEvent`s, or `None` when the data couldn't be signed or encrypted.
+    Send {
+        destination: GxsId,
+        signing_id: GxsId,
+        service_id: u32,
+        data: Vec<u8>,
+        tx: oneshot::Sender<Option<u64>>,
+    },
+}
+
+#[derive(Debug)]
+pub enum GRouterEvent {
+    /// Data was routed to one of our ids.
+    Received {
+        destination: GxsId,
+        signing_id: GxsId,
+        data: Vec<u8>,
+    },
+    /// The destination confirmed the reception (with a signed receipt).
+    Delivered(u64),
+    /// The data couldn't be delivered in time and was dropped.
+    Failed(u64),
+}
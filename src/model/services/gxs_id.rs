@@ -2,13 +2,13 @@ use std::{sync::Arc, time::Duration};
 
 use log::warn;
 use retroshare_compat::{
-    basics::{GxsGroupId, GxsId},
-    gxs::sqlite::types::GxsGroup,
+    basics::{GxsGroupId, GxsId, PeerId},
+    gxs::sqlite::types::{GxsGroup, SubscribeFlags},
     tlv::tlv_keys::{TlvKeyFlags, TlvPrivateRSAKey, TlvPublicRSAKey},
 };
 use tokio::sync::oneshot;
 
-use crate::gxs::{gxs_backend::{GxsItemsWrapper, GxsShared}};
+use crate::gxs::gxs_backend::{GxsItemsWrapper, GxsShared};
 
 use super::AppRequest;
 
@@ -34,6 +34,16 @@ impl GxsIdStore {
         }
     }
 
+    /// Returns all ids we own (and thus have the private keys for).
+    pub async fn get_own_ids(&self) -> Vec<GxsId> {
+        self.get_group_meta_all()
+            .await
+            .into_iter()
+            .filter(|entry| entry.subscribe_flags.contains(SubscribeFlags::ADMIN))
+            .map(|entry| entry.group_id.into())
+            .collect()
+    }
+
     // TODO support multiple ?
     pub async fn get_group_meta(&self, group_id: &GxsGroupId) -> Option<GxsGroup> {
         match self
@@ -48,6 +58,21 @@ impl GxsIdStore {
         }
     }
 
+    /// Fetches all ids from `peer_id` that are not known yet.
+    pub async fn request_ids(&self, ids: Vec<GxsId>, peer_id: Arc<PeerId>) {
+        let group_ids = ids.into_iter().map(|id| id.into()).collect();
+        if self
+            .handle_request(
+                GxsItemsWrapper::GxsGroupIdsFrom(group_ids, peer_id),
+                Duration::from_millis(1000),
+            )
+            .await
+            .is_none()
+        {
+            warn!("request for fetching ids timed out");
+        }
+    }
+
     async fn handle_request(
         &self,
         request: GxsItemsWrapper,
@@ -58,7 +83,7 @@ impl GxsIdStore {
         let req = AppRequest { ty: request, tx };
 
         // TODO this should be handled with a queue to avoid polling
-        self.shared.requests.add_request(req);
+        self.shared.requests.add_request(req).await;
 
         // TODO tune this!
         match tokio::time::timeout(timeout, rx).await {
@@ -0,0 +1,150 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, RwLock},
+};
+
+use log::warn;
+use retroshare_compat::basics::{GxsId, GxsTunnelId};
+
+use crate::channel::Sender;
+
+/// Shared gxs tunnel state.
+///
+/// Services that communicate through gxs tunnels (e.g. distant chat) register a channel for their gxs tunnel service id
+/// and control the gxs tunnel service with `GxsTunnelCmd`.
+#[derive(Debug, Default)]
+pub struct GxsTunnelStore {
+    clients: RwLock<HashMap<u32, Sender<GxsTunnelEvent>>>,
+    cmd: Mutex<Option<Sender<GxsTunnelCmd>>>,
+
+    /// our own ids, tunnels to these are accepted
+    own_ids: RwLock<HashSet<GxsId>>,
+    tunnels: RwLock<HashMap<GxsTunnelId, GxsTunnelInfo>>,
+}
+
+impl GxsTunnelStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a client service, data received for `service_id` is passed to `tx`.
+    pub fn register_client(&self, service_id: u32, tx: Sender<GxsTunnelEvent>) {
+        if self
+            .clients
+            .write()
+            .expect("failed to get clients, lock poisoned!")
+            .insert(service_id, tx)
+            .is_some()
+        {
+            warn!("replacing already registered gxs tunnel client {service_id:08x}");
+        }
+    }
+
+    pub fn get_client(&self, service_id: &u32) -> Option<Sender<GxsTunnelEvent>> {
+        self.clients
+            .read()
+            .expect("failed to get clients, lock poisoned!")
+            .get(service_id)
+            .cloned()
+    }
+
+    pub fn set_cmd(&self, tx: Sender<GxsTunnelCmd>) {
+        *self.cmd.lock().expect("failed to get cmd, lock poisoned!") = Some(tx);
+    }
+
+    /// Sends a command to the gxs tunnel service, returns `false` when the gxs tunnel service is not running or its queue is full.
+    pub fn send_cmd(&self, cmd: GxsTunnelCmd) -> bool {
+        match &*self.cmd.lock().expect("failed to get cmd, lock poisoned!") {
+            Some(tx) => tx.try_send(cmd).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn set_own_ids(&self, ids: HashSet<GxsId>) {
+        *self
+            .own_ids
+            .write()
+            .expect("failed to get own ids, lock poisoned!") = ids;
+    }
+
+    pub fn is_own_id(&self, id: &GxsId) -> bool {
+        self.own_ids
+            .read()
+            .expect("failed to get own ids, lock poisoned!")
+            .contains(id)
+    }
+
+    pub fn set_tunnel_info(&self, tunnel_id: GxsTunnelId, info: GxsTunnelInfo) {
+        self.tunnels
+            .write()
+            .expect("failed to get tunnels, lock poisoned!")
+            .insert(tunnel_id, info);
+    }
+
+    pub fn remove_tunnel_info(&self, tunnel_id: &GxsTunnelId) {
+        self.tunnels
+            .write()
+            .expect("failed to get tunnels, lock poisoned!")
+            .remove(tunnel_id);
+    }
+
+    pub fn get_tunnel_info(&self, tunnel_id: &GxsTunnelId) -> Option<GxsTunnelInfo> {
+        self.tunnels
+            .read()
+            .expect("failed to get tunnels, lock poisoned!")
+            .get(tunnel_id)
+            .cloned()
+    }
+}
+
+// static const uint32_t RS_GXS_TUNNEL_STATUS_UNKNOWN            = 0x00 ;
+// static const uint32_t RS_GXS_TUNNEL_STATUS_TUNNEL_DN          = 0x01 ;
+// static const uint32_t RS_GXS_TUNNEL_STATUS_CAN_TALK           = 0x02 ;
+// static const uint32_t RS_GXS_TUNNEL_STATUS_REMOTELY_CLOSED    = 0x03 ;
+// (unknown tunnels simply have no status)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GxsTunnelStatus {
+    TunnelDown,
+    CanTalk,
+    RemotelyClosed,
+}
+
+#[derive(Debug, Clone)]
+pub struct GxsTunnelInfo {
+    pub own_id: GxsId,
+    pub remote_id: GxsId,
+    pub status: GxsTunnelStatus,
+}
+
+#[derive(Debug)]
+pub enum GxsTunnelCmd {
+    /// Opens (or reuses) a tunnel from `own_id` to `remote_id` for the given service.
+    RequestTunnel {
+        own_id: GxsId,
+        remote_id: GxsId,
+        service_id: u32,
+    },
+    /// Sends data through a tunnel, the data is queued until the tunnel can talk and resent until it is acknowledged.
+    SendData {
+        tunnel_id: GxsTunnelId,
+        service_id: u32,
+        data: Vec<u8>,
+    },
+    /// Stops using a tunnel for the given service, the tunnel is closed once no service uses it.
+    CloseTunnel {
+        tunnel_id: GxsTunnelId,
+        service_id: u32,
+    },
+}
+
+#[derive(Debug)]
+pub enum GxsTunnelEvent {
+    /// A tunnel can be used by the client service.
+    Connected {
+        tunnel_id: GxsTunnelId,
+        own_id: GxsId,
+        remote_id: GxsId,
+    },
+    Status(GxsTunnelId, GxsTunnelStatus),
+    Data(GxsTunnelId, Vec<u8>),
+}
@@ -0,0 +1,33 @@
+use retroshare_compat::{basics::GxsId, services::msg::MsgItem};
+use tokio::sync::{oneshot, Mutex, RwLock};
+
+use crate::channel::Sender;
+
+use super::mailbox::Mailbox;
+
+#[derive(Debug)]
+pub struct MailStore {
+    pub cmd: RwLock<Option<Sender<MailCmd>>>,
+    /// persistent mailbox, `None` when the database couldn't be opened
+    pub mailbox: Mutex<Option<Mailbox>>,
+}
+
+impl MailStore {
+    pub fn new() -> Self {
+        Self {
+            cmd: RwLock::new(None),
+            mailbox: Mutex::new(None),
+        }
+    }
+}
+
+pub enum MailCmd {
+    /// Stores a message in the outbox and sends it to all (online) recipients, returns the id of the stored message.
+    ///
+    /// Distant mail (to gxs ids) is signed by `from`, or by one of our ids when `None`.
+    Send {
+        item: MsgItem,
+        from: Option<GxsId>,
+        tx: oneshot::Sender<Option<i64>>,
+    },
+}
@@ -0,0 +1,441 @@
+use std::path::PathBuf;
+
+use retroshare_compat::{
+    basics::PeerId,
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::msg::{MsgFlags, MsgItem, MsgItemFlags},
+    webui::mail::{BoxName, MsgInfoSummary},
+};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::Serialize;
+
+const TABLE_MESSAGES: &str = "messages";
+const TABLE_MSG_TAGS: &str = "msg_tags";
+const TABLE_TAG_TYPES: &str = "tag_types";
+const TABLE_OUTGOING: &str = "outgoing";
+const TABLE_DISTANT_OUTGOING: &str = "distant_outgoing";
+
+// #define RS_MSGTAGTYPE_IMPORTANT  1
+// #define RS_MSGTAGTYPE_WORK       2
+// #define RS_MSGTAGTYPE_PERSONAL   3
+// #define RS_MSGTAGTYPE_TODO       4
+// #define RS_MSGTAGTYPE_LATER      5
+// #define RS_MSGTAGTYPE_USER       100
+const DEFAULT_TAG_TYPES: [(u32, &str, u32); 5] = [
+    (1, "Important", 0xFF0000),
+    (2, "Work", 0xFF9900),
+    (3, "Personal", 0x009900),
+    (4, "Todo", 0x3333FF),
+    (5, "Later", 0x993399),
+];
+/// First id used for user defined tags
+pub const TAG_TYPE_USER: u32 = 100;
+
+/// Number of messages per box, see RS's `getMessageCount`.
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MessageCount {
+    pub nInbox: u32,
+    pub nInboxNew: u32,
+    pub nOutbox: u32,
+    pub nDraftbox: u32,
+    pub nSentbox: u32,
+    pub nTrashbox: u32,
+}
+
+/// Stores received and sent mails, their tags and the peers (or distant recipients) an outgoing mail is still pending for.
+///
+/// Messages are stored as (RS wire) `MsgItem`s, the flags are kept in their own column.
+#[derive(Debug)]
+pub struct Mailbox {
+    db: Connection,
+}
+
+impl Mailbox {
+    pub fn new_file(path: PathBuf, passwd: &str) -> Result<Self> {
+        let db = Connection::open(path)?;
+        if !passwd.is_empty() {
+            db.pragma_update(None, "key", passwd)?;
+        }
+
+        let db = Mailbox { db };
+        db.create_tables()?;
+        Ok(db)
+    }
+
+    #[allow(dead_code)]
+    pub fn new_mem() -> Result<Self> {
+        let db = Mailbox {
+            db: Connection::open_in_memory()?,
+        };
+        db.create_tables()?;
+        Ok(db)
+    }
+
+    fn create_tables(&self) -> Result<()> {
+        self.db.execute_batch(&format!(
+            "BEGIN;
+            CREATE TABLE IF NOT EXISTS {TABLE_MESSAGES} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                peer_id TEXT NOT NULL,
+                flags INT,
+                item BLOB
+            );
+
+            CREATE TABLE IF NOT EXISTS {TABLE_MSG_TAGS} (
+                msg_id INT NOT NULL,
+                tag_id INT NOT NULL,
+                PRIMARY KEY (msg_id, tag_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS {TABLE_TAG_TYPES} (
+                id INTEGER PRIMARY KEY,
+                label TEXT,
+                color INT
+            );
+
+            CREATE TABLE IF NOT EXISTS {TABLE_OUTGOING} (
+                msg_id INT NOT NULL,
+                peer_id TEXT NOT NULL,
+                PRIMARY KEY (msg_id, peer_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS {TABLE_DISTANT_OUTGOING} (
+                routing_id INT PRIMARY KEY,
+                msg_id INT NOT NULL
+            );
+            COMMIT;"
+        ))?;
+
+        let mut stm = self.db.prepare(&format!(
+            "INSERT OR IGNORE INTO {TABLE_TAG_TYPES} (id, label, color) VALUES (?1, ?2, ?3)"
+        ))?;
+        for (id, label, color) in DEFAULT_TAG_TYPES {
+            stm.execute(params![id, label, color])?;
+        }
+        Ok(())
+    }
+
+    /// Stores a message, `peer_id` is the source (our own id for outgoing messages). Returns the message id.
+    pub fn add_message(&self, peer_id: &PeerId, item: &MsgItem) -> Result<i64> {
+        self.db.execute(
+            &format!("INSERT INTO {TABLE_MESSAGES} (peer_id, flags, item) VALUES (?1, ?2, ?3)"),
+            params![
+                peer_id.to_string(),
+                item.msg_flags.bits(),
+                to_retroshare_wire(item)
+            ],
+        )?;
+        Ok(self.db.last_insert_rowid())
+    }
+
+    pub fn get_message(&self, msg_id: i64) -> Result<Option<(PeerId, MsgItem)>> {
+        Ok(self
+            .db
+            .query_row(
+                &format!("SELECT peer_id, flags, item FROM {TABLE_MESSAGES} WHERE id = ?1"),
+                [msg_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?
+            .and_then(|(peer_id, flags, item)| Self::parse(peer_id, flags, item)))
+    }
+
+    fn parse(peer_id: String, flags: u32, mut item: Vec<u8>) -> Option<(PeerId, MsgItem)> {
+        let mut item: MsgItem = from_retroshare_wire_result(&mut item).ok()?;
+        item.msg_flags = MsgItemFlags::from_bits_truncate(flags);
+        Some((PeerId::from(peer_id), item))
+    }
+
+    /// Returns the summaries of all messages in the given box, oldest first.
+    pub fn get_summaries(&self, box_name: BoxName) -> Result<Vec<MsgInfoSummary>> {
+        let mut stm = self.db.prepare(&format!(
+            "SELECT id, peer_id, flags, item FROM {TABLE_MESSAGES} ORDER BY id"
+        ))?;
+        let msgs: Vec<(i64, PeerId, MsgItem)> = stm
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .filter_map(|row| row.ok())
+            .filter(|(_, _, flags, _)| {
+                box_name.contains(MsgItemFlags::from_bits_truncate(*flags).into())
+            })
+            .filter_map(|(id, peer_id, flags, item)| {
+                Self::parse(peer_id, flags, item).map(|(peer_id, item)| (id, peer_id, item))
+            })
+            .collect();
+
+        msgs.into_iter()
+            .map(|(id, peer_id, item)| {
+                Ok(MsgInfoSummary::new(id, peer_id, &item, self.get_tags(id)?))
+            })
+            .collect()
+    }
+
+    /// Sets or clears the given flags, returns whether the message exists.
+    pub fn set_flags(&self, msg_id: i64, flags: MsgItemFlags, set: bool) -> Result<bool> {
+        let stm = if set {
+            format!("UPDATE {TABLE_MESSAGES} SET flags = flags | ?2 WHERE id = ?1")
+        } else {
+            format!("UPDATE {TABLE_MESSAGES} SET flags = flags & ~?2 WHERE id = ?1")
+        };
+        Ok(self.db.execute(&stm, params![msg_id, flags.bits()])? > 0)
+    }
+
+    /// Removes a message including its tags and pending deliveries, returns whether it existed.
+    pub fn remove_message(&self, msg_id: i64) -> Result<bool> {
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_MSG_TAGS} WHERE msg_id = ?1"),
+            [msg_id],
+        )?;
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_OUTGOING} WHERE msg_id = ?1"),
+            [msg_id],
+        )?;
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_DISTANT_OUTGOING} WHERE msg_id = ?1"),
+            [msg_id],
+        )?;
+        Ok(self.db.execute(
+            &format!("DELETE FROM {TABLE_MESSAGES} WHERE id = ?1"),
+            [msg_id],
+        )? > 0)
+    }
+
+    pub fn get_message_count(&self) -> Result<MessageCount> {
+        let mut stm = self
+            .db
+            .prepare(&format!("SELECT flags FROM {TABLE_MESSAGES}"))?;
+        let mut count = MessageCount::default();
+        for flags in stm.query_map([], |row| row.get::<_, u32>(0))? {
+            let flags: MsgFlags = MsgItemFlags::from_bits_truncate(flags?).into();
+            if BoxName::Inbox.contains(flags) {
+                count.nInbox += 1;
+                if flags.intersects(MsgFlags::NEW | MsgFlags::UNREAD_BY_USER) {
+                    count.nInboxNew += 1;
+                }
+            }
+            if BoxName::Outbox.contains(flags) {
+                count.nOutbox += 1;
+            }
+            if BoxName::Drafts.contains(flags) {
+                count.nDraftbox += 1;
+            }
+            if BoxName::Sent.contains(flags) {
+                count.nSentbox += 1;
+            }
+            if BoxName::Trash.contains(flags) {
+                count.nTrashbox += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn get_tags(&self, msg_id: i64) -> Result<Vec<u32>> {
+        let mut stm = self.db.prepare(&format!(
+            "SELECT tag_id FROM {TABLE_MSG_TAGS} WHERE msg_id = ?1 ORDER BY tag_id"
+        ))?;
+        let tags = stm.query_map([msg_id], |row| row.get(0))?.collect();
+        tags
+    }
+
+    /// Adds or removes a tag, returns whether anything changed.
+    pub fn set_tag(&self, msg_id: i64, tag_id: u32, set: bool) -> Result<bool> {
+        let stm = if set {
+            format!("INSERT OR IGNORE INTO {TABLE_MSG_TAGS} (msg_id, tag_id) VALUES (?1, ?2)")
+        } else {
+            format!("DELETE FROM {TABLE_MSG_TAGS} WHERE msg_id = ?1 AND tag_id = ?2")
+        };
+        Ok(self.db.execute(&stm, params![msg_id, tag_id])? > 0)
+    }
+
+    /// Returns all tag types as (id, label, rgb color).
+    pub fn get_tag_types(&self) -> Result<Vec<(u32, String, u32)>> {
+        let mut stm = self.db.prepare(&format!(
+            "SELECT id, label, color FROM {TABLE_TAG_TYPES} ORDER BY id"
+        ))?;
+        let types = stm
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect();
+        types
+    }
+
+    /// Adds or updates a tag type, returns `true` when it is a new one.
+    pub fn set_tag_type(&self, tag_id: u32, label: &str, color: u32) -> Result<bool> {
+        let updated = self.db.execute(
+            &format!("UPDATE {TABLE_TAG_TYPES} SET label = ?2, color = ?3 WHERE id = ?1"),
+            params![tag_id, label, color],
+        )?;
+        if updated == 0 {
+            self.db.execute(
+                &format!("INSERT INTO {TABLE_TAG_TYPES} (id, label, color) VALUES (?1, ?2, ?3)"),
+                params![tag_id, label, color],
+            )?;
+        }
+        Ok(updated == 0)
+    }
+
+    /// Removes a user defined tag type and untags all messages, the default types cannot be removed.
+    pub fn remove_tag_type(&self, tag_id: u32) -> Result<bool> {
+        if tag_id < TAG_TYPE_USER {
+            return Ok(false);
+        }
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_MSG_TAGS} WHERE tag_id = ?1"),
+            [tag_id],
+        )?;
+        Ok(self.db.execute(
+            &format!("DELETE FROM {TABLE_TAG_TYPES} WHERE id = ?1"),
+            [tag_id],
+        )? > 0)
+    }
+
+    /// Remembers that a message still has to be delivered to the given peer.
+    pub fn add_outgoing(&self, msg_id: i64, peer_id: &PeerId) -> Result<()> {
+        self.db.execute(
+            &format!("INSERT OR IGNORE INTO {TABLE_OUTGOING} (msg_id, peer_id) VALUES (?1, ?2)"),
+            params![msg_id, peer_id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns all messages that are still pending for the given peer.
+    pub fn get_outgoing(&self, peer_id: &PeerId) -> Result<Vec<(i64, MsgItem)>> {
+        let mut stm = self.db.prepare(&format!(
+            "SELECT m.id, m.peer_id, m.flags, m.item FROM {TABLE_OUTGOING} o JOIN {TABLE_MESSAGES} m ON m.id = o.msg_id
+            WHERE o.peer_id = ?1 ORDER BY m.id"
+        ))?;
+        let msgs = stm
+            .query_map([peer_id.to_string()], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .filter_map(|row| row.ok())
+            .filter_map(|(id, peer_id, flags, item)| {
+                Self::parse(peer_id, flags, item).map(|(_, item)| (id, item))
+            })
+            .collect();
+        Ok(msgs)
+    }
+
+    /// Marks a message as delivered to the given peer.
+    pub fn remove_outgoing(&self, msg_id: i64, peer_id: &PeerId) -> Result<()> {
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_OUTGOING} WHERE msg_id = ?1 AND peer_id = ?2"),
+            params![msg_id, peer_id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the number of peers (and distant recipients) a message is still pending for.
+    pub fn count_outgoing(&self, msg_id: i64) -> Result<u32> {
+        self.db.query_row(
+            &format!(
+                "SELECT (SELECT COUNT(*) FROM {TABLE_OUTGOING} WHERE msg_id = ?1)
+                    + (SELECT COUNT(*) FROM {TABLE_DISTANT_OUTGOING} WHERE msg_id = ?1)"
+            ),
+            [msg_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Remembers that a message was handed to the global router and waits for its receipt.
+    pub fn add_distant_outgoing(&self, msg_id: i64, routing_id: u64) -> Result<()> {
+        self.db.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {TABLE_DISTANT_OUTGOING} (routing_id, msg_id) VALUES (?1, ?2)"
+            ),
+            params![routing_id as i64, msg_id],
+        )?;
+        Ok(())
+    }
+
+    /// Marks a distant message as delivered, returns the id of the message.
+    pub fn remove_distant_outgoing(&self, routing_id: u64) -> Result<Option<i64>> {
+        let msg_id = self
+            .db
+            .query_row(
+                &format!("SELECT msg_id FROM {TABLE_DISTANT_OUTGOING} WHERE routing_id = ?1"),
+                [routing_id as i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+        self.db.execute(
+            &format!("DELETE FROM {TABLE_DISTANT_OUTGOING} WHERE routing_id = ?1"),
+            [routing_id as i64],
+        )?;
+        Ok(msg_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retroshare_compat::{
+        basics::PeerId,
+        services::msg::{MsgItem, MsgItemFlags},
+        webui::mail::BoxName,
+    };
+
+    use super::{Mailbox, TAG_TYPE_USER};
+
+    #[test]
+    fn mailbox() {
+        let db = Mailbox::new_mem().unwrap();
+        let own = PeerId::from("65d33bc7bee18b713364b0301dbed896");
+        let friend = PeerId::from("01dc22f128d9495541f780a254b89630");
+
+        let incoming = MsgItem {
+            msg_flags: MsgItemFlags::NEW,
+            subject: "hello".into(),
+            ..Default::default()
+        };
+        let outgoing = MsgItem {
+            msg_flags: MsgItemFlags::OUTGOING | MsgItemFlags::PENDING,
+            subject: "world".into(),
+            ..Default::default()
+        };
+        let in_id = db.add_message(&friend, &incoming).unwrap();
+        let out_id = db.add_message(&own, &outgoing).unwrap();
+        db.add_outgoing(out_id, &friend).unwrap();
+
+        assert_eq!(db.get_summaries(BoxName::Inbox).unwrap().len(), 1);
+        assert_eq!(db.get_summaries(BoxName::Outbox).unwrap().len(), 1);
+        assert_eq!(db.get_message_count().unwrap().nInboxNew, 1);
+
+        // delivery moves the message to the sent box
+        assert_eq!(db.get_outgoing(&friend).unwrap().len(), 1);
+        assert_eq!(db.count_outgoing(out_id).unwrap(), 1);
+        db.remove_outgoing(out_id, &friend).unwrap();
+        assert_eq!(db.count_outgoing(out_id).unwrap(), 0);
+        db.add_distant_outgoing(out_id, u64::MAX).unwrap();
+        assert_eq!(db.count_outgoing(out_id).unwrap(), 1);
+        assert_eq!(db.remove_distant_outgoing(u64::MAX).unwrap(), Some(out_id));
+        assert_eq!(db.remove_distant_outgoing(u64::MAX).unwrap(), None);
+        assert_eq!(db.count_outgoing(out_id).unwrap(), 0);
+        db.set_flags(out_id, MsgItemFlags::PENDING, false).unwrap();
+        assert_eq!(db.get_summaries(BoxName::Sent).unwrap().len(), 1);
+        assert!(db.get_outgoing(&friend).unwrap().is_empty());
+
+        // read and trash
+        db.set_flags(in_id, MsgItemFlags::NEW, false).unwrap();
+        db.set_flags(in_id, MsgItemFlags::TRASH, true).unwrap();
+        let (peer_id, item) = db.get_message(in_id).unwrap().unwrap();
+        assert_eq!(peer_id, friend);
+        assert_eq!(item.msg_flags, MsgItemFlags::TRASH);
+        assert!(db.get_summaries(BoxName::Inbox).unwrap().is_empty());
+        assert_eq!(db.get_summaries(BoxName::Trash).unwrap().len(), 1);
+
+        // tags
+        assert!(db.set_tag_type(TAG_TYPE_USER, "rusty", 0x123456).unwrap());
+        assert_eq!(db.get_tag_types().unwrap().len(), 6);
+        db.set_tag(in_id, 1, true).unwrap();
+        db.set_tag(in_id, TAG_TYPE_USER, true).unwrap();
+        assert_eq!(db.get_tags(in_id).unwrap(), vec![1, TAG_TYPE_USER]);
+        assert!(!db.remove_tag_type(1).unwrap());
+        assert!(db.remove_tag_type(TAG_TYPE_USER).unwrap());
+        assert_eq!(db.get_tags(in_id).unwrap(), vec![1]);
+
+        assert!(db.remove_message(in_id).unwrap());
+        assert!(db.get_message(in_id).unwrap().is_none());
+        assert!(db.get_tags(in_id).unwrap().is_empty());
+    }
+}
@@ -1,7 +1,17 @@
 use tokio::sync::oneshot;
 
+pub mod banlist;
+pub mod bwctrl;
 pub mod chat;
+pub mod chat_history;
+pub mod discovery;
+pub mod grouter;
 pub mod gxs_id;
+pub mod gxs_tunnel;
+pub mod mail;
+pub mod mailbox;
+pub mod service_control;
+pub mod turtle;
 
 #[derive(Debug)]
 pub struct AppRequest<IN, OUT> {
@@ -0,0 +1,450 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
+
+use log::warn;
+use retroshare_compat::{
+    basics::{NodeGroupId, PgpId, SslId},
+    config::NodeGroupItem,
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{
+        service_control::{
+            ServiceGroupPermissionItem, ServicePermissionItem,
+            SERVICE_CONTROL_SUB_TYPE_GROUP_PERMISSIONS, SERVICE_CONTROL_SUB_TYPE_PERMISSIONS,
+        },
+        service_info::RsServiceInfo,
+        ServiceType,
+    },
+};
+use serde::Serialize;
+
+use crate::low_level_parsing::{
+    headers::{read_config_records, Header, ServiceHeader},
+    Packet,
+};
+
+pub const SERVICE_CONTROL_CONFIG_FILE: &str = "rustyshare_service_control.cfg";
+
+/// RS's full service id (`RsServiceInfo::RsServiceInfoUIn16ToFullServiceId`).
+pub fn to_full_service_id(service: ServiceType) -> u32 {
+    0x02 << 24 | (u16::from(service) as u32) << 8
+}
+
+pub fn from_full_service_id(id: u32) -> ServiceType {
+    ((id >> 8) as u16).into()
+}
+
+/// Services that are always exchanged, they are required to negotiate everything else.
+fn is_always_allowed(service: ServiceType) -> bool {
+    matches!(
+        service,
+        ServiceType::ServiceInfo | ServiceType::SliceProbe | ServiceType::Unknown
+    )
+}
+
+fn serialize_item<T>(sub_type: u8, item: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let payload = to_retroshare_wire(item);
+    let header = ServiceHeader::new(ServiceType::ServiceControl, sub_type, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
+
+/// Who is allowed to use a service, RS's `RsServicePermissions` extended by node groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServicePermissions {
+    pub service_name: String,
+    pub default_allowed: bool,
+    pub peers_allowed: HashSet<SslId>,
+    pub peers_denied: HashSet<SslId>,
+    /// not part of RS, applies to all locations of the group's (pgp) members
+    pub groups_allowed: HashSet<NodeGroupId>,
+    pub groups_denied: HashSet<NodeGroupId>,
+}
+
+impl Default for ServicePermissions {
+    fn default() -> Self {
+        ServicePermissions {
+            service_name: String::new(),
+            default_allowed: true,
+            peers_allowed: HashSet::new(),
+            peers_denied: HashSet::new(),
+            groups_allowed: HashSet::new(),
+            groups_denied: HashSet::new(),
+        }
+    }
+}
+
+/// Services a connected peer announced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerServices {
+    /// the peer's service list
+    pub provided: Vec<RsServiceInfo>,
+    /// services both sides run in compatible versions
+    pub active: HashSet<ServiceType>,
+}
+
+/// Per peer service permissions.
+///
+/// Locations are matched first, then node groups (from peers.cfg) and finally the service's default applies.
+/// Services without permissions are allowed for everyone.
+#[derive(Debug, Default)]
+pub struct ServiceControlStore {
+    permissions: RwLock<HashMap<ServiceType, ServicePermissions>>,
+
+    /// node groups from peers.cfg
+    groups: RwLock<Vec<NodeGroupItem>>,
+    /// pgp id of each known location, required to resolve node groups
+    locations: RwLock<HashMap<SslId, PgpId>>,
+
+    /// service lists of connected peers
+    peer_services: RwLock<HashMap<SslId, PeerServices>>,
+}
+
+impl ServiceControlStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_peers(&self, locations: HashMap<SslId, PgpId>, groups: Vec<NodeGroupItem>) {
+        *self
+            .locations
+            .write()
+            .expect("failed to get locations, lock poisoned!") = locations;
+        *self
+            .groups
+            .write()
+            .expect("failed to get groups, lock poisoned!") = groups;
+    }
+
+    pub fn add_location(&self, ssl_id: SslId, pgp_id: PgpId) {
+        self.locations
+            .write()
+            .expect("failed to get locations, lock poisoned!")
+            .insert(ssl_id, pgp_id);
+    }
+
+    pub fn get_permissions(&self, service: &ServiceType) -> Option<ServicePermissions> {
+        self.permissions
+            .read()
+            .expect("failed to get permissions, lock poisoned!")
+            .get(service)
+            .cloned()
+    }
+
+    pub fn get_all_permissions(&self) -> HashMap<ServiceType, ServicePermissions> {
+        self.permissions
+            .read()
+            .expect("failed to get permissions, lock poisoned!")
+            .to_owned()
+    }
+
+    pub fn set_permissions(&self, service: ServiceType, permissions: ServicePermissions) {
+        self.permissions
+            .write()
+            .expect("failed to get permissions, lock poisoned!")
+            .insert(service, permissions);
+    }
+
+    /// Returns whether `peer_id` may use `service`, service info (and unknown services) are always allowed.
+    pub fn is_allowed(&self, service: ServiceType, peer_id: &SslId) -> bool {
+        if is_always_allowed(service) {
+            return true;
+        }
+
+        let permissions = self
+            .permissions
+            .read()
+            .expect("failed to get permissions, lock poisoned!");
+        let permissions = match permissions.get(&service) {
+            Some(permissions) => permissions,
+            None => return true,
+        };
+
+        if permissions.peers_denied.contains(peer_id) {
+            return false;
+        }
+        if permissions.peers_allowed.contains(peer_id) {
+            return true;
+        }
+
+        if !permissions.groups_allowed.is_empty() || !permissions.groups_denied.is_empty() {
+            let pgp_id = self
+                .locations
+                .read()
+                .expect("failed to get locations, lock poisoned!")
+                .get(peer_id)
+                .cloned();
+            if let Some(pgp_id) = pgp_id {
+                let groups = self
+                    .groups
+                    .read()
+                    .expect("failed to get groups, lock poisoned!");
+                let member_of = |ids: &HashSet<NodeGroupId>| {
+                    groups
+                        .iter()
+                        .any(|group| ids.contains(&group.id) && group.pgp_list.0.contains(&pgp_id))
+                };
+
+                if member_of(&permissions.groups_denied) {
+                    return false;
+                }
+                if member_of(&permissions.groups_allowed) {
+                    return true;
+                }
+            }
+        }
+
+        permissions.default_allowed
+    }
+
+    /// Stores the service list a peer sent us, only services it runs in a compatible version become active.
+    pub fn set_peer_services(
+        &self,
+        peer_id: SslId,
+        own: &[RsServiceInfo],
+        provided: Vec<RsServiceInfo>,
+    ) {
+        let active = provided
+            .iter()
+            .filter(|theirs| own.iter().any(|ours| ours.is_compatible(theirs)))
+            .map(|info| from_full_service_id(info.m_service_type))
+            .collect();
+
+        self.peer_services
+            .write()
+            .expect("failed to get peer services, lock poisoned!")
+            .insert(peer_id, PeerServices { provided, active });
+    }
+
+    pub fn remove_peer_services(&self, peer_id: &SslId) {
+        self.peer_services
+            .write()
+            .expect("failed to get peer services, lock poisoned!")
+            .remove(peer_id);
+    }
+
+    pub fn get_peer_services(&self, peer_id: &SslId) -> Option<PeerServices> {
+        self.peer_services
+            .read()
+            .expect("failed to get peer services, lock poisoned!")
+            .get(peer_id)
+            .cloned()
+    }
+
+    pub fn get_all_peer_services(&self) -> HashMap<SslId, PeerServices> {
+        self.peer_services
+            .read()
+            .expect("failed to get peer services, lock poisoned!")
+            .to_owned()
+    }
+
+    /// Returns whether `peer_id` runs `service`.
+    ///
+    /// As long as the peer's service list is unknown, all services are assumed to be active.
+    pub fn is_active(&self, service: ServiceType, peer_id: &SslId) -> bool {
+        if is_always_allowed(service) {
+            return true;
+        }
+
+        match self
+            .peer_services
+            .read()
+            .expect("failed to get peer services, lock poisoned!")
+            .get(peer_id)
+        {
+            Some(services) => services.active.contains(&service),
+            None => true,
+        }
+    }
+
+    /// Loads permissions from our config file (RS's `RsServicePermissionItem` plus our node group permissions).
+    pub fn load_config(&self, data: Vec<u8>) {
+        let mut permissions = self
+            .permissions
+            .write()
+            .expect("failed to get permissions, lock poisoned!");
+
+        for (header, mut payload) in read_config_records(data, SERVICE_CONTROL_CONFIG_FILE) {
+            let sub_type = match header {
+                Header::Service {
+                    service: ServiceType::ServiceControl,
+                    sub_type,
+                    ..
+                } => sub_type,
+                _ => {
+                    warn!("failed to parse {SERVICE_CONTROL_CONFIG_FILE}");
+                    break;
+                }
+            };
+
+            match sub_type {
+                SERVICE_CONTROL_SUB_TYPE_PERMISSIONS => {
+                    match from_retroshare_wire_result::<ServicePermissionItem>(&mut payload) {
+                        Ok(item) => {
+                            let entry = permissions
+                                .entry(from_full_service_id(item.service_id))
+                                .or_default();
+                            entry.service_name = item.service_name.into();
+                            entry.default_allowed = item.default_allowed != 0;
+                            entry.peers_allowed = item.peers_allowed.0;
+                            entry.peers_denied = item.peers_denied.0;
+                        }
+                        Err(err) => warn!("failed to deserialize service permissions: {err:?}"),
+                    }
+                }
+                SERVICE_CONTROL_SUB_TYPE_GROUP_PERMISSIONS => {
+                    match from_retroshare_wire_result::<ServiceGroupPermissionItem>(&mut payload) {
+                        Ok(item) => {
+                            let entry = permissions
+                                .entry(from_full_service_id(item.service_id))
+                                .or_default();
+                            entry.groups_allowed = item.groups_allowed.0;
+                            entry.groups_denied = item.groups_denied.0;
+                        }
+                        Err(err) => warn!("failed to deserialize group permissions: {err:?}"),
+                    }
+                }
+                sub_type => {
+                    warn!("unexpected config item {sub_type:02x} in {SERVICE_CONTROL_CONFIG_FILE}")
+                }
+            }
+        }
+    }
+
+    pub fn save_config(&self) -> Vec<u8> {
+        let mut data = vec![];
+        for (service, permissions) in self.get_all_permissions() {
+            let service_id = to_full_service_id(service);
+
+            let item = ServicePermissionItem {
+                service_id,
+                service_name: permissions.service_name.into(),
+                default_allowed: permissions.default_allowed as u8,
+                peers_allowed: permissions.peers_allowed.into_iter().collect(),
+                peers_denied: permissions.peers_denied.into_iter().collect(),
+            };
+            data.extend(serialize_item(SERVICE_CONTROL_SUB_TYPE_PERMISSIONS, &item));
+
+            if !permissions.groups_allowed.is_empty() || !permissions.groups_denied.is_empty() {
+                let item = ServiceGroupPermissionItem {
+                    service_id,
+                    groups_allowed: permissions.groups_allowed.into_iter().collect(),
+                    groups_denied: permissions.groups_denied.into_iter().collect(),
+                };
+                data.extend(serialize_item(
+                    SERVICE_CONTROL_SUB_TYPE_GROUP_PERMISSIONS,
+                    &item,
+                ));
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use retroshare_compat::{
+        basics::{NodeGroupId, PgpId, SslId},
+        config::NodeGroupItem,
+        services::{service_info::RsServiceInfo, ServiceType},
+    };
+
+    use super::{ServiceControlStore, ServicePermissions};
+
+    #[test]
+    fn is_allowed() {
+        let alice: SslId = vec![1; 16].into();
+        let bob: SslId = vec![2; 16].into();
+        let bob_pgp: PgpId = vec![2; 8].into();
+        let group: NodeGroupId = vec![3; 16].into();
+
+        let mut node_group = NodeGroupItem::default();
+        node_group.id = group;
+        node_group.pgp_list = [bob_pgp].into_iter().collect();
+
+        let store = ServiceControlStore::new();
+        store.set_peers(HashMap::from([(bob, bob_pgp)]), vec![node_group]);
+
+        // no permissions, everything is allowed
+        assert!(store.is_allowed(ServiceType::Chat, &alice));
+
+        store.set_permissions(
+            ServiceType::Chat,
+            ServicePermissions {
+                default_allowed: false,
+                peers_allowed: [alice].into_iter().collect(),
+                groups_allowed: [group].into_iter().collect(),
+                ..Default::default()
+            },
+        );
+        assert!(store.is_allowed(ServiceType::Chat, &alice));
+        assert!(store.is_allowed(ServiceType::Chat, &bob));
+        assert!(!store.is_allowed(ServiceType::Chat, &vec![4; 16].into()));
+
+        // locations win over groups
+        store.set_permissions(
+            ServiceType::Chat,
+            ServicePermissions {
+                peers_allowed: [bob].into_iter().collect(),
+                groups_denied: [group].into_iter().collect(),
+                ..Default::default()
+            },
+        );
+        assert!(store.is_allowed(ServiceType::Chat, &bob));
+        assert!(store.is_allowed(ServiceType::Chat, &alice));
+
+        // service info is always allowed
+        store.set_permissions(
+            ServiceType::ServiceInfo,
+            ServicePermissions {
+                default_allowed: false,
+                ..Default::default()
+            },
+        );
+        assert!(store.is_allowed(ServiceType::ServiceInfo, &alice));
+
+        // config round trip
+        let restored = ServiceControlStore::new();
+        restored.load_config(store.save_config());
+        assert_eq!(restored.get_all_permissions(), store.get_all_permissions());
+    }
+
+    #[test]
+    fn peer_services() {
+        let peer: SslId = vec![1; 16].into();
+        let own = vec![
+            RsServiceInfo::new(ServiceType::Chat.into(), "chat"),
+            RsServiceInfo::new(ServiceType::Msg.into(), "msg"),
+        ];
+        let mut msg = RsServiceInfo::new(ServiceType::Msg.into(), "msgs");
+        msg.m_min_version_major = 2;
+
+        let store = ServiceControlStore::new();
+        assert!(store.is_active(ServiceType::Msg, &peer));
+
+        store.set_peer_services(
+            peer,
+            &own,
+            vec![
+                RsServiceInfo::new(ServiceType::Chat.into(), "chat"),
+                RsServiceInfo::new(ServiceType::Turtle.into(), "turtle"),
+                msg,
+            ],
+        );
+        assert!(store.is_active(ServiceType::Chat, &peer));
+        assert!(store.is_active(ServiceType::ServiceInfo, &peer));
+        // we don't run it
+        assert!(!store.is_active(ServiceType::Turtle, &peer));
+        // version mismatch
+        assert!(!store.is_active(ServiceType::Msg, &peer));
+        assert_eq!(store.get_peer_services(&peer).unwrap().provided.len(), 3);
+
+        store.remove_peer_services(&peer);
+        assert!(store.is_active(ServiceType::Msg, &peer));
+    }
+}
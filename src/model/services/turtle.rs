@@ -0,0 +1,448 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
+};
+
+use byteorder::{ByteOrder, NetworkEndian};
+use log::trace;
+use retroshare_compat::{
+    basics::SslId,
+    services::{
+        turtle::{TurtleFileHash, TurtleSearchRequestId},
+        ServiceType,
+    },
+};
+use tokio::sync::oneshot;
+
+use crate::{
+    channel::Sender,
+    low_level_parsing::{headers::Header, Packet},
+    model::intercom::Intercom,
+    services::turtle::{TurtleClient, TURTLE_SUB_TYPE_GENERIC_DATA},
+};
+
+/// Shared turtle state that is accessed directly by peer tasks and turtle clients.
+///
+/// The turtle service installs a forwarding entry once a tunnel is established.
+/// Peer tasks then forward generic data of known tunnels directly to the other peer, skipping the core and the turtle service.
+///
+/// Services that use tunnels (e.g. GxsTunnel) register themselves as `TurtleClient` and control the turtle service with `TurtleCmd`.
+#[derive(Default)]
+pub struct TurtleStore {
+    tunnels: RwLock<HashMap<u32, Arc<TunnelForward>>>,
+
+    clients: RwLock<HashMap<ServiceType, Arc<dyn TurtleClient>>>,
+    cmd: Mutex<Option<Sender<TurtleCmd>>>,
+}
+
+impl TurtleStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a client service, it will be asked for incoming tunnel requests and generic searches.
+    pub fn register_client(&self, client: Arc<dyn TurtleClient>) {
+        let service = client.get_service_id();
+        if self
+            .clients
+            .write()
+            .expect("failed to get clients, lock poisoned!")
+            .insert(service, client)
+            .is_some()
+        {
+            log::warn!("replacing already registered turtle client {service:?}");
+        }
+    }
+
+    pub fn get_client(&self, service: &ServiceType) -> Option<Arc<dyn TurtleClient>> {
+        self.clients
+            .read()
+            .expect("failed to get clients, lock poisoned!")
+            .get(service)
+            .cloned()
+    }
+
+    pub fn get_clients(&self) -> Vec<Arc<dyn TurtleClient>> {
+        self.clients
+            .read()
+            .expect("failed to get clients, lock poisoned!")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Asks the registered clients in turn about a tunnel request, returns the first one that accepts it.
+    pub async fn find_tunnel_client(
+        &self,
+        hash: &TurtleFileHash,
+        peer_id: &Arc<SslId>,
+    ) -> Option<Arc<dyn TurtleClient>> {
+        for client in self.get_clients() {
+            if client.handle_tunnel_request(hash, peer_id).await {
+                return Some(client);
+            }
+        }
+        None
+    }
+
+    pub fn set_cmd(&self, tx: Sender<TurtleCmd>) {
+        *self.cmd.lock().expect("failed to get cmd, lock poisoned!") = Some(tx);
+    }
+
+    /// Sends a command to the turtle service, returns `false` when the turtle service is not running or its queue is full.
+    pub fn send_cmd(&self, cmd: TurtleCmd) -> bool {
+        match &*self.cmd.lock().expect("failed to get cmd, lock poisoned!") {
+            Some(tx) => tx.try_send(cmd).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn insert(&self, tunnel_id: u32, entry: TunnelForward) -> Option<Arc<TunnelForward>> {
+        self.tunnels
+            .write()
+            .expect("failed to get fast path, lock poisoned!")
+            .insert(tunnel_id, Arc::new(entry))
+    }
+
+    pub fn get(&self, tunnel_id: &u32) -> Option<Arc<TunnelForward>> {
+        self.tunnels
+            .read()
+            .expect("failed to get fast path, lock poisoned!")
+            .get(tunnel_id)
+            .cloned()
+    }
+
+    pub fn remove(&self, tunnel_id: &u32) -> Option<Arc<TunnelForward>> {
+        self.tunnels
+            .write()
+            .expect("failed to get fast path, lock poisoned!")
+            .remove(tunnel_id)
+    }
+
+    /// Removes all tunnels for which `f` returns `false` and returns the ids of the removed ones.
+    pub fn retain<F>(&self, mut f: F) -> Vec<u32>
+    where
+        F: FnMut(&u32, &TunnelForward) -> bool,
+    {
+        let mut removed = vec![];
+        self.tunnels
+            .write()
+            .expect("failed to get fast path, lock poisoned!")
+            .retain(|id, entry| {
+                let keep = f(id, entry);
+                if !keep {
+                    removed.push(*id);
+                }
+                keep
+            });
+        removed
+    }
+
+    /// Returns the (summed up) stats of all tunnels since the last call and resets them.
+    pub fn drain_stats(&self) -> (u64, u64) {
+        self.tunnels
+            .read()
+            .expect("failed to get fast path, lock poisoned!")
+            .values()
+            .map(|entry| entry.drain_stats())
+            .fold((0, 0), |acc, stats| {
+                (
+                    acc.0.saturating_add(stats.0 as u64),
+                    acc.1.saturating_add(stats.1),
+                )
+            })
+    }
+
+    /// Tries to forward a packet directly to the other end of a known tunnel.
+    ///
+    /// Returns the packet when it is not generic turtle data or belongs to no known tunnel, so that it can take the usual path.
+    pub fn try_forward(&self, mut packet: Packet) -> Result<(), Packet> {
+        match packet.header {
+            Header::Service {
+                service: ServiceType::Turtle,
+                sub_type: TURTLE_SUB_TYPE_GENERIC_DATA,
+                ..
+            } if packet.payload.len() >= 4 => {}
+            _ => return Err(packet),
+        }
+
+        // `TurtleGenericDataItem` starts with the tunnel id
+        let tunnel_id = NetworkEndian::read_u32(&packet.payload[0..4]);
+        let entry = match self.get(&tunnel_id) {
+            Some(entry) => entry,
+            None => return Err(packet),
+        };
+
+        // figure out direction, let the turtle service deal with everything unexpected
+        let tx = match entry.other_end(&packet.peer_id) {
+            Some((peer_id, tx)) => {
+                packet.peer_id = peer_id.to_owned();
+                tx
+            }
+            None => return Err(packet),
+        };
+
+        entry.account(packet.header.get_payload_size());
+
+        trace!(
+            "fast path: forwarding {:08x} to {}",
+            tunnel_id,
+            packet.peer_id
+        );
+        if tx.try_send(Intercom::Send(packet)).is_err() {
+            // the peer is gone, the turtle service will tear down the tunnel
+            trace!("fast path: failed to forward {tunnel_id:08x}, peer worker is gone");
+        }
+        Ok(())
+    }
+}
+
+/// Forwarding entry of an established tunnel.
+///
+/// Holds the senders of both peer tasks and the accounting data, which is collected by the turtle service.
+#[derive(Debug)]
+pub struct TunnelForward {
+    from: (Arc<SslId>, Sender<Intercom>),
+    to: (Arc<SslId>, Sender<Intercom>),
+
+    last_active: Mutex<Instant>,
+    times_forwarded: AtomicU32,
+    data_forwarded: AtomicU64,
+}
+
+impl TunnelForward {
+    pub fn new(from: (Arc<SslId>, Sender<Intercom>), to: (Arc<SslId>, Sender<Intercom>)) -> Self {
+        Self {
+            from,
+            to,
+
+            last_active: Mutex::new(Instant::now()),
+            times_forwarded: AtomicU32::new(0),
+            data_forwarded: AtomicU64::new(0),
+        }
+    }
+
+    pub fn involves(&self, peer_id: &SslId) -> bool {
+        *self.from.0 == *peer_id || *self.to.0 == *peer_id
+    }
+
+    /// Returns the destination for data coming from `peer_id`.
+    pub fn other_end(&self, peer_id: &Arc<SslId>) -> Option<(&Arc<SslId>, &Sender<Intercom>)> {
+        if *peer_id == self.from.0 {
+            Some((&self.to.0, &self.to.1))
+        } else if *peer_id == self.to.0 {
+            Some((&self.from.0, &self.from.1))
+        } else {
+            None
+        }
+    }
+
+    pub fn account(&self, size: usize) {
+        *self
+            .last_active
+            .lock()
+            .expect("failed to get last_active, lock poisoned!") = Instant::now();
+        self.times_forwarded.fetch_add(1, Ordering::Relaxed);
+        self.data_forwarded
+            .fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub fn last_active(&self) -> Instant {
+        *self
+            .last_active
+            .lock()
+            .expect("failed to get last_active, lock poisoned!")
+    }
+
+    fn drain_stats(&self) -> (u32, u64) {
+        (
+            self.times_forwarded.swap(0, Ordering::Relaxed),
+            self.data_forwarded.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+/// Which end of a tunnel we are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunnelDirection {
+    /// We requested the tunnel
+    Client,
+    /// We accepted the tunnel request
+    Server,
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum TurtleCmd {
+    /// Keep tunnels open to the given hash for the given client service.
+    MonitorTunnels(TurtleFileHash, ServiceType),
+    /// Stops requesting tunnels to the given hash and closes existing ones.
+    StopMonitoringTunnels(TurtleFileHash),
+    /// Sends data through a tunnel we are an end of.
+    SendData(u32, Vec<u8>),
+    /// Starts a generic search for a client service, the request id is returned.
+    GenericSearch {
+        service: ServiceType,
+        request_type: u8,
+        search_data: Vec<u8>,
+        tx: oneshot::Sender<TurtleSearchRequestId>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use retroshare_compat::{
+        basics::SslId,
+        services::{turtle::TurtleFileHash, ServiceType},
+    };
+
+    use crate::{
+        channel::{channel, Policy},
+        low_level_parsing::{headers::ServiceHeader, Packet},
+        model::intercom::Intercom,
+        services::turtle::{TurtleClient, TURTLE_SUB_TYPE_GENERIC_DATA},
+    };
+
+    use super::{TunnelDirection, TunnelForward, TurtleStore};
+
+    /// Accepts tunnels and searches for a single hash and records the requests it was asked about.
+    struct TestClient {
+        hash: TurtleFileHash,
+        requests: Mutex<Vec<TurtleFileHash>>,
+    }
+
+    #[async_trait]
+    impl TurtleClient for TestClient {
+        fn get_service_id(&self) -> ServiceType {
+            ServiceType::GxsTunnel
+        }
+
+        async fn handle_tunnel_request(
+            &self,
+            hash: &TurtleFileHash,
+            _peer_id: &Arc<SslId>,
+        ) -> bool {
+            self.requests.lock().unwrap().push(*hash);
+            *hash == self.hash
+        }
+
+        async fn receive_turtle_data(
+            &self,
+            _data: Vec<u8>,
+            _hash: &TurtleFileHash,
+            _tunnel_id: u32,
+            _direction: TunnelDirection,
+        ) {
+        }
+
+        async fn tunnel_up(
+            &self,
+            _hash: &TurtleFileHash,
+            _tunnel_id: u32,
+            _direction: TunnelDirection,
+        ) {
+        }
+
+        async fn tunnel_down(&self, _hash: &TurtleFileHash, _tunnel_id: u32) {}
+
+        async fn handle_generic_search(
+            &self,
+            _request_type: u8,
+            search_data: &[u8],
+        ) -> Option<Vec<u8>> {
+            (search_data == self.hash.as_ref()).then(|| vec![1, 2, 3])
+        }
+    }
+
+    #[tokio::test]
+    async fn client_dispatch() {
+        let store = TurtleStore::new();
+        let peer: Arc<SslId> = Arc::new("65d33bc7bee18b713364b0301dbed896".into());
+        let known: TurtleFileHash = "1d2ab7a45ea4d2f3ec1ee4bf0d51d0bd61e1f6b3".into();
+        let unknown: TurtleFileHash = "eb5b7b8be6f2f5e3b3e2a7d3f0a7c5d1c6e4b2a9".into();
+
+        // nobody registered
+        assert!(store.find_tunnel_client(&known, &peer).await.is_none());
+        assert!(store.get_client(&ServiceType::GxsTunnel).is_none());
+
+        let client = Arc::new(TestClient {
+            hash: known,
+            requests: Mutex::new(vec![]),
+        });
+        store.register_client(client.clone());
+
+        // tunnel requests
+        let found = store.find_tunnel_client(&known, &peer).await;
+        assert_eq!(
+            found.map(|client| client.get_service_id()),
+            Some(ServiceType::GxsTunnel)
+        );
+        assert!(store.find_tunnel_client(&unknown, &peer).await.is_none());
+        assert_eq!(*client.requests.lock().unwrap(), vec![known, unknown]);
+
+        // generic searches are dispatched by service
+        let found = store.get_client(&ServiceType::GxsTunnel).unwrap();
+        assert_eq!(
+            found.handle_generic_search(0, known.as_ref()).await,
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(found.handle_generic_search(0, unknown.as_ref()).await, None);
+        assert!(store.get_client(&ServiceType::Chat).is_none());
+    }
+
+    fn build_data(tunnel_id: u32, from: &Arc<SslId>) -> Packet {
+        let mut payload = tunnel_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0, 0, 0, 2, 0xaa, 0xbb]);
+        let header =
+            ServiceHeader::new(ServiceType::Turtle, TURTLE_SUB_TYPE_GENERIC_DATA, &payload);
+        Packet::new(header.into(), payload, from.to_owned())
+    }
+
+    #[tokio::test]
+    async fn fast_path_forward() {
+        let store = TurtleStore::new();
+        let a: Arc<SslId> = Arc::new("65d33bc7bee18b713364b0301dbed896".into());
+        let b: Arc<SslId> = Arc::new("01dc22f128d9495541f780a254b89630".into());
+        let (tx_a, mut rx_a) = channel("a", 8, Policy::Disconnect);
+        let (tx_b, mut rx_b) = channel("b", 8, Policy::Disconnect);
+
+        // unknown tunnel
+        assert!(store.try_forward(build_data(0x1234, &a)).is_err());
+
+        store.insert(
+            0x1234,
+            TunnelForward::new((a.clone(), tx_a), (b.clone(), tx_b)),
+        );
+
+        // a -> b
+        assert!(store.try_forward(build_data(0x1234, &a)).is_ok());
+        match rx_b.recv().await {
+            Some(Intercom::Send(packet)) => assert_eq!(packet.peer_id, b),
+            msg => panic!("unexpected message {msg:?}"),
+        }
+
+        // b -> a
+        assert!(store.try_forward(build_data(0x1234, &b)).is_ok());
+        match rx_a.recv().await {
+            Some(Intercom::Send(packet)) => assert_eq!(packet.peer_id, a),
+            msg => panic!("unexpected message {msg:?}"),
+        }
+
+        // unknown source is left to the turtle service
+        let c: Arc<SslId> = Arc::new("d6fb6c0f53d18303dcc9043111490e40".into());
+        assert!(store.try_forward(build_data(0x1234, &c)).is_err());
+
+        assert_eq!(store.drain_stats(), (2, 20));
+        assert_eq!(store.drain_stats(), (0, 0));
+
+        assert_eq!(store.retain(|_, e| !e.involves(&b)), vec![0x1234]);
+        assert!(store.try_forward(build_data(0x1234, &a)).is_err());
+    }
+}
@@ -5,7 +5,11 @@ use openssl::{
     pkey::{self, PKey},
     symm::Cipher,
 };
-use std::{fs::File, io::Read, path};
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path,
+};
 
 use super::ssl_key::SslKey;
 
@@ -78,3 +82,38 @@ pub fn decrypt_file(file: &path::Path, keys: SslKey) -> Result<Vec<u8>, std::io:
 
     return Ok(data_dec);
 }
+
+/// Counterpart to `decrypt_file`, writes `data` in the same (RS) format.
+///
+/// The file is written to a temporary file first and then moved into place.
+pub fn encrypt_file(file: &path::Path, keys: SslKey, data: &[u8]) -> Result<(), std::io::Error> {
+    let cipher = Cipher::aes_128_cbc();
+
+    let key: PKey<pkey::Private> = keys.into();
+    let mut env = envelope::Seal::new(cipher, &[key])
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+
+    let mut data_enc = vec![0; data.len() + cipher.block_size()];
+    let mut size_enc = env
+        .update(data, &mut data_enc)
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+    size_enc += env
+        .finalize(&mut data_enc[size_enc..])
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))?;
+    data_enc.truncate(size_enc);
+
+    let encrypted_key = &env.encrypted_keys()[0];
+    let iv = env.iv().expect("aes-128-cbc uses an IV");
+
+    let mut buf = vec![0; 4];
+    NetworkEndian::write_u32(&mut buf, encrypted_key.len() as u32);
+    buf.extend_from_slice(encrypted_key);
+    buf.extend_from_slice(iv);
+    buf.extend(data_enc);
+
+    let tmp = file.with_extension("tmp");
+    let mut f = File::create(&tmp)?;
+    f.write_all(&buf)?;
+    f.sync_all()?;
+    fs::rename(&tmp, file)
+}
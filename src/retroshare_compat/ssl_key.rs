@@ -17,6 +17,7 @@ use openpgp::{
 };
 use openssl::{
     pkey::{self, PKey},
+    sha::sha256,
     // ssl::Ssl,
     x509::X509,
 };
@@ -49,6 +50,13 @@ impl SslKey {
     pub fn private_key(&self) -> &PrivateKeyDer {
         &self.keys.1
     }
+
+    /// Password for our own encrypted databases (chat history, mailbox), derived from the private key.
+    ///
+    /// It is the same for every database of this location, changing the derivation locks out existing ones.
+    pub fn database_password(&self) -> String {
+        hex::encode(sha256(self.private_key()))
+    }
 }
 
 impl From<SslKey> for X509 {
@@ -386,3 +394,18 @@ impl<'a> DecryptionHelper for Helper<'a> {
 //     }
 //     file.sync_all().unwrap();
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::SslKey;
+
+    #[test]
+    fn database_password() {
+        // existing databases can't be opened anymore when this changes
+        let key = SslKey::from((vec![], vec![1, 2, 3]));
+        assert_eq!(
+            key.database_password(),
+            "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81"
+        );
+    }
+}
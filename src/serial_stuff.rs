@@ -6,12 +6,13 @@ use retroshare_compat::{
     },
     keyring::Keyring,
     serde::from_retroshare_wire_result,
+    services::discovery::VsDht,
 };
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use crate::{
     low_level_parsing::headers::{Header, HEADER_SIZE},
-    model::{location::Location, person::Peer},
+    model::{location::Location, net::HiddenType, person::Peer},
 };
 
 pub fn parse_general_cfg(data: &mut Vec<u8>) -> () {
@@ -83,9 +84,16 @@ pub fn parse_general_cfg(data: &mut Vec<u8>) -> () {
     }
 }
 
-pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Arc<Location>>) {
+pub type Friends = (Vec<Arc<Peer>>, Vec<Arc<Location>>, Vec<NodeGroupItem>);
+
+/// Loads friends and their locations, RS's general settings (e.g. proxies) are returned as key value pairs.
+pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Friends, HashMap<String, String>) {
     let mut persons: Vec<Arc<Peer>> = vec![];
     let mut locations: Vec<Arc<Location>> = vec![];
+    let mut permissions = vec![];
+    let mut bandwidth_limits = HashMap::new();
+    let mut groups = vec![];
+    let mut key_values = HashMap::new();
 
     while !data.is_empty() {
         // get header
@@ -114,6 +122,7 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                                 from_retroshare_wire_result(data).unwrap();
                             for (key, value) in item.0 {
                                 info!("[load_peers] KEY_VALUE {}: {}", key, value);
+                                key_values.insert(key.into(), value.into());
                             }
                         }
                         sub_type => {
@@ -131,9 +140,14 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                         // const uint8_t RS_PKT_SUBTYPE_PEER_STUN             = 0x02;
                         // const uint8_t RS_PKT_SUBTYPE_PEER_NET              = 0x03;
                         0x3 => {
-                            let (pgp_id, location, peer_id, ips) = {
+                            let (pgp_id, location, peer_id, ips, hidden_addr, dht) = {
                                 let item: PeerNetItem = from_retroshare_wire_result(data).unwrap();
 
+                                // hidden nodes store their onion / i2p address as domain
+                                let domain: String = item.domain_addr.into();
+                                let hidden_addr = HiddenType::from_host(&domain)
+                                    .map(|_| (domain, item.domain_port));
+
                                 (
                                     item.pgp_id,
                                     item.location.into(),
@@ -142,6 +156,8 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                                         item.local_addr_list.0.into_iter().map(|ip| ip).collect(),
                                         item.ext_addr_list.0.into_iter().map(|ip| ip).collect(),
                                     ),
+                                    hidden_addr,
+                                    item.vs_dht != VsDht::Off as u16,
                                 )
                             };
 
@@ -176,6 +192,8 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                                     ips,
                                     peer.to_owned(),
                                 ));
+                                loc.set_hidden_addr(hidden_addr);
+                                loc.set_dht(dht);
 
                                 peer.add_location(loc.to_owned());
                                 locations.push(loc);
@@ -191,6 +209,7 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                                     "[load_peers] PEER_PERMISSIONS {}: {:?}",
                                     entry.0, entry.1
                                 );
+                                permissions.push(entry);
                             }
                         }
                         // const uint8_t RS_PKT_SUBTYPE_PEER_BANDLIMITS       = 0x06;
@@ -198,12 +217,14 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
                             let entries: PeerBandwidthLimitsItem =
                                 from_retroshare_wire_result(data).expect("failed to deserialize");
                             info!("Bandwidth: {:?}", entries);
+                            bandwidth_limits.extend(entries.0);
                         }
                         // const uint8_t RS_PKT_SUBTYPE_NODE_GROUP            = 0x07;
                         0x07 => {
                             let group: NodeGroupItem =
                                 from_retroshare_wire_result(data).expect("failed to deserialize");
                             info!("group info: {:?}", group);
+                            groups.push(group);
                         }
                         sub_type => {
                             warn!(
@@ -223,6 +244,18 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
         }
     }
 
+    // permissions and bandwidth limits are stored before the (pgp) peers are known
+    for (pgp_id, flags) in permissions {
+        if let Some(peer) = persons.iter().find(|p| p.get_pgp_id() == &pgp_id) {
+            peer.set_service_perm_flags(flags);
+        }
+    }
+    for (pgp_id, limits) in bandwidth_limits {
+        if let Some(peer) = persons.iter().find(|p| p.get_pgp_id() == &pgp_id) {
+            peer.set_bandwidth_limits(limits);
+        }
+    }
+
     // summarize
     println!("loaded the following:");
     for person in &persons {
@@ -233,7 +266,7 @@ pub fn load_peers(data: &mut Vec<u8>, keys: &Keyring) -> (Vec<Arc<Peer>>, Vec<Ar
         }
     }
 
-    (persons, locations)
+    ((persons, locations, groups), key_values)
 }
 
 #[allow(unused_imports)]
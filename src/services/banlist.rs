@@ -0,0 +1,352 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    channel::{channel, Policy, Receiver},
+    controller::qos::QOS_PRIORITY_DEFAULT,
+    low_level_parsing::{
+        headers::{read_config_records, Header, ServiceHeader},
+        Packet,
+    },
+    model::{
+        intercom::{Intercom, PeerState, PeerUpdate},
+        services::banlist::{BanEntry, BanListCmd, BanListSettings},
+        DataCore,
+    },
+    service_items,
+    services::{Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE},
+};
+use async_trait::async_trait;
+use log::{debug, info, trace, warn};
+use retroshare_compat::{
+    basics::SslId,
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{
+        banlist::{
+            BanListConfigItem, BanListItem, BanListOwnEntryItem, BanListSettingsItem,
+            BANLIST_ORIGIN_FRIEND, BANLIST_ORIGIN_SELF, BANLIST_SETTINGS_ENABLED,
+            BANLIST_SETTINGS_SHARE, BANLIST_SETTINGS_USE_FRIENDS, BANLIST_SUB_TYPE_CONFIG_ITEM,
+            BANLIST_SUB_TYPE_ITEM, BANLIST_SUB_TYPE_OWN_ENTRY, BANLIST_SUB_TYPE_SETTINGS,
+            BANLIST_TYPE_PEERLIST,
+        },
+        ServiceType,
+    },
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+const BANLIST_CONFIG_FILE: &str = "rustyshare_banlist.cfg";
+
+/// RS's `RSBANLIST_SEND_PERIOD`
+const BANLIST_TIMER_SEND: (&str, Duration) = ("send", Duration::from_secs(600));
+const BANLIST_TIMER_MAINTENANCE: (&str, Duration) = ("maintenance", Duration::from_secs(60));
+/// Bans shared by friends are dropped after a week (unless they are shared again).
+const BANLIST_FRIEND_ENTRY_MAX_AGE: u64 = 7 * 24 * 60 * 60;
+/// Don't let a single friend flood us.
+const BANLIST_FRIEND_MAX_ENTRIES: usize = 1000;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Serializes an item including its (service) header.
+fn serialize_item<T>(sub_type: u8, item: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let payload = to_retroshare_wire(item);
+    let header = ServiceHeader::new(ServiceType::BanList, sub_type, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
+
+fn deserialize_item<T>(mut payload: Vec<u8>) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match from_retroshare_wire_result(&mut payload) {
+        Ok(item) => Some(item),
+        Err(err) => {
+            warn!("failed to deserialize ban list item: {err:?}");
+            None
+        }
+    }
+}
+
+service_items! {
+    pub enum BanListItems: BanList {
+        List(BanListItem) = BANLIST_SUB_TYPE_ITEM, priority: QOS_PRIORITY_DEFAULT;
+    }
+}
+
+pub struct BanList {
+    core: Arc<DataCore>,
+    cmd_rx: Receiver<BanListCmd>,
+}
+
+impl BanList {
+    pub fn new(core: &Arc<DataCore>) -> BanList {
+        let (cmd_tx, cmd_rx) = channel("banlist commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        core.get_service_data().banlist().set_cmd(cmd_tx);
+
+        BanList {
+            core: core.to_owned(),
+            cmd_rx,
+        }
+    }
+
+    async fn send_list(&self, ctx: &ServiceContext<BanListItems>, peer_id: Arc<SslId>) {
+        let store = self.core.get_service_data().banlist();
+        if !store.get_settings().share {
+            return;
+        }
+
+        // only share what we banned ourselves, RS does the same
+        let now = now();
+        let item = BanListItem {
+            peer_list: store
+                .get_own()
+                .iter()
+                .filter(|entry| entry.origin == BANLIST_ORIGIN_SELF && !entry.is_expired(now))
+                .map(|entry| entry.to_tlv(now))
+                .collect(),
+        };
+        trace!("sending {} ban(s) to {peer_id}", item.peer_list.0.len());
+
+        ctx.send(item, peer_id).await;
+    }
+
+    async fn send_list_to_all(&self, ctx: &ServiceContext<BanListItems>) {
+        let peers: Vec<_> = self
+            .core
+            .get_connected_peers()
+            .lock()
+            .await
+            .0
+            .keys()
+            .cloned()
+            .collect();
+        for peer_id in peers {
+            self.send_list(ctx, peer_id).await;
+        }
+    }
+
+    fn maintain(&self) {
+        let now = now();
+        if self
+            .core
+            .get_service_data()
+            .banlist()
+            .expire(now, now.saturating_sub(BANLIST_FRIEND_ENTRY_MAX_AGE))
+        {
+            self.save_config();
+        }
+    }
+
+    // --- config
+
+    fn load_config(&self) {
+        let data = match self.core.load_config(BANLIST_CONFIG_FILE) {
+            Some(data) => data,
+            None => return,
+        };
+        let store = self.core.get_service_data().banlist();
+
+        for (header, payload) in read_config_records(data, BANLIST_CONFIG_FILE) {
+            let sub_type = match header {
+                Header::Service {
+                    service: ServiceType::BanList,
+                    sub_type,
+                    ..
+                } => sub_type,
+                _ => {
+                    warn!("failed to parse {BANLIST_CONFIG_FILE}");
+                    break;
+                }
+            };
+
+            match sub_type {
+                BANLIST_SUB_TYPE_CONFIG_ITEM => {
+                    if let Some(item) = deserialize_item::<BanListConfigItem>(payload) {
+                        if item.type_ != BANLIST_TYPE_PEERLIST {
+                            continue;
+                        }
+                        let entries = item
+                            .banned_peers
+                            .0
+                            .into_iter()
+                            .map(|entry| {
+                                BanEntry::from_tlv(
+                                    entry.0,
+                                    BANLIST_ORIGIN_FRIEND,
+                                    item.update_time as u64,
+                                )
+                            })
+                            .collect();
+                        store.set_friend(item.peer_id, entries);
+                    }
+                }
+                BANLIST_SUB_TYPE_OWN_ENTRY => {
+                    if let Some(item) = deserialize_item::<BanListOwnEntryItem>(payload) {
+                        let origin = item.entry.0.level;
+                        let mut entry = BanEntry::from_tlv(item.entry.0, origin, now());
+                        entry.added = item.added;
+                        entry.expires = (item.expires != 0).then_some(item.expires);
+                        store.add_own(entry);
+                    }
+                }
+                BANLIST_SUB_TYPE_SETTINGS => {
+                    if let Some(item) = deserialize_item::<BanListSettingsItem>(payload) {
+                        store.set_settings(BanListSettings {
+                            enabled: item.flags & BANLIST_SETTINGS_ENABLED != 0,
+                            use_friends: item.flags & BANLIST_SETTINGS_USE_FRIENDS != 0,
+                            share: item.flags & BANLIST_SETTINGS_SHARE != 0,
+                        });
+                    }
+                }
+                sub_type => {
+                    warn!("unexpected config item {sub_type:02x} in {BANLIST_CONFIG_FILE}")
+                }
+            }
+        }
+
+        info!(
+            "loaded {} own ban(s) and ban lists of {} friend(s)",
+            store.get_own().len(),
+            store.get_friends().len()
+        );
+    }
+
+    fn save_config(&self) {
+        let store = self.core.get_service_data().banlist();
+        let now = now();
+
+        let settings = store.get_settings();
+        let mut flags = 0;
+        if settings.enabled {
+            flags |= BANLIST_SETTINGS_ENABLED;
+        }
+        if settings.use_friends {
+            flags |= BANLIST_SETTINGS_USE_FRIENDS;
+        }
+        if settings.share {
+            flags |= BANLIST_SETTINGS_SHARE;
+        }
+        let mut data = serialize_item(BANLIST_SUB_TYPE_SETTINGS, &BanListSettingsItem { flags });
+
+        for entry in store.get_own() {
+            let item = BanListOwnEntryItem {
+                added: entry.added,
+                expires: entry.expires.unwrap_or_default(),
+                entry: entry.to_tlv(now),
+            };
+            data.extend(serialize_item(BANLIST_SUB_TYPE_OWN_ENTRY, &item));
+        }
+        for (peer_id, entries) in store.get_friends() {
+            let item = BanListConfigItem {
+                type_: BANLIST_TYPE_PEERLIST,
+                peer_id,
+                update_time: now as i64,
+                banned_peers: entries.iter().map(|entry| entry.to_tlv(now)).collect(),
+            };
+            data.extend(serialize_item(BANLIST_SUB_TYPE_CONFIG_ITEM, &item));
+        }
+
+        self.core.save_config(BANLIST_CONFIG_FILE, &data);
+    }
+}
+
+#[async_trait]
+impl Service for BanList {
+    type Item = BanListItems;
+    type Command = BanListCmd;
+
+    const SERVICE: ServiceType = ServiceType::BanList;
+    const NAME: &'static str = "banlist";
+    const TIMERS: &'static [(&'static str, Duration)] =
+        &[BANLIST_TIMER_SEND, BANLIST_TIMER_MAINTENANCE];
+    const EVENTS: bool = true;
+
+    async fn start(&mut self, _ctx: &ServiceContext<BanListItems>) {
+        self.load_config();
+    }
+
+    async fn handle_item(
+        &mut self,
+        _ctx: &ServiceContext<BanListItems>,
+        item: BanListItems,
+        from: Arc<SslId>,
+    ) {
+        let BanListItems::List(item) = item;
+
+        let now = now();
+        let entries: Vec<_> = item
+            .peer_list
+            .0
+            .into_iter()
+            .map(|entry| BanEntry::from_tlv(entry.0, BANLIST_ORIGIN_FRIEND, now))
+            .filter(|entry| !entry.addr.is_unspecified())
+            .take(BANLIST_FRIEND_MAX_ENTRIES)
+            .collect();
+
+        debug!("received {} banned address(es) from {from}", entries.len());
+
+        self.core
+            .get_service_data()
+            .banlist()
+            .set_friend((*from).to_owned(), entries);
+        self.save_config();
+    }
+
+    async fn on_timer(&mut self, ctx: &ServiceContext<BanListItems>, timer: &'static str) {
+        match timer {
+            t if t == BANLIST_TIMER_SEND.0 => self.send_list_to_all(ctx).await,
+            _ => self.maintain(),
+        }
+    }
+
+    async fn handle_event(&mut self, ctx: &ServiceContext<BanListItems>, event: Intercom) {
+        if let Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _addr))) = event {
+            self.send_list(ctx, loc).await;
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<BanListCmd> {
+        self.cmd_rx.recv().await
+    }
+
+    async fn handle_command(&mut self, ctx: &ServiceContext<BanListItems>, cmd: BanListCmd) {
+        let store = self.core.get_service_data().banlist();
+        let send = match cmd {
+            BanListCmd::Ban(entry) => {
+                info!(
+                    "banning {}/{}: {}",
+                    entry.addr, entry.masked_bytes, entry.comment
+                );
+                let send = entry.origin == BANLIST_ORIGIN_SELF;
+                store.add_own(entry);
+                send
+            }
+            BanListCmd::Unban { addr, masked_bytes } => {
+                if !store.remove_own(&addr, masked_bytes) {
+                    return;
+                }
+                info!("unbanning {addr}/{masked_bytes}");
+                true
+            }
+            BanListCmd::SetSettings(settings) => {
+                let send = settings.share && !store.get_settings().share;
+                store.set_settings(settings);
+                send
+            }
+        };
+
+        self.save_config();
+        if send {
+            self.send_list_to_all(ctx).await;
+        }
+    }
+}
@@ -1,143 +1,277 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_trait::async_trait;
 use log::{debug, info, trace, warn};
 use retroshare_compat::{
-    serde::{from_retroshare_wire, to_retroshare_wire},
-    services::{bwctrl::BwCtrlAllowedItem, service_info::RsServiceInfo},
-};
-use tokio::{
-    select,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-    time::{interval, Interval},
+    basics::SslId,
+    config::{PeerBandwidthLimits, PeerBandwidthLimitsItem},
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{
+        bwctrl::{
+            BwCtrlAllowedItem, BWCTRL_SUB_TYPE_ALLOWED, BWCTRL_SUB_TYPE_MAX_RATES,
+            BWCTRL_SUB_TYPE_PEER_LIMITS,
+        },
+    },
 };
-
+use serde::{de::DeserializeOwned, Serialize};
 use crate::{
-    low_level_parsing::{headers::ServiceHeader, Packet},
+    channel::{channel, Policy, Receiver},
+    controller::qos::QOS_PRIORITY_TOP,
+    low_level_parsing::{
+        headers::{read_config_records, Header, ServiceHeader},
+        Packet,
+    },
     model::{
         intercom::{Intercom, PeerState, PeerUpdate},
+        services::bwctrl::{BwCtrlCmd, BWCTRL_CONFIG_FILE},
         DataCore,
     },
-    services::Service,
+    service_items,
+    services::{Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE},
     utils::units,
 };
 
 use ::retroshare_compat::services::ServiceType;
 
-const BWCTRL_SUB_TYPE: u8 = 0x01; // RS_PKT_SUBTYPE_BWCTRL_ALLOWED_ITEM ?!
+/// Serializes an item including its (service) header.
+fn serialize_item<T>(sub_type: u8, item: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let payload = to_retroshare_wire(item);
+    let header = ServiceHeader::new(ServiceType::BwCtrl, sub_type, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
 
-pub struct BwCtrl {
-    rx: UnboundedReceiver<Intercom>,
+fn deserialize_item<T>(mut payload: Vec<u8>) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match from_retroshare_wire_result(&mut payload) {
+        Ok(item) => Some(item),
+        Err(err) => {
+            warn!("failed to deserialize bandwidth control item: {err:?}");
+            None
+        }
+    }
+}
+
+const BWCTRL_TIMER: (&str, Duration) = ("update", Duration::from_secs(5));
+
+service_items! {
+    pub enum BwCtrlItem: BwCtrl {
+        Allowed(BwCtrlAllowedItem) = BWCTRL_SUB_TYPE_ALLOWED, priority: QOS_PRIORITY_TOP;
+    }
+}
 
+pub struct BwCtrl {
     core: Arc<DataCore>,
-    core_tx: UnboundedSender<Intercom>,
-    events: UnboundedReceiver<Intercom>,
+    cmd_rx: Receiver<BwCtrlCmd>,
 
-    timer: Interval,
+    last_update: Instant,
 }
 
 impl BwCtrl {
-    pub async fn new(
-        core: &Arc<DataCore>,
-        core_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-    ) -> BwCtrl {
-        let (tx_events, rx_events) = unbounded_channel();
-        core.events_subscribe(tx_events).await;
+    pub fn new(core: &Arc<DataCore>) -> BwCtrl {
+        let (cmd_tx, cmd_rx) = channel("bwctrl commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        core.get_service_data().bwctrl().set_cmd(cmd_tx);
 
         BwCtrl {
-            rx,
-
             core: core.to_owned(),
-            core_tx,
-            events: rx_events,
+            cmd_rx,
 
-            timer: interval(Duration::from_secs(5)),
+            last_update: Instant::now(),
         }
     }
 
-    fn handle_incoming(&self, header: &ServiceHeader, mut packet: Packet) {
-        assert_eq!(header.sub_type, BWCTRL_SUB_TYPE);
+    /// Recomputes all rates and tells each peer how much it may send us.
+    async fn update(&mut self, ctx: &ServiceContext<BwCtrlItem>) {
+        let peers: Vec<Arc<SslId>> = self
+            .core
+            .get_connected_peers()
+            .lock()
+            .await
+            .0
+            .keys()
+            .cloned()
+            .collect();
+        let connected: Vec<_> = peers
+            .iter()
+            .map(|peer_id| {
+                let limits = self
+                    .core
+                    .get_location_by_id(peer_id.to_owned())
+                    .map(|loc| loc.get_person().get_bandwidth_limits())
+                    .unwrap_or_default();
+                ((**peer_id).to_owned(), limits)
+            })
+            .collect();
 
-        let item: BwCtrlAllowedItem = from_retroshare_wire(&mut packet.payload);
+        let now = Instant::now();
+        let allowed = self
+            .core
+            .get_service_data()
+            .bwctrl()
+            .update(&connected, now - self.last_update);
+        self.last_update = now;
 
-        debug!(
-            "received bandwidth limit of {}/s from {}",
-            units::pretty_print_bytes(item.0 as u64),
-            &packet.peer_id
-        );
+        for (peer_id, rate) in allowed {
+            // RS has no notion of unlimited
+            let item = BwCtrlAllowedItem::new(rate.unwrap_or(u32::MAX));
+            ctx.send(item, Arc::new(peer_id)).await;
+        }
+    }
+
+    // --- config
+
+    fn load_config(&self) {
+        let data = match self.core.load_config(BWCTRL_CONFIG_FILE) {
+            Some(data) => data,
+            None => return,
+        };
+
+        for (header, payload) in read_config_records(data, BWCTRL_CONFIG_FILE) {
+            let sub_type = match header {
+                Header::Service {
+                    service: ServiceType::BwCtrl,
+                    sub_type,
+                    ..
+                } => sub_type,
+                _ => {
+                    warn!("failed to parse {BWCTRL_CONFIG_FILE}");
+                    break;
+                }
+            };
+
+            match sub_type {
+                BWCTRL_SUB_TYPE_MAX_RATES => {
+                    if let Some(rates) = deserialize_item::<PeerBandwidthLimits>(payload) {
+                        self.core.get_service_data().bwctrl().set_max_rates(rates);
+                    }
+                }
+                // overrides the limits from peers.cfg
+                BWCTRL_SUB_TYPE_PEER_LIMITS => {
+                    if let Some(item) = deserialize_item::<PeerBandwidthLimitsItem>(payload) {
+                        for peer in self.core.get_persons() {
+                            if let Some(limits) = item.0.get(peer.get_pgp_id()) {
+                                peer.set_bandwidth_limits(*limits);
+                            }
+                        }
+                    }
+                }
+                sub_type => {
+                    warn!("unexpected config item {sub_type:02x} in {BWCTRL_CONFIG_FILE}")
+                }
+            }
+        }
+    }
 
-        // TODO actually care about bw limits
+    fn save_config(&self) {
+        let mut data = serialize_item(
+            BWCTRL_SUB_TYPE_MAX_RATES,
+            &self.core.get_service_data().bwctrl().get_max_rates(),
+        );
+        let item = PeerBandwidthLimitsItem(
+            self.core
+                .get_persons()
+                .iter()
+                .map(|peer| (peer.get_pgp_id().to_owned(), peer.get_bandwidth_limits()))
+                .filter(|(_, limits)| limits != &PeerBandwidthLimits::default())
+                .collect(),
+        );
+        data.extend(serialize_item(BWCTRL_SUB_TYPE_PEER_LIMITS, &item));
 
-        // self.core_tx
-        //     .send(Intercom::Send(packet))
-        //     .expect("failed to send to core");
+        self.core.save_config(BWCTRL_CONFIG_FILE, &data);
     }
 }
 
 #[async_trait]
 impl Service for BwCtrl {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::BwCtrl
+    type Item = BwCtrlItem;
+    type Command = BwCtrlCmd;
+
+    const SERVICE: ServiceType = ServiceType::BwCtrl;
+    const NAME: &'static str = "bandwidth_ctrl";
+    const TIMERS: &'static [(&'static str, Duration)] = &[BWCTRL_TIMER];
+    const EVENTS: bool = true;
+
+    async fn start(&mut self, _ctx: &ServiceContext<BwCtrlItem>) {
+        self.load_config();
     }
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "bandwidth_ctrl")
+    async fn handle_item(
+        &mut self,
+        _ctx: &ServiceContext<BwCtrlItem>,
+        item: BwCtrlItem,
+        from: Arc<SslId>,
+    ) {
+        let BwCtrlItem::Allowed(item) = item;
+
+        debug!(
+            "received bandwidth limit of {}/s from {from}",
+            units::pretty_print_bytes(item.0 as u64)
+        );
+
+        // applied with the next update
+        self.core
+            .get_service_data()
+            .bwctrl()
+            .set_allowed_out((*from).to_owned(), item.0);
     }
 
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
-
-                            match msg {
-                                Intercom::Receive(packet) =>
-                                    self.handle_incoming(&packet.header.to_owned().into(), packet),
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                    event = self.events.recv() => {
-                        trace!("handling event: {event:?}");
-                        if let Some(event) = event {
-                            match event {
-                                Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _addr))) => {
-                                    let item = BwCtrlAllowedItem { 0: 1_000_000 }; // bytes/sec
-                                    let payload = to_retroshare_wire(&item);
-
-                                    let packet = Packet::new(
-                                        ServiceHeader::new(self.get_id(), BWCTRL_SUB_TYPE, &payload).into(),
-                                        payload,
-                                        loc.clone(),
-                                    );
-                                    self.core_tx.send(Intercom::Send(packet)).expect("failed to send to core");
-
-                                    info!("sending bw limit info to {loc}");
-                                }
-                                // we don't care for the rest!
-                                _ => {}
-                            }
-                        }
-                    }
-                    _ = self.timer.tick() => {
-                        for (peer_id, _) in self.core.get_connected_peers().lock().await.0.iter() {
-                            let item = BwCtrlAllowedItem { 0: 1_000_000 }; // bytes/sec
-                            let payload = to_retroshare_wire(&item);
-
-                            let packet = Packet::new(
-                                ServiceHeader::new(self.get_id(), BWCTRL_SUB_TYPE, &payload).into(),
-                                payload,
-                                peer_id.clone(),
-                            );
-                            self.core_tx.send(Intercom::Send(packet)).expect("failed to send to core");
-                        }
+    async fn on_timer(&mut self, ctx: &ServiceContext<BwCtrlItem>, _timer: &'static str) {
+        self.update(ctx).await;
+    }
+
+    async fn handle_event(&mut self, ctx: &ServiceContext<BwCtrlItem>, event: Intercom) {
+        trace!("handling event: {event:?}");
+        match event {
+            // the share of every peer changes
+            Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _addr))) => {
+                info!("sending bw limit info to {loc}");
+                self.update(ctx).await;
+            }
+            Intercom::PeerUpdate(PeerUpdate::Status(PeerState::NotConnected(loc))) => {
+                self.core.get_service_data().bwctrl().remove_peer(&loc);
+            }
+            // we don't care for the rest!
+            _ => {}
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<BwCtrlCmd> {
+        self.cmd_rx.recv().await
+    }
+
+    async fn handle_command(&mut self, ctx: &ServiceContext<BwCtrlItem>, cmd: BwCtrlCmd) {
+        match cmd {
+            BwCtrlCmd::SetMaxRates(rates) => {
+                info!(
+                    "setting max rates to {} kB/s up and {} kB/s down",
+                    rates.max_up_rate_kbs, rates.max_dl_rate_kbs
+                );
+                self.core.get_service_data().bwctrl().set_max_rates(rates);
+            }
+            BwCtrlCmd::SetPeerMaxRates(pgp_id, limits) => {
+                match self
+                    .core
+                    .get_persons()
+                    .into_iter()
+                    .find(|peer| peer.get_pgp_id() == &pgp_id)
+                {
+                    Some(peer) => peer.set_bandwidth_limits(limits),
+                    None => {
+                        warn!("can't set bandwidth limits of unknown friend {pgp_id}");
+                        return;
                     }
                 }
             }
-        })
+        }
+
+        self.save_config();
+        self.update(ctx).await;
     }
 }
@@ -1,6 +1,6 @@
 #[allow(deprecated)]
 use std::{
-    collections::hash_map::Entry,
+    collections::{hash_map::Entry, HashMap},
     sync::Arc,
     time::{Duration, SystemTime},
 };
@@ -9,43 +9,52 @@ use async_trait::async_trait;
 use log::{debug, info, trace, warn};
 use nanorand::Rng;
 use retroshare_compat::{
-    basics::{GxsId, PeerId},
-    events::EventType,
-    serde::{from_retroshare_wire, to_retroshare_wire, Toggleable},
+    basics::{DistantChatPeerId, GxsId, GxsTunnelId, PeerId},
+    events::{ChatFlags, ChatMessage, EventType},
+    gxs::service_string::{SSGxsIdGroup, ServiceString},
+    serde::{from_retroshare_wire, from_retroshare_wire_result, to_retroshare_wire, Toggleable},
     services::{
         chat::{
-            ChatIdType, ChatLobbyBouncingObject, ChatLobbyConnectChallengeItem, ChatLobbyEvent,
-            ChatLobbyEventItem, ChatLobbyFlags, ChatLobbyId, ChatLobbyInviteItem,
-            ChatLobbyListItem, ChatLobbyMsgItem, ChatMsgItem,
+            ChatAvatarItem, ChatId, ChatIdType, ChatLobbyBanConfigItem, ChatLobbyBouncingObject,
+            ChatLobbyConfigItem, ChatLobbyConnectChallengeItem, ChatLobbyEvent, ChatLobbyEventItem,
+            ChatLobbyFlags, ChatLobbyId, ChatLobbyInviteItem, ChatLobbyListItem, ChatLobbyMsgItem,
+            ChatModerationConfigItem, ChatMsgItem, ChatStatusItem, PrivateChatMsgConfigItem,
+            SubscribedChatLobbyConfigItem,
         },
-        service_info::RsServiceInfo,
     },
     tlv::tlv_keys::{KeyId, TlvKeyFlags, TlvKeySignature, TlvKeySignatureInner},
 };
-use serde::Serialize;
-use tokio::{
-    select,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-    time::{interval, Interval},
-};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::select;
 
 use crate::{
+    channel::{channel, Policy, Receiver, Sender},
     gxs::gxsid::{generate_signature, verify_signature},
     low_level_parsing::{
-        headers::{Header, ServiceHeader},
+        headers::{read_config_records, Header, ServiceHeader},
         Packet,
     },
     model::{
-        intercom::Intercom,
-        services::chat::{ChatCmd, Lobby},
+        intercom::{Intercom, PeerState, PeerUpdate},
+        services::{
+            chat::{
+                ChatCmd, Lobby, LobbyModeration, LobbyParticipant, LobbySubscription,
+                MAX_AVATAR_SIZE,
+            },
+            chat_history::ChatHistory,
+            gxs_tunnel::{GxsTunnelCmd, GxsTunnelEvent},
+        },
         DataCore,
     },
-    services::Service,
+    services::{Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE, SERVICE_EVENTS_QUEUE_SIZE},
 };
 
 use ::retroshare_compat::services::ServiceType;
 
+const CHAT_TIMER_LOBBY_KEEP_ALIVE: (&str, Duration) = ("lobby_keep_alive", Duration::from_secs(120));
+const CHAT_TIMER_LOBBY_MAINTENANCE: (&str, Duration) = ("lobby_maintenance", Duration::from_secs(30));
+const CHAT_TIMER_LOBBY_REQUEST: (&str, Duration) = ("lobby_request", Duration::from_secs(120));
+
 const CHAT_SUB_TYPE_CHAT_DEFAULT: u8 = 0x01;
 const CHAT_SUB_TYPE_CHAT_AVATAR: u8 = 0x03;
 const CHAT_SUB_TYPE_CHAT_STATUS: u8 = 0x04;
@@ -86,10 +95,33 @@ const CHAT_SUB_TYPE_CHAT_LOBBY_INVITE_DEPRECATED: u8 = 0x1A; // to be removed (d
 const CHAT_SUB_TYPE_CHAT_LOBBY_INVITE: u8 = 0x1B;
 const CHAT_SUB_TYPE_OUTGOING_MAP: u8 = 0x1C;
 const CHAT_SUB_TYPE_SUBSCRIBED_CHAT_LOBBY_CONFIG: u8 = 0x1D;
+// not part of RS, only used in our own config file
+const CHAT_SUB_TYPE_MODERATION_CONFIG: u8 = 0xF0;
+const CHAT_SUB_TYPE_LOBBY_BAN_CONFIG: u8 = 0xF1;
 
 pub const CHAT_MAX_KEEP_MSG_RECORD: Duration = Duration::from_secs(1200); // 20 minutes
 const CONNECTION_CHALLENGE_MAX_MSG_AGE: Duration = Duration::from_secs(30); // maximum age of a message to be used in a connection challenge
 
+/// RS signs its own `chat.cfg`, use our own file (in RS's format) instead.
+const CHAT_CONFIG_FILE: &str = "rustyshare_chat.cfg";
+const CHAT_HISTORY_FILE: &str = "rustyshare_chat_history.db";
+
+fn deserialize_item<T>(mut payload: Vec<u8>) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match from_retroshare_wire_result(&mut payload) {
+        Ok(item) => Some(item),
+        Err(err) => {
+            warn!("failed to deserialize chat config item: {err:?}");
+            None
+        }
+    }
+}
+
+// static const uint32_t DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID = 0xa0001 ;
+const DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID: u32 = 0xa0001;
+
 macro_rules! verify_item {
     ($self:expr, $item:expr, $packet:expr) => {
         let key_id = $item.bounce_obj.signature.key_id.to_owned();
@@ -151,70 +183,50 @@ macro_rules! sign_item {
 // ChatLobbyFlags lobby_flags ;				// see RS_CHAT_LOBBY_PRIVACY_LEVEL_PUBLIC / RS_CHAT_LOBBY_PRIVACY_LEVEL_PRIVATE
 
 pub struct Chat {
-    rx: UnboundedReceiver<Intercom>,
-
     core: Arc<DataCore>,
-    core_tx: UnboundedSender<Intercom>,
-
-    cmd_rx: UnboundedReceiver<ChatCmd>,
+    core_tx: Sender<Intercom>,
 
-    auto_join: Vec<ChatLobbyId>,
-
-    // TODO this id is used for auto joining
-    own_gxs_id: Arc<GxsId>,
-
-    timer_lobby_request: Interval,
-    timer_lobby_maintenance: Interval,
-    timer_lobby_keep_alive: Interval,
+    cmd_rx: Receiver<ChatCmd>,
+    gxs_tunnel_rx: Receiver<GxsTunnelEvent>,
 }
 
-/*
-TODOs:
-- banning
-*/
+pub enum ChatInput {
+    Cmd(ChatCmd),
+    GxsTunnel(GxsTunnelEvent),
+}
 
 impl Chat {
-    pub async fn new(
-        core: &Arc<DataCore>,
-        core_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-    ) -> Chat {
+    pub async fn new(core: &Arc<DataCore>, core_tx: Sender<Intercom>) -> Chat {
         let data = core.get_service_data().chat();
 
-        let (tx_chat, rx_chat) = unbounded_channel();
+        let (tx_chat, rx_chat) = channel("chat commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
         *data.cmd.write().await = Some(tx_chat);
 
-        // TODO FIXME
-        // let own_gxs_id = dc.get_identities_summaries().await.iter().find(|&entry| entry.)
-        let own_gxs_id = Arc::new("c59df722f56f2f886ac301acc5572e03".into());
+        let passwd = core.get_own_keypair().database_password();
+        match ChatHistory::new_file(core.get_config_dir().join(CHAT_HISTORY_FILE), &passwd) {
+            Ok(history) => *data.history.lock().await = Some(history),
+            Err(err) => warn!("failed to open chat history: {err}"),
+        }
 
-        Chat {
-            rx,
+        let (tx_gxs_tunnel, rx_gxs_tunnel) = channel(
+            "chat gxs tunnel events",
+            SERVICE_EVENTS_QUEUE_SIZE,
+            Policy::DropOldest,
+        );
+        core.get_service_data()
+            .gxs_tunnel()
+            .register_client(DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID, tx_gxs_tunnel);
 
+        Chat {
             core: core.clone(),
             core_tx,
 
             cmd_rx: rx_chat,
-
-            // TODO FIXME
-            // { id: 4347301314802127616, name: StringTagged("test") }
-            // { id: 7555643923972858789, name: StringTagged("Retroshare Devel (signed)") }
-            // { id: 8705058284245932812, name: StringTagged("][German][Deutsch][") }
-            auto_join: [
-                8705058284245932812,
-                7555643923972858789,
-                4347301314802127616,
-            ]
-            .into(),
-            own_gxs_id,
-
-            timer_lobby_request: interval(Duration::from_secs(120)),
-            timer_lobby_maintenance: interval(Duration::from_secs(30)),
-            timer_lobby_keep_alive: interval(Duration::from_secs(120)),
+            gxs_tunnel_rx: rx_gxs_tunnel,
         }
     }
 
-    fn send_packet<T>(&self, sub_type: u8, item: &T, receiving_peer: Arc<PeerId>)
+    async fn send_packet<T>(&self, sub_type: u8, item: &T, receiving_peer: Arc<PeerId>)
     where
         T: Serialize,
     {
@@ -224,10 +236,12 @@ impl Chat {
 
         self.core_tx
             .send(Intercom::Send(packet))
+            .await
             .expect("failed to send to core");
     }
 
-    async fn handle_incoming(&self, header: &ServiceHeader, mut packet: Packet) {
+    async fn handle_incoming(&self, mut packet: Packet) {
+        let header = ServiceHeader::from(packet.header.to_owned());
         trace!("[Chat] {header:?}");
 
         // just a read on a RwLock
@@ -235,20 +249,25 @@ impl Chat {
 
         #[allow(non_upper_case_globals)]
         match header.sub_type {
-            CHAT_SUB_TYPE_CHAT_AVATAR => debug!("AVATAR"),
-            CHAT_SUB_TYPE_CHAT_STATUS => debug!("STATUS"),
+            CHAT_SUB_TYPE_CHAT_AVATAR => {
+                let item: ChatAvatarItem = from_retroshare_wire(&mut packet.payload);
+                trace!("CHAT_SUB_TYPE_CHAT_AVATAR {} bytes", item.image_data.len());
+
+                self.handle_avatar(item, packet.peer_id.to_owned()).await;
+            }
             CHAT_SUB_TYPE_CHAT_LOBBY_ACCEPT
-            | CHAT_SUB_TYPE_CHAT_LOBBY_CONFIG
             | CHAT_SUB_TYPE_CHAT_LOBBY_EVENT
-            | CHAT_SUB_TYPE_CHAT_LOBBY_INVITE
             | CHAT_SUB_TYPE_CHAT_LOBBY_UNSUBSCRIBE => debug!("LOBBY"),
             CHAT_SUB_TYPE_CHAT_LOBBY_MSG => debug!("MSG"),
 
+            // distant chat is handled through gxs tunnels, these are old (or config) items
             CHAT_SUB_TYPE_DISTANT_CHAT_DH_PUBLIC_KEY | CHAT_SUB_TYPE_DISTANT_INVITE_CONFIG => {
                 debug!("DISTANT")
             }
             CHAT_SUB_TYPE_OUTGOING_MAP => debug!("OUTGOING MAP"),
+            // config items are not supposed to be send
             CHAT_SUB_TYPE_PRIVATECHATMSG_CONFIG => debug!("PRIVATE MSG"),
+            CHAT_SUB_TYPE_CHAT_LOBBY_CONFIG => debug!("LOBBY CONFIG"),
             CHAT_SUB_TYPE_SUBSCRIBED_CHAT_LOBBY_CONFIG => debug!("SUBSCRIBED CHAT"),
 
             #[allow(deprecated)]
@@ -265,19 +284,25 @@ impl Chat {
             CHAT_SUB_TYPE_CHAT_DEFAULT => {
                 trace!("[Chat] ChatMsgItem");
 
-                let msg: ChatMsgItem = from_retroshare_wire(&mut packet.payload);
+                let mut msg: ChatMsgItem = from_retroshare_wire(&mut packet.payload);
+                msg.recv_time = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as u32;
                 trace!("CHAT_SUB_TYPE_CHAT_DEFAULT {msg:?}");
 
-                if msg.chat_flags.contains(ChatLobbyFlags::PRIVATE) {
-                    info!(
-                        "received (direct) chat message from {}: {}",
-                        packet.peer_id(),
-                        msg.message
-                    );
-                }
-                // self.core_tx
-                //     .send(Intercom::Event(EventType::ChatMessage { msg: msg.into() }))
-                //     .expect("failed to send to core");
+                self.handle_chat_msg(msg, packet.peer_id.to_owned()).await;
+            }
+            CHAT_SUB_TYPE_CHAT_STATUS => {
+                let status: ChatStatusItem = from_retroshare_wire(&mut packet.payload);
+                trace!("CHAT_SUB_TYPE_CHAT_STATUS {status:?}");
+
+                // e.g. "is typing..."
+                debug!(
+                    "received status from {}: {}",
+                    packet.peer_id(),
+                    status.status_string
+                );
             }
             CHAT_SUB_TYPE_CHAT_LOBBY_CHALLENGE => {
                 trace!("[Chat] lobby challenge");
@@ -310,28 +335,41 @@ impl Chat {
                             let packet = self.build_lobby_invite(lobby);
                             self.core_tx
                                 .send(Intercom::Send(packet))
+                                .await
                                 .expect("failed to send to core");
                             break;
                         }
                     }
                 }
             }
+            CHAT_SUB_TYPE_CHAT_LOBBY_INVITE => {
+                let invite: ChatLobbyInviteItem = from_retroshare_wire(&mut packet.payload);
+                trace!("CHAT_SUB_TYPE_CHAT_LOBBY_INVITE {invite:?}");
+
+                self.handle_lobby_invite(invite, packet.peer_id.to_owned())
+                    .await;
+            }
             CHAT_SUB_TYPE_CHAT_LOBBY_LIST_REQUEST => {
                 trace!("[Chat] requested lobbies");
                 assert!(packet.payload.is_empty());
 
+                // private lobbies are only visible to friends participating in them
                 let lobbies = data
                     .lobbies
                     .read()
                     .await
                     .iter()
+                    .filter(|(_id, lobby)| {
+                        lobby.lobby_flags.contains(ChatLobbyFlags::LOBBY_PUBLIC)
+                            || lobby.participating_friends.contains_key(&packet.peer_id)
+                    })
                     .map(|(_id, lobby)| lobby.into())
                     .collect();
                 let list = ChatLobbyListItem { lobbies };
 
                 // let payload = to_retroshare_wire(&list);
                 // let header =
-                //     ServiceHeader::new(self.get_id(), CHAT_SUB_TYPE_CHAT_LOBBY_LIST, &payload)
+                //     ServiceHeader::new(Self::SERVICE, CHAT_SUB_TYPE_CHAT_LOBBY_LIST, &payload)
                 //         .into();
                 // return handle_packet!(Packet::new(header, payload, packet.peer_id.to_owned()));
                 self.send_packet(
@@ -339,6 +377,7 @@ impl Chat {
                     &list,
                     packet.peer_id.to_owned(),
                 )
+                .await
             }
             CHAT_SUB_TYPE_CHAT_LOBBY_LIST => {
                 let list: ChatLobbyListItem = from_retroshare_wire(&mut packet.payload);
@@ -355,36 +394,22 @@ impl Chat {
                     entry.update_max_peers(lobby.count);
                 }
 
-                // for auto_join in &self.auto_join {
-                //     if let Some(lobby) = lock.get_mut(auto_join) {
-                //         if !lobby.joined {
-                //             // FIXME, we are holding the lock for the whole duration of the call
-                //             // this also triggers a keep alive
-                //             self.join_lobby(lobby, self.own_gxs_id.to_owned()).await;
-                //         }
-                //     }
-                // }
-
                 // check for joinable lobbies
-                let join_id: Vec<_> = self
-                    .auto_join
+                let join_id: Vec<_> = data
+                    .subscriptions
+                    .read()
+                    .await
                     .iter()
-                    .filter_map(|auto_join| {
-                        if let Some(lobby) = lock.get_mut(auto_join) {
-                            if lobby.joined {
-                                None
-                            } else {
-                                Some(lobby.lobby_id)
-                            }
-                        } else {
-                            None
-                        }
+                    .filter(|(_, subscription)| subscription.auto_join)
+                    .filter_map(|(lobby_id, subscription)| match lock.get(lobby_id) {
+                        Some(lobby) if !lobby.joined => Some((*lobby_id, subscription.gxs_id)),
+                        _ => None,
                     })
                     .collect();
                 drop(lock);
 
-                for lobby_id in join_id {
-                    self.join_lobby(lobby_id, self.own_gxs_id.to_owned()).await;
+                for (lobby_id, gxs_id) in join_id {
+                    self.join_lobby(lobby_id, Arc::new(gxs_id)).await;
                 }
 
                 // for lobby_id in joined_id {
@@ -440,6 +465,78 @@ impl Chat {
         }
     }
 
+    async fn handle_chat_msg(&self, mut msg: ChatMsgItem, peer_id: Arc<PeerId>) {
+        let data = self.core.get_service_data().chat();
+
+        // avatars are requested (and announced) through chat message flags, usually with an empty message
+        let avatar_flags = ChatLobbyFlags::REQUESTS_AVATAR | ChatLobbyFlags::AVATAR_AVAILABLE;
+        if msg.chat_flags.intersects(avatar_flags) {
+            if msg.chat_flags.contains(ChatLobbyFlags::REQUESTS_AVATAR) {
+                self.send_avatar(&peer_id).await;
+            }
+            if msg.chat_flags.contains(ChatLobbyFlags::AVATAR_AVAILABLE) {
+                // the friend has a new avatar
+                self.request_avatar(peer_id.to_owned()).await;
+            }
+
+            let text: &str = msg.message.as_ref();
+            if text.is_empty() {
+                return;
+            }
+        }
+
+        // long messages are split up, put them back together
+        {
+            let mut lock = data.partial_private.write().await;
+            if msg.chat_flags.contains(ChatLobbyFlags::PARTIAL_MESSAGE) {
+                lock.entry(peer_id)
+                    .or_default()
+                    .push_str(msg.message.as_ref());
+                return;
+            }
+            if let Some(mut message) = lock.remove(&peer_id) {
+                message.push_str(msg.message.as_ref());
+                msg.message = message.into();
+            }
+        }
+
+        let private = msg.chat_flags.contains(ChatLobbyFlags::PRIVATE);
+        info!(
+            "received {} chat message from {peer_id}: {}",
+            if private { "(direct)" } else { "(broadcast)" },
+            msg.message
+        );
+
+        let event = if private {
+            ChatMessage {
+                chat_id: (*peer_id).into(),
+                chatflags: ChatFlags::Private,
+                ..Default::default()
+            }
+        } else {
+            ChatMessage {
+                chat_id: ChatId {
+                    ty: ChatIdType::TypeBroadcast,
+                    ..Default::default()
+                },
+                broadcast_peer_id: (*peer_id).into(),
+                chatflags: ChatFlags::Public,
+                ..Default::default()
+            }
+        };
+        let msg = ChatMessage {
+            send_time: msg.send_time,
+            recv_time: msg.recv_time,
+            msg: msg.message.into(),
+            incoming: true,
+            online: true,
+            ..event
+        };
+
+        // fire event
+        self.fire_chat_message(msg).await;
+    }
+
     async fn handle_chat_lobby_event(&self, event: ChatLobbyEventItem, packet: Packet) {
         let data = self.core.get_service_data().chat();
 
@@ -473,41 +570,43 @@ impl Chat {
                 packet.peer_id = participant.to_owned();
                 self.core_tx
                     .send(Intercom::Send(packet))
+                    .await
                     .expect("failed to send");
             }
 
-            let key_id = &event.bounce_obj.signature.key_id;
+            let gxs_id = Arc::new(event.bounce_obj.signature.key_id.to_owned().into());
+            let nick: &str = event.bounce_obj.nick.as_ref();
 
             use ChatLobbyEvent::*;
             match event.event_type {
                 KeepAlive => {
-                    *lobby
-                        .participants
-                        .entry(Arc::new(key_id.to_owned().into()))
-                        .or_insert(SystemTime::now()) = SystemTime::now();
+                    lobby.touch_participant(gxs_id, nick);
+                }
+                PeerChangeNickname => {
+                    debug!(
+                        "{nick} is now known as {} in {}",
+                        event.string1, lobby.lobby_name
+                    );
+                    lobby.touch_participant(gxs_id, nick).nickname = event.string1.to_string();
                 }
-                PeerChangeNickname => (),
                 PeerJoined => {
                     let check = lobby
                         .participants
-                        .insert(Arc::new(key_id.to_owned().into()), SystemTime::now());
+                        .insert(gxs_id, LobbyParticipant::new(nick.to_owned()));
                     if check.is_some() {
                         warn!(
-                            "added peer {} to lobby {} but they are already part of it",
-                            key_id.to_string(),
-                            lobby.lobby_name
+                            "added peer {} ({nick}) to lobby {} but they are already part of it",
+                            event.bounce_obj.signature.key_id, lobby.lobby_name
                         )
                     }
                     // FIXME?
                     // trigger a keep alive packets so as to inform the new participant of our presence in the chatroom
                     // it->second.last_keep_alive_packet_time = 0 ;
                 }
-                PeerLeft => {
-                    _ = lobby
-                        .participants
-                        .remove(&Arc::new(key_id.to_owned().into()))
+                PeerLeft => _ = lobby.participants.remove(&gxs_id),
+                PeerStatus => {
+                    lobby.touch_participant(gxs_id, nick).status = event.string1.to_string();
                 }
-                PeerStatus => (),
             }
 
             // TODO fire event to the rest of the code
@@ -525,8 +624,8 @@ impl Chat {
         // verify
         verify_item!(self, msg, packet);
 
-        let lock = data.lobbies.read().await;
-        if let Some(lobby) = lock.get(&msg.bounce_obj.public_lobby_id) {
+        let mut lock = data.lobbies.write().await;
+        if let Some(lobby) = lock.get_mut(&msg.bounce_obj.public_lobby_id) {
             info!("received message in {}:", lobby.lobby_name);
             info!(" -> [{}] {}", msg.bounce_obj.nick, msg.msg_obj.message);
 
@@ -541,21 +640,39 @@ impl Chat {
                 packet.peer_id = participant.to_owned();
                 self.core_tx
                     .send(Intercom::Send(packet))
+                    .await
                     .expect("failed to send");
             }
+
+            lobby.touch_participant(
+                Arc::new(msg.bounce_obj.signature.key_id.to_owned().into()),
+                msg.bounce_obj.nick.as_ref(),
+            );
+        }
+        drop(lock);
+
+        // muted identities are still relayed, they are only hidden locally
+        let gxs_id = msg.bounce_obj.signature.key_id.to_owned().into();
+        if data.moderation.read().await.is_muted(&gxs_id) {
+            debug!("dropping message from muted identity {gxs_id}");
+            return;
         }
 
         // fire event
-        self.core_tx
-            .send(Intercom::Event(EventType::ChatMessage { msg: msg.into() }))
-            .expect("failed to send to core");
+        let msg = ChatMessage {
+            peer_alternate_nickname: msg.bounce_obj.nick.to_owned().into(),
+            incoming: true,
+            online: true,
+            ..msg.into()
+        };
+        self.fire_chat_message(msg).await;
     }
 
-    fn request_lobbies(&self) {
+    async fn request_lobbies(&self) {
         let payload = vec![];
 
         let header = ServiceHeader::new(
-            self.get_id(),
+            Self::SERVICE,
             CHAT_SUB_TYPE_CHAT_LOBBY_LIST_REQUEST,
             &payload,
         )
@@ -564,6 +681,7 @@ impl Chat {
 
         self.core_tx
             .send(Intercom::Send(packet))
+            .await
             .expect("failed to send to core");
     }
 
@@ -614,7 +732,7 @@ impl Chat {
         };
 
         let header = ServiceHeader::new(
-            self.get_id(),
+            Self::SERVICE,
             CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_EVENT,
             &vec![],
         );
@@ -624,7 +742,7 @@ impl Chat {
 
         let payload = to_retroshare_wire(&event);
         let header = ServiceHeader::new(
-            self.get_id(),
+            Self::SERVICE,
             CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_EVENT,
             &payload,
         );
@@ -668,6 +786,7 @@ impl Chat {
         for packet in packets {
             self.core_tx
                 .send(Intercom::Send(packet))
+                .await
                 .expect("failed to send to core");
         }
     }
@@ -675,11 +794,125 @@ impl Chat {
     fn build_lobby_invite(&self, lobby: &Lobby) -> Packet {
         let invite: ChatLobbyInviteItem = lobby.into();
         let payload = to_retroshare_wire(&invite);
-        let header = ServiceHeader::new(self.get_id(), CHAT_SUB_TYPE_CHAT_LOBBY_INVITE, &payload);
+        let header = ServiceHeader::new(Self::SERVICE, CHAT_SUB_TYPE_CHAT_LOBBY_INVITE, &payload);
         Packet::new_without_location(header.into(), payload)
     }
 
+    async fn handle_lobby_invite(&self, invite: ChatLobbyInviteItem, peer_id: Arc<PeerId>) {
+        let data = self.core.get_service_data().chat();
+
+        // when we are subscribed already, the invite tells us that our friend joined
+        if let Some(lobby) = data.lobbies.write().await.get_mut(&invite.lobby_id) {
+            if lobby.joined {
+                debug!("{peer_id} joined lobby {}", lobby.lobby_name);
+                lobby.update_participant(peer_id);
+                return;
+            }
+        }
+
+        info!(
+            "received invite to lobby {} from {peer_id}",
+            invite.lobby_name
+        );
+        data.invites
+            .write()
+            .await
+            .insert(invite.lobby_id, (peer_id, invite));
+    }
+
+    async fn create_lobby(
+        &self,
+        name: String,
+        topic: String,
+        flags: ChatLobbyFlags,
+        gxs_id: Arc<GxsId>,
+    ) -> ChatLobbyId {
+        let lobby_id = {
+            let mut lock = self.core.get_service_data().chat().lobbies.write().await;
+
+            let mut rng = nanorand::WyRand::new();
+            let lobby_id = loop {
+                let lobby_id: ChatLobbyId = rng.generate();
+                if lobby_id != 0 && !lock.contains_key(&lobby_id) {
+                    break lobby_id;
+                }
+            };
+
+            info!("creating lobby {name} ({lobby_id})");
+            let invite = ChatLobbyInviteItem {
+                lobby_id,
+                lobby_name: name.into(),
+                lobby_topic: topic.into(),
+                lobby_flags: flags,
+            };
+            lock.insert(lobby_id, invite.into());
+            lobby_id
+        };
+
+        self.join_lobby(lobby_id, gxs_id).await;
+        lobby_id
+    }
+
+    async fn invite_to_lobby(&self, lobby_id: ChatLobbyId, peer_id: Arc<PeerId>) {
+        let mut packet = match self
+            .core
+            .get_service_data()
+            .chat()
+            .lobbies
+            .read()
+            .await
+            .get(&lobby_id)
+        {
+            Some(lobby) if lobby.joined => self.build_lobby_invite(lobby),
+            _ => {
+                warn!("cannot invite to lobby {lobby_id}, we are not subscribed");
+                return;
+            }
+        };
+
+        info!("inviting {peer_id} to lobby {lobby_id}");
+        packet.peer_id = peer_id;
+        self.core_tx
+            .send(Intercom::Send(packet))
+            .await
+            .expect("failed to send");
+    }
+
+    /// (Re)sends invites to a (re)connected friend for all lobbies they are participating in.
+    async fn send_lobby_invites(&self, peer_id: &Arc<PeerId>) {
+        let packets: Vec<_> = self
+            .core
+            .get_service_data()
+            .chat()
+            .lobbies
+            .read()
+            .await
+            .values()
+            .filter(|lobby| lobby.joined && lobby.participating_friends.contains_key(peer_id))
+            .map(|lobby| {
+                let mut packet = self.build_lobby_invite(lobby);
+                packet.peer_id = peer_id.to_owned();
+                packet
+            })
+            .collect();
+
+        for packet in packets {
+            self.core_tx
+                .send(Intercom::Send(packet))
+                .await
+                .expect("failed to send");
+        }
+    }
+
     async fn join_lobby(&self, lobby: ChatLobbyId, gxs_id: Arc<GxsId>) {
+        let nick = self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_group_meta(&(*gxs_id).into())
+            .await
+            .map_or(String::new(), |details| details.group_name);
+
         // get lobby
         let mut lock = self.core.get_service_data().chat().lobbies.write().await;
         let mut lobby = {
@@ -697,15 +930,25 @@ impl Chat {
         // update lobby
         lobby.joined = true;
         lobby.gxs_id = Some(*gxs_id);
-        *lobby
-            .participants
-            .entry(gxs_id)
-            .or_insert(SystemTime::now()) = SystemTime::now();
+        let participant = lobby.touch_participant(gxs_id, &nick);
+        participant.nickname = nick;
 
         // drop lock and keep a local copy of the lobby
         let lobby = lobby.to_owned();
         drop(lock);
 
+        // remember the lobby (and the used identity)
+        let gxs_id = lobby.gxs_id.unwrap();
+        self.core
+            .get_service_data()
+            .chat()
+            .subscriptions
+            .write()
+            .await
+            .entry(lobby.lobby_id)
+            .or_insert_with(|| LobbySubscription::new(gxs_id))
+            .gxs_id = gxs_id;
+
         let packet = self.build_lobby_invite(&lobby);
 
         for (peer, _) in &lobby.participating_friends {
@@ -713,6 +956,7 @@ impl Chat {
             p.peer_id = peer.to_owned();
             self.core_tx
                 .send(Intercom::Send(p))
+                .await
                 .expect("failed to send");
         }
 
@@ -723,8 +967,11 @@ impl Chat {
         {
             self.core_tx
                 .send(Intercom::Send(packet))
+                .await
                 .expect("failed to send");
         }
+
+        self.save_config().await;
     }
 
     async fn leave_lobby(&self, lobby: &mut Lobby) {
@@ -743,6 +990,7 @@ impl Chat {
         {
             self.core_tx
                 .send(Intercom::Send(packet))
+                .await
                 .expect("failed to send");
         }
     }
@@ -768,14 +1016,14 @@ impl Chat {
         };
 
         let header =
-            ServiceHeader::new(self.get_id(), CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_MSG, &vec![]);
+            ServiceHeader::new(Self::SERVICE, CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_MSG, &vec![]);
         if !sign_item!(self, msg, header) {
             return;
         }
 
         let payload = to_retroshare_wire(&msg);
         let header =
-            ServiceHeader::new(self.get_id(), CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_MSG, &payload);
+            ServiceHeader::new(Self::SERVICE, CHAT_SUB_TYPE_CHAT_LOBBY_SIGNED_MSG, &payload);
         let packet = Packet::new_without_location(header.into(), payload);
 
         for (peer, _) in &lobby.participating_friends {
@@ -783,8 +1031,18 @@ impl Chat {
             p.peer_id = peer.to_owned();
             self.core_tx
                 .send(Intercom::Send(p))
+                .await
                 .expect("failed to send");
         }
+
+        // let the web ui know, too
+        let msg = ChatMessage {
+            peer_alternate_nickname: msg.bounce_obj.nick.to_owned().into(),
+            incoming: false,
+            online: true,
+            ..msg.into()
+        };
+        self.fire_chat_message(msg).await;
     }
 
     async fn handle_cmd(&self, msg: ChatCmd) {
@@ -814,6 +1072,135 @@ impl Chat {
                 if let Some(lobby) = lock.get_mut(&lobby) {
                     self.leave_lobby(lobby).await;
                 }
+                drop(lock);
+
+                data.subscriptions.write().await.remove(&lobby);
+                self.save_config().await;
+            }
+            ChatCmd::SetLobbySubscription {
+                lobby_id,
+                auto_join,
+                notify,
+            } => {
+                match data.subscriptions.write().await.get_mut(&lobby_id) {
+                    Some(subscription) => {
+                        if let Some(auto_join) = auto_join {
+                            subscription.auto_join = auto_join;
+                        }
+                        if let Some(notify) = notify {
+                            subscription.notify = notify;
+                        }
+                        debug!("updated subscription of lobby {lobby_id}: {subscription:?}");
+                    }
+                    None => {
+                        warn!("cannot update lobby {lobby_id}, we are not subscribed");
+                        return;
+                    }
+                }
+
+                self.save_config().await;
+            }
+            ChatCmd::SetBanned {
+                lobby_id,
+                gxs_id,
+                banned,
+            } => {
+                info!("setting {gxs_id} banned ({banned}) in lobby {lobby_id:?}");
+                data.moderation
+                    .write()
+                    .await
+                    .set_banned(lobby_id, gxs_id, banned);
+                self.save_config().await;
+            }
+            ChatCmd::SetMuted(gxs_id, muted) => {
+                info!("setting {gxs_id} muted ({muted})");
+                data.moderation.write().await.set_muted(gxs_id, muted);
+                self.save_config().await;
+            }
+            ChatCmd::SetMinReputation(score) => {
+                data.moderation.write().await.min_reputation = score;
+                self.save_config().await;
+            }
+            ChatCmd::SetOwnAvatar(image_data) => {
+                if image_data.len() > MAX_AVATAR_SIZE {
+                    warn!("avatar is too large ({} bytes)", image_data.len());
+                    return;
+                }
+
+                *data.own_avatar.write().await = image_data;
+                // let everybody know (with the next message)
+                data.avatars
+                    .write()
+                    .await
+                    .values_mut()
+                    .for_each(|avatar| avatar.own_sent = false);
+                self.save_config().await;
+            }
+            ChatCmd::RequestAvatar(peer_id) => {
+                let peer_id = Arc::new(peer_id);
+                if !self.core.is_online(peer_id.to_owned()).await {
+                    return;
+                }
+                if data
+                    .avatars
+                    .write()
+                    .await
+                    .entry(peer_id.to_owned())
+                    .or_default()
+                    .should_request()
+                {
+                    self.request_avatar(peer_id).await;
+                }
+            }
+            ChatCmd::CreateLobby {
+                name,
+                topic,
+                flags,
+                gxs_id,
+                invited,
+                tx,
+            } => {
+                let lobby_id = self
+                    .create_lobby(name, topic, flags, Arc::new(gxs_id))
+                    .await;
+
+                for peer_id in invited {
+                    self.invite_to_lobby(lobby_id, Arc::new(peer_id)).await;
+                }
+                _ = tx.send(lobby_id);
+            }
+            ChatCmd::InviteToLobby(lobby_id, peer_id) => {
+                self.invite_to_lobby(lobby_id, Arc::new(peer_id)).await;
+            }
+            ChatCmd::AcceptLobbyInvite(lobby_id, gxs_id) => {
+                let (peer_id, invite) = match data.invites.write().await.remove(&lobby_id) {
+                    Some(invite) => invite,
+                    None => {
+                        warn!("there is no pending invite for lobby {lobby_id}");
+                        return;
+                    }
+                };
+                info!(
+                    "accepting invite to lobby {} from {peer_id}",
+                    invite.lobby_name
+                );
+
+                data.lobbies
+                    .write()
+                    .await
+                    .entry(lobby_id)
+                    .or_insert_with(|| invite.into())
+                    .update_participant(peer_id);
+                // this also sends an invite back, letting our friend know that we joined
+                self.join_lobby(lobby_id, Arc::new(gxs_id)).await;
+            }
+            ChatCmd::DenyLobbyInvite(lobby_id) => {
+                if let Some((peer_id, invite)) = data.invites.write().await.remove(&lobby_id) {
+                    info!(
+                        "denying invite to lobby {} from {peer_id}",
+                        invite.lobby_name
+                    );
+                }
             }
             ChatCmd::SendMessage(lobby_id, msg) => {
                 info!("SendMessage: msg {msg} to {lobby_id:?}");
@@ -836,9 +1223,518 @@ impl Chat {
 
                         self.send_message_lobby(&lobby, &msg).await;
                     }
+                    ChatIdType::TypePrivate => {
+                        self.send_message_private(Arc::new(*lobby_id.peer_id), &msg)
+                            .await;
+                    }
+                    ChatIdType::TypePrivateDistant => {
+                        let tunnel_id = GxsTunnelId::from(**lobby_id.distant_chat_id);
+                        self.send_message_distant(tunnel_id, &msg).await;
+                    }
                     _ => warn!("chat type {:?} is not supported", lobby_id.ty),
                 }
             }
+            ChatCmd::SendStatus(chat_id, status) => {
+                debug!("SendStatus: status {status} to {chat_id:?}");
+
+                match chat_id.ty {
+                    ChatIdType::TypePrivate => {
+                        let peer_id = Arc::new(*chat_id.peer_id);
+
+                        // status updates are not queued
+                        if self.core.is_online(peer_id.to_owned()).await {
+                            let item = ChatStatusItem {
+                                flags: ChatLobbyFlags::PRIVATE.bits(),
+                                status_string: status,
+                            };
+                            self.send_packet(CHAT_SUB_TYPE_CHAT_STATUS, &item, peer_id)
+                                .await;
+                        }
+                    }
+                    ChatIdType::TypeLobby => {
+                        let lobby = match data.lobbies.read().await.get(&chat_id.lobby_id.into()) {
+                            Some(lobby) if lobby.joined => lobby.to_owned(),
+                            _ => return,
+                        };
+
+                        for packet in self
+                            .send_lobby_event(&lobby, ChatLobbyEvent::PeerStatus, Some(status))
+                            .await
+                        {
+                            self.core_tx
+                                .send(Intercom::Send(packet))
+                                .await
+                                .expect("failed to send");
+                        }
+                    }
+                    _ => warn!("chat type {:?} is not supported", chat_id.ty),
+                }
+            }
+            ChatCmd::InitiateDistantChat(to_id, from_id) => {
+                info!("initiating distant chat from {from_id} to {to_id}");
+
+                self.send_gxs_tunnel_cmd(GxsTunnelCmd::RequestTunnel {
+                    own_id: from_id,
+                    remote_id: to_id,
+                    service_id: DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID,
+                });
+            }
+            ChatCmd::CloseDistantChat(distant_chat_id) => {
+                info!("closing distant chat {distant_chat_id}");
+
+                self.send_gxs_tunnel_cmd(GxsTunnelCmd::CloseTunnel {
+                    tunnel_id: GxsTunnelId::from(*distant_chat_id),
+                    service_id: DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID,
+                });
+            }
+        }
+    }
+
+    async fn send_message_private(&self, peer_id: Arc<PeerId>, msg: &str) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        let mut item = ChatMsgItem {
+            chat_flags: ChatLobbyFlags::PRIVATE,
+            send_time: now,
+            message: msg.into(),
+            recv_time: now,
+        };
+
+        // piggyback avatar requests and announcements like RS does
+        {
+            let chat = self.core.get_service_data().chat();
+            let has_own_avatar = !chat.own_avatar.read().await.is_empty();
+            let mut avatars = chat.avatars.write().await;
+            let avatar = avatars.entry(peer_id.to_owned()).or_default();
+            if avatar.should_request() {
+                item.chat_flags |= ChatLobbyFlags::REQUESTS_AVATAR;
+            }
+            if has_own_avatar && !avatar.own_sent {
+                item.chat_flags |= ChatLobbyFlags::AVATAR_AVAILABLE;
+            }
+        }
+
+        let online = self.core.is_online(peer_id.to_owned()).await;
+        if online {
+            self.send_packet(CHAT_SUB_TYPE_CHAT_DEFAULT, &item, peer_id.to_owned())
+                .await;
+        } else {
+            debug!("{peer_id} is offline, queuing message");
+
+            self.core
+                .get_service_data()
+                .chat()
+                .pending_private
+                .write()
+                .await
+                .push((peer_id.to_owned(), item));
+            self.save_config().await;
+        }
+
+        // let the web ui know, too
+        let msg = ChatMessage {
+            chat_id: (*peer_id).into(),
+            chatflags: ChatFlags::Private,
+            send_time: now,
+            recv_time: now,
+            msg: msg.to_owned(),
+            incoming: false,
+            online,
+            ..Default::default()
+        };
+        self.fire_chat_message(msg).await;
+    }
+
+    async fn handle_avatar(&self, item: ChatAvatarItem, peer_id: Arc<PeerId>) {
+        if item.image_data.len() > MAX_AVATAR_SIZE {
+            warn!(
+                "received avatar from {peer_id} is too large ({} bytes), dropping",
+                item.image_data.len()
+            );
+            return;
+        }
+
+        debug!("received avatar from {peer_id}");
+        let mut avatars = self.core.get_service_data().chat().avatars.write().await;
+        let avatar = avatars.entry(peer_id).or_default();
+        avatar.data = item.image_data;
+        avatar.last_update = Some(SystemTime::now());
+    }
+
+    /// Sends our own avatar to a friend (if we have one).
+    async fn send_avatar(&self, peer_id: &Arc<PeerId>) {
+        let chat = self.core.get_service_data().chat();
+        let item = ChatAvatarItem {
+            image_data: chat.own_avatar.read().await.to_owned(),
+        };
+        if item.image_data.is_empty() {
+            debug!("{peer_id} requested our avatar but we have none");
+            return;
+        }
+
+        debug!("sending avatar to {peer_id}");
+        self.send_packet(CHAT_SUB_TYPE_CHAT_AVATAR, &item, peer_id.to_owned())
+            .await;
+        chat.avatars
+            .write()
+            .await
+            .entry(peer_id.to_owned())
+            .or_default()
+            .own_sent = true;
+    }
+
+    /// Asks a friend for their avatar, this is done by sending an empty chat message.
+    async fn request_avatar(&self, peer_id: Arc<PeerId>) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        let item = ChatMsgItem {
+            chat_flags: ChatLobbyFlags::PRIVATE | ChatLobbyFlags::REQUESTS_AVATAR,
+            send_time: now,
+            message: String::new().into(),
+            recv_time: now,
+        };
+
+        debug!("requesting avatar from {peer_id}");
+        self.send_packet(CHAT_SUB_TYPE_CHAT_DEFAULT, &item, peer_id)
+            .await;
+    }
+
+    async fn send_pending_private(&self, peer_id: &Arc<PeerId>) {
+        let pending: Vec<_> = {
+            let mut lock = self
+                .core
+                .get_service_data()
+                .chat()
+                .pending_private
+                .write()
+                .await;
+            let (pending, keep) = lock.drain(..).partition(|(p, _)| p == peer_id);
+            *lock = keep;
+            pending
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        info!("sending {} queued message(s) to {peer_id}", pending.len());
+        for (peer_id, msg) in pending {
+            self.send_packet(CHAT_SUB_TYPE_CHAT_DEFAULT, &msg, peer_id)
+                .await;
+        }
+        self.save_config().await;
+    }
+
+    async fn load_config(&self) {
+        let data = match self.core.load_config(CHAT_CONFIG_FILE) {
+            Some(data) => data,
+            None => return,
+        };
+
+        let mut pending = vec![];
+        let mut lobby_flags = HashMap::new();
+        let mut lobbies = vec![];
+        let mut moderation = LobbyModeration::default();
+        let mut own_avatar = vec![];
+        for (header, payload) in read_config_records(data, CHAT_CONFIG_FILE) {
+            let sub_type = match header {
+                Header::Service {
+                    service: ServiceType::Chat,
+                    sub_type,
+                    ..
+                } => sub_type,
+                _ => {
+                    warn!("failed to parse {CHAT_CONFIG_FILE}");
+                    break;
+                }
+            };
+
+            match sub_type {
+                CHAT_SUB_TYPE_PRIVATECHATMSG_CONFIG => {
+                    if let Some(item) = deserialize_item::<PrivateChatMsgConfigItem>(payload) {
+                        let (peer_id, msg) = item.into();
+                        pending.push((Arc::new(peer_id), msg));
+                    }
+                }
+                CHAT_SUB_TYPE_CHAT_LOBBY_CONFIG => {
+                    if let Some(item) = deserialize_item::<ChatLobbyConfigItem>(payload) {
+                        lobby_flags.insert(item.lobby_id, item.flags);
+                    }
+                }
+                CHAT_SUB_TYPE_SUBSCRIBED_CHAT_LOBBY_CONFIG => {
+                    if let Some(item) = deserialize_item::<SubscribedChatLobbyConfigItem>(payload) {
+                        lobbies.push(item);
+                    }
+                }
+                CHAT_SUB_TYPE_CHAT_AVATAR => {
+                    if let Some(item) = deserialize_item::<ChatAvatarItem>(payload) {
+                        own_avatar = item.image_data;
+                    }
+                }
+                CHAT_SUB_TYPE_MODERATION_CONFIG => {
+                    if let Some(item) = deserialize_item::<ChatModerationConfigItem>(payload) {
+                        moderation.min_reputation = item.min_reputation;
+                        moderation.banned.extend(item.banned);
+                        moderation.muted.extend(item.muted);
+                    }
+                }
+                CHAT_SUB_TYPE_LOBBY_BAN_CONFIG => {
+                    if let Some(item) = deserialize_item::<ChatLobbyBanConfigItem>(payload) {
+                        moderation
+                            .lobby_banned
+                            .entry(item.lobby_id)
+                            .or_default()
+                            .extend(item.banned);
+                    }
+                }
+                sub_type => warn!("unexpected config item {sub_type:02x} in {CHAT_CONFIG_FILE}"),
+            }
+        }
+
+        let data = self.core.get_service_data().chat();
+
+        info!("loaded {} queued private message(s)", pending.len());
+        data.pending_private.write().await.extend(pending);
+
+        info!(
+            "loaded {} banned and {} muted identities",
+            moderation.banned.len()
+                + moderation
+                    .lobby_banned
+                    .values()
+                    .map(|b| b.len())
+                    .sum::<usize>(),
+            moderation.muted.len()
+        );
+        *data.moderation.write().await = moderation;
+        *data.own_avatar.write().await = own_avatar;
+
+        info!("loaded {} subscribed lobbies", lobbies.len());
+        let mut lock = data.lobbies.write().await;
+        let mut subscriptions = data.subscriptions.write().await;
+        for item in lobbies {
+            let flags = lobby_flags
+                .get(&item.lobby_id)
+                .copied()
+                .unwrap_or_else(ChatLobbyFlags::empty);
+            let subscription = LobbySubscription::from((item.gxs_id, flags));
+
+            // only rejoin lobbies that are marked as such
+            let mut lobby = Lobby::from(item);
+            if !subscription.auto_join {
+                lobby.joined = false;
+                lobby.gxs_id = None;
+                lobby.participants.clear();
+            }
+            subscriptions.insert(lobby.lobby_id, subscription);
+            lock.insert(lobby.lobby_id, lobby);
+        }
+    }
+
+    fn build_config_item<T>(&self, sub_type: u8, item: &T) -> Vec<u8>
+    where
+        T: Serialize,
+    {
+        let payload = to_retroshare_wire(item);
+        let header = ServiceHeader::new(Self::SERVICE, sub_type, &payload);
+        Packet::new_without_location(header.into(), payload).to_bytes()
+    }
+
+    async fn save_config(&self) {
+        let chat = self.core.get_service_data().chat();
+        let mut data = vec![];
+
+        for (peer_id, msg) in chat.pending_private.read().await.iter() {
+            let item = PrivateChatMsgConfigItem::new(msg.to_owned(), **peer_id);
+            data.extend(self.build_config_item(CHAT_SUB_TYPE_PRIVATECHATMSG_CONFIG, &item));
+        }
+
+        // the lobby config item holds the subscription's flags
+        let lobbies = chat.lobbies.read().await;
+        for (lobby_id, subscription) in chat.subscriptions.read().await.iter() {
+            let lobby = match lobbies.get(lobby_id) {
+                Some(lobby) => lobby,
+                None => continue,
+            };
+
+            let item = ChatLobbyConfigItem {
+                lobby_id: *lobby_id,
+                flags: subscription.flags(),
+            };
+            data.extend(self.build_config_item(CHAT_SUB_TYPE_CHAT_LOBBY_CONFIG, &item));
+
+            let mut item: SubscribedChatLobbyConfigItem = lobby.into();
+            item.gxs_id = subscription.gxs_id;
+            data.extend(self.build_config_item(CHAT_SUB_TYPE_SUBSCRIBED_CHAT_LOBBY_CONFIG, &item));
+        }
+        drop(lobbies);
+
+        let moderation = chat.moderation.read().await;
+        let item = ChatModerationConfigItem {
+            min_reputation: moderation.min_reputation,
+            banned: moderation.banned.iter().copied().collect(),
+            muted: moderation.muted.iter().copied().collect(),
+        };
+        data.extend(self.build_config_item(CHAT_SUB_TYPE_MODERATION_CONFIG, &item));
+        for (lobby_id, banned) in &moderation.lobby_banned {
+            let item = ChatLobbyBanConfigItem {
+                lobby_id: *lobby_id,
+                banned: banned.iter().copied().collect(),
+            };
+            data.extend(self.build_config_item(CHAT_SUB_TYPE_LOBBY_BAN_CONFIG, &item));
+        }
+        drop(moderation);
+
+        let own_avatar = chat.own_avatar.read().await;
+        if !own_avatar.is_empty() {
+            let item = ChatAvatarItem {
+                image_data: own_avatar.to_owned(),
+            };
+            data.extend(self.build_config_item(CHAT_SUB_TYPE_CHAT_AVATAR, &item));
+        }
+        drop(own_avatar);
+
+        self.core.save_config(CHAT_CONFIG_FILE, &data);
+    }
+
+    fn send_gxs_tunnel_cmd(&self, cmd: GxsTunnelCmd) {
+        if !self.core.get_service_data().gxs_tunnel().send_cmd(cmd) {
+            warn!("failed to send command to gxs tunnel");
+        }
+    }
+
+    async fn send_message_distant(&self, tunnel_id: GxsTunnelId, msg: &str) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+
+        let item = ChatMsgItem {
+            chat_flags: ChatLobbyFlags::PRIVATE,
+            send_time: now,
+            message: msg.into(),
+            recv_time: now,
+        };
+
+        // distant chat items are send with their header through the tunnel
+        let payload = to_retroshare_wire(&item);
+        let header = ServiceHeader::new(Self::SERVICE, CHAT_SUB_TYPE_CHAT_DEFAULT, &payload);
+        let data = Packet::new_without_location(header.into(), payload).to_bytes();
+
+        self.send_gxs_tunnel_cmd(GxsTunnelCmd::SendData {
+            tunnel_id,
+            service_id: DISTANT_CHAT_GXS_TUNNEL_SERVICE_ID,
+            data,
+        });
+
+        // let the web ui know, too
+        let own_id = self
+            .core
+            .get_service_data()
+            .gxs_tunnel()
+            .get_tunnel_info(&tunnel_id)
+            .map(|info| info.own_id)
+            .unwrap_or_default();
+        let msg = ChatMessage {
+            chat_id: DistantChatPeerId::from(*tunnel_id).into(),
+            lobby_peer_gxs_id: own_id.into(),
+            chatflags: ChatFlags::Private,
+            send_time: now,
+            recv_time: now,
+            msg: msg.to_owned(),
+            incoming: false,
+            online: true,
+            ..Default::default()
+        };
+        self.fire_chat_message(msg).await;
+    }
+
+    /// Records a (incoming or outgoing) message in the chat history and notifies the web ui.
+    async fn fire_chat_message(&self, msg: ChatMessage) {
+        if let Some(history) = &*self.core.get_service_data().chat().history.lock().await {
+            if let Err(err) = history.add_message(&(&msg).into()) {
+                warn!("failed to add message to chat history: {err}");
+            }
+        }
+
+        self.core_tx
+            .send(Intercom::Event(EventType::ChatMessage { msg }))
+            .await
+            .expect("failed to send to core");
+    }
+
+    async fn handle_gxs_tunnel_event(&self, event: GxsTunnelEvent) {
+        match event {
+            GxsTunnelEvent::Connected {
+                tunnel_id,
+                own_id,
+                remote_id,
+            } => info!("distant chat {tunnel_id} between {own_id} and {remote_id} is ready"),
+            GxsTunnelEvent::Status(tunnel_id, status) => {
+                debug!("distant chat {tunnel_id} changed status to {status:?}")
+            }
+            GxsTunnelEvent::Data(tunnel_id, mut data) => {
+                if data.len() < 8 {
+                    warn!("received too little data through distant chat {tunnel_id}");
+                    return;
+                }
+                let mut payload = data.split_off(8);
+                let header = match Header::try_parse(&data.try_into().unwrap()) {
+                    Ok(Header::Service {
+                        service: ServiceType::Chat,
+                        sub_type,
+                        ..
+                    }) => sub_type,
+                    _ => {
+                        warn!("received unexpected data through distant chat {tunnel_id}");
+                        return;
+                    }
+                };
+
+                match header {
+                    CHAT_SUB_TYPE_CHAT_DEFAULT => {
+                        let mut msg: ChatMsgItem = from_retroshare_wire(&mut payload);
+                        msg.recv_time = SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs() as u32;
+                        trace!("distant chat {tunnel_id}: {msg:?}");
+
+                        let remote_id = self
+                            .core
+                            .get_service_data()
+                            .gxs_tunnel()
+                            .get_tunnel_info(&tunnel_id)
+                            .map(|info| info.remote_id)
+                            .unwrap_or_default();
+                        info!(
+                            "received distant chat message from {remote_id}: {}",
+                            msg.message
+                        );
+
+                        let msg = ChatMessage {
+                            chat_id: DistantChatPeerId::from(*tunnel_id).into(),
+                            lobby_peer_gxs_id: remote_id.into(),
+                            chatflags: ChatFlags::Private,
+                            send_time: msg.send_time,
+                            recv_time: msg.recv_time,
+                            msg: msg.message.into(),
+                            incoming: true,
+                            online: true,
+                            ..Default::default()
+                        };
+                        self.fire_chat_message(msg).await;
+                    }
+                    // e.g. typing notifications
+                    CHAT_SUB_TYPE_CHAT_STATUS => trace!("distant chat {tunnel_id}: status"),
+                    sub_type => debug!("distant chat {tunnel_id}: unhandled sub type {sub_type}"),
+                }
+            }
         }
     }
 
@@ -896,7 +1792,7 @@ impl Chat {
                 );
 
                 // trigger lobby request
-                self.request_lobbies();
+                self.request_lobbies().await;
 
                 false
             }
@@ -908,20 +1804,84 @@ impl Chat {
             return false;
         }
 
-        // TODO reputation
-
         // check cache
-        self.filter_bouncing_obj(&event.bounce_obj).await
+        if !self.filter_bouncing_obj(&event.bounce_obj).await {
+            return false;
+        }
+
+        self.filter_sender(&event.bounce_obj).await
     }
 
     async fn filter_message(&self, msg: &ChatLobbyMsgItem) -> bool {
         if !self.filter_time(msg.msg_obj.send_time) {
             return false;
         }
-        // TODO reputation
 
         // check cache
-        self.filter_bouncing_obj(&msg.bounce_obj).await
+        if !self.filter_bouncing_obj(&msg.bounce_obj).await {
+            return false;
+        }
+
+        if !self.filter_sender(&msg.bounce_obj).await {
+            return false;
+        }
+
+        // flood protection, only messages count
+        let gxs_id = msg.bounce_obj.signature.key_id.to_owned().into();
+        let data = self.core.get_service_data().chat();
+        if !data
+            .moderation
+            .write()
+            .await
+            .check_flood(&gxs_id, SystemTime::now())
+        {
+            warn!(
+                "{gxs_id} is flooding lobby {}, dropping",
+                msg.bounce_obj.public_lobby_id
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Drops items from banned identities and identities with a bad reputation.
+    async fn filter_sender(&self, bounce_obj: &ChatLobbyBouncingObject) -> bool {
+        let gxs_id: GxsId = bounce_obj.signature.key_id.to_owned().into();
+
+        let min_reputation = {
+            let lock = self.core.get_service_data().chat().moderation.read().await;
+            if lock.is_banned(&bounce_obj.public_lobby_id, &gxs_id) {
+                debug!("dropping item from banned identity {gxs_id}");
+                return false;
+            }
+            lock.min_reputation
+        };
+
+        match self.get_reputation(&gxs_id).await {
+            Some(score) if score < min_reputation => {
+                debug!("dropping item from {gxs_id}, reputation {score} is below {min_reputation}");
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the overall reputation score of an identity, `None` when it is unknown.
+    async fn get_reputation(&self, gxs_id: &GxsId) -> Option<i32> {
+        let group = self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_group_meta(&gxs_id.to_owned().into())
+            .await?;
+
+        // `service_from_string` panics on anything unexpected
+        if !group.service_string.starts_with("v2 ") || !group.service_string.contains("{R:") {
+            return None;
+        }
+        let service_string = SSGxsIdGroup::service_from_string(&group.service_string);
+        Some(service_string.reputation().overall_score())
     }
 
     async fn generate_lobby_challenge(&self, lobby: &Lobby) -> Option<u64> {
@@ -1046,72 +2006,97 @@ impl Chat {
 
 #[async_trait]
 impl Service for Chat {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::Chat
+    // lobby items are verified and bounced as they were received
+    type Item = Packet;
+    type Command = ChatInput;
+
+    const SERVICE: ServiceType = ServiceType::Chat;
+    const NAME: &'static str = "chat";
+    const TIMERS: &'static [(&'static str, Duration)] = &[
+        CHAT_TIMER_LOBBY_KEEP_ALIVE,
+        CHAT_TIMER_LOBBY_MAINTENANCE,
+        CHAT_TIMER_LOBBY_REQUEST,
+    ];
+    const EVENTS: bool = true;
+
+    async fn start(&mut self, _ctx: &ServiceContext<Packet>) {
+        self.load_config().await;
     }
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "chat")
+    async fn handle_item(&mut self, _ctx: &ServiceContext<Packet>, packet: Packet, _from: Arc<PeerId>) {
+        self.handle_incoming(packet).await;
     }
 
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
-
-                            match msg {
-                                Intercom::Receive(packet) =>
-                                    self.handle_incoming(&packet.header.to_owned().into(), packet).await,
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                    command = self.cmd_rx.recv() => {
-                        if let Some(command) = command {
-                            self.handle_cmd(command).await;
-                        }
-                    }
-                    _ = self.timer_lobby_keep_alive.tick() => {
-                        trace!("keep alive");
+    async fn on_timer(&mut self, _ctx: &ServiceContext<Packet>, timer: &'static str) {
+        match timer {
+            t if t == CHAT_TIMER_LOBBY_KEEP_ALIVE.0 => {
+                trace!("keep alive");
 
-                        self.keep_alive(None).await
-                    }
-                    _ = self.timer_lobby_maintenance.tick() => {
-                        trace!("maintaining lobbies");
-
-                        let mut lock = self.core.get_service_data().chat().lobbies.write().await;
-
-                        // trigger cleanup
-                        lock.iter_mut()
-                            .for_each(|(_, lobby)| lobby.maintain_lobby());
-
-                        // gen challenges
-                        for (_id, lobby) in &mut *lock {
-                            if let Some(challenge_code) = self.generate_lobby_challenge(lobby).await {
-                                let item = ChatLobbyConnectChallengeItem { challenge_code };
-                                let payload = to_retroshare_wire(&item);
-                                let header = ServiceHeader::new(
-                                    self.get_id(),
-                                    CHAT_SUB_TYPE_CHAT_LOBBY_CHALLENGE,
-                                    &payload,
-                                );
-                                let packet = Packet::new_without_location(header.into(), payload);
-
-                                self.core_tx.send(Intercom::Send(packet)).expect("failed to send to core");
-                            }
-                        }
+                self.keep_alive(None).await
+            }
+            t if t == CHAT_TIMER_LOBBY_MAINTENANCE.0 => {
+                trace!("maintaining lobbies");
+
+                let mut lock = self.core.get_service_data().chat().lobbies.write().await;
+
+                // trigger cleanup
+                lock.iter_mut()
+                    .for_each(|(_, lobby)| lobby.maintain_lobby());
+                self.core.get_service_data().chat().moderation.write().await.maintain();
+                if let Some(history) = &*self.core.get_service_data().chat().history.lock().await {
+                    if let Err(err) = history.remove_expired() {
+                        warn!("failed to clean up chat history: {err}");
                     }
-                    _ = self.timer_lobby_request.tick() => {
-                        trace!("requesting lobbies");
+                }
 
-                        self.request_lobbies();
+                // gen challenges
+                for lobby in lock.values_mut() {
+                    if let Some(challenge_code) = self.generate_lobby_challenge(lobby).await {
+                        let item = ChatLobbyConnectChallengeItem { challenge_code };
+                        let payload = to_retroshare_wire(&item);
+                        let header = ServiceHeader::new(
+                            Self::SERVICE,
+                            CHAT_SUB_TYPE_CHAT_LOBBY_CHALLENGE,
+                            &payload,
+                        );
+                        let packet = Packet::new_without_location(header.into(), payload);
+
+                        self.core_tx.send(Intercom::Send(packet)).await.expect("failed to send to core");
                     }
                 }
             }
-        })
+            _ => {
+                trace!("requesting lobbies");
+
+                self.request_lobbies().await;
+            }
+        }
+    }
+
+    async fn handle_event(&mut self, _ctx: &ServiceContext<Packet>, event: Intercom) {
+        if let Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _))) = event {
+            // a (re)connected friend might have lost our avatar
+            if let Some(avatar) = self.core.get_service_data().chat().avatars.write().await.get_mut(&loc) {
+                avatar.own_sent = false;
+            }
+            self.send_pending_private(&loc).await;
+            self.send_lobby_invites(&loc).await;
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<ChatInput> {
+        select! {
+            Some(cmd) = self.cmd_rx.recv() => Some(ChatInput::Cmd(cmd)),
+            Some(event) = self.gxs_tunnel_rx.recv() => Some(ChatInput::GxsTunnel(event)),
+            else => None,
+        }
+    }
+
+    async fn handle_command(&mut self, _ctx: &ServiceContext<Packet>, input: ChatInput) {
+        match input {
+            ChatInput::Cmd(cmd) => self.handle_cmd(cmd).await,
+            ChatInput::GxsTunnel(event) => self.handle_gxs_tunnel_event(event).await,
+        }
     }
 }
 
@@ -1,158 +1,229 @@
-use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    sync::Arc,
-};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
-use log::{debug, info, trace, warn};
+use log::{debug, info, warn};
 use retroshare_compat::{
-    basics::SslId,
-    serde::{from_retroshare_wire, to_retroshare_wire},
-    services::{discovery::*, service_info::RsServiceInfo},
-    tlv::{
-        tlv_ip_addr::{TlvIpAddrSet, TlvIpAddress},
-        tlv_set::TlvPgpIdSet,
-    },
-};
-use tokio::{
-    select,
-    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
+    basics::{PgpId, SslId},
+    events::{EventType, GossipDiscoveryEventCode},
+    services::discovery::*,
+    tlv::{tlv_ip_addr::TlvIpAddressInfo, tlv_set::TlvPgpIdSet},
 };
+use sequoia_openpgp::{parse::Parse, serialize::MarshalInto, Cert};
 
 use crate::{
-    low_level_parsing::{headers::ServiceHeader, Packet},
     model::{
         intercom::{Intercom, PeerState, PeerUpdate},
+        location::Location,
         person::Peer,
         DataCore,
     },
-    send_to_core,
-    services::Service,
+    service_items,
+    services::{Service, ServiceContext},
 };
 
 use ::retroshare_compat::services::ServiceType;
 
 const DISCOVERY_SUB_TYPE_PGP_LIST: u8 = 0x01;
 const DISCOVERY_SUB_TYPE_PGP_CERT: u8 = 0x02;
-#[deprecated]
 const DISCOVERY_SUB_TYPE_CONTACT: u8 = 0x05;
 const DISCOVERY_SUB_TYPE_IDENTITY_LIST: u8 = 0x06;
 const DISCOVERY_SUB_TYPE_PGP_CERT_BINARY: u8 = 0x09;
 
-pub struct Discovery {
-    rx: UnboundedReceiver<Intercom>,
+service_items! {
+    pub enum DiscoveryItem: Discovery {
+        PgpList(DiscPgpListItem) = DISCOVERY_SUB_TYPE_PGP_LIST, priority: 5;
+        PgpCert(DiscPgpCertItem) = DISCOVERY_SUB_TYPE_PGP_CERT, priority: 5;
+        Contact(Box<DiscContactItem>) = DISCOVERY_SUB_TYPE_CONTACT, priority: 5, decode: read_contact_item;
+        IdentityList(DiscIdentityListItem) = DISCOVERY_SUB_TYPE_IDENTITY_LIST, priority: 5;
+        PgpCertBinary(DiscPgpKeyItem) = DISCOVERY_SUB_TYPE_PGP_CERT_BINARY, priority: 5;
+    }
+}
 
-    own_id: Arc<SslId>,
+// contact items are by far the largest ones, keep them on the heap
+fn read_contact_item(payload: &mut Vec<u8>) -> Box<DiscContactItem> {
+    Box::new(read_rs_disc_contact_item(payload))
+}
 
-    core_tx: UnboundedSender<Intercom>,
-    events_rx: UnboundedReceiver<Intercom>,
+impl From<DiscContactItem> for DiscoveryItem {
+    fn from(item: DiscContactItem) -> Self {
+        DiscoveryItem::Contact(Box::new(item))
+    }
+}
+
+/// Fills in the addresses of a contact info, the first address of each family is mandatory.
+fn set_contact_addrs(
+    info: &mut DiscContactItem,
+    local: &[TlvIpAddressInfo],
+    external: &[TlvIpAddressInfo],
+) {
+    let first = |ips: &[TlvIpAddressInfo], v4: bool| {
+        ips.iter()
+            .map(|ip| ip.addr.to_owned())
+            .find(|addr| addr.0.is_ipv4() == v4)
+            .unwrap_or_default()
+    };
+    info.local_addr_v4 = first(local, true);
+    info.local_addr_v6 = first(local, false);
+    info.ext_addr_v4 = first(external, true);
+    info.ext_addr_v6 = first(external, false);
+
+    info.local_addr_list = local.iter().cloned().collect();
+    info.ext_addr_list = external.iter().cloned().collect();
+}
+
+pub struct Discovery {
+    core: Arc<DataCore>,
+    own_id: Arc<SslId>,
 
     persons: Vec<Arc<Peer>>,
-    info: DiscContactItem,
+
+    /// keys we asked for and are still waiting for, with the location we asked
+    requested_keys: HashMap<PgpId, Arc<SslId>>,
 }
 
 impl Discovery {
-    pub async fn new(
-        core: &Arc<DataCore>,
-        core_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-    ) -> Discovery {
-        let (tx_events, rx_events) = unbounded_channel();
-        core.events_subscribe(tx_events).await;
+    pub fn new(core: &Arc<DataCore>) -> Discovery {
+        Discovery {
+            core: core.clone(),
+            own_id: core.get_own_location().get_location_id().clone(),
 
-        let mut d = Discovery {
-            rx,
+            persons: core.get_persons().clone(),
 
-            own_id: core.get_own_location().get_location_id().clone(),
+            requested_keys: HashMap::new(),
+        }
+    }
 
-            core_tx,
-            events_rx: rx_events,
+    fn get_person(&self, pgp_id: &PgpId) -> Option<&Arc<Peer>> {
+        self.persons.iter().find(|p| p.get_pgp_id() == pgp_id)
+    }
 
-            persons: core.get_persons().clone(),
-            info: DiscContactItem::default(),
+    /// Builds our contact info with our current addresses.
+    fn own_contact(&self) -> DiscContactItem {
+        let mut info = DiscContactItem {
+            pgp_id: *self.core.get_own_person().get_pgp_id(),
+            ssl_id: *self.own_id.to_owned(),
+            version: format!(
+                "{} {}",
+                env!("CARGO_PKG_VERSION"),
+                env!("CARGO_PKG_VERSION")
+            )
+            .into(),
+            location: String::from("Pluto").into(),
+
+            net_mode: NET_MODE_UDP,
+            vs_dht: VsDht::Off as u16,
+            vs_disc: VsDisc::Full as u16,
+            ..Default::default()
         };
 
-        d.info.pgp_id = core.get_own_person().get_pgp_id().clone();
-        d.info.ssl_id = *d.own_id.to_owned();
-        d.info.version = format!(
-            "{} {}",
-            env!("CARGO_PKG_VERSION"),
-            env!("CARGO_PKG_VERSION")
-        )
-        .into();
-        d.info.location = String::from("Pluto").into();
-
-        d.info.net_mode = 4;
-        d.info.vs_dht = VsDht::Off as u16;
-        d.info.vs_disc = VsDisc::Full as u16;
-
-        let me = core.get_own_location();
+        let me = self.core.get_own_location();
+
+        // hidden nodes advertise their onion / i2p address only
+        let net = self.core.get_net();
+        if net.is_hidden() {
+            info.net_mode = NET_MODE_HIDDEN;
+            info.is_hidden = true;
+            if let Some((host, port)) = me.get_hidden_addr() {
+                info.hidden_addr = host.into();
+                info.hidden_port = port;
+            } else {
+                warn!("hidden node without hidden address, check the location's settings");
+            }
+        }
+        if net.is_hidden() || net.is_hidden_only() {
+            return info;
+        }
         let ips = me.get_ips();
+        set_contact_addrs(&mut info, &ips.0, &ips.1);
+        if let Some(dyndns) = net.get_dyndns() {
+            info.dyndns = dyndns.into();
+        }
 
-        d.info.local_addr_v4 = TlvIpAddress(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(0)), 1337));
-        d.info.ext_addr_v4 = TlvIpAddress(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(0)), 1337));
+        info
+    }
 
-        d.info.local_addr_list = TlvIpAddrSet::default();
-        for local in &*ips.0 {
-            d.info.local_addr_list.0.insert(
-                retroshare_compat::tlv::tlv_ip_addr::TlvIpAddressInfoInner {
-                    addr: local.addr.to_owned(),
-                    seen_time: 0,
-                    source: 0,
-                }
-                .into(),
-            );
-        }
-        d.info.ext_addr_list = TlvIpAddrSet::default();
-        for local in &*ips.1 {
-            d.info.local_addr_list.0.insert(
-                retroshare_compat::tlv::tlv_ip_addr::TlvIpAddressInfoInner {
-                    addr: local.addr.to_owned(),
-                    seen_time: 0,
-                    source: 0,
-                }
-                .into(),
-            );
+    /// Builds the contact info of a (mutual) friend's location from what we know about it.
+    fn peer_contact(&self, loc: &Location) -> DiscContactItem {
+        let mut info = DiscContactItem {
+            pgp_id: *loc.get_person().get_pgp_id(),
+            ssl_id: *loc.get_location_id(),
+            location: loc.get_name().to_owned().into(),
+
+            net_mode: NET_MODE_UDP,
+            vs_dht: VsDht::Off as u16,
+            vs_disc: VsDisc::Full as u16,
+            ..Default::default()
+        };
+
+        if let Some((host, port)) = loc.get_hidden_addr() {
+            info.net_mode = NET_MODE_HIDDEN;
+            info.is_hidden = true;
+            info.hidden_addr = host.into();
+            info.hidden_port = port;
+        } else {
+            let ips = loc.get_ips();
+            set_contact_addrs(&mut info, &ips.0, &ips.1);
         }
 
-        d
+        info
     }
 
-    fn handle_incoming(&self, header: &ServiceHeader, mut packet: Packet) {
-        match header.sub_type {
-            #[allow(deprecated)]
-            DISCOVERY_SUB_TYPE_CONTACT => {
-                let item = read_rs_disc_contact_item(&mut packet.payload);
-                // println!("received DiscContactItem: {}", item);
-                // println!("pgp_id: {}", item.pgp_id);
-                // println!("ssl_id: {}", item.ssl_id);
+    /// Tells a friend under which address we see them, RS uses this to learn its external address.
+    ///
+    /// Nothing is sent when we don't know their address (e.g. hidden nodes).
+    fn their_contact(&self, loc: &SslId, addr: SocketAddr) -> Option<DiscContactItem> {
+        if addr.ip().is_unspecified() {
+            return None;
+        }
+        let peer = self.core.get_location_by_id(Arc::new(*loc))?;
+        Some(DiscContactItem {
+            pgp_id: *peer.get_person().get_pgp_id(),
+            ssl_id: *loc,
+            current_connect_address: addr.into(),
+            ..Default::default()
+        })
+    }
 
-                if item.ssl_id == *self.own_id {
-                    // describing us self
-                    info!("DiscContactItem: received our info");
-                } else {
-                    self.handle_peer_contact(&item, packet.peer_id.clone());
-                }
-            }
-            DISCOVERY_SUB_TYPE_IDENTITY_LIST => {
-                let item: DiscIdentityListItem = from_retroshare_wire(&mut packet.payload);
-                info!("received DiscIdentityListItem: {item}");
-            }
-            DISCOVERY_SUB_TYPE_PGP_LIST
-            | DISCOVERY_SUB_TYPE_PGP_CERT
-            | DISCOVERY_SUB_TYPE_PGP_CERT_BINARY => {
-                info!("received {header:?}");
-            }
-            sub_type => {
-                warn!("received unknown sub typ {sub_type}");
-            }
+    /// A friend reported under which address they see us.
+    async fn handle_own_contact(&self, contact: &DiscContactItem, from: Arc<SslId>) {
+        let addr = contact.current_connect_address.0;
+        debug!("DiscContactItem: {from} sees us as {addr}");
+
+        // connections through a proxy report the proxy's address
+        let proxied = self
+            .core
+            .get_location_by_id(from.to_owned())
+            .map(|loc| {
+                self.core
+                    .get_net()
+                    .outgoing_proxy_for(loc.get_person().get_pgp_id())
+                    .is_some()
+            })
+            .unwrap_or(true);
+        if proxied {
+            return;
+        }
+
+        if self.core.get_net().add_ext_report(*from, addr.ip()) {
+            info!(
+                "external address changed to {:?}",
+                self.core.get_net().get_ext_addr()
+            );
+            self.core.update_own_addresses().await;
         }
     }
 
-    fn handle_peer_contact(&self, contact: &DiscContactItem, from: Arc<SslId>) {
+    async fn handle_peer_contact(
+        &self,
+        ctx: &ServiceContext<DiscoveryItem>,
+        contact: &DiscContactItem,
+        from: Arc<SslId>,
+    ) {
         if contact.ssl_id == *from {
             // describing themselves
+            if let Some(loc) = self.core.get_location_by_id(from.to_owned()) {
+                loc.set_dht(contact.vs_dht != VsDht::Off as u16);
+            }
             if contact.vs_disc != VsDisc::Off as u16 {
                 // send own DISCOVERY_SUB_TYPE_PGP_LIST
                 let mut item = DiscPgpListItem {
@@ -164,11 +235,7 @@ impl Discovery {
                     item.pgp_id_set.0.insert(p.get_pgp_id().to_owned());
                 }
 
-                let payload = to_retroshare_wire(&item);
-                let header =
-                    ServiceHeader::new(self.get_id(), DISCOVERY_SUB_TYPE_PGP_LIST, &payload).into();
-
-                send_to_core!(self, Packet::new(header, payload, from));
+                ctx.send(item, from).await;
             }
         } else {
             // describing someone else
@@ -177,68 +244,281 @@ impl Discovery {
                 contact.ssl_id
             );
 
+            ctx.core_tx()
+                .send(Intercom::Event(EventType::GossipDiscovery {
+                    code: GossipDiscoveryEventCode::FriendPeerInfoReceived,
+                    from_id: *from,
+                    about_id: contact.ssl_id,
+                }))
+                .await
+                .expect("failed to communicate with core");
+
+            match self.core.get_location_by_id(Arc::new(contact.ssl_id)) {
+                Some(loc) if loc.get_person().get_pgp_id() != &contact.pgp_id => {
+                    warn!(
+                        "DiscContactItem: {from} claims {} belongs to {}, ignoring",
+                        contact.ssl_id, contact.pgp_id
+                    );
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    // new locations of our friends are added right away, like RS does
+                    let person = match self.get_person(&contact.pgp_id) {
+                        Some(person) => person.to_owned(),
+                        None => {
+                            debug!(
+                                "DiscContactItem: {} is not a friend of ours",
+                                contact.pgp_id
+                            );
+                            return;
+                        }
+                    };
+                    let loc = Arc::new(Location::new(
+                        contact.location.to_owned().into(),
+                        Arc::new(contact.ssl_id),
+                        Arc::new(contact.pgp_id),
+                        (vec![], vec![]),
+                        person.to_owned(),
+                    ));
+                    if self.core.add_location(loc) {
+                        info!(
+                            "discovered new location {} of {}",
+                            contact.location,
+                            person.get_name()
+                        );
+                    }
+                }
+            }
+
+            if contact.is_hidden {
+                let host: String = contact.hidden_addr.to_owned().into();
+                ctx.core_tx()
+                    .send(Intercom::PeerUpdate(PeerUpdate::HiddenAddress(
+                        Arc::new(contact.ssl_id),
+                        host,
+                        contact.hidden_port,
+                    )))
+                    .await
+                    .expect("failed to communicate with core");
+                return;
+            }
+
             let local = contact.local_addr_list.0.to_owned();
             let external = contact.ext_addr_list.0.to_owned();
-            self.core_tx
+            ctx.core_tx()
                 .send(Intercom::PeerUpdate(PeerUpdate::Address(
                     Arc::new(contact.ssl_id),
                     local,
                     external,
                 )))
+                .await
                 .expect("failed to communicate with core");
         }
     }
+
+    async fn handle_pgp_list(
+        &mut self,
+        ctx: &ServiceContext<DiscoveryItem>,
+        list: DiscPgpListItem,
+        from: Arc<SslId>,
+    ) {
+        match list.mode {
+            GossipDiscoveryPgpListMode::Friends => {
+                let from_hidden = match self.core.get_location_by_id(from.to_owned()) {
+                    Some(loc) => loc.get_hidden_addr().is_some(),
+                    None => return,
+                };
+
+                // tell them about the locations of our mutual friends
+                for person in self
+                    .persons
+                    .iter()
+                    .filter(|p| list.pgp_id_set.0.contains(p.get_pgp_id()))
+                {
+                    let locations = person.get_locations().clone();
+                    for loc in locations {
+                        let id = loc.get_location_id();
+                        if id == from || id == self.own_id {
+                            continue;
+                        }
+                        // only hidden friends learn about hidden locations
+                        if loc.get_hidden_addr().is_some() && !from_hidden {
+                            continue;
+                        }
+                        ctx.send(self.peer_contact(&loc), from.to_owned()).await;
+                    }
+                }
+
+                // ask for the keys we don't have yet
+                let store = self.core.get_service_data().discovery();
+                let unknown: TlvPgpIdSet = list
+                    .pgp_id_set
+                    .0
+                    .iter()
+                    .filter(|id| {
+                        self.get_person(id).is_none()
+                            && !store.has_key(id)
+                            && !self.requested_keys.contains_key(id)
+                    })
+                    .cloned()
+                    .collect();
+                if !unknown.0.is_empty() {
+                    debug!("requesting {} keys from {from}", unknown.0.len());
+                    self.requested_keys
+                        .extend(unknown.0.iter().map(|id| (*id, from.to_owned())));
+                    let item = DiscPgpListItem {
+                        mode: GossipDiscoveryPgpListMode::Getcert,
+                        pgp_id_set: unknown,
+                    };
+                    ctx.send(item, from).await;
+                }
+            }
+            GossipDiscoveryPgpListMode::Getcert => {
+                for pgp_id in &list.pgp_id_set.0 {
+                    // only keys of friends (and our own) are handed out
+                    let person = match self.get_person(pgp_id) {
+                        Some(person) => person,
+                        None => {
+                            debug!("{from} asked for key {pgp_id}, which is not a friend");
+                            continue;
+                        }
+                    };
+                    let bin_data = match person.get_pgp().to_vec() {
+                        Ok(data) => data,
+                        Err(err) => {
+                            warn!("failed to serialize key {pgp_id}: {err}");
+                            continue;
+                        }
+                    };
+                    let item = DiscPgpKeyItem {
+                        pgp_key_id: *pgp_id,
+                        bin_data,
+                    };
+                    ctx.send(item, from.to_owned()).await;
+                }
+            }
+            GossipDiscoveryPgpListMode::None => {}
+        }
+    }
+
+    /// Stores a key we asked for, `data` is either binary or armored.
+    fn handle_pgp_key(&mut self, pgp_id: PgpId, data: &[u8], from: &SslId) {
+        if self.requested_keys.remove(&pgp_id).is_none() {
+            debug!("received unrequested key {pgp_id} from {from}");
+            return;
+        }
+
+        let cert = match Cert::from_bytes(data) {
+            Ok(cert) => cert,
+            Err(err) => {
+                warn!("received invalid key {pgp_id} from {from}: {err}");
+                return;
+            }
+        };
+        if cert.keyid().as_bytes() != pgp_id.0 {
+            warn!(
+                "received key {} from {from}, expected {pgp_id}",
+                cert.keyid()
+            );
+            return;
+        }
+
+        info!(
+            "received key {pgp_id} ({})",
+            cert.userids()
+                .next()
+                .map(|uid| uid.userid().to_string())
+                .unwrap_or_default()
+        );
+        self.core
+            .get_service_data()
+            .discovery()
+            .add_key(pgp_id, cert);
+    }
 }
 
 #[async_trait]
 impl Service for Discovery {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::Discovery
-    }
+    type Item = DiscoveryItem;
+    type Command = ();
+
+    const SERVICE: ServiceType = ServiceType::Discovery;
+    const NAME: &'static str = "disc";
+    const EVENTS: bool = true;
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "disc")
+    async fn handle_item(
+        &mut self,
+        ctx: &ServiceContext<DiscoveryItem>,
+        item: DiscoveryItem,
+        from: Arc<SslId>,
+    ) {
+        match item {
+            DiscoveryItem::Contact(item) => {
+                if item.ssl_id == *self.own_id {
+                    // describing us self
+                    self.handle_own_contact(&item, from).await;
+                } else {
+                    self.handle_peer_contact(ctx, &item, from).await;
+                }
+            }
+            DiscoveryItem::IdentityList(item) => {
+                debug!("received {item} from {from}");
+                if !item.own_identity_list.is_empty() {
+                    self.core
+                        .get_service_data()
+                        .gxs_id()
+                        .request_ids(item.own_identity_list, from)
+                        .await;
+                }
+            }
+            DiscoveryItem::PgpList(item) => self.handle_pgp_list(ctx, item, from).await,
+            DiscoveryItem::PgpCertBinary(item) => {
+                self.handle_pgp_key(item.pgp_key_id, &item.bin_data, &from)
+            }
+            DiscoveryItem::PgpCert(item) => {
+                let cert: String = item.pgp_cert.into();
+                self.handle_pgp_key(item.pgp_id, cert.as_bytes(), &from)
+            }
+        }
     }
 
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
-
-                            match msg {
-                                Intercom::Receive(packet) => self.handle_incoming(&packet.header.to_owned().into(), packet),
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                    event = self.events_rx.recv() => {
-                        if let Some(event) = event {
-                            match event {
-                                Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _addr))) => {
-                                    // write_rs_disc_contact_item(&mut payload, &self.info);
-                                    let payload = to_retroshare_wire(&self.info);
-                                    let packet = Packet::new(
-                                        // FIXME
-                                        #[allow(deprecated)]
-                                        ServiceHeader::new(self.get_id(), DISCOVERY_SUB_TYPE_CONTACT, &payload)
-                                            .into(),
-                                        payload,
-                                        loc.clone(),
-                                    );
-
-                                    info!("sending contact info to {loc}");
-                                    send_to_core!(self, packet);
-                                }
-                                // we don't care for the rest!
-                                _ => {}
-                            }
-                        }
-                    }
+    async fn handle_event(&mut self, ctx: &ServiceContext<DiscoveryItem>, event: Intercom) {
+        match event {
+            Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, addr))) => {
+                info!("sending contact info to {loc}");
+                ctx.send(self.own_contact(), loc.to_owned()).await;
+                if let Some(item) = self.their_contact(&loc, addr) {
+                    ctx.send(item, loc.to_owned()).await;
+                }
+
+                // let them fetch our identities
+                let own_ids = self.core.get_service_data().gxs_id().get_own_ids().await;
+                if !own_ids.is_empty() {
+                    let item = DiscIdentityListItem {
+                        own_identity_list: own_ids,
+                    };
+                    ctx.send(item, loc).await;
                 }
             }
-        })
+            Intercom::PeerUpdate(PeerUpdate::Address(loc, _, _)) if loc == self.own_id => {
+                // our addresses changed, tell everyone
+                let info = self.own_contact();
+                for loc in self
+                    .core
+                    .get_locations()
+                    .into_iter()
+                    .filter(|loc| loc.is_connected())
+                {
+                    ctx.send(info.clone(), loc.get_location_id()).await;
+                }
+            }
+            Intercom::PeerUpdate(PeerUpdate::Status(PeerState::NotConnected(loc))) => {
+                // they won't answer anymore, ask someone else next time
+                self.requested_keys.retain(|_, asked| *asked != loc);
+            }
+            // we don't care for the rest!
+            _ => {}
+        }
     }
 }
@@ -0,0 +1,1087 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use log::{debug, info, trace, warn};
+use nanorand::{Rng, WyRand};
+use openssl::sha::sha1;
+use retroshare_compat::{
+    basics::{GxsId, SslId},
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{grouter::*, turtle::TurtleFileHash, ServiceType},
+    tlv::tlv_keys::{TlvKeySignature, TlvKeySignatureInner},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::select;
+
+use crate::{
+    channel::{channel, Policy, Receiver, Sender},
+    gxs::gxsid::{decrypt_data, encrypt_data, generate_signature, verify_signature},
+    low_level_parsing::{
+        headers::{read_config_records, Header, ServiceHeader, HEADER_SIZE},
+        Packet,
+    },
+    model::{
+        services::{
+            grouter::{GRouterCmd, GRouterEvent},
+            turtle::{TunnelDirection, TurtleCmd},
+        },
+        DataCore,
+    },
+    services::{
+        turtle::TurtleClient, NoItem, Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE,
+        SERVICE_EVENTS_QUEUE_SIZE,
+    },
+};
+
+const GROUTER_CONFIG_FILE: &str = "rustyshare_grouter.cfg";
+
+const GROUTER_TIMER_MAINTENANCE: (&str, Duration) = ("maintenance", Duration::from_secs(5));
+const GROUTER_TIMER_OWN_IDS: (&str, Duration) = ("own_ids", Duration::from_secs(60));
+
+/// items are split into chunks of this size when sent through a tunnel.
+const GROUTER_TRANSACTION_CHUNK_SIZE: usize = 16 * 1024;
+/// incomplete transactions are dropped after this time.
+const GROUTER_TRANSACTION_TIMEOUT: Duration = Duration::from_secs(120);
+/// items that were not delivered in this time are dropped.
+const GROUTER_ITEM_MAX_LIFE: Duration = Duration::from_secs(14 * 24 * 3600);
+/// time to wait for a receipt before sending an item again (doubled with every attempt).
+const GROUTER_RESEND_DELAY: Duration = Duration::from_secs(120);
+/// the resend delay is doubled at most this many times.
+const GROUTER_MAX_BACKOFF: u32 = 5;
+/// keys that delivered a receipt in this time are considered reachable and retried without backoff.
+const GROUTER_REACHABLE_TIME: Duration = Duration::from_secs(24 * 3600);
+/// time during which received items are remembered to drop duplicates.
+const GROUTER_RECEIVED_LIFE_TIME: Duration = GROUTER_ITEM_MAX_LIFE;
+
+// pending items as stored in the config
+const GROUTER_DATA_STATUS_PENDING: u32 = 0;
+const GROUTER_DATA_STATUS_SENT: u32 = 1;
+
+/// RS's `p3GRouter::makeTunnelHash`: the destination followed by the client id.
+pub fn make_tunnel_hash(destination: &GxsId, client: u32) -> TurtleFileHash {
+    let mut buf = destination.to_vec();
+    buf.extend_from_slice(&[0, 0, (client >> 8) as u8, client as u8]);
+    buf.into()
+}
+
+/// Counterpart of `make_tunnel_hash`, returns `None` for hashes that were not made by it.
+fn destination_from_hash(hash: &TurtleFileHash) -> Option<(GxsId, u32)> {
+    if hash[16..18] != [0, 0] {
+        return None;
+    }
+    let client = (hash[18] as u32) << 8 | hash[19] as u32;
+    Some((hash[..16].to_vec().into(), client))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Serializes an item including its (service) header.
+fn serialize_item<T>(sub_type: u8, item: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let payload = to_retroshare_wire(item);
+    serialize_payload(sub_type, payload)
+}
+
+fn serialize_payload(sub_type: u8, payload: Vec<u8>) -> Vec<u8> {
+    let header = ServiceHeader::new(ServiceType::GRouter, sub_type, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
+
+/// Splits serialized data into sub type and payload.
+fn parse_item(mut data: Vec<u8>) -> Option<(u8, Vec<u8>)> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let mut payload = data.split_off(HEADER_SIZE);
+    let header: [u8; HEADER_SIZE] = data.try_into().ok()?;
+
+    match Header::try_parse(&header) {
+        Ok(Header::Service {
+            service: ServiceType::GRouter,
+            sub_type,
+            size,
+        }) if (HEADER_SIZE..=HEADER_SIZE + payload.len()).contains(&(size as usize)) => {
+            payload.truncate(size as usize - HEADER_SIZE);
+            Some((sub_type, payload))
+        }
+        _ => None,
+    }
+}
+
+fn deserialize_item<T>(mut payload: Vec<u8>) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match from_retroshare_wire_result(&mut payload) {
+        Ok(item) => Some(item),
+        Err(err) => {
+            warn!("failed to deserialize item: {err:?}");
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TurtleEvent {
+    Up(TurtleFileHash, u32, TunnelDirection),
+    Down(TurtleFileHash, u32),
+    Data(TurtleFileHash, u32, Vec<u8>),
+}
+
+/// Connects the GRouter service to the turtle service.
+struct GRouterTurtleClient {
+    core: Arc<DataCore>,
+    tx: Sender<TurtleEvent>,
+}
+
+#[async_trait]
+impl TurtleClient for GRouterTurtleClient {
+    fn get_service_id(&self) -> ServiceType {
+        ServiceType::GRouter
+    }
+
+    async fn handle_tunnel_request(&self, hash: &TurtleFileHash, _peer_id: &Arc<SslId>) -> bool {
+        let store = self.core.get_service_data().grouter();
+        destination_from_hash(hash).is_some_and(|(destination, client)| {
+            store.is_own_id(&destination) && store.get_client(&client).is_some()
+        })
+    }
+
+    async fn receive_turtle_data(
+        &self,
+        data: Vec<u8>,
+        hash: &TurtleFileHash,
+        tunnel_id: u32,
+        _direction: TunnelDirection,
+    ) {
+        _ = self.tx.try_send(TurtleEvent::Data(*hash, tunnel_id, data));
+    }
+
+    async fn tunnel_up(&self, hash: &TurtleFileHash, tunnel_id: u32, direction: TunnelDirection) {
+        _ = self
+            .tx
+            .try_send(TurtleEvent::Up(*hash, tunnel_id, direction));
+    }
+
+    async fn tunnel_down(&self, hash: &TurtleFileHash, tunnel_id: u32) {
+        _ = self.tx.try_send(TurtleEvent::Down(*hash, tunnel_id));
+    }
+}
+
+/// A partially received item.
+struct IncomingTransaction {
+    data: Vec<u8>,
+    received: usize,
+    last_activity: Instant,
+}
+
+/// A turtle tunnel to (or from) a GRouter key.
+struct Tunnel {
+    hash: TurtleFileHash,
+    direction: TunnelDirection,
+    incoming: HashMap<u64, IncomingTransaction>,
+}
+
+/// An outgoing item waiting for its receipt.
+struct PendingItem {
+    item: GRouterGenericDataItem,
+    /// the transaction was acknowledged by the other end of the tunnel
+    sent: bool,
+    received_time: SystemTime,
+    last_sent: Option<SystemTime>,
+    attempts: u32,
+    /// tunnel the item was sent through the last time
+    tunnel_id: Option<u32>,
+}
+
+impl PendingItem {
+    fn hash(&self) -> TurtleFileHash {
+        make_tunnel_hash(&self.item.destination_key, self.item.service_id)
+    }
+}
+
+/// What we learned about reaching a key.
+#[derive(Default)]
+struct RoutingClues {
+    last_receipt: u64,
+    receipts: u32,
+    failures: u32,
+    /// tunnels that delivered receipts, these are preferred
+    good_tunnels: HashSet<u32>,
+}
+
+/// Global router, routes signed and encrypted data to gxs ids through turtle tunnels.
+///
+/// Outgoing items are kept (across restarts) until the destination returns a signed receipt or they expire.
+/// Items are resent through other tunnels when no receipt arrives, tunnels that delivered receipts before are preferred.
+pub struct GRouter {
+    core: Arc<DataCore>,
+
+    turtle_rx: Receiver<TurtleEvent>,
+    cmd_rx: Receiver<GRouterCmd>,
+
+    rng: WyRand,
+
+    tunnels: HashMap<u32, Tunnel>,
+    /// hashes we are digging tunnels for
+    monitored: HashSet<TurtleFileHash>,
+
+    pending: HashMap<u64, PendingItem>,
+    /// sent transactions waiting for an ack, maps to the routing id of the item
+    transactions: HashMap<u64, u64>,
+    /// received items, used to drop duplicates
+    received: HashMap<u64, Instant>,
+    clues: HashMap<GxsId, RoutingClues>,
+    config_changed: bool,
+}
+
+pub enum GRouterInput {
+    Turtle(TurtleEvent),
+    Cmd(GRouterCmd),
+}
+
+impl GRouter {
+    pub fn new(core: &Arc<DataCore>) -> GRouter {
+        let (tx_turtle, rx_turtle) = channel(
+            "grouter turtle events",
+            SERVICE_EVENTS_QUEUE_SIZE,
+            Policy::DropOldest,
+        );
+        core.get_service_data()
+            .turtle()
+            .register_client(Arc::new(GRouterTurtleClient {
+                core: core.to_owned(),
+                tx: tx_turtle,
+            }));
+
+        let (tx_cmd, rx_cmd) = channel("grouter commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        core.get_service_data().grouter().set_cmd(tx_cmd);
+
+        GRouter {
+            core: core.to_owned(),
+
+            turtle_rx: rx_turtle,
+            cmd_rx: rx_cmd,
+
+            rng: WyRand::new(),
+
+            tunnels: HashMap::new(),
+            monitored: HashSet::new(),
+
+            pending: HashMap::new(),
+            transactions: HashMap::new(),
+            received: HashMap::new(),
+            clues: HashMap::new(),
+            config_changed: false,
+        }
+    }
+
+    fn notify(&self, service_id: u32, event: GRouterEvent) {
+        match self
+            .core
+            .get_service_data()
+            .grouter()
+            .get_client(&service_id)
+        {
+            Some(tx) => _ = tx.try_send(event),
+            None => warn!("unable to find GRouter client {service_id:08x}"),
+        }
+    }
+
+    fn send_turtle(&self, cmd: TurtleCmd) {
+        if !self.core.get_service_data().turtle().send_cmd(cmd) {
+            warn!("failed to send command to turtle");
+        }
+    }
+
+    async fn update_own_ids(&self) {
+        let ids: HashSet<_> = self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_own_ids()
+            .await
+            .into_iter()
+            .collect();
+
+        trace!("own ids: {ids:?}");
+        self.core.get_service_data().grouter().set_own_ids(ids);
+    }
+
+    /// Signs the signed part of an item (including a header, like RS does).
+    async fn sign(
+        &self,
+        signing_id: &GxsId,
+        sub_type: u8,
+        payload: Vec<u8>,
+    ) -> Option<TlvKeySignature> {
+        let key = match self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_priv_keys_by_id(signing_id)
+            .await
+        {
+            Some(key) => key,
+            None => {
+                warn!("failed to find private key of {signing_id}");
+                return None;
+            }
+        };
+
+        match generate_signature(&key, &serialize_payload(sub_type, payload)) {
+            Ok(signature) => {
+                let mut inner = TlvKeySignatureInner::new(signing_id.to_owned().into());
+                inner.sign_data = signature.into();
+                Some(TlvKeySignature::new(inner))
+            }
+            Err(err) => {
+                warn!("failed to sign item: {err}");
+                None
+            }
+        }
+    }
+
+    /// Verifies a signature of `signer` on the signed part of an item.
+    async fn verify(
+        &self,
+        signer: &GxsId,
+        sub_type: u8,
+        payload: Vec<u8>,
+        signature: &TlvKeySignature,
+    ) -> bool {
+        if GxsId::from(signature.key_id.to_owned()) != *signer {
+            warn!("item is signed by an unexpected key {}", *signature.key_id);
+            return false;
+        }
+
+        let key = match self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_pub_keys_by_id(signer)
+            .await
+        {
+            Some(key) => key,
+            None => {
+                debug!("unable to verify signature, {signer} is unknown");
+                return false;
+            }
+        };
+
+        match verify_signature(
+            &key,
+            &serialize_payload(sub_type, payload),
+            &signature.sign_data,
+        ) {
+            Ok(valid) => valid,
+            Err(err) => {
+                warn!("failed to verify signature of {signer}: {err}");
+                false
+            }
+        }
+    }
+
+    // --- commands
+
+    async fn handle_cmd(&mut self, cmd: GRouterCmd) {
+        match cmd {
+            GRouterCmd::Send {
+                destination,
+                signing_id,
+                service_id,
+                data,
+                tx,
+            } => {
+                let routing_id = self
+                    .send_data(destination, signing_id, service_id, data)
+                    .await;
+                _ = tx.send(routing_id);
+            }
+        }
+    }
+
+    async fn send_data(
+        &mut self,
+        destination: GxsId,
+        signing_id: GxsId,
+        service_id: u32,
+        data: Vec<u8>,
+    ) -> Option<u64> {
+        let key = match self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_pub_keys_by_id(&destination)
+            .await
+        {
+            Some(key) => key,
+            None => {
+                warn!("unable to send data to {destination}, the id is unknown");
+                return None;
+            }
+        };
+        let data = match encrypt_data(&key, &data) {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("failed to encrypt data for {destination}: {err}");
+                return None;
+            }
+        };
+
+        let mut item = GRouterGenericDataItem {
+            routing_id: self.rng.generate(),
+            destination_key: destination,
+            service_id,
+            data,
+            signature: TlvKeySignature::default(),
+            duplication_factor: 1,
+            flags: GROUTER_DATA_FLAGS_ENCRYPTED,
+        };
+        item.signature = self
+            .sign(&signing_id, GROUTER_SUB_TYPE_DATA, item.signed_payload())
+            .await?;
+
+        debug!("routing {item}");
+
+        let routing_id = item.routing_id;
+        self.pending.insert(
+            routing_id,
+            PendingItem {
+                item,
+                sent: false,
+                received_time: SystemTime::now(),
+                last_sent: None,
+                attempts: 0,
+                tunnel_id: None,
+            },
+        );
+        self.config_changed = true;
+
+        self.update_monitoring();
+        self.send_pending();
+
+        Some(routing_id)
+    }
+
+    // --- routing
+
+    /// Digs tunnels for all keys with pending items, stops digging for all others.
+    fn update_monitoring(&mut self) {
+        let needed: HashSet<_> = self.pending.values().map(PendingItem::hash).collect();
+
+        for hash in needed.difference(&self.monitored) {
+            trace!("monitoring tunnels for {hash}");
+            self.send_turtle(TurtleCmd::MonitorTunnels(*hash, ServiceType::GRouter));
+        }
+        for hash in self.monitored.difference(&needed) {
+            trace!("stop monitoring tunnels for {hash}");
+            self.send_turtle(TurtleCmd::StopMonitoringTunnels(*hash));
+        }
+        self.monitored = needed;
+    }
+
+    /// Keys that were reachable recently are retried fast, everything else backs off.
+    fn resend_delay(&self, pending: &PendingItem) -> Duration {
+        let reachable = self
+            .clues
+            .get(&pending.item.destination_key)
+            .is_some_and(|clues| clues.last_receipt + GROUTER_REACHABLE_TIME.as_secs() > now());
+        let backoff = if reachable {
+            0
+        } else {
+            pending.attempts.min(GROUTER_MAX_BACKOFF)
+        };
+        GROUTER_RESEND_DELAY * 2u32.pow(backoff)
+    }
+
+    /// Picks a tunnel for an item, preferring tunnels that delivered receipts and avoiding the previously used one.
+    fn pick_tunnel(&self, pending: &PendingItem) -> Option<u32> {
+        let hash = pending.hash();
+        let good = self.clues.get(&pending.item.destination_key);
+
+        self.tunnels
+            .iter()
+            .filter(|(_, tunnel)| {
+                tunnel.hash == hash && tunnel.direction == TunnelDirection::Client
+            })
+            .map(|(tunnel_id, _)| *tunnel_id)
+            .min_by_key(|tunnel_id| {
+                (
+                    !good.is_some_and(|clues| clues.good_tunnels.contains(tunnel_id)),
+                    pending.tunnel_id == Some(*tunnel_id),
+                )
+            })
+    }
+
+    /// Sends all pending items that are due and have a tunnel.
+    fn send_pending(&mut self) {
+        let due: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| match p.last_sent {
+                Some(last_sent) => last_sent.elapsed().unwrap_or_default() >= self.resend_delay(p),
+                None => true,
+            })
+            .filter_map(|(routing_id, p)| Some((*routing_id, self.pick_tunnel(p)?)))
+            .collect();
+
+        for (routing_id, tunnel_id) in due {
+            let data = serialize_item(GROUTER_SUB_TYPE_DATA, &self.pending[&routing_id].item);
+            let propagation_id = self.send_transaction(tunnel_id, data);
+            self.transactions.insert(propagation_id, routing_id);
+
+            let pending = self.pending.get_mut(&routing_id).unwrap();
+            pending.sent = false;
+            pending.last_sent = Some(SystemTime::now());
+            pending.attempts += 1;
+            pending.tunnel_id = Some(tunnel_id);
+            debug!(
+                "sent {} through tunnel {tunnel_id:08x} (attempt {})",
+                pending.item, pending.attempts
+            );
+            self.config_changed = true;
+        }
+    }
+
+    /// Sends (serialized) data through a tunnel, split into chunks, returns the propagation id.
+    fn send_transaction(&mut self, tunnel_id: u32, data: Vec<u8>) -> u64 {
+        let propagation_id = self.rng.generate();
+        let total_size = data.len() as u32;
+
+        for (i, chunk) in data.chunks(GROUTER_TRANSACTION_CHUNK_SIZE).enumerate() {
+            let item = GRouterTransactionChunkItem {
+                propagation_id,
+                chunk_start: (i * GROUTER_TRANSACTION_CHUNK_SIZE) as u32,
+                total_size,
+                chunk_data: chunk.to_vec(),
+            };
+            trace!("sending {item}");
+            self.send_turtle(TurtleCmd::SendData(
+                tunnel_id,
+                serialize_item(GROUTER_SUB_TYPE_TRANSACTION_CHUNK, &item),
+            ));
+        }
+        propagation_id
+    }
+
+    // --- turtle
+
+    async fn handle_turtle_event(&mut self, event: TurtleEvent) {
+        match event {
+            TurtleEvent::Up(hash, tunnel_id, direction) => {
+                debug!("tunnel {tunnel_id:08x} for {hash} is up");
+                self.tunnels.insert(
+                    tunnel_id,
+                    Tunnel {
+                        hash,
+                        direction,
+                        incoming: HashMap::new(),
+                    },
+                );
+                self.send_pending();
+            }
+            TurtleEvent::Down(_hash, tunnel_id) => self.handle_tunnel_down(tunnel_id),
+            TurtleEvent::Data(_hash, tunnel_id, data) => {
+                self.handle_turtle_data(tunnel_id, data).await
+            }
+        }
+    }
+
+    fn handle_tunnel_down(&mut self, tunnel_id: u32) {
+        if self.tunnels.remove(&tunnel_id).is_none() {
+            return;
+        }
+        debug!("tunnel {tunnel_id:08x} is down");
+
+        for clues in self.clues.values_mut() {
+            clues.good_tunnels.remove(&tunnel_id);
+        }
+        // items that didn't make it through the tunnel are sent again right away
+        for pending in self.pending.values_mut() {
+            if pending.tunnel_id == Some(tunnel_id) && !pending.sent {
+                pending.last_sent = None;
+            }
+        }
+        self.send_pending();
+    }
+
+    async fn handle_turtle_data(&mut self, tunnel_id: u32, data: Vec<u8>) {
+        match parse_item(data) {
+            Some((GROUTER_SUB_TYPE_TRANSACTION_CHUNK, payload)) => {
+                if let Some(item) = deserialize_item(payload) {
+                    self.handle_chunk(tunnel_id, item).await;
+                }
+            }
+            Some((GROUTER_SUB_TYPE_TRANSACTION_ACKN, payload)) => {
+                if let Some(item) = deserialize_item::<GRouterTransactionAcknItem>(payload) {
+                    self.handle_transaction_ack(item);
+                }
+            }
+            Some((sub_type, _)) => {
+                warn!("received unexpected item {sub_type:02x} through tunnel {tunnel_id:08x}")
+            }
+            None => warn!("failed to parse data of tunnel {tunnel_id:08x}"),
+        }
+    }
+
+    async fn handle_chunk(&mut self, tunnel_id: u32, item: GRouterTransactionChunkItem) {
+        trace!("received {item}");
+
+        let tunnel = match self.tunnels.get_mut(&tunnel_id) {
+            Some(tunnel) => tunnel,
+            None => {
+                warn!("received data through unknown tunnel {tunnel_id:08x}");
+                return;
+            }
+        };
+
+        let start = item.chunk_start as usize;
+        let end = start + item.chunk_data.len();
+        if end > item.total_size as usize {
+            warn!("received invalid chunk through tunnel {tunnel_id:08x}");
+            return;
+        }
+
+        let transaction = tunnel
+            .incoming
+            .entry(item.propagation_id)
+            .or_insert_with(|| IncomingTransaction {
+                data: vec![0; item.total_size as usize],
+                received: 0,
+                last_activity: Instant::now(),
+            });
+        if transaction.data.len() != item.total_size as usize {
+            warn!("received inconsistent chunk through tunnel {tunnel_id:08x}");
+            return;
+        }
+        transaction.data[start..end].copy_from_slice(&item.chunk_data);
+        transaction.received += item.chunk_data.len();
+        transaction.last_activity = Instant::now();
+
+        if transaction.received < transaction.data.len() {
+            return;
+        }
+        let data = tunnel.incoming.remove(&item.propagation_id).unwrap().data;
+
+        // acknowledge the transaction, the receipt can take a while
+        let ack = GRouterTransactionAcknItem {
+            propagation_id: item.propagation_id,
+        };
+        self.send_turtle(TurtleCmd::SendData(
+            tunnel_id,
+            serialize_item(GROUTER_SUB_TYPE_TRANSACTION_ACKN, &ack),
+        ));
+
+        match parse_item(data) {
+            Some((GROUTER_SUB_TYPE_DATA, payload)) => {
+                if let Some(item) = deserialize_item(payload) {
+                    self.handle_data(tunnel_id, item).await;
+                }
+            }
+            Some((GROUTER_SUB_TYPE_SIGNED_RECEIPT, payload)) => {
+                if let Some(item) = deserialize_item(payload) {
+                    self.handle_receipt(tunnel_id, item).await;
+                }
+            }
+            Some((sub_type, _)) => {
+                warn!("received unexpected item {sub_type:02x} through tunnel {tunnel_id:08x}")
+            }
+            None => warn!("failed to parse item received through tunnel {tunnel_id:08x}"),
+        }
+    }
+
+    fn handle_transaction_ack(&mut self, item: GRouterTransactionAcknItem) {
+        let routing_id = match self.transactions.remove(&item.propagation_id) {
+            Some(routing_id) => routing_id,
+            None => return,
+        };
+        if let Some(pending) = self.pending.get_mut(&routing_id) {
+            trace!("{} reached the end of the tunnel", pending.item);
+            pending.sent = true;
+            self.config_changed = true;
+        }
+    }
+
+    // --- items
+
+    async fn handle_data(&mut self, tunnel_id: u32, item: GRouterGenericDataItem) {
+        trace!("received {item}");
+
+        let destination = item.destination_key;
+        if !self
+            .core
+            .get_service_data()
+            .grouter()
+            .is_own_id(&destination)
+        {
+            warn!("received {item}, which is not for us");
+            return;
+        }
+
+        // the sender needs a valid signature to make sense of the receipt
+        let signing_id: GxsId = item.signature.key_id.to_owned().into();
+        if !self
+            .verify(
+                &signing_id,
+                GROUTER_SUB_TYPE_DATA,
+                item.signed_payload(),
+                &item.signature,
+            )
+            .await
+        {
+            warn!("dropping {item} with invalid signature of {signing_id}");
+            return;
+        }
+
+        // always send a receipt, the previous one might got lost
+        self.send_receipt(tunnel_id, &item).await;
+
+        if self
+            .received
+            .insert(item.routing_id, Instant::now())
+            .is_some()
+        {
+            trace!("dropping duplicate {item}");
+            return;
+        }
+
+        let data = if item.flags & GROUTER_DATA_FLAGS_ENCRYPTED != 0 {
+            let key = match self
+                .core
+                .get_service_data()
+                .gxs_id()
+                .get_priv_keys_by_id(&destination)
+                .await
+            {
+                Some(key) => key,
+                None => {
+                    warn!("failed to find private key of {destination}");
+                    return;
+                }
+            };
+            match decrypt_data(&key, &item.data) {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    warn!("received malformed encrypted data in {item}");
+                    return;
+                }
+                Err(err) => {
+                    warn!("failed to decrypt {item}: {err}");
+                    return;
+                }
+            }
+        } else {
+            item.data
+        };
+
+        info!("received data from {signing_id} for {destination}");
+        self.notify(
+            item.service_id,
+            GRouterEvent::Received {
+                destination,
+                signing_id,
+                data,
+            },
+        );
+    }
+
+    async fn send_receipt(&mut self, tunnel_id: u32, item: &GRouterGenericDataItem) {
+        let mut receipt = GRouterSignedReceiptItem {
+            routing_id: item.routing_id,
+            flags: 0,
+            destination_key: item.destination_key,
+            service_id: item.service_id,
+            // the sender only knows the encrypted data
+            data_hash: sha1(&item.data).to_vec().into(),
+            signature: TlvKeySignature::default(),
+        };
+        receipt.signature = match self
+            .sign(
+                &item.destination_key,
+                GROUTER_SUB_TYPE_SIGNED_RECEIPT,
+                receipt.signed_payload(),
+            )
+            .await
+        {
+            Some(signature) => signature,
+            None => return,
+        };
+
+        trace!("sending receipt for {:016x}", item.routing_id);
+        let data = serialize_item(GROUTER_SUB_TYPE_SIGNED_RECEIPT, &receipt);
+        self.send_transaction(tunnel_id, data);
+    }
+
+    async fn handle_receipt(&mut self, tunnel_id: u32, receipt: GRouterSignedReceiptItem) {
+        let pending = match self.pending.get(&receipt.routing_id) {
+            Some(pending) => pending,
+            None => {
+                trace!(
+                    "received receipt for unknown item {:016x}",
+                    receipt.routing_id
+                );
+                return;
+            }
+        };
+
+        let destination = pending.item.destination_key;
+        if receipt.destination_key != destination
+            || receipt.service_id != pending.item.service_id
+            || receipt.data_hash != sha1(&pending.item.data).to_vec().into()
+        {
+            warn!("receipt for {} doesn't match", pending.item);
+            return;
+        }
+        if !self
+            .verify(
+                &destination,
+                GROUTER_SUB_TYPE_SIGNED_RECEIPT,
+                receipt.signed_payload(),
+                &receipt.signature,
+            )
+            .await
+        {
+            warn!(
+                "receipt for {:016x} has an invalid signature",
+                receipt.routing_id
+            );
+            return;
+        }
+
+        let pending = self.pending.remove(&receipt.routing_id).unwrap();
+        info!("{} was delivered", pending.item);
+
+        let clues = self.clues.entry(destination).or_default();
+        clues.last_receipt = now();
+        clues.receipts += 1;
+        clues.good_tunnels.insert(tunnel_id);
+        self.config_changed = true;
+
+        self.notify(
+            pending.item.service_id,
+            GRouterEvent::Delivered(receipt.routing_id),
+        );
+        self.update_monitoring();
+    }
+
+    fn maintain(&mut self) {
+        // drop expired items
+        let expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.received_time.elapsed().unwrap_or_default() > GROUTER_ITEM_MAX_LIFE)
+            .map(|(routing_id, _)| *routing_id)
+            .collect();
+        for routing_id in expired {
+            let pending = self.pending.remove(&routing_id).unwrap();
+            info!("giving up on {}", pending.item);
+
+            self.clues
+                .entry(pending.item.destination_key)
+                .or_default()
+                .failures += 1;
+            self.config_changed = true;
+
+            self.notify(pending.item.service_id, GRouterEvent::Failed(routing_id));
+        }
+        if !self.pending.is_empty() || !self.monitored.is_empty() {
+            self.update_monitoring();
+        }
+        self.send_pending();
+
+        let pending = &self.pending;
+        self.transactions
+            .retain(|_, routing_id| pending.contains_key(routing_id));
+        for tunnel in self.tunnels.values_mut() {
+            tunnel
+                .incoming
+                .retain(|_, t| t.last_activity.elapsed() < GROUTER_TRANSACTION_TIMEOUT);
+        }
+        self.received
+            .retain(|_, time| time.elapsed() < GROUTER_RECEIVED_LIFE_TIME);
+
+        if self.config_changed {
+            self.save_config();
+        }
+    }
+
+    // --- config
+
+    fn load_config(&mut self) {
+        let data = match self.core.load_config(GROUTER_CONFIG_FILE) {
+            Some(data) => data,
+            None => return,
+        };
+
+        for (header, payload) in read_config_records(data, GROUTER_CONFIG_FILE) {
+            let sub_type = match header {
+                Header::Service {
+                    service: ServiceType::GRouter,
+                    sub_type,
+                    ..
+                } => sub_type,
+                _ => {
+                    warn!("failed to parse {GROUTER_CONFIG_FILE}");
+                    break;
+                }
+            };
+
+            match sub_type {
+                GROUTER_SUB_TYPE_ROUTING_INFO => {
+                    if let Some(item) = deserialize_item::<GRouterRoutingInfoItem>(payload) {
+                        let since_epoch = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+                        self.pending.insert(
+                            item.data_item.routing_id,
+                            PendingItem {
+                                sent: item.data_status == GROUTER_DATA_STATUS_SENT,
+                                received_time: since_epoch(item.received_time),
+                                last_sent: (item.last_sent != 0)
+                                    .then(|| since_epoch(item.last_sent)),
+                                attempts: item.sending_attempts,
+                                tunnel_id: None,
+                                item: item.data_item,
+                            },
+                        );
+                    }
+                }
+                GROUTER_SUB_TYPE_MATRIX_CLUES => {
+                    if let Some(item) = deserialize_item::<GRouterMatrixCluesItem>(payload) {
+                        self.clues.insert(
+                            item.destination_key,
+                            RoutingClues {
+                                last_receipt: item.last_receipt,
+                                receipts: item.receipts,
+                                failures: item.failures,
+                                good_tunnels: HashSet::new(),
+                            },
+                        );
+                    }
+                }
+                sub_type => {
+                    warn!("unexpected config item {sub_type:02x} in {GROUTER_CONFIG_FILE}")
+                }
+            }
+        }
+
+        info!(
+            "loaded {} pending item(s) and routing clues for {} key(s)",
+            self.pending.len(),
+            self.clues.len()
+        );
+    }
+
+    fn save_config(&mut self) {
+        let secs = |time: SystemTime| {
+            time.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        };
+
+        let mut data = vec![];
+        for pending in self.pending.values() {
+            let item = GRouterRoutingInfoItem {
+                data_status: if pending.sent {
+                    GROUTER_DATA_STATUS_SENT
+                } else {
+                    GROUTER_DATA_STATUS_PENDING
+                },
+                received_time: secs(pending.received_time),
+                last_sent: pending.last_sent.map(secs).unwrap_or_default(),
+                sending_attempts: pending.attempts,
+                data_item: pending.item.to_owned(),
+            };
+            data.extend(serialize_item(GROUTER_SUB_TYPE_ROUTING_INFO, &item));
+        }
+        for (destination, clues) in &self.clues {
+            let item = GRouterMatrixCluesItem {
+                destination_key: *destination,
+                last_receipt: clues.last_receipt,
+                receipts: clues.receipts,
+                failures: clues.failures,
+            };
+            data.extend(serialize_item(GROUTER_SUB_TYPE_MATRIX_CLUES, &item));
+        }
+
+        if self.core.save_config(GROUTER_CONFIG_FILE, &data) {
+            self.config_changed = false;
+        }
+    }
+}
+
+#[async_trait]
+impl Service for GRouter {
+    // everything goes through turtle
+    type Item = NoItem;
+    type Command = GRouterInput;
+
+    const SERVICE: ServiceType = ServiceType::GRouter;
+    const NAME: &'static str = "Global Router";
+    const TIMERS: &'static [(&'static str, Duration)] =
+        &[GROUTER_TIMER_MAINTENANCE, GROUTER_TIMER_OWN_IDS];
+
+    async fn start(&mut self, _ctx: &ServiceContext<NoItem>) {
+        self.load_config();
+    }
+
+    async fn handle_item(&mut self, _ctx: &ServiceContext<NoItem>, item: NoItem, _from: Arc<SslId>) {
+        match item {}
+    }
+
+    async fn on_timer(&mut self, _ctx: &ServiceContext<NoItem>, timer: &'static str) {
+        match timer {
+            t if t == GROUTER_TIMER_OWN_IDS.0 => self.update_own_ids().await,
+            _ => self.maintain(),
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<GRouterInput> {
+        select! {
+            Some(event) = self.turtle_rx.recv() => Some(GRouterInput::Turtle(event)),
+            Some(cmd) = self.cmd_rx.recv() => Some(GRouterInput::Cmd(cmd)),
+            else => None,
+        }
+    }
+
+    async fn handle_command(&mut self, _ctx: &ServiceContext<NoItem>, input: GRouterInput) {
+        match input {
+            GRouterInput::Turtle(event) => self.handle_turtle_event(event).await,
+            GRouterInput::Cmd(cmd) => self.handle_cmd(cmd).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retroshare_compat::{basics::GxsId, services::grouter::GROUTER_CLIENT_ID_MESSAGES};
+
+    use super::{destination_from_hash, make_tunnel_hash};
+
+    #[test]
+    fn tunnel_hash() {
+        let id: GxsId = "c59df722f56f2f886ac301acc5572e03".into();
+
+        let hash = make_tunnel_hash(&id, GROUTER_CLIENT_ID_MESSAGES);
+        assert_eq!(hash.to_string(), "c59df722f56f2f886ac301acc5572e0300001001");
+        assert_eq!(
+            destination_from_hash(&hash),
+            Some((id, GROUTER_CLIENT_ID_MESSAGES))
+        );
+
+        // gxs tunnel hashes start with random bytes
+        let mut other = hash.to_vec();
+        other[16] = 0x42;
+        assert_eq!(destination_from_hash(&other.into()), None);
+    }
+}
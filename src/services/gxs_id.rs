@@ -1,82 +1,53 @@
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use log::{trace, warn};
-use retroshare_compat::{
-    gxs::sqlite::database::GxsDatabase,
-    services::{service_info::RsServiceInfo, SERVICE_GXS_GXSID},
-};
-use tokio::{
-    select,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-};
+use retroshare_compat::{basics::SslId, gxs::sqlite::database::GxsDatabase, services::SERVICE_GXS_GXSID};
 
 use crate::{
-    gxs::{gxs_backend::{GxsBackend, GxsShared}, nxs::NxsTransactionController},
+    channel::Policy,
+    gxs::{
+        gxs_backend::{GxsBackend, GxsShared},
+        nxs::NxsTransactionController,
+    },
     low_level_parsing::Packet,
-    model::{intercom::Intercom, DataCore},
-    services::Service,
+    model::DataCore,
+    services::{Service, ServiceContext},
 };
 
 use ::retroshare_compat::services::ServiceType;
 
 pub struct GxsId {
-    rx: UnboundedReceiver<Intercom>,
     backend: GxsBackend<SERVICE_GXS_GXSID>,
 }
 
 impl GxsId {
-    pub async fn new(
-        core: &Arc<DataCore>,
-        _core_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-
-        (db, shared): (GxsDatabase, Arc<GxsShared>),
-    ) -> Self {
+    pub fn new(core: &Arc<DataCore>, (db, shared): (GxsDatabase, Arc<GxsShared>)) -> Self {
         let nxs = NxsTransactionController::new(shared.to_owned());
         let backend = GxsBackend::new(core.to_owned(), db, nxs, shared.to_owned());
 
-        GxsId { rx, backend }
-    }
-
-    async fn handle_incoming(&self, packet: Packet) {
-        self.backend.handle_packet(packet).await;
+        GxsId { backend }
     }
 }
 
 #[async_trait]
 impl Service for GxsId {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::GxsId
-    }
+    // the backend handles the (nxs) packets
+    type Item = Packet;
+    type Command = ();
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "gxsid")
-    }
+    const SERVICE: ServiceType = ServiceType::GxsId;
+    const NAME: &'static str = "gxsid";
+    // nxs transactions break when single items get lost
+    const QUEUE_POLICY: Policy = Policy::Block;
 
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
+    async fn handle_item(&mut self, _ctx: &ServiceContext<Packet>, packet: Packet, _from: Arc<SslId>) {
+        self.backend.handle_packet(packet).await;
+    }
 
-                            match msg {
-                                Intercom::Receive(packet) => {
-                                    self.handle_incoming(packet).await;
-                                }
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                    _ = self.backend.run() => {
-                        log::error!("gxs backend stopped");
-                        panic!();
-                    }
-                }
-            }
-        })
+    /// Drives the backend, it never hands out any commands.
+    async fn recv_command(&mut self) -> Option<()> {
+        self.backend.run().await;
+        log::error!("gxs backend stopped");
+        panic!();
     }
 }
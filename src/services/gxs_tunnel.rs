@@ -0,0 +1,1087 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use log::{debug, info, trace, warn};
+use nanorand::{Rng, WyRand};
+use openssl::{
+    bn::BigNum,
+    dh::Dh,
+    error::ErrorStack,
+    hash::MessageDigest,
+    memcmp,
+    pkcs5::bytes_to_key,
+    pkey::{PKey, Private},
+    sha::sha1,
+    sign::Signer,
+    symm::{decrypt, encrypt, Cipher},
+};
+use retroshare_compat::{
+    basics::{GxsId, GxsTunnelId, SslId},
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{gxs_tunnel::*, turtle::TurtleFileHash},
+    tlv::tlv_keys::{TlvKeyFlags, TlvKeySignature, TlvKeySignatureInner},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::select;
+
+use crate::{
+    channel::{channel, Policy, Receiver, Sender},
+    gxs::gxsid::{generate_signature, verify_signature},
+    low_level_parsing::{
+        headers::{Header, ServiceHeader, HEADER_SIZE},
+        Packet,
+    },
+    model::{
+        services::{
+            gxs_tunnel::{GxsTunnelCmd, GxsTunnelEvent, GxsTunnelInfo, GxsTunnelStatus},
+            turtle::{TunnelDirection, TurtleCmd},
+        },
+        DataCore,
+    },
+    services::{
+        turtle::TurtleClient, NoItem, Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE,
+        SERVICE_EVENTS_QUEUE_SIZE,
+    },
+};
+
+use ::retroshare_compat::services::ServiceType;
+
+// We use our own DH group prime. This has been generated with command-line openssl and checked. (copied from RS)
+const DH_PRIME_2048_HEX: &str = "B3B86A844550486C7EA459FA468D3A8EFD71139593FE1C658BBEFA9B2FC0AD2628242C2CDC2F91F5B220ED29AAC271192A7374DFA28CDDCA70252F342D0821273940344A7A6A3CB70C7897A39864309F6CAC5C7EA18020EF882693CA2C12BB211B7BA8367D5A7C7252A5B5E840C9E8F081469EBA0B98BCC3F593A4D9C4D5DF539362084F1B9581316C1F80FDAD452FD56DBC6B8ED0775F596F7BB22A3FE2B4753764221528D33DB4140DE58083DB660E3E105123FC963BFF108AC3A268B7380FFA72005A1515C371287C5706FFA6062C9AC73A9B1A6AC842C2764CDACFC85556607E86611FDF486C222E4896CDF6908F239E177ACC641FCBFF72A758D1C10CBB";
+const DH_GENERATOR: u32 = 5;
+
+const GXS_TUNNEL_AES_KEY_SIZE: usize = 16;
+
+const GXS_TUNNEL_TIMER_MAINTENANCE: (&str, Duration) = ("maintenance", Duration::from_secs(2));
+const GXS_TUNNEL_TIMER_OWN_IDS: (&str, Duration) = ("own_ids", Duration::from_secs(60));
+const GXS_TUNNEL_ENCRYPTION_IV_SIZE: usize = 8;
+const GXS_TUNNEL_ENCRYPTION_HMAC_SIZE: usize = 20;
+
+/// time between keep alive packets.
+const GXS_TUNNEL_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(6);
+/// tunnels without any contact for this long are considered down.
+const GXS_TUNNEL_CONTACT_TIMEOUT: Duration = Duration::from_secs(26);
+/// time after which unacknowledged data is sent again.
+const GXS_TUNNEL_DELAY_BETWEEN_RESEND: Duration = Duration::from_secs(10);
+/// time during which received data is remembered to drop duplicates.
+const GXS_TUNNEL_RECEIVED_DATA_LIFE_TIME: Duration = Duration::from_secs(120);
+
+/// Tunnel ids are derived from both ids, sorted, so that both ends end up with the same id.
+pub fn make_tunnel_id(own_id: &GxsId, remote_id: &GxsId) -> GxsTunnelId {
+    let (a, b) = if own_id < remote_id {
+        (own_id, remote_id)
+    } else {
+        (remote_id, own_id)
+    };
+
+    let mut buf = a.to_vec();
+    buf.extend_from_slice(b.as_ref());
+    sha1(&buf)[..16].to_vec().into()
+}
+
+/// The hash to dig tunnels for ends with the destination id, the first bytes are random to make tunnels unlinkable.
+fn random_hash_from_destination(destination: &GxsId, rng: &mut WyRand) -> TurtleFileHash {
+    let mut buf = rng.generate::<u32>().to_be_bytes().to_vec();
+    buf.extend_from_slice(destination.as_ref());
+    buf.into()
+}
+
+fn destination_from_hash(hash: &TurtleFileHash) -> GxsId {
+    hash[4..].to_vec().into()
+}
+
+/// RS's `RsAES::aes_crypt_8_16` and `RsAES::aes_decrypt_8_16`: AES-256-CBC with key and iv derived from the 16 bytes key and the 8 bytes salt.
+fn aes_crypt_8_16(
+    data: &[u8],
+    key: &[u8; GXS_TUNNEL_AES_KEY_SIZE],
+    salt: &[u8; GXS_TUNNEL_ENCRYPTION_IV_SIZE],
+    encrypting: bool,
+) -> Result<Vec<u8>, ErrorStack> {
+    let cipher = Cipher::aes_256_cbc();
+    let pair = bytes_to_key(cipher, MessageDigest::sha1(), key, Some(salt), 5)?;
+
+    if encrypting {
+        encrypt(cipher, &pair.key, pair.iv.as_deref(), data)
+    } else {
+        decrypt(cipher, &pair.key, pair.iv.as_deref(), data)
+    }
+}
+
+fn hmac_sha1(key: &[u8; GXS_TUNNEL_AES_KEY_SIZE], data: &[u8]) -> Result<Vec<u8>, ErrorStack> {
+    let pkey = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &pkey)?;
+    signer.update(data)?;
+    signer.sign_to_vec()
+}
+
+/// Serializes an item including its (service) header, like RS does for items send through tunnels.
+fn serialize_item<T>(sub_type: u8, item: &T) -> Vec<u8>
+where
+    T: Serialize,
+{
+    let payload = to_retroshare_wire(item);
+    let header = ServiceHeader::new(ServiceType::GxsTunnel, sub_type, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
+
+/// Splits serialized data into sub type and payload.
+fn parse_item(mut data: Vec<u8>) -> Option<(u8, Vec<u8>)> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let mut payload = data.split_off(HEADER_SIZE);
+    let header: [u8; HEADER_SIZE] = data.try_into().ok()?;
+
+    match Header::try_parse(&header) {
+        Ok(Header::Service {
+            service: ServiceType::GxsTunnel,
+            sub_type,
+            size,
+        }) if size as usize - HEADER_SIZE <= payload.len() => {
+            payload.truncate(size as usize - HEADER_SIZE);
+            Some((sub_type, payload))
+        }
+        _ => None,
+    }
+}
+
+fn deserialize_item<T>(mut payload: Vec<u8>) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match from_retroshare_wire_result(&mut payload) {
+        Ok(item) => Some(item),
+        Err(err) => {
+            warn!("failed to deserialize item: {err:?}");
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TurtleEvent {
+    Up(TurtleFileHash, u32, TunnelDirection),
+    Down(TurtleFileHash, u32),
+    Data(TurtleFileHash, u32, Vec<u8>),
+}
+
+/// Connects the gxs tunnel service to the turtle service.
+struct GxsTunnelTurtleClient {
+    core: Arc<DataCore>,
+    tx: Sender<TurtleEvent>,
+}
+
+#[async_trait]
+impl TurtleClient for GxsTunnelTurtleClient {
+    fn get_service_id(&self) -> ServiceType {
+        ServiceType::GxsTunnel
+    }
+
+    async fn handle_tunnel_request(&self, hash: &TurtleFileHash, _peer_id: &Arc<SslId>) -> bool {
+        self.core
+            .get_service_data()
+            .gxs_tunnel()
+            .is_own_id(&destination_from_hash(hash))
+    }
+
+    async fn receive_turtle_data(
+        &self,
+        data: Vec<u8>,
+        hash: &TurtleFileHash,
+        tunnel_id: u32,
+        _direction: TunnelDirection,
+    ) {
+        _ = self.tx.try_send(TurtleEvent::Data(*hash, tunnel_id, data));
+    }
+
+    async fn tunnel_up(&self, hash: &TurtleFileHash, tunnel_id: u32, direction: TunnelDirection) {
+        _ = self
+            .tx
+            .try_send(TurtleEvent::Up(*hash, tunnel_id, direction));
+    }
+
+    async fn tunnel_down(&self, hash: &TurtleFileHash, tunnel_id: u32) {
+        _ = self.tx.try_send(TurtleEvent::Down(*hash, tunnel_id));
+    }
+}
+
+/// A turtle tunnel, used by at most one gxs tunnel.
+struct VirtualPeer {
+    direction: TunnelDirection,
+    own_id: GxsId,
+    /// DH session, until the key exchange is done
+    dh: Option<Dh<Private>>,
+    tunnel_id: Option<GxsTunnelId>,
+}
+
+/// A gxs tunnel.
+struct Contact {
+    own_id: GxsId,
+    remote_id: GxsId,
+    /// hash we are digging tunnels for (only set for tunnels we requested)
+    hash: Option<TurtleFileHash>,
+
+    status: GxsTunnelStatus,
+    virtual_peer: Option<u32>,
+    aes_key: [u8; GXS_TUNNEL_AES_KEY_SIZE],
+
+    last_contact: Instant,
+    last_keep_alive_sent: Instant,
+
+    /// client services using the tunnel
+    services: HashSet<u32>,
+}
+
+impl Contact {
+    fn info(&self) -> GxsTunnelInfo {
+        GxsTunnelInfo {
+            own_id: self.own_id,
+            remote_id: self.remote_id,
+            status: self.status,
+        }
+    }
+}
+
+struct PendingData {
+    tunnel_id: GxsTunnelId,
+    item: GxsTunnelDataItem,
+    last_sent: Option<Instant>,
+}
+
+/// Secured tunnels between two gxs ids, build on top of turtle tunnels.
+///
+/// Both ends perform a DH key exchange (signed by their ids), everything else is encrypted with the resulting key.
+pub struct GxsTunnel {
+    core: Arc<DataCore>,
+
+    turtle_rx: Receiver<TurtleEvent>,
+    cmd_rx: Receiver<GxsTunnelCmd>,
+
+    rng: WyRand,
+    dh_prime: BigNum,
+    counter: u64,
+
+    virtual_peers: HashMap<u32, VirtualPeer>,
+    contacts: HashMap<GxsTunnelId, Contact>,
+
+    /// sent data waiting for an ack
+    pending: HashMap<u64, PendingData>,
+    /// received data, used to drop duplicates
+    received: HashMap<u64, Instant>,
+}
+
+pub enum GxsTunnelInput {
+    Turtle(TurtleEvent),
+    Cmd(GxsTunnelCmd),
+}
+
+impl GxsTunnel {
+    pub fn new(core: &Arc<DataCore>) -> GxsTunnel {
+        let (tx_turtle, rx_turtle) = channel(
+            "gxs tunnel turtle events",
+            SERVICE_EVENTS_QUEUE_SIZE,
+            Policy::DropOldest,
+        );
+        core.get_service_data()
+            .turtle()
+            .register_client(Arc::new(GxsTunnelTurtleClient {
+                core: core.to_owned(),
+                tx: tx_turtle,
+            }));
+
+        let (tx_cmd, rx_cmd) =
+            channel("gxs tunnel commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        core.get_service_data().gxs_tunnel().set_cmd(tx_cmd);
+
+        let mut rng = WyRand::new();
+        let counter = rng.generate();
+
+        GxsTunnel {
+            core: core.to_owned(),
+
+            turtle_rx: rx_turtle,
+            cmd_rx: rx_cmd,
+
+            rng,
+            dh_prime: BigNum::from_hex_str(DH_PRIME_2048_HEX).expect("failed to parse DH prime"),
+            counter,
+
+            virtual_peers: HashMap::new(),
+            contacts: HashMap::new(),
+
+            pending: HashMap::new(),
+            received: HashMap::new(),
+        }
+    }
+
+    fn notify(&self, service_id: u32, event: GxsTunnelEvent) {
+        match self
+            .core
+            .get_service_data()
+            .gxs_tunnel()
+            .get_client(&service_id)
+        {
+            Some(tx) => _ = tx.try_send(event),
+            None => warn!("unable to find gxs tunnel client {service_id:08x}"),
+        }
+    }
+
+    fn notify_status(&self, tunnel_id: &GxsTunnelId) {
+        let store = self.core.get_service_data().gxs_tunnel();
+        match self.contacts.get(tunnel_id) {
+            Some(contact) => {
+                store.set_tunnel_info(tunnel_id.to_owned(), contact.info());
+                for service_id in &contact.services {
+                    self.notify(
+                        *service_id,
+                        GxsTunnelEvent::Status(tunnel_id.to_owned(), contact.status),
+                    );
+                }
+            }
+            None => store.remove_tunnel_info(tunnel_id),
+        }
+    }
+
+    fn send_turtle(&self, cmd: TurtleCmd) {
+        if !self.core.get_service_data().turtle().send_cmd(cmd) {
+            warn!("failed to send command to turtle");
+        }
+    }
+
+    async fn update_own_ids(&self) {
+        let ids: HashSet<_> = self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_own_ids()
+            .await
+            .into_iter()
+            .collect();
+
+        trace!("own ids: {ids:?}");
+        self.core.get_service_data().gxs_tunnel().set_own_ids(ids);
+    }
+
+    // --- commands
+
+    async fn handle_cmd(&mut self, cmd: GxsTunnelCmd) {
+        match cmd {
+            GxsTunnelCmd::RequestTunnel {
+                own_id,
+                remote_id,
+                service_id,
+            } => self.request_tunnel(own_id, remote_id, service_id),
+            GxsTunnelCmd::SendData {
+                tunnel_id,
+                service_id,
+                data,
+            } => {
+                self.counter = self.counter.wrapping_add(1);
+                let item = GxsTunnelDataItem {
+                    unique_item_counter: self.counter,
+                    flags: 0,
+                    service_id,
+                    data,
+                };
+                self.pending.insert(
+                    self.counter,
+                    PendingData {
+                        tunnel_id,
+                        item,
+                        last_sent: None,
+                    },
+                );
+                self.send_pending();
+            }
+            GxsTunnelCmd::CloseTunnel {
+                tunnel_id,
+                service_id,
+            } => self.close_tunnel(tunnel_id, service_id),
+        }
+    }
+
+    fn request_tunnel(&mut self, own_id: GxsId, remote_id: GxsId, service_id: u32) {
+        let tunnel_id = make_tunnel_id(&own_id, &remote_id);
+
+        if let Some(contact) = self.contacts.get_mut(&tunnel_id) {
+            debug!("reusing tunnel {tunnel_id} for {service_id:08x}");
+
+            contact.services.insert(service_id);
+            if contact.status == GxsTunnelStatus::CanTalk {
+                self.notify(
+                    service_id,
+                    GxsTunnelEvent::Connected {
+                        tunnel_id,
+                        own_id,
+                        remote_id,
+                    },
+                );
+            }
+            return;
+        }
+
+        debug!("requesting tunnel {tunnel_id} from {own_id} to {remote_id}");
+
+        let hash = random_hash_from_destination(&remote_id, &mut self.rng);
+        self.contacts.insert(
+            tunnel_id,
+            Contact {
+                own_id,
+                remote_id,
+                hash: Some(hash),
+
+                status: GxsTunnelStatus::TunnelDown,
+                virtual_peer: None,
+                aes_key: [0; GXS_TUNNEL_AES_KEY_SIZE],
+
+                last_contact: Instant::now(),
+                last_keep_alive_sent: Instant::now(),
+
+                services: [service_id].into(),
+            },
+        );
+        self.notify_status(&tunnel_id);
+
+        self.send_turtle(TurtleCmd::MonitorTunnels(hash, ServiceType::GxsTunnel));
+    }
+
+    fn close_tunnel(&mut self, tunnel_id: GxsTunnelId, service_id: u32) {
+        let contact = match self.contacts.get_mut(&tunnel_id) {
+            Some(contact) => contact,
+            None => {
+                debug!("cannot close unknown tunnel {tunnel_id}");
+                return;
+            }
+        };
+
+        contact.services.remove(&service_id);
+        if !contact.services.is_empty() {
+            return;
+        }
+
+        debug!("closing tunnel {tunnel_id}");
+
+        if contact.status == GxsTunnelStatus::CanTalk {
+            self.send_status(&tunnel_id, GXS_TUNNEL_FLAG_CLOSING_DISTANT_CONNECTION);
+        }
+
+        let contact = self.contacts.remove(&tunnel_id).unwrap();
+        if let Some(hash) = contact.hash {
+            self.send_turtle(TurtleCmd::StopMonitoringTunnels(hash));
+        }
+        if let Some(turtle_id) = contact.virtual_peer {
+            self.virtual_peers.remove(&turtle_id);
+        }
+        self.pending.retain(|_, p| p.tunnel_id != tunnel_id);
+        self.notify_status(&tunnel_id);
+    }
+
+    // --- turtle
+
+    async fn handle_turtle_event(&mut self, event: TurtleEvent) {
+        match event {
+            TurtleEvent::Up(hash, turtle_id, direction) => {
+                self.handle_tunnel_up(hash, turtle_id, direction).await
+            }
+            TurtleEvent::Down(_hash, turtle_id) => self.handle_tunnel_down(turtle_id),
+            TurtleEvent::Data(_hash, turtle_id, data) => {
+                self.handle_turtle_data(turtle_id, data).await
+            }
+        }
+    }
+
+    async fn handle_tunnel_up(
+        &mut self,
+        hash: TurtleFileHash,
+        turtle_id: u32,
+        direction: TunnelDirection,
+    ) {
+        let own_id = match direction {
+            TunnelDirection::Client => {
+                match self.contacts.values().find(|c| c.hash == Some(hash)) {
+                    Some(contact) if contact.status == GxsTunnelStatus::CanTalk => {
+                        trace!("ignoring tunnel {turtle_id:08x}, there is already a working one");
+                        return;
+                    }
+                    Some(contact) => contact.own_id,
+                    None => {
+                        warn!("got tunnel {turtle_id:08x} for an unknown hash {hash}");
+                        return;
+                    }
+                }
+            }
+            TunnelDirection::Server => destination_from_hash(&hash),
+        };
+
+        trace!("tunnel {turtle_id:08x} is up, starting DH session");
+
+        let dh = match Dh::from_pqg(
+            self.dh_prime.to_owned().expect("failed to copy DH prime"),
+            None,
+            BigNum::from_u32(DH_GENERATOR).expect("failed to create DH generator"),
+        )
+        .and_then(|dh| dh.generate_key())
+        {
+            Ok(dh) => dh,
+            Err(err) => {
+                warn!("failed to create DH session: {err}");
+                return;
+            }
+        };
+
+        self.send_dh_public_key(turtle_id, &own_id, &dh).await;
+
+        self.virtual_peers.insert(
+            turtle_id,
+            VirtualPeer {
+                direction,
+                own_id,
+                dh: Some(dh),
+                tunnel_id: None,
+            },
+        );
+    }
+
+    fn handle_tunnel_down(&mut self, turtle_id: u32) {
+        let tunnel_id = match self.virtual_peers.remove(&turtle_id) {
+            Some(VirtualPeer {
+                tunnel_id: Some(tunnel_id),
+                ..
+            }) => tunnel_id,
+            _ => return,
+        };
+
+        let server_side = match self.contacts.get_mut(&tunnel_id) {
+            Some(contact) if contact.virtual_peer == Some(turtle_id) => {
+                debug!("tunnel {tunnel_id} is down");
+
+                contact.status = GxsTunnelStatus::TunnelDown;
+                contact.virtual_peer = None;
+                contact.hash.is_none()
+            }
+            _ => return,
+        };
+        self.notify_status(&tunnel_id);
+
+        // only the requesting side can dig a new tunnel
+        if server_side {
+            self.contacts.remove(&tunnel_id);
+            self.pending.retain(|_, p| p.tunnel_id != tunnel_id);
+            self.notify_status(&tunnel_id);
+        }
+    }
+
+    async fn handle_turtle_data(&mut self, turtle_id: u32, mut data: Vec<u8>) {
+        if data.len() < GXS_TUNNEL_ENCRYPTION_IV_SIZE {
+            warn!(
+                "received too little data ({} bytes) through tunnel {turtle_id:08x}",
+                data.len()
+            );
+            return;
+        }
+
+        let mut iv = [0u8; GXS_TUNNEL_ENCRYPTION_IV_SIZE];
+        iv.copy_from_slice(&data[..GXS_TUNNEL_ENCRYPTION_IV_SIZE]);
+
+        // special case: an IV of 0 is used for the (unencrypted) DH public key
+        if iv == [0; GXS_TUNNEL_ENCRYPTION_IV_SIZE] {
+            match parse_item(data.split_off(GXS_TUNNEL_ENCRYPTION_IV_SIZE)) {
+                Some((GXS_TUNNEL_SUB_TYPE_DH_PUBLIC_KEY, payload)) => {
+                    if let Some(item) = deserialize_item(payload) {
+                        self.handle_dh_public_key(turtle_id, item).await;
+                    }
+                }
+                _ => warn!("received unexpected unencrypted item through tunnel {turtle_id:08x}"),
+            }
+            return;
+        }
+
+        let tunnel_id = match self
+            .virtual_peers
+            .get(&turtle_id)
+            .and_then(|vp| vp.tunnel_id)
+        {
+            Some(tunnel_id) => tunnel_id,
+            None => {
+                warn!("received encrypted data through tunnel {turtle_id:08x} without a key");
+                return;
+            }
+        };
+        let contact = match self.contacts.get_mut(&tunnel_id) {
+            Some(contact) => contact,
+            None => {
+                warn!("received encrypted data for unknown tunnel {tunnel_id}");
+                return;
+            }
+        };
+
+        if data.len() < GXS_TUNNEL_ENCRYPTION_IV_SIZE + GXS_TUNNEL_ENCRYPTION_HMAC_SIZE {
+            warn!(
+                "received too little data ({} bytes) through tunnel {turtle_id:08x}",
+                data.len()
+            );
+            return;
+        }
+        let encrypted =
+            data.split_off(GXS_TUNNEL_ENCRYPTION_IV_SIZE + GXS_TUNNEL_ENCRYPTION_HMAC_SIZE);
+        let hmac = &data[GXS_TUNNEL_ENCRYPTION_IV_SIZE..];
+
+        // first, check the HMAC
+        match hmac_sha1(&contact.aes_key, &encrypted) {
+            Ok(expected) if memcmp::eq(&expected, hmac) => {}
+            Ok(_) => {
+                warn!("HMAC check failed for tunnel {tunnel_id}, dropping data");
+                return;
+            }
+            Err(err) => {
+                warn!("failed to compute HMAC: {err}");
+                return;
+            }
+        }
+
+        let decrypted = match aes_crypt_8_16(&encrypted, &contact.aes_key, &iv, false) {
+            Ok(decrypted) => decrypted,
+            Err(err) => {
+                warn!("failed to decrypt data of tunnel {tunnel_id}: {err}");
+                return;
+            }
+        };
+        contact.last_contact = Instant::now();
+
+        match parse_item(decrypted) {
+            Some((sub_type, payload)) => self.handle_item(tunnel_id, sub_type, payload),
+            None => warn!("failed to parse decrypted data of tunnel {tunnel_id}"),
+        }
+    }
+
+    async fn send_dh_public_key(&self, turtle_id: u32, own_id: &GxsId, dh: &Dh<Private>) {
+        let public_key = dh.public_key().to_vec();
+
+        let gxs_id = self.core.get_service_data().gxs_id();
+        let (priv_key, pub_key) = match (
+            gxs_id.get_priv_keys_by_id(own_id).await,
+            gxs_id.get_pub_keys_by_id(own_id).await,
+        ) {
+            (Some(priv_key), Some(pub_key)) => (priv_key, pub_key),
+            _ => {
+                warn!("failed to find keys for {own_id}");
+                return;
+            }
+        };
+        let signature = match generate_signature(&priv_key, &public_key) {
+            Ok(signature) => signature,
+            Err(err) => {
+                warn!("failed to sign DH public key: {err}");
+                return;
+            }
+        };
+
+        let mut inner = TlvKeySignatureInner::new(own_id.to_owned().into());
+        inner.sign_data = signature.into();
+        let item = GxsTunnelDHPublicKeyItem {
+            public_key,
+            signature: TlvKeySignature::new(inner),
+            gxs_key: pub_key,
+        };
+        trace!("sending {item}");
+
+        // by convention, we use an IV of 0 for unencrypted data
+        let mut data = vec![0; GXS_TUNNEL_ENCRYPTION_IV_SIZE];
+        data.extend(serialize_item(GXS_TUNNEL_SUB_TYPE_DH_PUBLIC_KEY, &item));
+        self.send_turtle(TurtleCmd::SendData(turtle_id, data));
+    }
+
+    async fn handle_dh_public_key(&mut self, turtle_id: u32, item: GxsTunnelDHPublicKeyItem) {
+        trace!("received {item}");
+
+        let vp = match self.virtual_peers.get_mut(&turtle_id) {
+            Some(vp) => vp,
+            None => {
+                warn!("received DH public key for unknown tunnel {turtle_id:08x}");
+                return;
+            }
+        };
+        let dh = match vp.dh.take() {
+            Some(dh) => dh,
+            None => {
+                warn!("received DH public key for tunnel {turtle_id:08x} without DH session");
+                return;
+            }
+        };
+
+        // check signature
+        let remote_id: GxsId = item.signature.key_id.to_owned().into();
+        if GxsId::from(item.gxs_key.key_id.to_owned()) != remote_id
+            || item.gxs_key.key_flags.contains(TlvKeyFlags::TYPE_FULL)
+        {
+            warn!("DH public key of {remote_id} comes with an unexpected key");
+            return;
+        }
+        match verify_signature(&item.gxs_key, &item.public_key, &item.signature.sign_data) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("DH public key of {remote_id} has an invalid signature");
+                return;
+            }
+            Err(err) => {
+                warn!("failed to verify DH public key of {remote_id}: {err}");
+                return;
+            }
+        }
+        // when we know the id, the key must match
+        if let Some(key) = self
+            .core
+            .get_service_data()
+            .gxs_id()
+            .get_pub_keys_by_id(&remote_id)
+            .await
+        {
+            if key.key_data != item.gxs_key.key_data {
+                warn!("DH public key of {remote_id} is signed with a different key");
+                return;
+            }
+        }
+
+        // compute the shared key
+        let aes_key = match BigNum::from_slice(&item.public_key)
+            .and_then(|public_key| dh.compute_key(&public_key))
+        {
+            Ok(secret) if secret.len() == dh.prime_p().num_bytes() as usize => {
+                let mut aes_key = [0; GXS_TUNNEL_AES_KEY_SIZE];
+                aes_key.copy_from_slice(&sha1(&secret)[..GXS_TUNNEL_AES_KEY_SIZE]);
+                aes_key
+            }
+            // RS does the same
+            Ok(_) => {
+                warn!("DH key exchange with {remote_id} resulted in a short key");
+                return;
+            }
+            Err(err) => {
+                warn!("failed to compute DH key: {err}");
+                return;
+            }
+        };
+
+        // the VirtualPeer borrow ends here, copy what's needed
+        let (own_id, direction) = (vp.own_id, vp.direction);
+        let tunnel_id = make_tunnel_id(&own_id, &remote_id);
+        vp.tunnel_id = Some(tunnel_id);
+
+        let contact = match (self.contacts.get_mut(&tunnel_id), direction) {
+            (Some(contact), _) => contact,
+            (None, TunnelDirection::Server) => self.contacts.entry(tunnel_id).or_insert(Contact {
+                own_id,
+                remote_id,
+                hash: None,
+
+                status: GxsTunnelStatus::TunnelDown,
+                virtual_peer: None,
+                aes_key,
+
+                last_contact: Instant::now(),
+                last_keep_alive_sent: Instant::now(),
+
+                services: HashSet::new(),
+            }),
+            (None, TunnelDirection::Client) => {
+                warn!("tunnel {turtle_id:08x} ends at {remote_id}, which we did not ask for");
+                return;
+            }
+        };
+
+        info!("tunnel {tunnel_id} with {remote_id} can talk");
+
+        contact.status = GxsTunnelStatus::CanTalk;
+        contact.virtual_peer = Some(turtle_id);
+        contact.aes_key = aes_key;
+        contact.last_contact = Instant::now();
+        contact.last_keep_alive_sent = Instant::now();
+
+        // let the other end know that the tunnel works
+        self.send_status(&tunnel_id, GXS_TUNNEL_FLAG_ACK_DISTANT_CONNECTION);
+
+        self.notify_status(&tunnel_id);
+        let contact = &self.contacts[&tunnel_id];
+        for service_id in &contact.services {
+            self.notify(
+                *service_id,
+                GxsTunnelEvent::Connected {
+                    tunnel_id,
+                    own_id,
+                    remote_id,
+                },
+            );
+        }
+
+        self.send_pending();
+    }
+
+    // --- tunnel items
+
+    fn handle_item(&mut self, tunnel_id: GxsTunnelId, sub_type: u8, payload: Vec<u8>) {
+        match sub_type {
+            GXS_TUNNEL_SUB_TYPE_DATA => {
+                if let Some(item) = deserialize_item(payload) {
+                    self.handle_data(tunnel_id, item);
+                }
+            }
+            GXS_TUNNEL_SUB_TYPE_DATA_ACK => {
+                if let Some(item) = deserialize_item::<GxsTunnelDataAckItem>(payload) {
+                    trace!("received ack for {:016x}", item.unique_item_counter);
+                    self.pending.remove(&item.unique_item_counter);
+                }
+            }
+            GXS_TUNNEL_SUB_TYPE_STATUS => {
+                if let Some(item) = deserialize_item::<GxsTunnelStatusItem>(payload) {
+                    self.handle_status(tunnel_id, item);
+                }
+            }
+            sub_type => warn!("received unexpected item {sub_type} through tunnel {tunnel_id}"),
+        }
+    }
+
+    fn handle_data(&mut self, tunnel_id: GxsTunnelId, item: GxsTunnelDataItem) {
+        trace!("received {item}");
+
+        // always ack, the previous ack might got lost
+        let ack = GxsTunnelDataAckItem {
+            unique_item_counter: item.unique_item_counter,
+        };
+        self.send_encrypted(&tunnel_id, GXS_TUNNEL_SUB_TYPE_DATA_ACK, &ack);
+
+        if self
+            .received
+            .insert(item.unique_item_counter, Instant::now())
+            .is_some()
+        {
+            trace!("dropping duplicate {item}");
+            return;
+        }
+
+        let contact = match self.contacts.get_mut(&tunnel_id) {
+            Some(contact) => contact,
+            None => return,
+        };
+        if contact.services.insert(item.service_id) {
+            let event = GxsTunnelEvent::Connected {
+                tunnel_id,
+                own_id: contact.own_id,
+                remote_id: contact.remote_id,
+            };
+            self.notify(item.service_id, event);
+        }
+        self.notify(item.service_id, GxsTunnelEvent::Data(tunnel_id, item.data));
+    }
+
+    fn handle_status(&mut self, tunnel_id: GxsTunnelId, item: GxsTunnelStatusItem) {
+        match item.status {
+            GXS_TUNNEL_FLAG_CLOSING_DISTANT_CONNECTION => {
+                debug!("tunnel {tunnel_id} was closed remotely");
+
+                if let Some(contact) = self.contacts.get_mut(&tunnel_id) {
+                    contact.status = GxsTunnelStatus::RemotelyClosed;
+                }
+                self.notify_status(&tunnel_id);
+            }
+            GXS_TUNNEL_FLAG_ACK_DISTANT_CONNECTION => trace!("tunnel {tunnel_id} acknowledged"),
+            GXS_TUNNEL_FLAG_KEEP_ALIVE => trace!("tunnel {tunnel_id} keep alive"),
+            status => warn!("received unknown status {status:08x} through tunnel {tunnel_id}"),
+        }
+    }
+
+    fn send_status(&self, tunnel_id: &GxsTunnelId, status: u32) {
+        self.send_encrypted(
+            tunnel_id,
+            GXS_TUNNEL_SUB_TYPE_STATUS,
+            &GxsTunnelStatusItem { status },
+        );
+    }
+
+    /// Sends an item through a tunnel that can talk, returns `false` otherwise.
+    fn send_encrypted<T>(&self, tunnel_id: &GxsTunnelId, sub_type: u8, item: &T) -> bool
+    where
+        T: Serialize,
+    {
+        let (turtle_id, aes_key) = match self.contacts.get(tunnel_id) {
+            Some(Contact {
+                status: GxsTunnelStatus::CanTalk,
+                virtual_peer: Some(turtle_id),
+                aes_key,
+                ..
+            }) => (*turtle_id, aes_key),
+            _ => return false,
+        };
+
+        // make a random 8 bytes IV, that is not 0
+        let mut iv = 0;
+        while iv == 0 {
+            iv = WyRand::new().generate::<u64>();
+        }
+        let iv = iv.to_le_bytes();
+
+        let encrypted = match aes_crypt_8_16(&serialize_item(sub_type, item), aes_key, &iv, true) {
+            Ok(encrypted) => encrypted,
+            Err(err) => {
+                warn!("failed to encrypt data for tunnel {tunnel_id}: {err}");
+                return false;
+            }
+        };
+        let hmac = match hmac_sha1(aes_key, &encrypted) {
+            Ok(hmac) => hmac,
+            Err(err) => {
+                warn!("failed to compute HMAC: {err}");
+                return false;
+            }
+        };
+
+        let mut data = iv.to_vec();
+        data.extend(hmac);
+        data.extend(encrypted);
+        self.send_turtle(TurtleCmd::SendData(turtle_id, data));
+        true
+    }
+
+    /// Sends all pending data that is due.
+    fn send_pending(&mut self) {
+        let due: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| match p.last_sent {
+                Some(last_sent) => last_sent.elapsed() >= GXS_TUNNEL_DELAY_BETWEEN_RESEND,
+                None => true,
+            })
+            .map(|(counter, _)| *counter)
+            .collect();
+
+        for counter in due {
+            let p = &self.pending[&counter];
+            if self.send_encrypted(&p.tunnel_id, GXS_TUNNEL_SUB_TYPE_DATA, &p.item) {
+                self.pending.get_mut(&counter).unwrap().last_sent = Some(Instant::now());
+            }
+        }
+    }
+
+    fn maintain_tunnels(&mut self) {
+        // tunnels that timed out or need a keep alive
+        let mut due = vec![];
+        for (tunnel_id, contact) in &mut self.contacts {
+            if contact.status != GxsTunnelStatus::CanTalk {
+                continue;
+            }
+
+            if contact.last_contact.elapsed() > GXS_TUNNEL_CONTACT_TIMEOUT {
+                debug!("tunnel {tunnel_id} timed out");
+
+                contact.status = GxsTunnelStatus::TunnelDown;
+                due.push(tunnel_id.to_owned());
+            } else if contact.last_keep_alive_sent.elapsed() > GXS_TUNNEL_KEEP_ALIVE_TIMEOUT {
+                contact.last_keep_alive_sent = Instant::now();
+                due.push(tunnel_id.to_owned());
+            }
+        }
+        for tunnel_id in due {
+            match self.contacts[&tunnel_id].status {
+                GxsTunnelStatus::CanTalk => {
+                    self.send_status(&tunnel_id, GXS_TUNNEL_FLAG_KEEP_ALIVE)
+                }
+                _ => self.notify_status(&tunnel_id),
+            }
+        }
+
+        self.send_pending();
+
+        self.received
+            .retain(|_, time| time.elapsed() < GXS_TUNNEL_RECEIVED_DATA_LIFE_TIME);
+    }
+}
+
+#[async_trait]
+impl Service for GxsTunnel {
+    // everything goes through turtle
+    type Item = NoItem;
+    type Command = GxsTunnelInput;
+
+    const SERVICE: ServiceType = ServiceType::GxsTunnel;
+    const NAME: &'static str = "GxsTunnels";
+    const TIMERS: &'static [(&'static str, Duration)] =
+        &[GXS_TUNNEL_TIMER_MAINTENANCE, GXS_TUNNEL_TIMER_OWN_IDS];
+
+    async fn handle_item(&mut self, _ctx: &ServiceContext<NoItem>, item: NoItem, _from: Arc<SslId>) {
+        match item {}
+    }
+
+    async fn on_timer(&mut self, _ctx: &ServiceContext<NoItem>, timer: &'static str) {
+        match timer {
+            t if t == GXS_TUNNEL_TIMER_OWN_IDS.0 => self.update_own_ids().await,
+            _ => self.maintain_tunnels(),
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<GxsTunnelInput> {
+        select! {
+            Some(event) = self.turtle_rx.recv() => Some(GxsTunnelInput::Turtle(event)),
+            Some(cmd) = self.cmd_rx.recv() => Some(GxsTunnelInput::Cmd(cmd)),
+            else => None,
+        }
+    }
+
+    async fn handle_command(&mut self, _ctx: &ServiceContext<NoItem>, input: GxsTunnelInput) {
+        match input {
+            GxsTunnelInput::Turtle(event) => self.handle_turtle_event(event).await,
+            GxsTunnelInput::Cmd(cmd) => self.handle_cmd(cmd).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use retroshare_compat::basics::GxsId;
+
+    use super::{
+        aes_crypt_8_16, destination_from_hash, make_tunnel_id, parse_item, serialize_item,
+    };
+    use retroshare_compat::services::gxs_tunnel::{
+        GxsTunnelStatusItem, GXS_TUNNEL_FLAG_KEEP_ALIVE, GXS_TUNNEL_SUB_TYPE_STATUS,
+    };
+
+    #[test]
+    fn tunnel_id() {
+        let a: GxsId = "c59df722f56f2f886ac301acc5572e03".into();
+        let b: GxsId = "0d2a6c2e3b1e4d3c8a6e5d6f7a8b9c0d".into();
+
+        assert_eq!(make_tunnel_id(&a, &b), make_tunnel_id(&b, &a));
+        assert_ne!(make_tunnel_id(&a, &b), make_tunnel_id(&a, &a));
+
+        let mut rng = nanorand::WyRand::new();
+        let hash = super::random_hash_from_destination(&b, &mut rng);
+        assert_eq!(destination_from_hash(&hash), b);
+    }
+
+    #[test]
+    fn encryption() {
+        let key = [0x42; 16];
+        let iv = 0x0102030405060708u64.to_le_bytes();
+
+        let data = serialize_item(
+            GXS_TUNNEL_SUB_TYPE_STATUS,
+            &GxsTunnelStatusItem {
+                status: GXS_TUNNEL_FLAG_KEEP_ALIVE,
+            },
+        );
+        let encrypted = aes_crypt_8_16(&data, &key, &iv, true).unwrap();
+        assert_ne!(encrypted, data);
+        // AES block size
+        assert_eq!(encrypted.len() % 16, 0);
+
+        let decrypted = aes_crypt_8_16(&encrypted, &key, &iv, false).unwrap();
+        assert_eq!(decrypted, data);
+
+        let (sub_type, payload) = parse_item(decrypted).unwrap();
+        assert_eq!(sub_type, GXS_TUNNEL_SUB_TYPE_STATUS);
+        assert_eq!(payload, GXS_TUNNEL_FLAG_KEEP_ALIVE.to_be_bytes());
+    }
+}
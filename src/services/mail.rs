@@ -0,0 +1,462 @@
+use std::{sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use log::{debug, info, trace, warn};
+use retroshare_compat::{
+    basics::{GxsId, PeerId},
+    events::{EventType, MailStatusEventCode},
+    serde::{from_retroshare_wire_result, to_retroshare_wire},
+    services::{
+        grouter::GROUTER_CLIENT_ID_MESSAGES,
+        msg::{MsgItem, MsgItemFlags},
+        ServiceType,
+    },
+    tlv::tlv_set::{TlvGxsIdSet, TlvPeerIdSet},
+};
+use tokio::{select, sync::oneshot};
+
+use crate::{
+    channel::{channel, Policy, Receiver},
+    low_level_parsing::{
+        headers::{Header, ServiceHeader, HEADER_SIZE},
+        Packet,
+    },
+    model::{
+        intercom::{Intercom, PeerState, PeerUpdate},
+        services::{
+            grouter::{GRouterCmd, GRouterEvent},
+            mail::MailCmd,
+            mailbox::Mailbox,
+        },
+        DataCore,
+    },
+    service_items,
+    services::{Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE, SERVICE_EVENTS_QUEUE_SIZE},
+};
+
+// const uint8_t RS_PKT_SUBTYPE_DEFAULT = 0x01; /* if only one subtype */
+// const uint8_t RS_PKT_SUBTYPE_MSG_TAG_TYPE = 0x03;
+// const uint8_t RS_PKT_SUBTYPE_MSG_TAGS = 0x04;
+// const uint8_t RS_PKT_SUBTYPE_MSG_SRC_TAG = 0x05;
+// const uint8_t RS_PKT_SUBTYPE_MSG_PARENT_TAG = 0x06;
+// const uint8_t RS_PKT_SUBTYPE_MSG_INVITE = 0x07;
+// const uint8_t RS_PKT_SUBTYPE_MSG_GROUTER_MAP = 0x08;
+// const uint8_t RS_PKT_SUBTYPE_MSG_DISTANT_MSG_MAP = 0x09;
+// const uint8_t RS_PKT_SUBTYPE_MSG_OUTGOING_MAP_DEPRECATED = 0x0a;
+// const uint8_t RS_PKT_SUBTYPE_MSG_OUTGOING_MAP = 0x0b;
+const MSG_SUB_TYPE_DEFAULT: u8 = 0x01;
+
+service_items! {
+    pub enum MailItem: Msg {
+        Msg(MsgItem) = MSG_SUB_TYPE_DEFAULT, priority: 2;
+    }
+}
+
+pub enum MailInput {
+    // commands carry a whole message, keep them on the heap
+    Cmd(Box<MailCmd>),
+    GRouter(GRouterEvent),
+}
+
+const MAIL_DB_FILE: &str = "rustyshare_mail.db";
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+/// Distant mail is routed as serialized `MsgItem` including its header.
+fn serialize_distant(item: &MsgItem) -> Vec<u8> {
+    let payload = to_retroshare_wire(item);
+    let header = ServiceHeader::new(ServiceType::Msg, MSG_SUB_TYPE_DEFAULT, &payload);
+    Packet::new_without_location(header.into(), payload).to_bytes()
+}
+
+fn parse_distant(mut data: Vec<u8>) -> Option<MsgItem> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let mut payload = data.split_off(HEADER_SIZE);
+    let header: [u8; HEADER_SIZE] = data.try_into().ok()?;
+
+    match Header::try_parse(&header) {
+        Ok(Header::Service {
+            service: ServiceType::Msg,
+            sub_type: MSG_SUB_TYPE_DEFAULT,
+            size,
+        }) if (HEADER_SIZE..=HEADER_SIZE + payload.len()).contains(&(size as usize)) => {
+            payload.truncate(size as usize - HEADER_SIZE);
+            from_retroshare_wire_result(&mut payload).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Mail between friends, messages are stored in a local (encrypted) mailbox.
+///
+/// Outgoing messages stay in the outbox until they were delivered to every recipient.
+/// Distant mail (to gxs ids) is routed through the global router and counts as delivered once the receipt arrives.
+pub struct Mail {
+    core: Arc<DataCore>,
+
+    cmd_rx: Receiver<MailCmd>,
+    grouter_rx: Receiver<GRouterEvent>,
+}
+
+impl Mail {
+    pub async fn new(core: &Arc<DataCore>) -> Mail {
+        let data = core.get_service_data().mail();
+
+        let (tx_mail, rx_mail) = channel("mail commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        *data.cmd.write().await = Some(tx_mail);
+
+        let (tx_grouter, rx_grouter) = channel(
+            "mail grouter events",
+            SERVICE_EVENTS_QUEUE_SIZE,
+            Policy::DropOldest,
+        );
+        core.get_service_data()
+            .grouter()
+            .register_client(GROUTER_CLIENT_ID_MESSAGES, tx_grouter);
+
+        let passwd = core.get_own_keypair().database_password();
+        match Mailbox::new_file(core.get_config_dir().join(MAIL_DB_FILE), &passwd) {
+            Ok(mailbox) => *data.mailbox.lock().await = Some(mailbox),
+            Err(err) => warn!("failed to open mailbox: {err}"),
+        }
+
+        Mail {
+            core: core.clone(),
+
+            cmd_rx: rx_mail,
+            grouter_rx: rx_grouter,
+        }
+    }
+
+    /// Stores a received message in the inbox, distant messages are stored with the gxs id of their sender.
+    async fn store_incoming(&self, src: &PeerId, mut item: MsgItem, flags: MsgItemFlags) {
+        trace!("{item:?}");
+
+        // only keep flags that describe the message's content
+        item.msg_flags = MsgItemFlags::NEW
+            | flags
+            | (item.msg_flags
+                & (MsgItemFlags::USER_REQUEST
+                    | MsgItemFlags::FRIEND_RECOMMENDATION
+                    | MsgItemFlags::PUBLISH_KEY));
+        item.recv_time = now();
+
+        let msg_id = match &*self.core.get_service_data().mail().mailbox.lock().await {
+            Some(mailbox) => match mailbox.add_message(src, &item) {
+                Ok(msg_id) => msg_id,
+                Err(err) => {
+                    warn!("[Mail] failed to store message: {err}");
+                    return;
+                }
+            },
+            None => return,
+        };
+
+        self.core
+            .webui_send(EventType::MailStatus {
+                code: MailStatusEventCode::NewMessage,
+                msg_ids: vec![msg_id.to_string()],
+            })
+            .await;
+    }
+
+    async fn handle_grouter_event(&self, event: GRouterEvent) {
+        match event {
+            GRouterEvent::Received {
+                destination,
+                signing_id,
+                data,
+            } => {
+                let item = match parse_distant(data) {
+                    Some(item) => item,
+                    None => {
+                        warn!("[Mail] failed to parse distant message from {signing_id}");
+                        return;
+                    }
+                };
+                info!("[Mail] received distant mail from {signing_id} for {destination}");
+                self.store_incoming(&signing_id.to_vec().into(), item, MsgItemFlags::DISTANT)
+                    .await;
+            }
+            GRouterEvent::Delivered(routing_id) => {
+                let msg_id = match &*self.core.get_service_data().mail().mailbox.lock().await {
+                    Some(mailbox) => match mailbox.remove_distant_outgoing(routing_id) {
+                        Ok(msg_id) => msg_id,
+                        Err(err) => {
+                            warn!("[Mail] failed to update pending message: {err}");
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                if let Some(msg_id) = msg_id {
+                    debug!("[Mail] distant mail {msg_id} was delivered");
+                    self.check_sent(msg_id).await;
+                }
+            }
+            // the message stays in the outbox
+            GRouterEvent::Failed(routing_id) => {
+                warn!("[Mail] failed to deliver distant mail {routing_id:016x}")
+            }
+        }
+    }
+
+    /// Stores a message in the outbox and delivers it to all recipients that are online.
+    async fn send_mail(
+        &self,
+        ctx: &ServiceContext<MailItem>,
+        mut item: MsgItem,
+        from: Option<GxsId>,
+    ) -> Option<i64> {
+        let own_id = *self.core.get_own_location().get_location_id();
+
+        item.msg_flags = MsgItemFlags::OUTGOING | MsgItemFlags::PENDING;
+        item.send_time = now();
+        item.recv_time = item.send_time;
+
+        let recipients: Vec<PeerId> = item
+            .rspeerid_msgto
+            .0
+            .iter()
+            .chain(item.rspeerid_msgcc.0.iter())
+            .chain(item.rspeerid_msgbcc.0.iter())
+            .copied()
+            .collect();
+        let distant_recipients: Vec<GxsId> = item
+            .rsgxsid_msgto
+            .0
+            .iter()
+            .chain(item.rsgxsid_msgcc.0.iter())
+            .chain(item.rsgxsid_msgbcc.0.iter())
+            .copied()
+            .collect();
+
+        let msg_id = {
+            let lock = self.core.get_service_data().mail().mailbox.lock().await;
+            let mailbox = lock.as_ref()?;
+            let msg_id = match mailbox.add_message(&own_id, &item) {
+                Ok(msg_id) => msg_id,
+                Err(err) => {
+                    warn!("[Mail] failed to store message: {err}");
+                    return None;
+                }
+            };
+            for peer_id in &recipients {
+                if let Err(err) = mailbox.add_outgoing(msg_id, peer_id) {
+                    warn!("[Mail] failed to queue message for {peer_id}: {err}");
+                }
+            }
+            msg_id
+        };
+
+        if !distant_recipients.is_empty() {
+            self.send_distant(msg_id, &item, from, distant_recipients)
+                .await;
+        }
+
+        for peer_id in recipients {
+            let peer_id = Arc::new(peer_id);
+            if self.core.is_online(peer_id.to_owned()).await {
+                self.deliver(ctx, msg_id, &item, peer_id).await;
+            }
+        }
+        self.check_sent(msg_id).await;
+
+        Some(msg_id)
+    }
+
+    /// Hands a message to the global router, once for every distant recipient.
+    async fn send_distant(
+        &self,
+        msg_id: i64,
+        item: &MsgItem,
+        from: Option<GxsId>,
+        recipients: Vec<GxsId>,
+    ) {
+        let from = match from {
+            Some(from) => from,
+            None => match self
+                .core
+                .get_service_data()
+                .gxs_id()
+                .get_own_ids()
+                .await
+                .into_iter()
+                .next()
+            {
+                Some(from) => from,
+                None => {
+                    warn!("[Mail] unable to send distant mail without an own identity");
+                    return;
+                }
+            },
+        };
+
+        for destination in recipients {
+            let mut item = item.to_owned();
+            item.msg_flags = MsgItemFlags::empty();
+            // other bcc recipients must not be revealed
+            item.rspeerid_msgbcc = TlvPeerIdSet::default();
+            item.rsgxsid_msgbcc = if item.rsgxsid_msgbcc.0.contains(&destination) {
+                TlvGxsIdSet::from_iter([destination])
+            } else {
+                TlvGxsIdSet::default()
+            };
+
+            let (tx, rx) = oneshot::channel();
+            if !self
+                .core
+                .get_service_data()
+                .grouter()
+                .send_cmd(GRouterCmd::Send {
+                    destination,
+                    signing_id: from,
+                    service_id: GROUTER_CLIENT_ID_MESSAGES,
+                    data: serialize_distant(&item),
+                    tx,
+                })
+            {
+                warn!("[Mail] failed to send distant mail, GRouter is not running");
+                return;
+            }
+
+            let routing_id = match rx.await {
+                Ok(Some(routing_id)) => routing_id,
+                _ => {
+                    warn!("[Mail] failed to send mail {msg_id} to {destination}");
+                    continue;
+                }
+            };
+            debug!("[Mail] routing mail {msg_id} to {destination} as {routing_id:016x}");
+
+            if let Some(mailbox) = &*self.core.get_service_data().mail().mailbox.lock().await {
+                if let Err(err) = mailbox.add_distant_outgoing(msg_id, routing_id) {
+                    warn!("[Mail] failed to queue message for {destination}: {err}");
+                }
+            }
+        }
+    }
+
+    /// Sends a message to one recipient and removes it from the recipient's pending messages.
+    async fn deliver(
+        &self,
+        ctx: &ServiceContext<MailItem>,
+        msg_id: i64,
+        item: &MsgItem,
+        peer_id: Arc<PeerId>,
+    ) {
+        let mut item = item.to_owned();
+        item.msg_flags = MsgItemFlags::empty();
+        // other bcc recipients must not be revealed
+        item.rspeerid_msgbcc = if item.rspeerid_msgbcc.0.contains(&peer_id) {
+            TlvPeerIdSet::from_iter([*peer_id])
+        } else {
+            TlvPeerIdSet::default()
+        };
+        item.rsgxsid_msgbcc = TlvGxsIdSet::default();
+
+        debug!("[Mail] sending mail {msg_id} to {peer_id}");
+        ctx.send(item, peer_id.to_owned()).await;
+
+        if let Some(mailbox) = &*self.core.get_service_data().mail().mailbox.lock().await {
+            if let Err(err) = mailbox.remove_outgoing(msg_id, &peer_id) {
+                warn!("[Mail] failed to update pending message: {err}");
+            }
+        }
+    }
+
+    /// Moves a message to the sent box once it was delivered to every recipient.
+    async fn check_sent(&self, msg_id: i64) {
+        let sent = match &*self.core.get_service_data().mail().mailbox.lock().await {
+            Some(mailbox) => match mailbox.count_outgoing(msg_id) {
+                Ok(0) => mailbox
+                    .set_flags(msg_id, MsgItemFlags::PENDING, false)
+                    .unwrap_or_default(),
+                Ok(_) => false,
+                Err(err) => {
+                    warn!("[Mail] failed to check pending message: {err}");
+                    false
+                }
+            },
+            None => false,
+        };
+
+        if sent {
+            self.core
+                .webui_send(EventType::MailStatus {
+                    code: MailStatusEventCode::MessageSent,
+                    msg_ids: vec![msg_id.to_string()],
+                })
+                .await;
+        }
+    }
+
+    async fn send_pending(&self, ctx: &ServiceContext<MailItem>, peer_id: &Arc<PeerId>) {
+        let pending = match &*self.core.get_service_data().mail().mailbox.lock().await {
+            Some(mailbox) => mailbox.get_outgoing(peer_id).unwrap_or_default(),
+            None => return,
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        info!(
+            "[Mail] sending {} queued mail(s) to {peer_id}",
+            pending.len()
+        );
+        for (msg_id, item) in pending {
+            self.deliver(ctx, msg_id, &item, peer_id.to_owned()).await;
+            self.check_sent(msg_id).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Service for Mail {
+    type Item = MailItem;
+    type Command = MailInput;
+
+    const SERVICE: ServiceType = ServiceType::Msg;
+    const NAME: &'static str = "msg";
+    const EVENTS: bool = true;
+
+    async fn handle_item(&mut self, _ctx: &ServiceContext<MailItem>, item: MailItem, from: Arc<PeerId>) {
+        let MailItem::Msg(item) = item;
+
+        info!("[Mail] received mail from {from}");
+        self.store_incoming(&from, item, MsgItemFlags::empty())
+            .await;
+    }
+
+    async fn handle_event(&mut self, ctx: &ServiceContext<MailItem>, event: Intercom) {
+        if let Intercom::PeerUpdate(PeerUpdate::Status(PeerState::Connected(loc, _))) = event {
+            self.send_pending(ctx, &loc).await;
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<MailInput> {
+        select! {
+            Some(cmd) = self.cmd_rx.recv() => Some(MailInput::Cmd(Box::new(cmd))),
+            Some(event) = self.grouter_rx.recv() => Some(MailInput::GRouter(event)),
+            else => None,
+        }
+    }
+
+    async fn handle_command(&mut self, ctx: &ServiceContext<MailItem>, input: MailInput) {
+        match input {
+            MailInput::Cmd(cmd) => {
+                let MailCmd::Send { item, from, tx } = *cmd;
+                let msg_id = self.send_mail(ctx, item, from).await;
+                let _ = tx.send(msg_id);
+            }
+            MailInput::GRouter(event) => self.handle_grouter_event(event).await,
+        }
+    }
+}
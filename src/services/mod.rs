@@ -1,10 +1,12 @@
-use std::{collections::hash_map::HashMap, sync::Arc};
-
+pub mod banlist;
 pub mod bwctrl;
 pub mod chat;
 pub mod discovery;
+pub mod grouter;
 pub mod gxs_id;
+pub mod gxs_tunnel;
 pub mod heartbeat;
+pub mod mail;
 pub mod rtt;
 pub mod service_info;
 pub mod status;
@@ -12,19 +14,24 @@ pub mod turtle;
 
 use ::retroshare_compat::services::ServiceType;
 use async_trait::async_trait;
-use log::{trace, warn};
+use futures::future::select_all;
+use log::{debug, trace, warn};
 use retroshare_compat::{
-    basics::{PeerId, RsPacket},
-    gxs::sqlite::database::GxsDatabase,
-    services::service_info::RsServiceInfo,
+    basics::SslId, gxs::sqlite::database::GxsDatabase, services::service_info::RsServiceInfo,
+};
+use std::{
+    collections::hash_map::HashMap, fmt::Debug, future::pending, marker::PhantomData, sync::Arc,
+    time::Duration,
 };
-use serde::Serialize;
 use tokio::{
-    sync::mpsc::{unbounded_channel, UnboundedSender},
+    select,
     task::JoinHandle,
+    time::{interval, timeout, Interval},
 };
 
 use crate::{
+    channel::{channel, Policy, Receiver, Sender, TrySendError},
+    controller::qos,
     gxs::gxs_backend::GxsShared,
     low_level_parsing::{
         headers::{Header, ServiceHeader},
@@ -33,209 +40,495 @@ use crate::{
     model::{intercom::Intercom, DataCore},
 };
 
-#[macro_export]
-macro_rules! send_to_peer {
-    ($self:expr, $packet:expr) => {
-        $self
-            .peer_tx
-            .send(Intercom::Send($packet))
-            .expect("failed to send to peer");
-    };
-}
+/// Queue size of each service, what happens when it is full depends on the service's `QUEUE_POLICY`.
+const SERVICE_QUEUE_SIZE: usize = 1024;
+/// How long the core waits for a full blocking service queue before dropping the packet.
+const SERVICE_QUEUE_WAIT: Duration = Duration::from_secs(1);
+/// Queue size of a service's command channel, commands are rejected (not dropped) when it is full.
+pub const SERVICE_CMD_QUEUE_SIZE: usize = 256;
+/// Queue size of the events a service hands to its clients (e.g. turtle data), the oldest events are dropped when a
+/// client can't keep up.
+pub const SERVICE_EVENTS_QUEUE_SIZE: usize = 1024;
+
 #[macro_export]
 macro_rules! send_to_core {
     ($self:expr, $packet:expr) => {
         $self
             .core_tx
             .send(Intercom::Send($packet))
-            .expect("failed to send to peer");
+            .await
+            .expect("failed to send to core");
     };
 }
 
-macro_rules! create_service {
-    // peer services
-    (PEER: $services:expr, $core_tx:expr, $peer_tx:expr, $ty:ident, $module:ident :: $class:ident) => {
-        let _ = ::retroshare_compat::services::ServiceType::$ty; // this makes $ty having the correct color
-        let (tx, rx) = unbounded_channel();
-        let s = Box::new($module::$class::new($core_tx.to_owned(), $peer_tx.to_owned(), rx));
-        let ty = s.get_id();
-        let info = s.get_service_info();
-        let handle = s.run();
-        $services.add_service(ty, tx, info, handle);
+/// Declares the items of a service, mapping each one to its sub type and QoS priority.
+///
+/// ```ignore
+/// service_items! {
+///     pub enum RttItem: Rtt {
+///         Ping(RttPingItem) = RTT_SUB_TYPE_PING, priority: QOS_PRIORITY_TOP;
+///         Pong(RttPongItem) = RTT_SUB_TYPE_PONG, priority: QOS_PRIORITY_TOP;
+///     }
+/// }
+/// ```
+///
+/// Items that don't deserialize with serde can name a decode function (`decode: read_item`).
+#[macro_export]
+macro_rules! service_items {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident : $service:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident($ty:ty) = $sub_type:expr, priority: $priority:expr $(, decode: $decode:path)?;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug)]
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant($ty),
+            )*
+        }
+
+        impl $crate::services::ServiceItem for $name {
+            fn decode(
+                mut packet: $crate::low_level_parsing::Packet,
+            ) -> Result<Self, $crate::services::ItemError> {
+                let sub_type = $crate::low_level_parsing::headers::ServiceHeader::from(
+                    packet.header.to_owned(),
+                )
+                .sub_type;
+                $(
+                    if sub_type == $sub_type {
+                        return $crate::service_items!(@decode packet.payload, $variant $(, $decode)?);
+                    }
+                )*
+                Err($crate::services::ItemError::UnknownSubType(sub_type))
+            }
+
+            fn sub_type(&self) -> u8 {
+                match self {
+                    $(Self::$variant(_) => $sub_type,)*
+                }
+            }
+
+            fn priority(&self) -> u8 {
+                match self {
+                    $(Self::$variant(_) => $priority,)*
+                }
+            }
+
+            fn to_payload(&self) -> Vec<u8> {
+                match self {
+                    $(Self::$variant(item) => ::retroshare_compat::serde::to_retroshare_wire(item),)*
+                }
+            }
+        }
+
+        impl ::retroshare_compat::basics::RsPacket for $name {
+            fn get_service(&self) -> u16 {
+                ::retroshare_compat::services::ServiceType::$service.into()
+            }
+
+            fn get_sub_type(&self) -> u8 {
+                $crate::services::ServiceItem::sub_type(self)
+            }
+        }
+
+        $(
+            impl From<$ty> for $name {
+                fn from(item: $ty) -> Self {
+                    Self::$variant(item)
+                }
+            }
+        )*
     };
 
-    (CORE: $services:expr, $data_core:expr , $core_tx:expr, $ty:ident, $module:ident :: $class:ident) => {
-        let _ = ::retroshare_compat::services::ServiceType::$ty; // this makes $ty having the correct color
-        let (tx, rx) = unbounded_channel();
-        let s = Box::new($module::$class::new($data_core, $core_tx.clone(), rx).await);
-        let ty = s.get_id();
-        let info = s.get_service_info();
-        let handle = s.run();
-        $services.add_service(ty, tx, info, handle);
+    (@decode $payload:expr, $variant:ident) => {
+        ::retroshare_compat::serde::from_retroshare_wire_result(&mut $payload)
+            .map(Self::$variant)
+            .map_err($crate::services::ItemError::Deserialize)
+    };
+
+    // for items that can't be deserialized with serde
+    (@decode $payload:expr, $variant:ident, $decode:path) => {
+        Ok(Self::$variant($decode(&mut $payload)))
+    };
+}
+
+#[derive(Debug)]
+pub enum ItemError {
+    UnknownSubType(u8),
+    Deserialize(retroshare_compat::serde::Error),
+}
+
+impl std::fmt::Display for ItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemError::UnknownSubType(sub_type) => write!(f, "unknown sub type {sub_type:02x}"),
+            ItemError::Deserialize(err) => write!(f, "failed to deserialize: {err:?}"),
+        }
     }
+}
 
+/// An item a service sends and receives, usually declared with `service_items!`.
+pub trait ServiceItem: Debug + Send + Sized + 'static {
+    fn decode(packet: Packet) -> Result<Self, ItemError>;
+    fn sub_type(&self) -> u8;
+    fn priority(&self) -> u8;
+    fn to_payload(&self) -> Vec<u8>;
 }
 
-// Test for a more streamlined sending system
-// See services/status.rs
-#[allow(dead_code)]
-fn build_packet<T>(item: &T, peer: Arc<PeerId>) -> Packet
-where
-    T: Serialize + RsPacket,
-{
-    let service = item.get_service();
-    let sub_type = item.get_sub_type();
-    let payload = retroshare_compat::serde::to_retroshare_wire(item);
-    let header = ServiceHeader::new(service.into(), sub_type, &payload);
-    let p = Packet::new(header.into(), payload, peer);
-    p
+/// Raw packets, for services that forward or verify the received bytes as they are.
+impl ServiceItem for Packet {
+    fn decode(packet: Packet) -> Result<Self, ItemError> {
+        Ok(packet)
+    }
+
+    fn sub_type(&self) -> u8 {
+        ServiceHeader::from(self.header.to_owned()).sub_type
+    }
+
+    fn priority(&self) -> u8 {
+        self.priority.unwrap_or_else(|| qos::priority(&self.header))
+    }
+
+    fn to_payload(&self) -> Vec<u8> {
+        self.payload.to_owned()
+    }
 }
 
-#[allow(dead_code)]
-fn build_packet_without_location<T>(item: &T) -> Packet
-where
-    T: Serialize + RsPacket,
-{
-    let service = item.get_service();
-    let sub_type = item.get_sub_type();
-    let payload = retroshare_compat::serde::to_retroshare_wire(item);
-    let header = ServiceHeader::new(service.into(), sub_type, &payload);
-    let p = Packet::new_without_location(header.into(), payload);
-    p
+/// Services that don't receive anything directly, e.g. because everything goes through turtle.
+#[derive(Debug)]
+pub enum NoItem {}
+
+impl ServiceItem for NoItem {
+    fn decode(packet: Packet) -> Result<Self, ItemError> {
+        Err(ItemError::UnknownSubType(
+            ServiceHeader::from(packet.header.to_owned()).sub_type,
+        ))
+    }
+
+    fn sub_type(&self) -> u8 {
+        match *self {}
+    }
+
+    fn priority(&self) -> u8 {
+        match *self {}
+    }
+
+    fn to_payload(&self) -> Vec<u8> {
+        match *self {}
+    }
+}
+
+/// Handed to every call of a `Service`, used to send items.
+pub struct ServiceContext<I: ServiceItem> {
+    service: ServiceType,
+    core_tx: Sender<Intercom>,
+    /// only set for peer services
+    peer_tx: Option<Sender<Intercom>>,
+    _item: PhantomData<fn(I)>,
+}
+
+impl<I: ServiceItem> ServiceContext<I> {
+    fn build_packet(&self, item: I, to: Option<Arc<SslId>>) -> Packet {
+        let payload = item.to_payload();
+        let header = ServiceHeader::new(self.service, item.sub_type(), &payload).into();
+        match to {
+            Some(to) => Packet::new(header, payload, to),
+            None => Packet::new_without_location(header, payload),
+        }
+        .with_priority(item.priority())
+    }
+
+    /// Sends an item to `to` (through the core).
+    pub async fn send(&self, item: impl Into<I>, to: Arc<SslId>) {
+        let packet = self.build_packet(item.into(), Some(to));
+        self.send_packet(packet).await;
+    }
+
+    /// Sends an item to the peer of a peer service.
+    pub fn send_to_peer(&self, item: impl Into<I>) {
+        let packet = self.build_packet(item.into(), None);
+        match &self.peer_tx {
+            // the peer worker is gone or disconnected
+            Some(peer_tx) => {
+                if peer_tx.try_send(Intercom::Send(packet)).is_err() {
+                    warn!("failed to send to peer");
+                }
+            }
+            None => warn!("{:?} is not a peer service", self.service),
+        }
+    }
+
+    /// Sends an already built packet (through the core).
+    pub async fn send_packet(&self, packet: Packet) {
+        self.core_tx
+            .send(Intercom::Send(packet))
+            .await
+            .expect("failed to send to core");
+    }
+
+    pub fn core_tx(&self) -> &Sender<Intercom> {
+        &self.core_tx
+    }
+
+    pub fn peer_tx(&self) -> Option<&Sender<Intercom>> {
+        self.peer_tx.as_ref()
+    }
 }
 
+/// A RetroShare service, driven by `Services` which decodes its items and runs its timers.
 #[async_trait]
-pub trait Service {
-    fn get_id(&self) -> ServiceType;
-    fn get_service_info(&self) -> RsServiceInfo;
-    // async fn handle_packet(&self, packet: Packet) -> HandlePacketResult;
-    // async fn tick(
-    //     &mut self,
-    //     stats: &mut StatsCollection,
-    //     timers: &mut Timers,
-    // ) -> Option<Vec<Packet>>;
-    fn run(self) -> JoinHandle<()>;
+pub trait Service: Send + Sized + 'static {
+    /// Received and sent items.
+    type Item: ServiceItem;
+    /// Additional input (e.g. commands from the web UI), see `recv_command`.
+    type Command: Send;
+
+    const SERVICE: ServiceType;
+    const NAME: &'static str;
+    /// Named timers, `on_timer` is called on every tick.
+    const TIMERS: &'static [(&'static str, Duration)] = &[];
+    /// Whether to subscribe to the core's events, see `handle_event`.
+    const EVENTS: bool = false;
+    /// What happens to received packets when the service can't keep up, `Policy::Block` for services that must not
+    /// lose any (e.g. GXS transactions).
+    const QUEUE_POLICY: Policy = Policy::DropOldest;
+
+    fn get_service_info(&self) -> RsServiceInfo {
+        RsServiceInfo::new(Self::SERVICE.into(), Self::NAME)
+    }
+
+    /// Called once when the service starts.
+    async fn start(&mut self, _ctx: &ServiceContext<Self::Item>) {}
+
+    async fn handle_item(
+        &mut self,
+        ctx: &ServiceContext<Self::Item>,
+        item: Self::Item,
+        from: Arc<SslId>,
+    );
+
+    async fn on_timer(&mut self, _ctx: &ServiceContext<Self::Item>, _timer: &'static str) {}
+
+    async fn handle_event(&mut self, _ctx: &ServiceContext<Self::Item>, _event: Intercom) {}
+
+    /// Waits for the next command, this future is dropped whenever anything else happens.
+    ///
+    /// Returning `None` stops polling for commands.
+    async fn recv_command(&mut self) -> Option<Self::Command> {
+        pending().await
+    }
+
+    async fn handle_command(
+        &mut self,
+        _ctx: &ServiceContext<Self::Item>,
+        _cmd: Self::Command,
+    ) {
+    }
+}
+
+/// Waits for the next tick of any timer and returns its name.
+async fn next_tick(timers: &mut [(&'static str, Interval)]) -> &'static str {
+    if timers.is_empty() {
+        return pending().await;
+    }
+
+    select_all(timers.iter_mut().map(|(name, timer)| {
+        Box::pin(async move {
+            timer.tick().await;
+            *name
+        })
+    }))
+    .await
+    .0
+}
+
+async fn next_event(events: &mut Option<Receiver<Intercom>>) -> Option<Intercom> {
+    match events {
+        Some(events) => events.recv().await,
+        None => pending().await,
+    }
+}
+
+async fn run_service<S: Service>(
+    mut service: S,
+    ctx: ServiceContext<S::Item>,
+    mut rx: Receiver<Intercom>,
+    mut events: Option<Receiver<Intercom>>,
+) {
+    let mut timers: Vec<_> = S::TIMERS
+        .iter()
+        .map(|(name, period)| (*name, interval(*period)))
+        .collect();
+    let mut commands = true;
+
+    service.start(&ctx).await;
+
+    loop {
+        select! {
+            msg = rx.recv() => {
+                trace!("handling msg {msg:?}");
+                match msg {
+                    Some(Intercom::Receive(packet)) => {
+                        let from = packet.peer_id.to_owned();
+                        match S::Item::decode(packet) {
+                            Ok(item) => service.handle_item(&ctx, item, from).await,
+                            Err(err) => warn!("[{}] dropping item from {from}: {err}", S::NAME),
+                        }
+                    }
+                    Some(msg) => warn!("unexpected message: {msg:?}"),
+                    // the services were dropped, e.g. the peer disconnected
+                    None => break,
+                }
+            }
+            timer = next_tick(&mut timers) => {
+                service.on_timer(&ctx, timer).await;
+            }
+            Some(event) = next_event(&mut events) => {
+                service.handle_event(&ctx, event).await;
+            }
+            cmd = service.recv_command(), if commands => {
+                match cmd {
+                    Some(cmd) => service.handle_command(&ctx, cmd).await,
+                    None => commands = false,
+                }
+            }
+        }
+    }
 }
 
 pub struct Services {
-    // services: HashMap<ServiceType, (Box<dyn Service + Sync + Send>, UnboundedSender<Intercom>)>,
-    services: HashMap<ServiceType, (UnboundedSender<Intercom>, RsServiceInfo, JoinHandle<()>)>,
-    // timers: HashMap<ServiceType, Timers>,
+    services: HashMap<ServiceType, (Sender<Intercom>, RsServiceInfo, JoinHandle<()>)>,
     /// used to distinguish between core services and peer services
     is_core_service: bool,
     /// usd by peer services
-    core_tx: UnboundedSender<Intercom>,
+    core_tx: Sender<Intercom>,
+    /// only set for peer services
+    peer_tx: Option<Sender<Intercom>>,
+    /// used to check service permissions
+    core: Arc<DataCore>,
 }
 
 impl Services {
-    pub fn new(is_core_service: bool, core_tx: UnboundedSender<Intercom>) -> Services {
+    pub fn new(
+        core_tx: Sender<Intercom>,
+        peer_tx: Option<Sender<Intercom>>,
+        core: Arc<DataCore>,
+    ) -> Services {
         Services {
             services: HashMap::new(),
-            // timers: HashMap::new(),
-            is_core_service,
+            is_core_service: peer_tx.is_none(),
             core_tx,
+            peer_tx,
+            core,
         }
     }
 
     pub async fn get_peer_services(
-        core_tx: UnboundedSender<Intercom>,
-        peer_tx: UnboundedSender<Intercom>,
+        core: &Arc<DataCore>,
+        core_tx: Sender<Intercom>,
+        peer_tx: Sender<Intercom>,
     ) -> Services {
-        let mut services = Services::new(false, core_tx.to_owned());
-
-        // RTT
-        create_service!(PEER: services, core_tx, peer_tx, Rtt, rtt::Rtt);
+        let mut services = Services::new(core_tx, Some(peer_tx), core.to_owned());
 
-        // ServiceInfo
-        create_service!(
-            PEER: services,
-            core_tx,
-            peer_tx,
-            ServiceInfo,
-            service_info::ServiceInfo
-        );
-
-        // Heartbeat
-        create_service!(
-            PEER: services,
-            core_tx,
-            peer_tx,
-            Heartbeat,
-            heartbeat::Heartbeat
-        );
-
-        // Status
-        create_service!(PEER: services, core_tx, peer_tx, Status, status::Status);
+        services.register(rtt::Rtt::new()).await;
+        services.register(service_info::ServiceInfo::new()).await;
+        services.register(heartbeat::Heartbeat::new()).await;
+        services.register(status::Status::new()).await;
 
         services
     }
 
     pub async fn get_core_services(
         dc: &Arc<DataCore>,
-        core_tx: UnboundedSender<Intercom>,
+        core_tx: Sender<Intercom>,
         (gxs_id_db, gxs_shared_id): (GxsDatabase, Arc<GxsShared>),
     ) -> Services {
-        let mut services = Services::new(true, core_tx.to_owned());
-
-        // Discovery
-        create_service!(CORE: services, dc, core_tx, Discovery, discovery::Discovery);
-
-        // Turtle
-        create_service!(CORE: services, dc, core_tx, Turtle, turtle::Turtle);
-
-        // BwCtrl
-        create_service!(CORE: services, dc, core_tx, BwCtrl, bwctrl::BwCtrl);
+        let mut services = Services::new(core_tx.to_owned(), None, dc.to_owned());
 
-        // Chat
-        create_service!(CORE: services, dc, core_tx, Chat, chat::Chat);
+        services.register(discovery::Discovery::new(dc)).await;
+        services
+            .register(turtle::Turtle::new(dc, core_tx.to_owned()))
+            .await;
+        services.register(gxs_tunnel::GxsTunnel::new(dc)).await;
+        services.register(grouter::GRouter::new(dc)).await;
+        services.register(banlist::BanList::new(dc)).await;
+        services.register(bwctrl::BwCtrl::new(dc)).await;
+        services
+            .register(chat::Chat::new(dc, core_tx.to_owned()).await)
+            .await;
+        services.register(mail::Mail::new(dc).await).await;
 
         // GXS
-
-        // Gxs Id
-        // create_service!(services, GxsId, gxs_id::GxsId | CORE, TX, TIMER);
-        let (tx, rx) = unbounded_channel();
-        let s = Box::new(
-            gxs_id::GxsId::new(&dc, core_tx.clone(), rx, (gxs_id_db, gxs_shared_id)).await,
-        );
-        let ty = s.get_id();
-        let info = s.get_service_info();
-        let handle = s.run();
-        services.add_service(ty, tx, info, handle);
+        services
+            .register(gxs_id::GxsId::new(dc, (gxs_id_db, gxs_shared_id)))
+            .await;
 
         services
     }
 
-    // pub fn add_service(
-    //     &mut self,
-    //     service: (
-    //         Box<dyn Service + Sync + Send + 'static>,
-    //         UnboundedSender<Intercom>,
-    //     ),
-    // ) {
-    //     let id = service.0.get_id();
-    //     // ensure that a timer entry is created
-    //     self.timers.entry(id).or_insert(HashMap::new());
-
-    //     self.services.insert(id, service);
-    // }
-    fn add_service(
-        &mut self,
-        ty: ServiceType,
-        tx: UnboundedSender<Intercom>,
-        info: RsServiceInfo,
-        handle: JoinHandle<()>,
-    ) {
-        self.services.insert(ty, (tx, info, handle));
+    /// Spawns a service, incoming packets of its type are passed to it from now on.
+    pub async fn register<S: Service>(&mut self, service: S) {
+        let (tx, rx) = channel(
+            format!("service {:?}", S::SERVICE),
+            SERVICE_QUEUE_SIZE,
+            S::QUEUE_POLICY,
+        );
+        let events = match S::EVENTS {
+            true => Some(self.core.events_subscribe(S::NAME).await),
+            false => None,
+        };
+        let ctx = ServiceContext {
+            service: S::SERVICE,
+            core_tx: self.core_tx.to_owned(),
+            peer_tx: self.peer_tx.to_owned(),
+            _item: PhantomData,
+        };
+
+        let info = service.get_service_info();
+        let handle = tokio::spawn(run_service(service, ctx, rx, events));
+        self.services.insert(S::SERVICE, (tx, info, handle));
     }
 
     pub async fn handle_packet(&mut self, packet: Packet) {
         trace!("handle_packet {packet:?}");
 
         match &packet.header {
-            Header::Service { service, .. } => match self.services.get_mut(&service) {
-                Some((tx, _, _)) => tx
-                    .send(Intercom::Receive(packet))
-                    .expect("failed to send to service"),
+            Header::Service { service, .. }
+                if !self
+                    .core
+                    .get_service_data()
+                    .service_control()
+                    .is_allowed(*service, &packet.peer_id) =>
+            {
+                debug!(
+                    "dropping packet of service {service:?} from {}, service is not allowed",
+                    packet.peer_id
+                );
+            }
+            Header::Service { service, .. } => match self.services.get_mut(service) {
+                // Services send to the core themselves, so waiting for a blocking service could dead lock. The wait
+                // is bounded, the packet is dropped when the service doesn't catch up in time.
+                Some((tx, _, _)) => {
+                    let service = *service;
+                    match tx.try_send(Intercom::Receive(packet)) {
+                        Ok(()) => (),
+                        Err(TrySendError::Full(msg)) => {
+                            if timeout(SERVICE_QUEUE_WAIT, tx.send(msg)).await.is_err() {
+                                warn!("service {service:?} is not keeping up, dropping packet");
+                            }
+                        }
+                        Err(err) => {
+                            warn!("failed to send to service {service:?}, dropping packet: {err:?}")
+                        }
+                    }
+                }
 
                 None => {
                     if self.is_core_service {
@@ -243,6 +536,7 @@ impl Services {
                     } else {
                         self.core_tx
                             .send(Intercom::Receive(packet))
+                            .await
                             .expect("failed to send to core");
                     }
                 }
@@ -1,56 +1,54 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use log::{info, trace, warn};
+use log::{info, warn};
 use retroshare_compat::{
-    serde::{from_retroshare_wire, to_retroshare_wire},
+    basics::SslId,
+    serde::to_retroshare_wire,
     services::service_info::{RsServiceInfo, TlvServiceInfoMapRef},
 };
-use tokio::{
-    select,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-};
 
 use crate::{
     low_level_parsing::{headers::ServiceHeader, Packet},
-    model::intercom::Intercom,
-    services::Service,
+    model::{
+        intercom::Intercom,
+        services::service_control::{from_full_service_id, ServiceControlStore},
+    },
+    service_items,
+    services::{Service, ServiceContext},
 };
 
 use ::retroshare_compat::services::ServiceType;
 
 pub const SERVICE_INFO_SUB_TYPE: u8 = 0x01;
 
-pub struct ServiceInfo {
-    #[allow(dead_code)]
-    peer_tx: UnboundedSender<Intercom>,
-
-    rx: UnboundedReceiver<Intercom>,
-}
-
-impl ServiceInfo {
-    pub fn new(
-        _core_tx: UnboundedSender<Intercom>,
-        peer_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-    ) -> ServiceInfo {
-        ServiceInfo { peer_tx, rx }
+service_items! {
+    pub enum ServiceInfoItem: ServiceInfo {
+        Services(TlvServiceInfoMapRef) = SERVICE_INFO_SUB_TYPE, priority: 7;
     }
+}
 
-    fn handle_incoming(&self, header: &ServiceHeader, mut packet: Packet) {
-        match header.sub_type {
-            SERVICE_INFO_SUB_TYPE => {
-                let services = from_retroshare_wire::<TlvServiceInfoMapRef>(&mut packet.payload).0;
+pub struct ServiceInfo {}
 
-                for s in services {
-                    info!("num: {:#08X} -> {:?}", s.0 .0, s.1 .0);
-                }
-            }
-            sub_type => log::error!("received unknown sub typ {sub_type}"),
-        }
+impl ServiceInfo {
+    pub fn new() -> ServiceInfo {
+        ServiceInfo {}
     }
 
-    pub fn gen_service_info(services: &Vec<RsServiceInfo>) -> Packet {
-        let services: TlvServiceInfoMapRef = services.to_owned().into();
+    /// Generates the list of services that `peer_id` is allowed to use.
+    pub fn gen_service_info(
+        services: &[RsServiceInfo],
+        service_control: &ServiceControlStore,
+        peer_id: &SslId,
+    ) -> Packet {
+        let services: TlvServiceInfoMapRef = services
+            .iter()
+            .filter(|info| {
+                service_control.is_allowed(from_full_service_id(info.m_service_type), peer_id)
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+            .into();
         let payload = to_retroshare_wire(&services);
         let header = ServiceHeader::new(ServiceType::ServiceInfo, SERVICE_INFO_SUB_TYPE, &payload);
 
@@ -60,28 +58,32 @@ impl ServiceInfo {
 
 #[async_trait]
 impl Service for ServiceInfo {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::ServiceInfo
-    }
+    type Item = ServiceInfoItem;
+    type Command = ();
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "service_info")
-    }
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
-                            match msg {
-                                Intercom::Receive(packet) => self.handle_incoming(&packet.header.to_owned().into(), packet),
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                }
-            }
-        })
+    const SERVICE: ServiceType = ServiceType::ServiceInfo;
+    const NAME: &'static str = "service_info";
+
+    async fn handle_item(
+        &mut self,
+        ctx: &ServiceContext<ServiceInfoItem>,
+        item: ServiceInfoItem,
+        _from: Arc<SslId>,
+    ) {
+        let ServiceInfoItem::Services(services) = item;
+        let services = services.0;
+
+        for s in &services {
+            info!("num: {:#08X} -> {:?}", s.0 .0, s.1 .0);
+        }
+
+        // the peer worker stores the list and negotiates the services
+        if let Some(peer_tx) = ctx.peer_tx() {
+            peer_tx
+                .try_send(Intercom::ServiceInfoUpdate(
+                    services.into_values().map(|info| info.0).collect(),
+                ))
+                .unwrap_or_else(|_| warn!("failed to send service info update"));
+        }
     }
 }
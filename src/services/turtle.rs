@@ -1,31 +1,30 @@
 use async_trait::async_trait;
-use log::{info, trace, warn};
+use log::{debug, info, trace, warn};
 use nanorand::{Rng, WyRand};
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     sync::{Arc, Mutex, RwLock},
     time::{Duration, Instant},
 };
-use tokio::{
-    select,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-    time::{interval, Interval},
-};
-
 use retroshare_compat::{
     basics::SslId,
-    serde::from_retroshare_wire,
-    services::{service_info::RsServiceInfo, turtle::*},
+    serde::{from_retroshare_wire, to_retroshare_wire},
+    services::turtle::*,
 };
+use serde::Serialize;
 
 use crate::{
+    channel::{channel, Policy, Receiver, Sender},
     low_level_parsing::{headers::ServiceHeader, Packet},
     // error,
-    model::{intercom::Intercom, location::Location, DataCore},
+    model::{
+        intercom::{Intercom, PeerState, PeerUpdate},
+        services::turtle::{TunnelDirection, TunnelForward, TurtleCmd},
+        DataCore,
+    },
     send_to_core,
-    services::Service,
-    utils::{self, simple_stats::StatsPrinter, units::pretty_print_bytes},
+    services::{Service, ServiceContext, SERVICE_CMD_QUEUE_SIZE},
+    utils::{self, units::pretty_print_bytes},
 };
 
 use ::retroshare_compat::services::ServiceType;
@@ -37,7 +36,7 @@ const TURTLE_SUB_TYPE_TUNNEL_OK: u8 = 0x04;
 const TURTLE_SUB_TYPE_FILE_REQUEST: u8 = 0x07;
 const TURTLE_SUB_TYPE_FILE_DATA: u8 = 0x08;
 const TURTLE_SUB_TYPE_REGEXP_SEARCH_REQUEST: u8 = 0x09;
-const TURTLE_SUB_TYPE_GENERIC_DATA: u8 = 0x0a;
+pub const TURTLE_SUB_TYPE_GENERIC_DATA: u8 = 0x0a;
 const TURTLE_SUB_TYPE_GENERIC_SEARCH_REQUEST: u8 = 0x0b;
 const TURTLE_SUB_TYPE_GENERIC_SEARCH_RESULT: u8 = 0x0c;
 const TURTLE_SUB_TYPE_FILE_MAP: u8 = 0x10;
@@ -48,69 +47,148 @@ const TURTLE_SUB_TYPE_CHUNK_CRC: u8 = 0x14;
 const TURTLE_SUB_TYPE_CHUNK_CRC_REQUEST: u8 = 0x15;
 const TURTLE_SUB_TYPE_GENERIC_FAST_DATA: u8 = 0x16;
 
+const TURTLE_TIMER_MAINTENANCE: (&str, Duration) = ("maintenance", Duration::from_secs(5));
+
 /// life time for tunnel requests in the cache.
 const TUNNEL_REQUESTS_LIFE_TIME: Duration = Duration::from_secs(600);
 /// maximum time during which we process/forward results for known tunnel requests
 const TUNNEL_REQUESTS_RESULT_TIME: Duration = Duration::from_secs(20);
 /// maximum life time of an unused tunnel.
 const MAXIMUM_TUNNEL_IDLE_TIME: Duration = Duration::from_secs(60);
+/// life time for search requests in the cache, results are routed back during this time.
+const SEARCH_REQUESTS_LIFE_TIME: Duration = Duration::from_secs(240);
+/// time between tunnel requests for monitored hashes without tunnels.
+const EMPTY_TUNNELS_DIGGING_TIME: Duration = Duration::from_secs(50);
+/// time between tunnel requests for monitored hashes with tunnels.
+const MINIMUM_TUNNELS_DIGGING_TIME: Duration = Duration::from_secs(101);
+
+/// Interface for services that use turtle tunnels (RetroShare's `RsTurtleClientService`).
+///
+/// Clients register with `TurtleStore::register_client` and control the turtle service with `TurtleCmd`.
+#[async_trait]
+pub trait TurtleClient: Send + Sync {
+    /// Service id of the client, generic searches are dispatched based on it.
+    fn get_service_id(&self) -> ServiceType;
+
+    /// Called for tunnel requests, returning `true` accepts the tunnel and makes us its end point.
+    async fn handle_tunnel_request(&self, hash: &TurtleFileHash, peer_id: &Arc<SslId>) -> bool;
 
-// stats stuff
-#[allow(dead_code)]
-const ENTRY_A: &str = &"times_forwarded";
-#[allow(dead_code)]
-const ENTRY_A_FN: StatsPrinter = |data| -> String { format!("{} times", data) };
+    /// Called for data that was received through one of the client's tunnels.
+    async fn receive_turtle_data(
+        &self,
+        data: Vec<u8>,
+        hash: &TurtleFileHash,
+        tunnel_id: u32,
+        direction: TunnelDirection,
+    );
 
-#[allow(dead_code)]
-const ENTRY_B: &str = &"data_forwarded";
-#[allow(dead_code)]
-const ENTRY_B_FN: StatsPrinter =
-    |data| -> String { format!("{}", pretty_print_bytes(data as u64)) };
+    /// Called when a tunnel of the client was established.
+    async fn tunnel_up(&self, hash: &TurtleFileHash, tunnel_id: u32, direction: TunnelDirection);
+
+    /// Called when a tunnel of the client was closed.
+    async fn tunnel_down(&self, hash: &TurtleFileHash, tunnel_id: u32);
+
+    /// Called for generic search requests for the client's service id, the returned data (if any) is sent back as result.
+    async fn handle_generic_search(
+        &self,
+        _request_type: u8,
+        _search_data: &[u8],
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called for results of generic searches started by the client.
+    async fn receive_search_result(
+        &self,
+        _request_id: TurtleSearchRequestId,
+        _result_data: Vec<u8>,
+    ) {
+    }
+}
 
 pub struct Turtle {
-    rx: UnboundedReceiver<Intercom>,
+    core: Arc<DataCore>,
+    core_tx: Sender<Intercom>,
 
-    core_tx: UnboundedSender<Intercom>,
+    cmd_rx: Receiver<TurtleCmd>,
 
+    own_id: Arc<SslId>,
     rng: Arc<RwLock<WyRand>>,
-    locations: Vec<Arc<Location>>,
+    random_bias: u32,
 
     tunnel_history: RwLock<HashMap<u32, TunnelRequest>>,
-    tunnel_active: RwLock<HashMap<u32, TunnelActive>>,
+    /// tunnels we are an end of
+    tunnels_local: RwLock<HashMap<u32, TunnelLocal>>,
+    /// tunnel requests we sent for our clients
+    tunnel_requests_own: RwLock<HashMap<u32, (TurtleFileHash, ServiceType)>>,
+    monitored_hashes: RwLock<HashMap<TurtleFileHash, MonitoredHash>>,
 
-    stats_forwarded_count: Mutex<i32>,
-    stats_forwarded_data: Mutex<i32>,
+    search_history: RwLock<HashMap<TurtleSearchRequestId, SearchRequest>>,
 
-    timer_maintenance: Interval,
+    /// totals, saturating
+    stats_forwarded_count: Mutex<u64>,
+    stats_forwarded_data: Mutex<u64>,
 }
 
-// TODO handle peers disconnecting
-
 impl Turtle {
-    pub async fn new(
-        core: &Arc<DataCore>,
-        core_tx: UnboundedSender<Intercom>,
-        rx: UnboundedReceiver<Intercom>,
-    ) -> Turtle {
-        Turtle {
-            rx,
+    pub fn new(core: &Arc<DataCore>, core_tx: Sender<Intercom>) -> Turtle {
+        let (tx_cmd, rx_cmd) = channel("turtle commands", SERVICE_CMD_QUEUE_SIZE, Policy::Block);
+        core.get_service_data().turtle().set_cmd(tx_cmd);
+
+        let mut rng = WyRand::new();
+        let random_bias = rng.generate();
 
+        Turtle {
+            core: core.to_owned(),
             core_tx,
 
-            rng: Arc::new(RwLock::new(WyRand::new())),
-            locations: core.get_locations().clone(),
+            cmd_rx: rx_cmd,
+
+            own_id: core.get_own_location().get_location_id(),
+            rng: Arc::new(RwLock::new(rng)),
+            random_bias,
 
             tunnel_history: RwLock::new(HashMap::new()),
-            tunnel_active: RwLock::new(HashMap::new()),
+            tunnels_local: RwLock::new(HashMap::new()),
+            tunnel_requests_own: RwLock::new(HashMap::new()),
+            monitored_hashes: RwLock::new(HashMap::new()),
+
+            search_history: RwLock::new(HashMap::new()),
 
             stats_forwarded_count: Mutex::new(0),
             stats_forwarded_data: Mutex::new(0),
+        }
+    }
+
+    async fn send_packet<T>(&self, sub_type: u8, item: &T, receiving_peer: Arc<SslId>)
+    where
+        T: Serialize,
+    {
+        let payload = to_retroshare_wire(item);
+        let header = ServiceHeader::new(ServiceType::Turtle, sub_type, &payload);
+        let packet = Packet::new(header.into(), payload, receiving_peer);
+
+        send_to_core!(self, packet);
+    }
+
+    /// Sends a packet to all connected peers except `origin`.
+    async fn spread(&self, mut packet: Packet, origin: &Arc<SslId>) {
+        // locations are looked up every time, discovery adds new ones while running
+        for loc in self.core.get_locations() {
+            if loc.is_connected() {
+                // skip the packet's origin
+                if &loc.get_location_id() == origin {
+                    continue;
+                }
 
-            timer_maintenance: interval(Duration::from_secs(5)),
+                packet.peer_id = loc.get_location_id().to_owned();
+                send_to_core!(self, packet.to_owned());
+            }
         }
     }
 
-    fn handle_incoming(&self, header: &ServiceHeader, mut packet: Packet) {
+    async fn handle_incoming(&self, mut packet: Packet) {
+        let header = ServiceHeader::from(packet.header.to_owned());
         trace!("handle_incoming: {header:?}");
         // // exclude handled ones
         // if ![
@@ -130,10 +208,10 @@ impl Turtle {
             }
             TURTLE_SUB_TYPE_FT_SEARCH_RESULT => {}
             TURTLE_SUB_TYPE_OPEN_TUNNEL => {
-                self.handle_open_tunnel(packet);
+                self.handle_open_tunnel(packet).await;
             }
             TURTLE_SUB_TYPE_TUNNEL_OK => {
-                self.handle_tunnel_ok(packet);
+                self.handle_tunnel_ok(packet).await;
             }
             TURTLE_SUB_TYPE_FILE_REQUEST => {}
             TURTLE_SUB_TYPE_FILE_DATA => {}
@@ -142,14 +220,14 @@ impl Turtle {
                 info!("search request: regex: {item:?}");
             }
             TURTLE_SUB_TYPE_GENERIC_DATA => {
-                self.handle_generic_data(packet);
+                self.handle_generic_data(packet).await;
             }
             TURTLE_SUB_TYPE_GENERIC_SEARCH_REQUEST => {
-                let item: TurtleGenericSearchRequestItem =
-                    from_retroshare_wire(&mut packet.payload);
-                info!("search request: generic: {item:?}");
+                self.handle_generic_search_request(packet).await;
+            }
+            TURTLE_SUB_TYPE_GENERIC_SEARCH_RESULT => {
+                self.handle_generic_search_result(packet).await;
             }
-            TURTLE_SUB_TYPE_GENERIC_SEARCH_RESULT => {}
             TURTLE_SUB_TYPE_FILE_MAP => {}
             TURTLE_SUB_TYPE_FILE_MAP_REQUEST => {}
             TURTLE_SUB_TYPE_FILE_CRC | TURTLE_SUB_TYPE_FILE_CRC_REQUEST => {
@@ -167,7 +245,7 @@ impl Turtle {
         }
     }
 
-    fn handle_open_tunnel(&self, mut packet: Packet) {
+    async fn handle_open_tunnel(&self, packet: Packet) {
         // forward based on simple probability
         // RS does a lot of math to be "safe", this has been discussed often in the past
 
@@ -187,13 +265,19 @@ impl Turtle {
             return;
         }
 
+        // ask our clients first, an accepted tunnel ends here
+        if self
+            .handle_tunnel_request_local(&item, &packet.peer_id)
+            .await
+        {
+            return;
+        }
+
         if !self.forward() {
             trace!("dropping tunnel request! {}", item);
             return;
         }
 
-        // TODO add own file sharing ability BELOW the forward check
-
         let entry = TunnelRequest {
             from: packet.peer_id.clone(),
             time: Instant::now(),
@@ -203,24 +287,71 @@ impl Turtle {
             .expect("failed to get history, lock poisoned!")
             .insert(item.request_id, entry);
 
-        for loc in &self.locations {
-            if loc.is_connected() {
-                // skip the packet's origin
-                if loc.get_location_id() == packet.peer_id {
-                    continue;
-                }
-
-                packet.peer_id = loc.get_location_id().to_owned();
-                // self.core_tx
-                //     .send(Intercom::Send(packet.clone()))
-                //     .expect("failed to communicate with core!");
-                send_to_core!(self, packet.to_owned());
-            }
-        }
+        let origin = packet.peer_id.to_owned();
+        self.spread(packet, &origin).await;
         trace!("spreading tunnel request! {}", item);
     }
 
-    fn handle_tunnel_ok(&self, mut packet: Packet) {
+    async fn handle_tunnel_request_local(
+        &self,
+        item: &TurtleOpenTunnelItem,
+        peer_id: &Arc<SslId>,
+    ) -> bool {
+        let client = match self
+            .core
+            .get_service_data()
+            .turtle()
+            .find_tunnel_client(&item.file_hash, peer_id)
+            .await
+        {
+            Some(client) => client,
+            None => return false,
+        };
+
+        let tunnel_id =
+            item.partial_tunnel_id ^ self.generate_personal_file_print(&item.file_hash, false);
+        let service = client.get_service_id();
+        trace!("accepting tunnel {tunnel_id:08x} for {service:?}");
+
+        // remember the request to drop bounced ones
+        self.tunnel_history
+            .write()
+            .expect("failed to get history, lock poisoned!")
+            .insert(
+                item.request_id,
+                TunnelRequest {
+                    from: peer_id.to_owned(),
+                    time: Instant::now(),
+                },
+            );
+        self.tunnels_local
+            .write()
+            .expect("failed to get local tunnels, lock poisoned!")
+            .insert(
+                tunnel_id,
+                TunnelLocal {
+                    peer_id: peer_id.to_owned(),
+                    hash: item.file_hash,
+                    service,
+                    direction: TunnelDirection::Server,
+                    last_active: Instant::now(),
+                },
+            );
+
+        let ok = TurtleTunnelOkItem {
+            tunnel_id,
+            request_id: item.request_id,
+        };
+        self.send_packet(TURTLE_SUB_TYPE_TUNNEL_OK, &ok, peer_id.to_owned())
+            .await;
+
+        client
+            .tunnel_up(&item.file_hash, tunnel_id, TunnelDirection::Server)
+            .await;
+        true
+    }
+
+    async fn handle_tunnel_ok(&self, mut packet: Packet) {
         // create a copy for simple forward
         let item: TurtleTunnelOkItem = from_retroshare_wire(&mut packet.payload.clone());
 
@@ -250,16 +381,38 @@ impl Turtle {
             return;
         }
 
-        // everything is ok, insert new tunnel
-        let entry = TunnelActive {
-            from: request.from.clone(),
-            to: packet.peer_id.clone(),
-            last_active: Instant::now(),
+        // our own request?
+        if request.from == self.own_id {
+            self.handle_tunnel_ok_own(&item, &packet.peer_id).await;
+            return;
+        }
+
+        // everything is ok, install the new tunnel for the fast path
+        let (from_tx, to_tx) = {
+            let lock = self.core.get_connected_peers().lock().await;
+            (
+                lock.0.get(&request.from).map(|(tx, _)| tx.to_owned()),
+                lock.0.get(&packet.peer_id).map(|(tx, _)| tx.to_owned()),
+            )
         };
+        let (from_tx, to_tx) = match (from_tx, to_tx) {
+            (Some(from_tx), Some(to_tx)) => (from_tx, to_tx),
+            _ => {
+                trace!(
+                    "unable to install tunnel {:08x}, peer is no longer connected",
+                    &item.tunnel_id
+                );
+                return;
+            }
+        };
+        let entry = TunnelForward::new(
+            (request.from.clone(), from_tx),
+            (packet.peer_id.clone(), to_tx),
+        );
         let prev = self
-            .tunnel_active
-            .write()
-            .expect("failed to get active tunnels, lock poisoned!")
+            .core
+            .get_service_data()
+            .turtle()
             .insert(item.tunnel_id, entry);
         if prev.is_some() {
             warn!(
@@ -275,41 +428,135 @@ impl Turtle {
         send_to_core!(self, packet);
     }
 
-    fn handle_generic_data(&self, mut packet: Packet) {
+    async fn handle_tunnel_ok_own(&self, item: &TurtleTunnelOkItem, peer_id: &Arc<SslId>) {
+        let (hash, service) = match self
+            .tunnel_requests_own
+            .write()
+            .expect("failed to get own tunnel requests, lock poisoned!")
+            .remove(&item.request_id)
+        {
+            Some(entry) => entry,
+            None => {
+                warn!(
+                    "unable to find own tunnel request for id {:08x}",
+                    &item.request_id
+                );
+                return;
+            }
+        };
+
+        // still interested?
+        if !self
+            .monitored_hashes
+            .read()
+            .expect("failed to get monitored hashes, lock poisoned!")
+            .contains_key(&hash)
+        {
+            trace!(
+                "dropping tunnel {:08x}, hash is no longer monitored",
+                &item.tunnel_id
+            );
+            return;
+        }
+
+        let client = match self.core.get_service_data().turtle().get_client(&service) {
+            Some(client) => client,
+            None => {
+                warn!("unable to find turtle client {service:?}");
+                return;
+            }
+        };
+
+        trace!("established tunnel {:08x} for {service:?}", &item.tunnel_id);
+        self.tunnels_local
+            .write()
+            .expect("failed to get local tunnels, lock poisoned!")
+            .insert(
+                item.tunnel_id,
+                TunnelLocal {
+                    peer_id: peer_id.to_owned(),
+                    hash,
+                    service,
+                    direction: TunnelDirection::Client,
+                    last_active: Instant::now(),
+                },
+            );
+
+        client
+            .tunnel_up(&hash, item.tunnel_id, TunnelDirection::Client)
+            .await;
+    }
+
+    /// Handles generic data that did not take the fast path.
+    ///
+    /// This happens for data of tunnels we are an end of, of tunnels the fast path has no entry for (which are dropped) or for data with an unexpected source.
+    async fn handle_generic_data(&self, mut packet: Packet) {
         // create a copy for simple forward
         let item: TurtleGenericDataItem = from_retroshare_wire(&mut packet.payload.clone());
 
         trace!("received generic data: {item}");
 
-        // find tunnel id
-        let mut lock = self
-            .tunnel_active
+        // tunnel ends here?
+        let local = self
+            .tunnels_local
             .write()
-            .expect("failed to get active tunnels, lock poisoned!");
-        let entry = lock.get_mut(&item.tunnel_id);
-        if entry.is_none() {
-            trace!(
-                "unable to find active tunnel request for id {:08x}",
-                &item.tunnel_id
-            );
+            .expect("failed to get local tunnels, lock poisoned!")
+            .get_mut(&item.tunnel_id)
+            .map(|entry| {
+                entry.last_active = Instant::now();
+                (
+                    entry.peer_id.to_owned(),
+                    entry.hash,
+                    entry.service,
+                    entry.direction,
+                )
+            });
+        if let Some((peer_id, hash, service, direction)) = local {
+            if peer_id != packet.peer_id {
+                info!(
+                    "generic data item for local tunnel {:08x} has an unexpected source! Dropping it!",
+                    &item.tunnel_id
+                );
+                return;
+            }
+
+            match self.core.get_service_data().turtle().get_client(&service) {
+                Some(client) => {
+                    client
+                        .receive_turtle_data(item.data, &hash, item.tunnel_id, direction)
+                        .await
+                }
+                None => warn!("unable to find turtle client {service:?}"),
+            }
             return;
         }
-        let mut entry = entry.unwrap();
+
+        // find tunnel id
+        let tunnels = self.core.get_service_data().turtle();
+        let entry = match tunnels.get(&item.tunnel_id) {
+            Some(entry) => entry,
+            None => {
+                trace!(
+                    "unable to find active tunnel request for id {:08x}",
+                    &item.tunnel_id
+                );
+                return;
+            }
+        };
 
         // found it, figure out direction
-        if packet.peer_id == entry.from {
-            packet.peer_id = entry.to.clone();
-        } else if packet.peer_id == entry.to {
-            packet.peer_id = entry.from.clone();
-        } else {
-            info!(
-                "generic data item has active tunnel {:08x} but no matching source / destination! Dropping tunnel!",
-                &item.tunnel_id
-            );
-            lock.remove(&item.tunnel_id);
-            return;
+        match entry.other_end(&packet.peer_id) {
+            Some((peer_id, _)) => packet.peer_id = peer_id.to_owned(),
+            None => {
+                info!(
+                    "generic data item has active tunnel {:08x} but no matching source / destination! Dropping tunnel!",
+                    &item.tunnel_id
+                );
+                tunnels.remove(&item.tunnel_id);
+                return;
+            }
         }
-        entry.last_active = Instant::now();
+        entry.account(packet.header.get_payload_size());
 
         trace!(
             "forwarding data (id: {:08x}, size: {})",
@@ -317,20 +564,383 @@ impl Turtle {
             utils::units::pretty_print_bytes(packet.header.get_payload_size() as u64)
         );
 
-        // handle stats
-        *self
-            .stats_forwarded_count
-            .lock()
-            .expect("failed to get stats_forwarded_count, lock poisoned!") += 1;
-        *self
-            .stats_forwarded_data
-            .lock()
-            .expect("failed to get stats_forwarded_data, lock poisoned!") +=
-            packet.header.get_payload_size() as i32;
-
         send_to_core!(self, packet);
     }
 
+    async fn handle_generic_search_request(&self, packet: Packet) {
+        let item: TurtleGenericSearchRequestItem =
+            from_retroshare_wire(&mut packet.payload.clone());
+
+        trace!("received generic search request: {item}");
+
+        // bounce check!
+        let service: ServiceType = item.service_id.into();
+        match self
+            .search_history
+            .write()
+            .expect("failed to get search history, lock poisoned!")
+            .entry(item.base.request_id)
+        {
+            Entry::Occupied(_) => {
+                trace!("dropping bounced search request! {item}");
+                return;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(SearchRequest {
+                    from: packet.peer_id.to_owned(),
+                    service,
+                    time: Instant::now(),
+                });
+            }
+        }
+
+        // answer
+        if let Some(client) = self.core.get_service_data().turtle().get_client(&service) {
+            if let Some(result_data) = client
+                .handle_generic_search(item.request_type, &item.search_data)
+                .await
+            {
+                let result = TurtleGenericSearchResultItem {
+                    base: TurtleSearchResultItem {
+                        request_id: item.base.request_id,
+                    },
+                    result_data,
+                };
+                self.send_packet(
+                    TURTLE_SUB_TYPE_GENERIC_SEARCH_RESULT,
+                    &result,
+                    packet.peer_id.to_owned(),
+                )
+                .await;
+            }
+        }
+
+        // and forward
+        if !self.forward() {
+            trace!("dropping search request! {item}");
+            return;
+        }
+        let origin = packet.peer_id.to_owned();
+        self.spread(packet, &origin).await;
+    }
+
+    async fn handle_generic_search_result(&self, mut packet: Packet) {
+        let item: TurtleGenericSearchResultItem = from_retroshare_wire(&mut packet.payload.clone());
+
+        trace!("received generic search result: {item}");
+
+        let (from, service) = match self
+            .search_history
+            .read()
+            .expect("failed to get search history, lock poisoned!")
+            .get(&item.base.request_id)
+        {
+            Some(entry) => (entry.from.to_owned(), entry.service),
+            None => {
+                trace!(
+                    "unable to find search request for id {:08x}",
+                    &item.base.request_id
+                );
+                return;
+            }
+        };
+
+        if from != self.own_id {
+            // route back
+            packet.peer_id = from;
+            send_to_core!(self, packet);
+            return;
+        }
+
+        match self.core.get_service_data().turtle().get_client(&service) {
+            Some(client) => {
+                client
+                    .receive_search_result(item.base.request_id, item.result_data)
+                    .await
+            }
+            None => warn!("unable to find turtle client {service:?}"),
+        }
+    }
+
+    async fn handle_cmd(&self, cmd: TurtleCmd) {
+        match cmd {
+            TurtleCmd::MonitorTunnels(hash, service) => {
+                debug!("monitoring tunnels to {hash} for {service:?}");
+
+                self.monitored_hashes
+                    .write()
+                    .expect("failed to get monitored hashes, lock poisoned!")
+                    .insert(hash, MonitoredHash::new(service));
+                self.dig_tunnel(hash, service).await;
+            }
+            TurtleCmd::StopMonitoringTunnels(hash) => {
+                debug!("stop monitoring tunnels to {hash}");
+
+                self.monitored_hashes
+                    .write()
+                    .expect("failed to get monitored hashes, lock poisoned!")
+                    .remove(&hash);
+                self.close_local_tunnels(|_, e| {
+                    e.hash == hash && e.direction == TunnelDirection::Client
+                })
+                .await;
+            }
+            TurtleCmd::SendData(tunnel_id, data) => {
+                let peer_id = self
+                    .tunnels_local
+                    .write()
+                    .expect("failed to get local tunnels, lock poisoned!")
+                    .get_mut(&tunnel_id)
+                    .map(|entry| {
+                        entry.last_active = Instant::now();
+                        entry.peer_id.to_owned()
+                    });
+                match peer_id {
+                    Some(peer_id) => {
+                        let item = TurtleGenericDataItem { tunnel_id, data };
+                        self.send_packet(TURTLE_SUB_TYPE_GENERIC_DATA, &item, peer_id)
+                            .await;
+                    }
+                    None => debug!("unable to send data, tunnel {tunnel_id:08x} is unknown"),
+                }
+            }
+            TurtleCmd::GenericSearch {
+                service,
+                request_type,
+                search_data,
+                tx,
+            } => {
+                let request_id = self
+                    .rng
+                    .write()
+                    .expect("failed to get rng, lock poisoned!")
+                    .generate();
+                self.search_history
+                    .write()
+                    .expect("failed to get search history, lock poisoned!")
+                    .insert(
+                        request_id,
+                        SearchRequest {
+                            from: self.own_id.to_owned(),
+                            service,
+                            time: Instant::now(),
+                        },
+                    );
+
+                let item = TurtleGenericSearchRequestItem {
+                    base: TurtleSearchRequestItem {
+                        request_id,
+                        depth: 0,
+                    },
+                    service_id: service.into(),
+                    _search_data_len: 0,
+                    request_type,
+                    search_data,
+                };
+                let payload = to_retroshare_wire(&item);
+                let header = ServiceHeader::new(
+                    ServiceType::Turtle,
+                    TURTLE_SUB_TYPE_GENERIC_SEARCH_REQUEST,
+                    &payload,
+                );
+                self.spread(
+                    Packet::new_without_location(header.into(), payload),
+                    &self.own_id,
+                )
+                .await;
+
+                let _ = tx.send(request_id);
+            }
+        }
+    }
+
+    /// Sends a tunnel request for one of our clients.
+    async fn dig_tunnel(&self, hash: TurtleFileHash, service: ServiceType) {
+        let request_id = self
+            .rng
+            .write()
+            .expect("failed to get rng, lock poisoned!")
+            .generate();
+        let item = TurtleOpenTunnelItem {
+            file_hash: hash,
+            request_id,
+            partial_tunnel_id: self.generate_personal_file_print(&hash, true),
+            depth: 0,
+        };
+
+        trace!("digging tunnel {item}");
+
+        self.tunnel_history
+            .write()
+            .expect("failed to get history, lock poisoned!")
+            .insert(
+                request_id,
+                TunnelRequest {
+                    from: self.own_id.to_owned(),
+                    time: Instant::now(),
+                },
+            );
+        self.tunnel_requests_own
+            .write()
+            .expect("failed to get own tunnel requests, lock poisoned!")
+            .insert(request_id, (hash, service));
+
+        let payload = to_retroshare_wire(&item);
+        let header = ServiceHeader::new(ServiceType::Turtle, TURTLE_SUB_TYPE_OPEN_TUNNEL, &payload);
+        self.spread(
+            Packet::new_without_location(header.into(), payload),
+            &self.own_id,
+        )
+        .await;
+    }
+
+    /// Requests new tunnels for all monitored hashes that are due.
+    async fn dig_tunnels(&self) {
+        let tunnels: Vec<_> = self
+            .tunnels_local
+            .read()
+            .expect("failed to get local tunnels, lock poisoned!")
+            .values()
+            .filter(|e| e.direction == TunnelDirection::Client)
+            .map(|e| e.hash)
+            .collect();
+
+        let due: Vec<_> = self
+            .monitored_hashes
+            .write()
+            .expect("failed to get monitored hashes, lock poisoned!")
+            .iter_mut()
+            .filter_map(|(hash, entry)| {
+                let pause = if tunnels.contains(hash) {
+                    MINIMUM_TUNNELS_DIGGING_TIME
+                } else {
+                    EMPTY_TUNNELS_DIGGING_TIME
+                };
+                if entry.last_request.elapsed() < pause {
+                    return None;
+                }
+                entry.last_request = Instant::now();
+                Some((hash.to_owned(), entry.service))
+            })
+            .collect();
+
+        for (hash, service) in due {
+            self.dig_tunnel(hash, service).await;
+        }
+    }
+
+    /// Removes all local tunnels for which `f` returns `true` and notifies the clients.
+    async fn close_local_tunnels<F>(&self, mut f: F)
+    where
+        F: FnMut(&u32, &TunnelLocal) -> bool,
+    {
+        let mut removed = vec![];
+        self.tunnels_local
+            .write()
+            .expect("failed to get local tunnels, lock poisoned!")
+            .retain(|id, entry| {
+                if f(id, entry) {
+                    removed.push((*id, entry.hash, entry.service));
+                    false
+                } else {
+                    true
+                }
+            });
+
+        let turtle = self.core.get_service_data().turtle();
+        for (tunnel_id, hash, service) in removed {
+            trace!("closing local tunnel {tunnel_id:08x}");
+            if let Some(client) = turtle.get_client(&service) {
+                client.tunnel_down(&hash, tunnel_id).await;
+            }
+        }
+    }
+
+    /// Generates a tunnel id (part) that is unique for us and the given hash.
+    ///
+    /// Same as RetroShare's `generatePersonalFilePrint`, both ends combine their prints to the tunnel id.
+    fn generate_personal_file_print(&self, hash: &TurtleFileHash, symmetrical: bool) -> u32 {
+        let buff = hash.to_string() + &self.own_id.to_string();
+
+        let mut res = self.random_bias;
+        let mut decal = 0u32;
+        for c in buff.bytes() {
+            res = res
+                .wrapping_add(7u32.wrapping_mul(c as u32))
+                .wrapping_add(decal);
+            decal = if symmetrical {
+                decal
+                    .wrapping_mul(44497)
+                    .wrapping_add(15641)
+                    .wrapping_add(res % 86243)
+            } else {
+                decal
+                    .wrapping_mul(86243)
+                    .wrapping_add(15649)
+                    .wrapping_add(res % 44497)
+            };
+        }
+        res
+    }
+
+    async fn maintain_tunnels(&self) {
+        let tunnels = self.core.get_service_data().turtle();
+
+        // collect stats from the fast path
+        let (count, data) = tunnels.drain_stats();
+        {
+            let mut total = self
+                .stats_forwarded_count
+                .lock()
+                .expect("failed to get stats_forwarded_count, lock poisoned!");
+            *total = total.saturating_add(count);
+        }
+        {
+            let mut total = self
+                .stats_forwarded_data
+                .lock()
+                .expect("failed to get stats_forwarded_data, lock poisoned!");
+            *total = total.saturating_add(data);
+        }
+        if count > 0 {
+            trace!("forwarded {count} times ({})", pretty_print_bytes(data));
+        }
+
+        // remove unused tunnels
+        for tunnel_id in tunnels.retain(|_, e| e.last_active().elapsed() < MAXIMUM_TUNNEL_IDLE_TIME)
+        {
+            trace!("removing idle tunnel {tunnel_id:08x}");
+        }
+        self.close_local_tunnels(|_, e| e.last_active.elapsed() >= MAXIMUM_TUNNEL_IDLE_TIME)
+            .await;
+
+        // clean up requests
+        if let Ok(mut requests) = self.tunnel_requests_own.try_write() {
+            let history = self
+                .tunnel_history
+                .read()
+                .expect("failed to get history, lock poisoned!");
+            requests.retain(|id, _| history.contains_key(id));
+        }
+        if let Ok(mut history) = self.search_history.try_write() {
+            history.retain(|_, e| e.time.elapsed() < SEARCH_REQUESTS_LIFE_TIME);
+        }
+
+        self.dig_tunnels().await;
+    }
+
+    async fn handle_peer_disconnected(&self, peer_id: &Arc<SslId>) {
+        for tunnel_id in self
+            .core
+            .get_service_data()
+            .turtle()
+            .retain(|_, e| !e.involves(peer_id))
+        {
+            trace!("removing tunnel {tunnel_id:08x}, {peer_id} disconnected");
+        }
+        self.close_local_tunnels(|_, e| &e.peer_id == peer_id).await;
+    }
+
     fn forward(&self) -> bool {
         // Here be dragons!
         //
@@ -355,72 +965,39 @@ impl Turtle {
 
 #[async_trait]
 impl Service for Turtle {
-    fn get_id(&self) -> ServiceType {
-        ServiceType::Turtle
+    // packets are forwarded as they are
+    type Item = Packet;
+    type Command = TurtleCmd;
+
+    const SERVICE: ServiceType = ServiceType::Turtle;
+    const NAME: &'static str = "turtle";
+    const TIMERS: &'static [(&'static str, Duration)] = &[TURTLE_TIMER_MAINTENANCE];
+    const EVENTS: bool = true;
+
+    async fn handle_item(&mut self, _ctx: &ServiceContext<Packet>, packet: Packet, _from: Arc<SslId>) {
+        self.handle_incoming(packet).await;
     }
 
-    fn get_service_info(&self) -> RsServiceInfo {
-        RsServiceInfo::new(self.get_id().into(), "turtle")
+    async fn on_timer(&mut self, _ctx: &ServiceContext<Packet>, _timer: &'static str) {
+        // Do not block! It is not worth blocking the main tick!
+        if let Ok(mut history) = self.tunnel_history.try_write() {
+            history.retain(|_, e| e.time.elapsed() < TUNNEL_REQUESTS_LIFE_TIME);
+        }
+        self.maintain_tunnels().await;
     }
 
-    fn run(mut self) -> JoinHandle<()> {
-        tokio::spawn(async move {
-            loop {
-                select! {
-                    msg = self.rx.recv() => {
-                        if let Some(msg) = msg {
-                            trace!("handling msg {msg:?}");
-
-                            match msg {
-                                Intercom::Receive(packet) =>
-                                    self.handle_incoming(&packet.header.to_owned().into(), packet),
-                                _ => warn!("unexpected message: {msg:?}"),
-                            }
-                        }
-                    }
-                    _ = self.timer_maintenance.tick() => {
-                        // Do not block! It is not worth blocking the main tick!
-                        if let Ok(mut history) = self.tunnel_history.try_write() {
-                            history.retain(|_, e| e.time.elapsed() < TUNNEL_REQUESTS_LIFE_TIME);
-                        }
-                        if let Ok(mut active) = self.tunnel_active.try_write() {
-                            active.retain(|_, e| e.last_active.elapsed() < MAXIMUM_TUNNEL_IDLE_TIME);
-                        }
-
-                        // DEBUG
-
-                        // println!("[Turtle] tunnel request history:");
-                        // let lock = self
-                        //     .tunnel_history
-                        //     .read()
-                        //     .expect("failed to get history, lock poisoned!");
-                        // for entry in lock.iter() {
-                        //     print!(" {:08x}", entry.0);
-                        // }
-                        // if !lock.is_empty() {
-                        //     print!("\n");
-                        // }
-                        // drop(lock);
-
-                        // println!("[Turtle] active tunnels:");
-                        // let lock = self
-                        //     .tunnel_active
-                        //     .read()
-                        //     .expect("failed to get active tunnels, lock poisoned!");
-                        // for entry in lock.iter() {
-                        //     println!(
-                        //         " - id: {:08x}, entry: {} -> {} (last active: {:2?} secs)",
-                        //         entry.0,
-                        //         entry.1.from,
-                        //         entry.1.to,
-                        //         entry.1.last_active.elapsed().as_secs()
-                        //     );
-                        // }
-                        // drop(lock);
-                    }
-                }
-            }
-        })
+    async fn handle_event(&mut self, _ctx: &ServiceContext<Packet>, event: Intercom) {
+        if let Intercom::PeerUpdate(PeerUpdate::Status(PeerState::NotConnected(loc))) = event {
+            self.handle_peer_disconnected(&loc).await;
+        }
+    }
+
+    async fn recv_command(&mut self) -> Option<TurtleCmd> {
+        self.cmd_rx.recv().await
+    }
+
+    async fn handle_command(&mut self, _ctx: &ServiceContext<Packet>, cmd: TurtleCmd) {
+        self.handle_cmd(cmd).await;
     }
 }
 
@@ -431,8 +1008,32 @@ struct TunnelRequest {
 }
 
 #[derive(Debug)]
-struct TunnelActive {
-    from: Arc<SslId>,
-    to: Arc<SslId>,
+struct TunnelLocal {
+    peer_id: Arc<SslId>,
+    hash: TurtleFileHash,
+    service: ServiceType,
+    direction: TunnelDirection,
     last_active: Instant,
 }
+
+#[derive(Debug)]
+struct MonitoredHash {
+    service: ServiceType,
+    last_request: Instant,
+}
+
+impl MonitoredHash {
+    fn new(service: ServiceType) -> Self {
+        Self {
+            service,
+            last_request: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SearchRequest {
+    from: Arc<SslId>,
+    service: ServiceType,
+    time: Instant,
+}
@@ -4,28 +4,59 @@ use log::info;
 use openpgp::parse::Parse;
 #[allow(unused_imports)]
 use openssl::{self, hash::MessageDigest};
+use retroshare_compat::basics::SslId;
 use rustls::{
     client::{InvalidDnsNameError, ServerCertVerifier},
+    server::{ClientCertVerified, ClientCertVerifier},
     version::TLS13,
-    Certificate, ClientConfig, ServerName,
+    Certificate, ClientConfig, DistinguishedNames, ServerConfig, ServerName,
 };
 use sequoia_openpgp as openpgp;
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    net::TcpStream,
+    net::{TcpStream, UdpSocket},
 };
-use tokio_rustls::TlsConnector;
+use tokio_rustls::{server, TlsAcceptor, TlsConnector};
 
 use crate::retroshare_compat::ssl_key::SslKey;
 
+use self::proxy::Proxy;
+
+pub mod proxy;
+pub mod socks5;
+pub mod tou;
+
 #[derive(Clone, Debug)]
 pub enum ConnectionType {
     Tcp(SocketAddr),
-    #[allow(dead_code)]
+    /// TCP over UDP, see [`tou`]
     Udp(SocketAddr),
+    /// Tor or I2P hidden node, reached through the given SOCKS5 proxy, see [`socks5`]
+    Hidden {
+        host: String,
+        port: u16,
+        proxy: SocketAddr,
+    },
 }
 
+impl ConnectionType {
+    /// Address of the peer, hidden nodes have none (we only know their proxy).
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            ConnectionType::Tcp(addr) | ConnectionType::Udp(addr) => Some(*addr),
+            ConnectionType::Hidden { .. } => None,
+        }
+    }
+}
+
+/// The stream below TLS, either TCP (possibly proxied) or TOU.
+trait RawStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> RawStream for T {}
+
 struct PeerVerifier {
     _peer_name: ServerName,
     _peer_cert: sequoia_openpgp::Cert,
@@ -89,9 +120,97 @@ impl ServerCertVerifier for PeerVerifier {
     }
 }
 
+/// Accepts any client certificate, like `PeerVerifier` does for servers.
+struct ClientVerifier;
+
+#[allow(unused)]
+impl ClientCertVerifier for ClientVerifier {
+    fn client_auth_root_subjects(&self) -> Option<DistinguishedNames> {
+        // location certificates are signed with the owner's PGP key, there is no CA
+        Some(vec![])
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: std::time::SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        info!("verify_client_cert");
+
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &rustls::internal::msgs::handshake::DigitallySignedStruct,
+    ) -> Result<rustls::client::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &Certificate,
+        dss: &rustls::internal::msgs::handshake::DigitallySignedStruct,
+    ) -> Result<rustls::client::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::HandshakeSignatureValid::assertion())
+    }
+}
+
+/// RS takes a location's ssl id from the end of its certificate's signature (see RS's `getX509id`).
+pub fn ssl_id_from_cert(cert: &Certificate) -> Option<SslId> {
+    let cert = openssl::x509::X509::from_der(cert.as_ref()).ok()?;
+    let signature = cert.signature().as_slice();
+    let start = signature.len().checked_sub(16)?;
+    Some(SslId(signature[start..].try_into().ok()?))
+}
+
+pub type IncomingStream = server::TlsStream<TcpStream>;
+
+/// Accepts incoming connections, the peer is identified by the ssl id of its certificate.
+#[derive(Clone)]
+pub struct Acceptor {
+    acceptor: TlsAcceptor,
+}
+
+impl Acceptor {
+    pub fn new(own_key_pair: &SslKey) -> Result<Self, rustls::Error> {
+        let config = ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&TLS13])?
+            .with_client_cert_verifier(Arc::new(ClientVerifier))
+            .with_single_cert(vec![own_key_pair.into()], own_key_pair.into())?;
+
+        Ok(Acceptor {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+    ) -> io::Result<(SslId, server::TlsStream<S>)> {
+        let stream = self.acceptor.accept(stream).await?;
+        let ssl_id = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(ssl_id_from_cert)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no usable certificate"))?;
+        Ok((ssl_id, stream))
+    }
+}
+
 pub struct Connection {
     config: Arc<ClientConfig>,
     peer_name: ServerName,
+    /// outgoing proxy for clear-net TCP connections
+    proxy: Option<Proxy>,
 }
 
 impl Connection {
@@ -123,17 +242,136 @@ impl Connection {
 
         let config = Arc::new(config);
 
-        Ok(Connection { config, peer_name })
+        Ok(Connection {
+            config,
+            peer_name,
+            proxy: None,
+        })
+    }
+
+    /// Routes clear-net TCP connections through `proxy`, UDP can't be proxied and is refused.
+    pub fn with_proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = proxy;
+        self
     }
 
     pub async fn connect(&self, target: ConnectionType) -> io::Result<impl AsyncWrite + AsyncRead> {
-        let ip_addr = match target {
-            ConnectionType::Tcp(ip) => ip,
-            ConnectionType::Udp(_) => unimplemented!("UDP support not implemented"),
+        let stream: Box<dyn RawStream> = match target {
+            ConnectionType::Tcp(addr) => match &self.proxy {
+                Some(proxy) => Box::new(proxy.connect(&addr.ip().to_string(), addr.port()).await?),
+                None => Box::new(TcpStream::connect(&addr).await?),
+            },
+            ConnectionType::Udp(_) if self.proxy.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "udp is not supported through a proxy",
+                ))
+            }
+            ConnectionType::Udp(addr) => {
+                let local: SocketAddr = if addr.is_ipv4() {
+                    (Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let socket = UdpSocket::bind(local).await?;
+                Box::new(tou::connect(socket, addr).await?)
+            }
+            ConnectionType::Hidden { host, port, proxy } => {
+                Box::new(socks5::connect(proxy, &host, port).await?)
+            }
         };
 
         let connector = TlsConnector::from(self.config.clone());
-        let stream = TcpStream::connect(&ip_addr).await?;
         connector.connect(self.peer_name.clone(), stream).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use openssl::{
+        asn1::Asn1Time, hash::MessageDigest, pkey::PKey, rsa::Rsa, x509::X509NameBuilder,
+        x509::X509,
+    };
+    use rustls::Certificate;
+    use sequoia_openpgp::cert::CertBuilder;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::retroshare_compat::ssl_key::SslKey;
+
+    use super::{ssl_id_from_cert, Acceptor, Connection, ConnectionType};
+
+    fn gen_key(name: &str) -> SslKey {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&pkey).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&pkey, MessageDigest::sha256()).unwrap();
+
+        (
+            cert.build().to_der().unwrap(),
+            pkey.private_key_to_der().unwrap(),
+        )
+            .into()
+    }
+
+    #[tokio::test]
+    async fn accept() {
+        let server_key = gen_key("server");
+        let client_key = gen_key("client");
+        let client_id = ssl_id_from_cert(&Certificate::from(&client_key)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = Acceptor::new(&server_key).unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, from) = listener.accept().await.unwrap();
+            let (ssl_id, mut stream) = acceptor.accept(stream).await.unwrap();
+            stream.write_all(b"pong").await.unwrap();
+            stream.flush().await.unwrap();
+            (ssl_id, from)
+        });
+
+        let (pgp, _) = CertBuilder::general_purpose(None, Some("server"))
+            .generate()
+            .unwrap();
+        let con = Connection::new(&client_key, pgp, "server").unwrap();
+        let mut stream = con.connect(ConnectionType::Tcp(addr)).await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // the peer is identified by its certificate, its address is the one it connected from
+        let (ssl_id, from) = server.await.unwrap();
+        assert_eq!(ssl_id, client_id);
+        assert_eq!(from.ip(), addr.ip());
+    }
+
+    #[test]
+    fn peer_addr() {
+        let addr: SocketAddr = "192.0.2.1:7812".parse().unwrap();
+        assert_eq!(ConnectionType::Tcp(addr).peer_addr(), Some(addr));
+        assert_eq!(ConnectionType::Udp(addr).peer_addr(), Some(addr));
+
+        let hidden = ConnectionType::Hidden {
+            host: "example.onion".into(),
+            port: 7812,
+            proxy: "127.0.0.1:9050".parse().unwrap(),
+        };
+        assert_eq!(hidden.peer_addr(), None);
+    }
+}
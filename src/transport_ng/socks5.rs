@@ -0,0 +1,283 @@
+//! Minimal SOCKS5 client (RFC 1928), hidden nodes are reached through the local Tor or I2P proxy.
+//!
+//! Only `CONNECT` is supported (without authentication or with username and password, RFC 1929), host names are
+//! passed on to the proxy (which resolves them).
+
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const SOCKS_VERSION: u8 = 0x05;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+const USER_PASS_VERSION: u8 = 0x01;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("socks5: {msg}"))
+}
+
+fn reply_error(reply: u8) -> io::Error {
+    let (kind, msg) = match reply {
+        0x01 => (io::ErrorKind::Other, "general failure"),
+        0x02 => (io::ErrorKind::PermissionDenied, "connection not allowed"),
+        0x03 => (io::ErrorKind::Other, "network unreachable"),
+        0x04 => (io::ErrorKind::Other, "host unreachable"),
+        0x05 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (io::ErrorKind::TimedOut, "TTL expired"),
+        0x07 => (io::ErrorKind::Unsupported, "command not supported"),
+        0x08 => (io::ErrorKind::Unsupported, "address type not supported"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    io::Error::new(kind, format!("socks5: {msg} ({reply:#04x})"))
+}
+
+/// Connects to `host:port` through the SOCKS5 proxy at `proxy`.
+pub async fn connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+    handshake(&mut stream, host, port, None).await?;
+    Ok(stream)
+}
+
+/// Runs the SOCKS5 negotiation on an established stream to the proxy, `auth` is `(username, password)`.
+pub async fn handshake<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<(&str, &str)>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // greeting
+    match auth {
+        Some(_) => {
+            stream
+                .write_all(&[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS])
+                .await?
+        }
+        None => {
+            stream
+                .write_all(&[SOCKS_VERSION, 1, METHOD_NO_AUTH])
+                .await?
+        }
+    }
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    match (reply, auth) {
+        ([SOCKS_VERSION, METHOD_NO_AUTH], _) => {}
+        ([SOCKS_VERSION, METHOD_USER_PASS], Some((username, password))) => {
+            authenticate(stream, username, password).await?
+        }
+        ([SOCKS_VERSION, METHOD_NONE_ACCEPTABLE], _) => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "socks5: proxy requires authentication",
+            ))
+        }
+        _ => return Err(invalid_data("unexpected method selection")),
+    }
+
+    // request
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend(ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend(ip.octets());
+        }
+        Err(_) => {
+            let len: u8 = host
+                .len()
+                .try_into()
+                .map_err(|_| invalid_data("host name too long"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend(host.as_bytes());
+        }
+    }
+    request.extend(port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // reply: VER REP RSV ATYP BND.ADDR BND.PORT
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(invalid_data("unexpected version"));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(reply_error(reply[1]));
+    }
+    let len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(invalid_data("unexpected address type")),
+    };
+    // the bound address is of no interest
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// Username / password authentication (RFC 1929).
+async fn authenticate<S>(stream: &mut S, username: &str, password: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let too_long = |_| invalid_data("username or password too long");
+    let mut request = vec![USER_PASS_VERSION];
+    request.push(username.len().try_into().map_err(too_long)?);
+    request.extend(username.as_bytes());
+    request.push(password.len().try_into().map_err(too_long)?);
+    request.extend(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    match reply {
+        [USER_PASS_VERSION, 0x00] => Ok(()),
+        [USER_PASS_VERSION, _] => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "socks5: authentication failed",
+        )),
+        _ => Err(invalid_data("unexpected authentication reply")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Stand-in for Tor's SOCKS port, expects a connection to `host:port` and answers with `reply`.
+    ///
+    /// On success the connection is echoed.
+    async fn proxy(host: &'static str, port: u16, reply: u8) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).await.unwrap();
+
+            let mut request = [0; 5];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[..4], [5, 1, 0, 3]);
+            let mut name = vec![0; request[4] as usize];
+            stream.read_exact(&mut name).await.unwrap();
+            assert_eq!(name, host.as_bytes());
+            assert_eq!(stream.read_u16().await.unwrap(), port);
+
+            stream
+                .write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            let mut buf = [0; 64];
+            loop {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => stream.write_all(&buf[..len]).await.unwrap(),
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn connect() {
+        let host = "rustysharexxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion";
+        let addr = proxy(host, 1234, 0).await;
+
+        let mut stream = super::connect(addr, host, 1234).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn refused() {
+        let host = "rustyshare.b32.i2p";
+        let addr = proxy(host, 1234, 5).await;
+
+        let err = super::connect(addr, host, 1234).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn authentication_required() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[5, 0xFF]).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let err = super::handshake(&mut stream, "rustyshare.onion", 1234, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn authentication() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 4];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            stream.write_all(&[5, 2]).await.unwrap();
+
+            let mut request = [0; 12];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"\x01\x05alice\x04pass");
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0x04, 0xD2]);
+            stream
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        super::handshake(&mut stream, "10.0.0.1", 1234, Some(("alice", "pass")))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+//! TCP over UDP (TOU), RetroShare's reliable stream on top of a UDP socket.
+//!
+//! RetroShare uses TOU for connections set up through bitdht (hole punching or relays). Each UDP packet carries a
+//! stripped down TCP header (see `tcponudp/tcppacket.cc`):
+//!
+//! | bytes | content                          |
+//! |-------|----------------------------------|
+//! | 0..4  | source and destination port (0)  |
+//! | 4..8  | sequence number                  |
+//! | 8..12 | acknowledgement number           |
+//! | 12..14| flags                            |
+//! | 14..16| window                           |
+//! | 16..20| checksum and urgent pointer (0)  |
+//!
+//! A task drives the protocol (handshake, acknowledgements, retransmissions), the caller gets one end of an in-memory
+//! pipe to read from and write to. Both sides may open the stream at the same time (simultaneous open), which is what
+//! hole punching relies on.
+//!
+//! Experimental and off by default (see `NetStore::is_experimental_udp`): there is no bitdht, thus no rendezvous and no
+//! hole punching, and `connect` uses an ephemeral socket. Only peers that answer on their UDP port directly are reached.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use log::{debug, trace};
+use nanorand::{Rng, WyRand};
+use tokio::{
+    io::{duplex, split, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::UdpSocket,
+    select,
+    time::{interval, timeout},
+};
+
+/// The caller's end of a TOU stream, dropping it closes the stream.
+pub type TouStream = DuplexStream;
+
+const TOU_HEADER_SIZE: usize = 20;
+
+const TOU_FLAG_FIN: u16 = 0x01;
+const TOU_FLAG_SYN: u16 = 0x02;
+const TOU_FLAG_RST: u16 = 0x04;
+const TOU_FLAG_ACK: u16 = 0x10;
+
+/// Payload per packet, keeps packets below common MTUs.
+const TOU_MSS: usize = 1000;
+/// Segments in flight.
+const TOU_SEND_WINDOW: usize = 64;
+/// Received data the caller hasn't read yet, further segments are dropped (and retransmitted by the peer).
+const TOU_RECV_BUFFER: usize = 256 * 1024;
+/// Segments received ahead of a missing one.
+const TOU_OUT_OF_ORDER_MAX: usize = 2 * TOU_SEND_WINDOW;
+const TOU_PIPE_SIZE: usize = 64 * 1024;
+
+const TOU_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const TOU_SYN_RESEND: Duration = Duration::from_millis(500);
+/// Initial retransmission timeout, doubled on every retry.
+const TOU_RTO: Duration = Duration::from_millis(300);
+const TOU_MAX_RETRIES: u32 = 8;
+const TOU_TICK: Duration = Duration::from_millis(50);
+/// Time to keep answering the peer after both sides closed, in case our last ACK got lost.
+const TOU_LINGER: Duration = Duration::from_secs(2);
+
+/// `a < b` in sequence space (wrapping).
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TouPacket {
+    seq: u32,
+    ack: u32,
+    flags: u16,
+    window: u16,
+    data: Vec<u8>,
+}
+
+impl TouPacket {
+    fn new(seq: u32, ack: u32, flags: u16) -> Self {
+        TouPacket {
+            seq,
+            ack,
+            flags,
+            window: u16::MAX,
+            data: vec![],
+        }
+    }
+
+    fn has(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Sequence space used, SYN and FIN count as one byte each.
+    fn seq_len(&self) -> u32 {
+        self.data.len() as u32 + self.has(TOU_FLAG_SYN) as u32 + self.has(TOU_FLAG_FIN) as u32
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(TOU_HEADER_SIZE + self.data.len());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.ack.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        buf.extend_from_slice(&self.window.to_be_bytes());
+        buf.extend_from_slice(&[0; 4]);
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+
+    fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < TOU_HEADER_SIZE {
+            return None;
+        }
+        Some(TouPacket {
+            seq: u32::from_be_bytes(buf[4..8].try_into().unwrap()),
+            ack: u32::from_be_bytes(buf[8..12].try_into().unwrap()),
+            flags: u16::from_be_bytes(buf[12..14].try_into().unwrap()),
+            window: u16::from_be_bytes(buf[14..16].try_into().unwrap()),
+            data: buf[TOU_HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+/// Opens a TOU stream to `peer`.
+pub async fn connect(socket: UdpSocket, peer: SocketAddr) -> io::Result<TouStream> {
+    let (peer, first) = timeout(TOU_CONNECT_TIMEOUT, handshake(&socket, Some(peer)))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TOU handshake timed out"))??;
+    Ok(spawn(socket, peer, first))
+}
+
+/// Waits for a peer to open a TOU stream on `socket`.
+///
+/// Only the tests listen, incoming TOU connections aren't accepted yet.
+#[cfg(test)]
+pub async fn accept(socket: UdpSocket) -> io::Result<(TouStream, SocketAddr)> {
+    let (peer, first) = handshake(&socket, None).await?;
+    Ok((spawn(socket, peer, first), peer))
+}
+
+/// State right after the handshake.
+struct Established {
+    snd_nxt: u32,
+    rcv_nxt: u32,
+    /// The handshake's last ACK may already carry data.
+    first: Option<TouPacket>,
+}
+
+async fn handshake(
+    socket: &UdpSocket,
+    mut peer: Option<SocketAddr>,
+) -> io::Result<(SocketAddr, Established)> {
+    let isn: u32 = WyRand::new().generate();
+    let mut syn_received = false;
+    let mut rcv_nxt = 0;
+
+    let mut resend = interval(TOU_SYN_RESEND);
+    let mut buf = vec![0; u16::MAX as usize];
+
+    loop {
+        select! {
+            _ = resend.tick() => {
+                // passive side: nothing to send until a SYN arrives
+                let Some(peer) = peer else { continue };
+                let packet = if syn_received {
+                    TouPacket::new(isn, rcv_nxt, TOU_FLAG_SYN | TOU_FLAG_ACK)
+                } else {
+                    TouPacket::new(isn, 0, TOU_FLAG_SYN)
+                };
+                socket.send_to(&packet.to_bytes(), peer).await?;
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (len, from) = res?;
+                if peer.is_some_and(|peer| peer != from) {
+                    trace!("ignoring packet from {from} during handshake");
+                    continue;
+                }
+                let Some(packet) = TouPacket::from_bytes(&buf[..len]) else {
+                    continue;
+                };
+                if packet.has(TOU_FLAG_RST) {
+                    return Err(io::ErrorKind::ConnectionRefused.into());
+                }
+
+                let syn = packet.has(TOU_FLAG_SYN);
+                let acks_syn = packet.has(TOU_FLAG_ACK) && packet.ack == isn.wrapping_add(1);
+                match (syn, acks_syn) {
+                    // our SYN was answered
+                    (true, true) => {
+                        rcv_nxt = packet.seq.wrapping_add(1);
+                        let ack = TouPacket::new(isn.wrapping_add(1), rcv_nxt, TOU_FLAG_ACK);
+                        socket.send_to(&ack.to_bytes(), from).await?;
+                        let established = Established {
+                            snd_nxt: isn.wrapping_add(1),
+                            rcv_nxt,
+                            first: None,
+                        };
+                        return Ok((from, established));
+                    }
+                    // their SYN (either passive open or simultaneous open)
+                    (true, false) => {
+                        peer = Some(from);
+                        syn_received = true;
+                        rcv_nxt = packet.seq.wrapping_add(1);
+                        let syn_ack = TouPacket::new(isn, rcv_nxt, TOU_FLAG_SYN | TOU_FLAG_ACK);
+                        socket.send_to(&syn_ack.to_bytes(), from).await?;
+                    }
+                    // our SYN ACK was answered
+                    (false, true) if syn_received => {
+                        let established = Established {
+                            snd_nxt: isn.wrapping_add(1),
+                            rcv_nxt,
+                            first: Some(packet),
+                        };
+                        return Ok((from, established));
+                    }
+                    _ => trace!("unexpected packet during handshake: {packet:?}"),
+                }
+            }
+        }
+    }
+}
+
+fn spawn(socket: UdpSocket, peer: SocketAddr, established: Established) -> TouStream {
+    let (stream, local) = duplex(TOU_PIPE_SIZE);
+
+    let tou = Tou {
+        socket,
+        peer,
+        snd_nxt: established.snd_nxt,
+        rcv_nxt: established.rcv_nxt,
+        unacked: VecDeque::new(),
+        out_of_order: HashMap::new(),
+        pending: vec![],
+        local_fin: false,
+        peer_fin: false,
+    };
+    tokio::spawn(async move {
+        match tou.run(local, established.first).await {
+            Ok(()) => debug!("TOU stream to {peer} closed"),
+            Err(err) => debug!("TOU stream to {peer} failed: {err}"),
+        }
+    });
+
+    stream
+}
+
+struct Segment {
+    packet: TouPacket,
+    sent: Instant,
+    retries: u32,
+}
+
+struct Tou {
+    socket: UdpSocket,
+    peer: SocketAddr,
+
+    snd_nxt: u32,
+    rcv_nxt: u32,
+
+    /// Sent segments waiting for their ACK, oldest first.
+    unacked: VecDeque<Segment>,
+    /// Segments received ahead of `rcv_nxt`.
+    out_of_order: HashMap<u32, TouPacket>,
+    /// Received in order, not yet read by the caller.
+    pending: Vec<u8>,
+
+    local_fin: bool,
+    peer_fin: bool,
+}
+
+impl Tou {
+    async fn run(mut self, local: DuplexStream, first: Option<TouPacket>) -> io::Result<()> {
+        let (mut local_rx, mut local_tx) = split(local);
+        let mut buf = vec![0; u16::MAX as usize];
+        let mut read_buf = vec![0; TOU_MSS];
+        let mut tick = interval(TOU_TICK);
+        let mut eof_passed = false;
+        let mut linger = None;
+
+        if let Some(packet) = first {
+            self.handle(packet).await?;
+        }
+
+        loop {
+            if linger.is_some_and(|until| Instant::now() >= until) {
+                return Ok(());
+            }
+
+            select! {
+                res = self.socket.recv_from(&mut buf) => {
+                    let (len, from) = res?;
+                    if from != self.peer {
+                        continue;
+                    }
+                    if let Some(packet) = TouPacket::from_bytes(&buf[..len]) {
+                        if !self.handle(packet).await? {
+                            return Err(io::ErrorKind::ConnectionReset.into());
+                        }
+                    }
+                }
+                res = local_rx.read(&mut read_buf), if !self.local_fin && self.unacked.len() < TOU_SEND_WINDOW => {
+                    match res {
+                        Ok(0) | Err(_) => self.send_segment(vec![], TOU_FLAG_FIN).await?,
+                        Ok(len) => self.send_segment(read_buf[..len].to_vec(), 0).await?,
+                    }
+                }
+                res = local_tx.write(&self.pending), if !self.pending.is_empty() => {
+                    match res {
+                        Ok(len) => _ = self.pending.drain(..len),
+                        // the caller is gone, nobody will read this
+                        Err(_) => self.pending.clear(),
+                    }
+                }
+                _ = tick.tick() => self.retransmit().await?,
+            }
+
+            if self.peer_fin && self.pending.is_empty() && !eof_passed {
+                let _ = local_tx.shutdown().await;
+                eof_passed = true;
+            }
+            if linger.is_none() && self.local_fin && self.peer_fin && self.unacked.is_empty() {
+                linger = Some(Instant::now() + TOU_LINGER);
+            }
+        }
+    }
+
+    /// Returns `false` when the peer reset the stream.
+    async fn handle(&mut self, packet: TouPacket) -> io::Result<bool> {
+        if packet.has(TOU_FLAG_RST) {
+            return Ok(false);
+        }
+        if packet.has(TOU_FLAG_SYN) {
+            // the peer missed our handshake ACK
+            self.send_ack().await?;
+            return Ok(true);
+        }
+
+        if packet.has(TOU_FLAG_ACK) {
+            while let Some(segment) = self.unacked.front() {
+                let end = segment.packet.seq.wrapping_add(segment.packet.seq_len());
+                if seq_lt(packet.ack, end) {
+                    break;
+                }
+                self.unacked.pop_front();
+            }
+        }
+
+        // pure ACK
+        if packet.seq_len() == 0 {
+            return Ok(true);
+        }
+
+        if packet.seq == self.rcv_nxt {
+            if self.receive(packet) {
+                while let Some(next) = self.out_of_order.remove(&self.rcv_nxt) {
+                    if !self.receive(next) {
+                        break;
+                    }
+                }
+                let rcv_nxt = self.rcv_nxt;
+                self.out_of_order.retain(|seq, _| seq_lt(rcv_nxt, *seq));
+            }
+        } else if seq_lt(self.rcv_nxt, packet.seq) && self.out_of_order.len() < TOU_OUT_OF_ORDER_MAX
+        {
+            self.out_of_order.insert(packet.seq, packet);
+        }
+        // (re)acknowledge everything, including duplicates
+        self.send_ack().await?;
+
+        Ok(true)
+    }
+
+    /// Takes an in order segment, returns `false` when the caller is too far behind.
+    fn receive(&mut self, packet: TouPacket) -> bool {
+        if self.peer_fin {
+            return true;
+        }
+        if self.pending.len() + packet.data.len() > TOU_RECV_BUFFER {
+            return false;
+        }
+
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(packet.seq_len());
+        self.peer_fin = packet.has(TOU_FLAG_FIN);
+        self.pending.extend(packet.data);
+        true
+    }
+
+    async fn send_segment(&mut self, data: Vec<u8>, flags: u16) -> io::Result<()> {
+        let mut packet = TouPacket::new(self.snd_nxt, self.rcv_nxt, flags | TOU_FLAG_ACK);
+        packet.data = data;
+        self.snd_nxt = self.snd_nxt.wrapping_add(packet.seq_len());
+        self.local_fin |= packet.has(TOU_FLAG_FIN);
+
+        self.send(&packet).await?;
+        self.unacked.push_back(Segment {
+            packet,
+            sent: Instant::now(),
+            retries: 0,
+        });
+        Ok(())
+    }
+
+    async fn send_ack(&self) -> io::Result<()> {
+        self.send(&TouPacket::new(self.snd_nxt, self.rcv_nxt, TOU_FLAG_ACK))
+            .await
+    }
+
+    async fn send(&self, packet: &TouPacket) -> io::Result<()> {
+        self.socket.send_to(&packet.to_bytes(), self.peer).await?;
+        Ok(())
+    }
+
+    async fn retransmit(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        for segment in self.unacked.iter_mut() {
+            if now.duration_since(segment.sent) < TOU_RTO * 2u32.pow(segment.retries.min(5)) {
+                continue;
+            }
+            if segment.retries >= TOU_MAX_RETRIES {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "peer stopped acknowledging",
+                ));
+            }
+
+            trace!("retransmitting segment {}", segment.packet.seq);
+            segment.retries += 1;
+            segment.sent = now;
+            segment.packet.ack = self.rcv_nxt;
+            self.socket
+                .send_to(&segment.packet.to_bytes(), self.peer)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UdpSocket,
+    };
+
+    use super::{accept, connect, TouPacket, TOU_FLAG_ACK, TOU_FLAG_SYN};
+
+    async fn socket() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    #[test]
+    fn packet() {
+        let mut packet = TouPacket::new(0x01020304, 0x05060708, TOU_FLAG_SYN | TOU_FLAG_ACK);
+        packet.data = vec![0xaa, 0xbb];
+
+        let bytes = packet.to_bytes();
+        assert_eq!(
+            bytes,
+            hex::decode("0000000001020304050607080012ffff00000000aabb").unwrap()
+        );
+        assert_eq!(TouPacket::from_bytes(&bytes), Some(packet));
+        assert_eq!(TouPacket::from_bytes(&bytes[..19]), None);
+    }
+
+    #[tokio::test]
+    async fn connect_accept() {
+        let (a, _) = socket().await;
+        let (b, b_addr) = socket().await;
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = accept(b).await.unwrap();
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await.unwrap();
+            stream.write_all(b"bye").await.unwrap();
+            data
+        });
+
+        let mut stream = connect(a, b_addr).await.unwrap();
+        // larger than the send window and the pipes
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| i as u8).collect();
+        stream.write_all(&data).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut reply = vec![];
+        stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"bye");
+        assert_eq!(server.await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn simultaneous_open() {
+        let (a, a_addr) = socket().await;
+        let (b, b_addr) = socket().await;
+
+        let (a, b) = tokio::join!(connect(a, b_addr), connect(b, a_addr));
+        let (mut a, mut b) = (a.unwrap(), b.unwrap());
+
+        a.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        b.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        b.write_all(b"pong").await.unwrap();
+        a.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+    }
+
+    #[tokio::test]
+    async fn lossy() {
+        let (a, _) = socket().await;
+        let (b, b_addr) = socket().await;
+        let (relay, relay_addr) = socket().await;
+
+        // forwards between a and b, dropping every 7th packet
+        tokio::spawn(async move {
+            let mut buf = vec![0; u16::MAX as usize];
+            let mut a_addr = None;
+            for i in 0.. {
+                let (len, from) = relay.recv_from(&mut buf).await.unwrap();
+                let to = if from == b_addr {
+                    match a_addr {
+                        Some(addr) => addr,
+                        None => continue,
+                    }
+                } else {
+                    a_addr = Some(from);
+                    b_addr
+                };
+                if i % 7 != 3 {
+                    relay.send_to(&buf[..len], to).await.unwrap();
+                }
+            }
+        });
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = accept(b).await.unwrap();
+            let mut data = vec![];
+            stream.read_to_end(&mut data).await.unwrap();
+            data
+        });
+
+        let mut stream = connect(a, relay_addr).await.unwrap();
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7) as u8).collect();
+        stream.write_all(&data).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        assert_eq!(server.await.unwrap(), data);
+    }
+}
@@ -52,6 +52,106 @@ pub mod units {
     }
 }
 
+pub mod rate_limiter {
+    use std::time::{Duration, Instant};
+
+    /// Token bucket limiting a byte rate, bursts of up to one second worth of data are allowed.
+    #[derive(Debug)]
+    pub struct RateLimiter {
+        /// bytes/s, `None` means unlimited
+        rate: Option<u32>,
+        tokens: f64,
+        last: Instant,
+    }
+
+    impl RateLimiter {
+        /// A rate of 0 is taken as unlimited, such a bucket would never refill.
+        pub fn new(rate: Option<u32>) -> Self {
+            let rate = rate.filter(|rate| *rate != 0);
+            RateLimiter {
+                rate,
+                tokens: rate.unwrap_or_default() as f64,
+                last: Instant::now(),
+            }
+        }
+
+        pub fn set_rate(&mut self, rate: Option<u32>) {
+            self.refill(Instant::now());
+            self.rate = rate.filter(|rate| *rate != 0);
+        }
+
+        fn refill(&mut self, now: Instant) {
+            if let Some(rate) = self.rate {
+                let elapsed = now.duration_since(self.last).as_secs_f64();
+                self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+            }
+            self.last = now;
+        }
+
+        /// Returns how long to wait until sending is allowed again.
+        pub fn delay(&mut self) -> Duration {
+            self.delay_at(Instant::now())
+        }
+
+        fn delay_at(&mut self, now: Instant) -> Duration {
+            self.refill(now);
+            match self.rate {
+                Some(rate) if self.tokens < 0.0 => {
+                    Duration::from_secs_f64(-self.tokens / rate as f64)
+                }
+                _ => Duration::ZERO,
+            }
+        }
+
+        /// Takes `bytes` from the bucket, the bucket may go into debt which is paid off by waiting.
+        pub fn consume(&mut self, bytes: usize) {
+            if self.rate.is_some() {
+                self.tokens -= bytes as f64;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::{Duration, Instant};
+
+        use super::RateLimiter;
+
+        #[test]
+        fn rate_limiter() {
+            let start = Instant::now();
+            let mut limiter = RateLimiter::new(Some(1024));
+            limiter.last = start;
+
+            // one second burst
+            limiter.consume(1024);
+            assert_eq!(limiter.delay_at(start), Duration::ZERO);
+            limiter.consume(512);
+            assert_eq!(limiter.delay_at(start), Duration::from_millis(500));
+            assert_eq!(
+                limiter.delay_at(start + Duration::from_millis(250)),
+                Duration::from_millis(250)
+            );
+            assert_eq!(
+                limiter.delay_at(start + Duration::from_millis(500)),
+                Duration::ZERO
+            );
+
+            // unlimited
+            limiter.set_rate(None);
+            limiter.consume(1_000_000);
+            assert_eq!(limiter.delay(), Duration::ZERO);
+
+            limiter.set_rate(Some(0));
+            limiter.consume(1_000_000);
+            assert_eq!(limiter.delay(), Duration::ZERO);
+            let mut limiter = RateLimiter::new(Some(0));
+            limiter.consume(1_000_000);
+            assert_eq!(limiter.delay(), Duration::ZERO);
+        }
+    }
+}
+
 // TODO remove
 #[allow(dead_code)]
 pub mod timer_stuff {
@@ -8,14 +8,13 @@ use futures::Stream;
 #[allow(unused_imports)]
 use log::info;
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedReceiver;
 
-use crate::model::DataCore;
+use crate::{channel::Receiver, model::DataCore};
 
-use super::{identity, msgs, peers};
+use super::{banlist, config, history, identity, msgs, peers, service_control};
 
 // rsEvents/registerEventsHandler
-struct SSEClient<T>(UnboundedReceiver<T>);
+struct SSEClient<T>(Receiver<T>);
 impl<T> Stream for SSEClient<T>
 where
     T: ToString,
@@ -37,10 +36,7 @@ where
 }
 #[post("/registerEventsHandler")]
 pub async fn rs_events_register_events_handler(state: web::Data<Arc<DataCore>>) -> HttpResponse {
-    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
-    state.webui_add_client(tx).await;
-
-    let rx = SSEClient(rx);
+    let rx = SSEClient(state.webui_add_client().await);
     HttpResponse::Ok()
         .content_type("text/event-stream")
         .streaming(rx)
@@ -70,6 +66,14 @@ pub async fn run_actix(data_core: Arc<DataCore>) {
             .service(web::scope("/rsEvents").service(rs_events_register_events_handler))
             // rsIdentity
             .service(identity::get_entry_points())
+            // rsHistory
+            .service(history::get_entry_points())
+            // rsBanList
+            .service(banlist::get_entry_points())
+            // rsServiceControl
+            .service(service_control::get_entry_points())
+            // rsConfig
+            .service(config::get_entry_points())
             // // debug
             // .service(test)
             // files server
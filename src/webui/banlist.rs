@@ -0,0 +1,252 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use actix_web::{post, web, Responder, Result};
+use retroshare_compat::services::banlist::{
+    BANLIST_ORIGIN_SELF, BANLIST_REASON_USER, BANLIST_TYPE_BLACKLIST,
+};
+use serde::Serialize;
+
+use crate::{
+    gen_webui_param_type, gen_webui_return_type,
+    model::{
+        services::banlist::{BanEntry, BanListCmd, BanListSettings},
+        DataCore,
+    },
+    webui::RetVal,
+};
+
+/// Accepts both plain addresses and addresses with port.
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+fn update_settings(state: &DataCore, f: impl FnOnce(&mut BanListSettings)) -> bool {
+    let store = state.get_service_data().banlist();
+    let mut settings = store.get_settings();
+    f(&mut settings);
+    store.send_cmd(BanListCmd::SetSettings(settings))
+}
+
+// struct BanListPeer
+// {
+// 	struct sockaddr_storage addr;
+// 	uint8_t masked_bytes;
+// 	uint32_t reason;
+// 	uint32_t level;
+// 	bool state;
+// 	int connect_attempts;
+// 	rstime_t mTs;
+// 	std::string comment;
+// };
+// the address is a plain string, `expires` is not part of RS
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct BanListPeer {
+    addr: String,
+    masked_bytes: u8,
+    reason: u32,
+    level: u32,
+    state: bool,
+    mTs: u64,
+    comment: String,
+    expires: Option<u64>,
+}
+
+impl From<BanEntry> for BanListPeer {
+    fn from(entry: BanEntry) -> Self {
+        BanListPeer {
+            addr: entry.addr.to_string(),
+            masked_bytes: entry.masked_bytes,
+            reason: entry.reason,
+            level: entry.origin,
+            state: true,
+            mTs: entry.added,
+            comment: entry.comment,
+            expires: entry.expires,
+        }
+    }
+}
+
+// rsBanList/getBannedIps
+// virtual void getBannedIps(std::list<BanListPeer>& list) =0;
+// includes the lists our friends shared with us
+gen_webui_return_type!(GetBannedIpsRet, list, Vec<BanListPeer>);
+#[post("/getBannedIps")]
+pub async fn rs_ban_list_get_banned_ips(state: web::Data<Arc<DataCore>>) -> Result<impl Responder> {
+    let store = state.get_service_data().banlist();
+    let mut list: Vec<BanListPeer> = store.get_own().into_iter().map(Into::into).collect();
+    list.extend(
+        store
+            .get_friends()
+            .into_values()
+            .flatten()
+            .map(BanListPeer::from),
+    );
+
+    Ok(web::Json(GetBannedIpsRet { retval: true, list }))
+}
+
+// rsBanList/addIpRange
+// virtual bool addIpRange(const sockaddr_storage& addr, int masked_bytes, uint32_t list_type, const std::string& comment) =0;
+// extended by an optional `duration` (in seconds) after which the ban expires, only blacklisting is supported
+gen_webui_param_type!(
+    AddIpRange,
+    addr: String,
+    masked_bytes: u8,
+    list_type: u32,
+    comment: String,
+    duration: Option<u64>
+);
+#[post("/addIpRange")]
+pub async fn rs_ban_list_add_ip_range(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<AddIpRange>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let retval = match parse_addr(&params.addr) {
+        Some(addr) if params.list_type == BANLIST_TYPE_BLACKLIST && params.masked_bytes <= 2 => {
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let entry = BanEntry {
+                addr: BanEntry::masked(&addr, params.masked_bytes),
+                masked_bytes: params.masked_bytes,
+                reason: BANLIST_REASON_USER,
+                origin: BANLIST_ORIGIN_SELF,
+                comment: params.comment,
+                added: now,
+                expires: params.duration.map(|duration| now + duration),
+            };
+            state
+                .get_service_data()
+                .banlist()
+                .send_cmd(BanListCmd::Ban(entry))
+        }
+        _ => false,
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/removeIpRange
+// virtual bool removeIpRange(const sockaddr_storage& addr, int masked_bytes, uint32_t list_type) =0;
+gen_webui_param_type!(RemoveIpRange, addr: String, masked_bytes: u8, list_type: u32);
+#[post("/removeIpRange")]
+pub async fn rs_ban_list_remove_ip_range(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<RemoveIpRange>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let retval = match parse_addr(&params.addr) {
+        Some(addr) if params.list_type == BANLIST_TYPE_BLACKLIST => state
+            .get_service_data()
+            .banlist()
+            .send_cmd(BanListCmd::Unban {
+                addr: BanEntry::masked(&addr, params.masked_bytes),
+                masked_bytes: params.masked_bytes,
+            }),
+        _ => false,
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/isAddressAccepted
+// virtual bool isAddressAccepted(const sockaddr_storage& addr, uint32_t checking_flags, uint32_t *check_result=NULL) =0;
+// without checking flags, always checks all enabled lists
+gen_webui_param_type!(IsAddressAccepted, addr: String);
+#[post("/isAddressAccepted")]
+pub async fn rs_ban_list_is_address_accepted(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<IsAddressAccepted>,
+) -> Result<impl Responder> {
+    let retval = parse_addr(&params.0.addr).is_some_and(|addr| {
+        state
+            .get_service_data()
+            .banlist()
+            .is_banned(&addr)
+            .is_none()
+    });
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/enableIPFiltering
+// virtual void enableIPFiltering(bool b) =0;
+gen_webui_param_type!(Enable, b: bool);
+#[post("/enableIPFiltering")]
+pub async fn rs_ban_list_enable_ip_filtering(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<Enable>,
+) -> Result<impl Responder> {
+    let retval = update_settings(&state, |settings| settings.enabled = params.0.b);
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/ipFilteringEnabled
+// virtual bool ipFilteringEnabled() =0;
+#[post("/ipFilteringEnabled")]
+pub async fn rs_ban_list_ip_filtering_enabled(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let retval = state.get_service_data().banlist().get_settings().enabled;
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/enableIPsFromFriends
+// virtual void enableIPsFromFriends(bool b) =0;
+#[post("/enableIPsFromFriends")]
+pub async fn rs_ban_list_enable_ips_from_friends(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<Enable>,
+) -> Result<impl Responder> {
+    let retval = update_settings(&state, |settings| settings.use_friends = params.0.b);
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/IPsFromFriendsEnabled
+// virtual bool IPsFromFriendsEnabled() =0;
+#[post("/IPsFromFriendsEnabled")]
+pub async fn rs_ban_list_ips_from_friends_enabled(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let retval = state
+        .get_service_data()
+        .banlist()
+        .get_settings()
+        .use_friends;
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsBanList/enableSharing
+// not part of RS, RS always shares its own list
+#[post("/enableSharing")]
+pub async fn rs_ban_list_enable_sharing(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<Enable>,
+) -> Result<impl Responder> {
+    let retval = update_settings(&state, |settings| settings.share = params.0.b);
+    Ok(web::Json(RetVal { retval }))
+}
+
+pub fn get_entry_points() -> actix_web::Scope {
+    web::scope("/rsBanList")
+        .service(rs_ban_list_get_banned_ips)
+        .service(rs_ban_list_add_ip_range)
+        .service(rs_ban_list_remove_ip_range)
+        .service(rs_ban_list_is_address_accepted)
+        .service(rs_ban_list_enable_ip_filtering)
+        .service(rs_ban_list_ip_filtering_enabled)
+        .service(rs_ban_list_enable_ips_from_friends)
+        .service(rs_ban_list_ips_from_friends_enabled)
+        .service(rs_ban_list_enable_sharing)
+}
@@ -0,0 +1,281 @@
+use std::sync::Arc;
+
+use actix_web::{post, web, Responder, Result};
+use retroshare_compat::{basics::SslIdHex, config::PeerBandwidthLimits, webui::XInt64};
+use serde::Serialize;
+
+use crate::{
+    channel::{self, ChannelStats},
+    gen_webui_param_type,
+    model::{
+        services::bwctrl::{BwCtrlCmd, PeerBandwidth, TrafficStats},
+        DataCore,
+    },
+    webui::RetVal,
+};
+
+fn to_kbs(rate: Option<u32>) -> f32 {
+    rate.map(|rate| rate as f32 / 1024.0).unwrap_or_default()
+}
+
+// class RsConfigDataRates : RsSerializable
+// {
+// 	/* all in kB/s */
+// 	float mRateIn;
+// 	float mRateMaxIn;
+// 	float mAllocIn;
+// 	rstime_t mAllocTs;
+// 	float mRateOut;
+// 	float mRateMaxOut;
+// 	float mAllowedOut;
+// 	rstime_t mAllowedTs;
+// 	int mQueueIn;
+// 	int mQueueOut;
+// };
+// unlimited rates are 0
+#[allow(non_snake_case)]
+#[derive(Serialize, Default)]
+pub struct RsConfigDataRates {
+    mRateIn: f32,
+    mRateMaxIn: f32,
+    mAllocIn: f32,
+    mAllocTs: XInt64<i64>,
+    mRateOut: f32,
+    mRateMaxOut: f32,
+    mAllowedOut: f32,
+    mAllowedTs: XInt64<i64>,
+    mQueueIn: i32,
+    mQueueOut: i32,
+}
+
+impl From<&PeerBandwidth> for RsConfigDataRates {
+    fn from(peer: &PeerBandwidth) -> Self {
+        RsConfigDataRates {
+            mRateIn: peer.rate_in / 1024.0,
+            mRateMaxIn: to_kbs(peer.allowed_in),
+            mAllocIn: to_kbs(peer.allowed_in),
+            mAllocTs: 0.into(),
+            mRateOut: peer.rate_out / 1024.0,
+            mRateMaxOut: to_kbs(peer.send_rate),
+            mAllowedOut: to_kbs(peer.allowed_out),
+            mAllowedTs: 0.into(),
+            mQueueIn: 0,
+            mQueueOut: 0,
+        }
+    }
+}
+
+// rsConfig/GetMaxDataRates
+// virtual int GetMaxDataRates( int &inKb, int &outKb) = 0;
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct GetMaxDataRatesRet {
+    retval: i32,
+    inKb: i32,
+    outKb: i32,
+}
+#[post("/GetMaxDataRates")]
+pub async fn rs_config_get_max_data_rates(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let rates = state.get_service_data().bwctrl().get_max_rates();
+
+    Ok(web::Json(GetMaxDataRatesRet {
+        retval: 1,
+        inKb: rates.max_dl_rate_kbs as i32,
+        outKb: rates.max_up_rate_kbs as i32,
+    }))
+}
+
+// rsConfig/SetMaxDataRates
+// virtual int SetMaxDataRates( int downKb, int upKb ) = 0;
+// 0 means unlimited
+gen_webui_param_type!(
+    SetMaxDataRates,
+    #[serde(rename = "downKb")]
+    down_kb: u32,
+    #[serde(rename = "upKb")]
+    up_kb: u32
+);
+#[post("/SetMaxDataRates")]
+pub async fn rs_config_set_max_data_rates(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetMaxDataRates>,
+) -> Result<impl Responder> {
+    let sent = state
+        .get_service_data()
+        .bwctrl()
+        .send_cmd(BwCtrlCmd::SetMaxRates(PeerBandwidthLimits {
+            max_up_rate_kbs: params.0.up_kb,
+            max_dl_rate_kbs: params.0.down_kb,
+        }));
+
+    Ok(web::Json(RetVal {
+        retval: sent as i32,
+    }))
+}
+
+// rsConfig/getTotalBandwidthRates
+// virtual int getTotalBandwidthRates(RsConfigDataRates &rates) = 0;
+#[derive(Serialize)]
+pub struct GetTotalBandwidthRatesRet {
+    retval: i32,
+    rates: RsConfigDataRates,
+}
+#[post("/getTotalBandwidthRates")]
+pub async fn rs_config_get_total_bandwidth_rates(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let store = state.get_service_data().bwctrl();
+    let max_rates = store.get_max_rates();
+
+    let mut rates = RsConfigDataRates {
+        mRateMaxIn: max_rates.max_dl_rate_kbs as f32,
+        mRateMaxOut: max_rates.max_up_rate_kbs as f32,
+        ..Default::default()
+    };
+    for peer in store.get_peers().values() {
+        let peer: RsConfigDataRates = peer.into();
+        rates.mRateIn += peer.mRateIn;
+        rates.mAllocIn += peer.mAllocIn;
+        rates.mRateOut += peer.mRateOut;
+        rates.mAllowedOut += peer.mAllowedOut;
+    }
+
+    Ok(web::Json(GetTotalBandwidthRatesRet { retval: 1, rates }))
+}
+
+// rsConfig/getAllBandwidthRates
+// virtual int getAllBandwidthRates(std::map<RsPeerId, RsConfigDataRates> &ratemap) = 0;
+#[derive(Serialize)]
+pub struct RateMapEntry {
+    key: SslIdHex,
+    value: RsConfigDataRates,
+}
+#[derive(Serialize)]
+pub struct GetAllBandwidthRatesRet {
+    retval: i32,
+    ratemap: Vec<RateMapEntry>,
+}
+#[post("/getAllBandwidthRates")]
+pub async fn rs_config_get_all_bandwidth_rates(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let ratemap = state
+        .get_service_data()
+        .bwctrl()
+        .get_peers()
+        .iter()
+        .map(|(peer_id, peer)| RateMapEntry {
+            key: peer_id.to_owned().into(),
+            value: peer.into(),
+        })
+        .collect();
+
+    Ok(web::Json(GetAllBandwidthRatesRet { retval: 1, ratemap }))
+}
+
+// rsConfig/getTrafficStats
+// not part of RS (which reports single items with getTrafficInfo), total traffic per connected peer and per service
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct TrafficStatsEntry<K> {
+    key: K,
+    bytesIn: XInt64<u64>,
+    bytesOut: XInt64<u64>,
+    packetsIn: XInt64<u64>,
+    packetsOut: XInt64<u64>,
+}
+
+impl<K> TrafficStatsEntry<K> {
+    fn new(key: K, stats: &TrafficStats) -> Self {
+        TrafficStatsEntry {
+            key,
+            bytesIn: stats.bytes_in.into(),
+            bytesOut: stats.bytes_out.into(),
+            packetsIn: stats.packets_in.into(),
+            packetsOut: stats.packets_out.into(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetTrafficStatsRet {
+    retval: bool,
+    peers: Vec<TrafficStatsEntry<SslIdHex>>,
+    /// RS's 16 bit service ids
+    services: Vec<TrafficStatsEntry<u16>>,
+}
+#[post("/getTrafficStats")]
+pub async fn rs_config_get_traffic_stats(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let store = state.get_service_data().bwctrl();
+
+    Ok(web::Json(GetTrafficStatsRet {
+        retval: true,
+        peers: store
+            .get_peers()
+            .iter()
+            .map(|(peer_id, peer)| TrafficStatsEntry::new(peer_id.to_owned().into(), &peer.stats))
+            .collect(),
+        services: store
+            .get_services()
+            .iter()
+            .map(|(service, stats)| TrafficStatsEntry::new(u16::from(*service), stats))
+            .collect(),
+    }))
+}
+
+// rsConfig/getQueueStats
+// not part of RS, queue depths of the internal channels (to see which component is lagging)
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct QueueStats {
+    name: String,
+    policy: String,
+    capacity: usize,
+    len: usize,
+    maxLen: usize,
+    sent: XInt64<u64>,
+    dropped: XInt64<u64>,
+    closed: bool,
+}
+
+impl From<ChannelStats> for QueueStats {
+    fn from(stats: ChannelStats) -> Self {
+        QueueStats {
+            name: stats.name,
+            policy: format!("{:?}", stats.policy),
+            capacity: stats.capacity,
+            len: stats.len,
+            maxLen: stats.max_len,
+            sent: stats.sent.into(),
+            dropped: stats.dropped.into(),
+            closed: stats.closed,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetQueueStatsRet {
+    retval: bool,
+    queues: Vec<QueueStats>,
+}
+#[post("/getQueueStats")]
+pub async fn rs_config_get_queue_stats() -> Result<impl Responder> {
+    Ok(web::Json(GetQueueStatsRet {
+        retval: true,
+        queues: channel::stats().into_iter().map(Into::into).collect(),
+    }))
+}
+
+pub fn get_entry_points() -> actix_web::Scope {
+    web::scope("/rsConfig")
+        .service(rs_config_get_max_data_rates)
+        .service(rs_config_set_max_data_rates)
+        .service(rs_config_get_total_bandwidth_rates)
+        .service(rs_config_get_all_bandwidth_rates)
+        .service(rs_config_get_traffic_stats)
+        .service(rs_config_get_queue_stats)
+}
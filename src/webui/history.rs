@@ -0,0 +1,181 @@
+use std::{sync::Arc, time::Duration};
+
+use actix_web::{post, web, Responder, Result};
+use retroshare_compat::services::chat::ChatId;
+
+use crate::{
+    gen_webui_param_type, gen_webui_return_type,
+    model::{
+        services::chat_history::{HistoryMsg, RetentionLimits},
+        DataCore,
+    },
+    webui::RetVal,
+};
+
+// rsHistory/getMessages
+// virtual bool getMessages(const ChatId &chatPeerId, std::list<HistoryMsg> &msgs, uint32_t loadCount) = 0;
+// extended by an offset for paging (from the most recent message)
+gen_webui_param_type!(
+    GetMessages,
+    chat_peer_id: ChatId,
+    load_count: u32,
+    offset: Option<u32>
+);
+gen_webui_return_type!(GetMessagesRet, msgs, Vec<HistoryMsg>);
+#[post("/getMessages")]
+pub async fn rs_history_get_messages(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetMessages>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let lock = state.get_service_data().chat().history.lock().await;
+    let msgs = lock.as_ref().and_then(|history| {
+        history
+            .get_messages(
+                &params.chat_peer_id,
+                params.load_count,
+                params.offset.unwrap_or_default(),
+            )
+            .ok()
+    });
+
+    Ok(web::Json(GetMessagesRet {
+        retval: msgs.is_some(),
+        msgs: msgs.unwrap_or_default(),
+    }))
+}
+
+// rsHistory/searchMessages
+// not part of RS, full text search, limited to one chat when `chat_peer_id` is set
+gen_webui_param_type!(
+    SearchMessages,
+    chat_peer_id: Option<ChatId>,
+    query: String,
+    load_count: u32,
+    offset: Option<u32>
+);
+#[post("/searchMessages")]
+pub async fn rs_history_search_messages(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SearchMessages>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let lock = state.get_service_data().chat().history.lock().await;
+    let msgs = lock.as_ref().and_then(|history| {
+        history
+            .search(
+                params.chat_peer_id.as_ref(),
+                &params.query,
+                params.load_count,
+                params.offset.unwrap_or_default(),
+            )
+            .ok()
+    });
+
+    Ok(web::Json(GetMessagesRet {
+        retval: msgs.is_some(),
+        msgs: msgs.unwrap_or_default(),
+    }))
+}
+
+// rsHistory/removeMessages
+// virtual void removeMessages(const std::list<uint32_t> &msgIds) = 0;
+gen_webui_param_type!(RemoveMessages, msg_ids: Vec<i64>);
+#[post("/removeMessages")]
+pub async fn rs_history_remove_messages(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<RemoveMessages>,
+) -> Result<impl Responder> {
+    let lock = state.get_service_data().chat().history.lock().await;
+    let retval = lock
+        .as_ref()
+        .map(|history| history.remove_messages(&params.0.msg_ids).is_ok())
+        .unwrap_or_default();
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsHistory/clear
+// virtual void clear(const ChatId &chatPeerId) = 0;
+gen_webui_param_type!(Clear, chat_peer_id: ChatId);
+#[post("/clear")]
+pub async fn rs_history_clear(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<Clear>,
+) -> Result<impl Responder> {
+    let lock = state.get_service_data().chat().history.lock().await;
+    let retval = lock
+        .as_ref()
+        .map(|history| history.clear(&params.0.chat_peer_id).is_ok())
+        .unwrap_or_default();
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsHistory/getChatRetention
+// not part of RS, RS only has global limits (`getSaveCount` and `getMaxStorageDuration`)
+gen_webui_param_type!(GetChatRetention, chat_peer_id: ChatId);
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatRetention {
+    retval: bool,
+    save_count: u32,
+    /// in seconds
+    max_storage_duration: u64,
+}
+#[post("/getChatRetention")]
+pub async fn rs_history_get_chat_retention(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetChatRetention>,
+) -> Result<impl Responder> {
+    let lock = state.get_service_data().chat().history.lock().await;
+    let limits = lock
+        .as_ref()
+        .and_then(|history| history.get_retention(&params.0.chat_peer_id).ok());
+
+    Ok(web::Json(ChatRetention {
+        retval: limits.is_some(),
+        save_count: limits.unwrap_or_default().max_messages,
+        max_storage_duration: limits.unwrap_or_default().max_age.as_secs(),
+    }))
+}
+
+// rsHistory/setChatRetention
+// not part of RS, 0 means unlimited
+gen_webui_param_type!(
+    SetChatRetention,
+    chat_peer_id: ChatId,
+    save_count: u32,
+    max_storage_duration: u64
+);
+#[post("/setChatRetention")]
+pub async fn rs_history_set_chat_retention(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetChatRetention>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let limits = RetentionLimits {
+        max_messages: params.save_count,
+        max_age: Duration::from_secs(params.max_storage_duration),
+    };
+
+    let lock = state.get_service_data().chat().history.lock().await;
+    let retval = lock
+        .as_ref()
+        .map(|history| history.set_retention(&params.chat_peer_id, limits).is_ok())
+        .unwrap_or_default();
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+pub fn get_entry_points() -> actix_web::Scope {
+    web::scope("/rsHistory")
+        .service(rs_history_get_messages)
+        .service(rs_history_search_messages)
+        .service(rs_history_remove_messages)
+        .service(rs_history_clear)
+        .service(rs_history_get_chat_retention)
+        .service(rs_history_set_chat_retention)
+}
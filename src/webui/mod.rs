@@ -3,9 +3,13 @@ use serde::Serialize;
 #[cfg(feature = "webui_actix")]
 pub mod actix;
 
+mod banlist;
+mod config;
+mod history;
 pub(self) mod identity;
 pub(self) mod msgs;
 pub(self) mod peers;
+mod service_control;
 
 #[derive(Serialize)]
 pub struct RetVal<S> {
@@ -14,10 +18,10 @@ pub struct RetVal<S> {
 
 #[macro_export]
 macro_rules! gen_webui_param_type {
-    ($name:ident, $($inner:ident: $ty:ty),+) => {
+    ($name:ident, $($(#[$meta:meta])* $inner:ident: $ty:ty),+) => {
         #[derive(serde::Deserialize)]
         pub struct $name {
-            $($inner: $ty,)+
+            $($(#[$meta])* $inner: $ty,)+
         }
     };
 }
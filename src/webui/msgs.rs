@@ -1,18 +1,41 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::SystemTime};
 
 use actix_web::{post, web, Responder, Result};
 use retroshare_compat::{
-    basics::GxsIdHex,
-    services::chat::{ChatId, ChatLobbyId},
+    basics::{DistantChatPeerId, DistantChatPeerIdHex, GxsIdHex, GxsTunnelId, SslIdHex},
+    events::{EventType, MailStatusEventCode, MailTagEventCode},
+    services::{
+        chat::{ChatId, ChatLobbyFlags, ChatLobbyId},
+        msg::{MsgItem, MsgItemFlags},
+    },
     webui::{
-        chat::{ChatLobbyIdWrapped, ChatLobbyInfo, VisibleChatLobbyRecord},
+        chat::{
+            ChatLobbyIdWrapped, ChatLobbyInfo, ChatLobbyInvite, ChatLobbyModeration,
+            ChatLobbySubscription, DistantChatPeerInfo, VisibleChatLobbyRecord,
+            DISTANT_CHAT_STATUS_CAN_TALK, DISTANT_CHAT_STATUS_REMOTELY_CLOSED,
+            DISTANT_CHAT_STATUS_TUNNEL_DN, DISTANT_CHAT_STATUS_UNKNOWN,
+        },
+        mail::{
+            BoxName, MessageInfo, MsgInfoSummary, MsgTagInfo, MsgTagType, MsgTagTypeEntry,
+            MsgTagTypeValue,
+        },
         XInt64,
     },
 };
+use tokio::sync::oneshot;
 
 use crate::{
     gen_webui_param_type, gen_webui_return_type,
-    model::{services::chat::ChatCmd, DataCore},
+    model::{
+        services::{
+            chat::{to_chat_lobby_invite, ChatCmd, MAX_AVATAR_SIZE},
+            gxs_tunnel::GxsTunnelStatus,
+            mail::MailCmd,
+            mailbox::{Mailbox, MessageCount},
+        },
+        DataCore,
+    },
+    services::gxs_tunnel::make_tunnel_id,
     webui::RetVal,
 };
 
@@ -66,8 +89,8 @@ pub async fn rs_msgs_get_list_of_nearby_chat_lobbies(
         .lobbies
         .read()
         .await
-        .iter()
-        .map(|(_, lobby)| lobby.into())
+        .values()
+        .map(|lobby| lobby.into())
         .collect();
 
     Ok(web::Json(GetListOfNearbyChatLobbies {
@@ -129,7 +152,33 @@ pub async fn rs_msgs_send_chat(
 
     let lock = state.get_service_data().chat().cmd.read().await;
     match &*lock {
-        Some(tx) => _ = tx.send(ChatCmd::SendMessage(id, msg)),
+        Some(tx) => _ = tx.send(ChatCmd::SendMessage(id, msg)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/sendStatusString
+// /**
+//  * @brief sendStatusString send a status string
+//  * @jsonapi{development}
+//  * @param[in] id chat id to send the status string to
+//  * @param[in] status_string status string
+//  */
+// virtual void sendStatusString(const ChatId &id, const std::string &status_string) = 0;
+gen_webui_param_type!(SendStatusString, id: ChatId, status_string: String);
+#[post("/sendStatusString")]
+pub async fn rs_msgs_send_status_string(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SendStatusString>,
+) -> Result<impl Responder> {
+    let id = params.0.id;
+    let status_string = params.0.status_string;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::SendStatus(id, status_string)).await,
         None => return Ok(web::Json(RetVal { retval: false })),
     }
 
@@ -145,10 +194,13 @@ pub async fn rs_msgs_send_chat(
 //  * @return true on success
 //  */
 //  virtual bool joinVisibleChatLobby(const ChatLobbyId &lobby_id, const RsGxsId &own_id) = 0 ;
+// extended by the (optional) subscription settings, joining an already joined lobby changes the identity
 gen_webui_param_type!(
     JoinVisibleChatLobby,
     lobby_id: ChatLobbyId,
-    own_id: GxsIdHex
+    own_id: GxsIdHex,
+    auto_subscribe: Option<bool>,
+    notify: Option<bool>
 );
 #[post("/joinVisibleChatLobby")]
 pub async fn rs_msgs_join_visible_chat_lobby(
@@ -160,7 +212,18 @@ pub async fn rs_msgs_join_visible_chat_lobby(
 
     let lock = state.get_service_data().chat().cmd.read().await;
     match &*lock {
-        Some(tx) => _ = tx.send(ChatCmd::JoinLobby(lobby, gxs_id)),
+        Some(tx) => {
+            _ = tx.send(ChatCmd::JoinLobby(lobby, gxs_id)).await;
+            if params.0.auto_subscribe.is_some() || params.0.notify.is_some() {
+                _ = tx
+                    .send(ChatCmd::SetLobbySubscription {
+                        lobby_id: lobby,
+                        auto_join: params.0.auto_subscribe,
+                        notify: params.0.notify,
+                    })
+                    .await;
+            }
+        }
         None => return Ok(web::Json(RetVal { retval: false })),
     }
 
@@ -184,19 +247,1234 @@ pub async fn rs_msgs_unsubscribe_chat_lobby(
 
     let lock = state.get_service_data().chat().cmd.read().await;
     match &*lock {
-        Some(tx) => _ = tx.send(ChatCmd::LeaveLobby(lobby)),
+        Some(tx) => _ = tx.send(ChatCmd::LeaveLobby(lobby)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/setLobbyAutoSubscribe
+// /**
+//  * @brief setLobbyAutoSubscribe Set lobby auto subscription
+//  * @jsonapi{development}
+//  * @param[in] lobby_id lobby to auto (un)subscribe
+//  * @param[in] autoSubscribe set value for auto subscribe
+//  */
+// virtual void setLobbyAutoSubscribe(const ChatLobbyId &lobby_id, const bool autoSubscribe) = 0 ;
+#[derive(serde::Deserialize)]
+pub struct SetLobbyAutoSubscribe {
+    lobby_id: ChatLobbyId,
+    #[serde(rename = "autoSubscribe")]
+    auto_subscribe: bool,
+}
+#[post("/setLobbyAutoSubscribe")]
+pub async fn rs_msgs_set_lobby_auto_subscribe(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetLobbyAutoSubscribe>,
+) -> Result<impl Responder> {
+    let lobby = params.0.lobby_id;
+    let auto_subscribe = params.0.auto_subscribe;
+
+    let data = state.get_service_data().chat();
+    if !data.subscriptions.read().await.contains_key(&lobby) {
+        return Ok(web::Json(RetVal { retval: false }));
+    }
+
+    let lock = data.cmd.read().await;
+    match &*lock {
+        Some(tx) => {
+            _ = tx
+                .send(ChatCmd::SetLobbySubscription {
+                    lobby_id: lobby,
+                    auto_join: Some(auto_subscribe),
+                    notify: None,
+                })
+                .await
+        }
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/getLobbyAutoSubscribe
+// /**
+//  * @brief getLobbyAutoSubscribe get current value of auto subscribe
+//  * @jsonapi{development}
+//  * @param[in] lobby_id lobby to get value from
+//  * @return wether lobby has auto subscribe enabled or disabled
+//  */
+// virtual bool getLobbyAutoSubscribe(const ChatLobbyId &lobby_id) = 0 ;
+gen_webui_param_type!(GetLobbyAutoSubscribe, lobby_id: ChatLobbyId);
+#[post("/getLobbyAutoSubscribe")]
+pub async fn rs_msgs_get_lobby_auto_subscribe(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetLobbyAutoSubscribe>,
+) -> Result<impl Responder> {
+    let retval = state
+        .get_service_data()
+        .chat()
+        .subscriptions
+        .read()
+        .await
+        .get(&params.0.lobby_id)
+        .map(|subscription| subscription.auto_join)
+        .unwrap_or_default();
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsMsgs/getChatLobbySubscriptions
+// not part of RS, lists all subscribed lobbies with their settings
+gen_webui_return_type!(
+    GetChatLobbySubscriptions,
+    subscriptions,
+    Vec<ChatLobbySubscription>
+);
+#[post("/getChatLobbySubscriptions")]
+pub async fn rs_msgs_get_chat_lobby_subscriptions(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let subscriptions = state
+        .get_service_data()
+        .chat()
+        .subscriptions
+        .read()
+        .await
+        .iter()
+        .map(|(&lobby_id, subscription)| subscription.to_chat_lobby_subscription(lobby_id))
+        .collect();
+
+    Ok(web::Json(GetChatLobbySubscriptions {
+        retval: true,
+        subscriptions,
+    }))
+}
+
+// rsMsgs/setChatLobbyNotify
+// not part of RS
+gen_webui_param_type!(SetChatLobbyNotify, lobby_id: ChatLobbyId, notify: bool);
+#[post("/setChatLobbyNotify")]
+pub async fn rs_msgs_set_chat_lobby_notify(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetChatLobbyNotify>,
+) -> Result<impl Responder> {
+    let lobby = params.0.lobby_id;
+    let notify = params.0.notify;
+
+    let data = state.get_service_data().chat();
+    if !data.subscriptions.read().await.contains_key(&lobby) {
+        return Ok(web::Json(RetVal { retval: false }));
+    }
+
+    let lock = data.cmd.read().await;
+    match &*lock {
+        Some(tx) => {
+            _ = tx
+                .send(ChatCmd::SetLobbySubscription {
+                    lobby_id: lobby,
+                    auto_join: None,
+                    notify: Some(notify),
+                })
+                .await
+        }
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/createChatLobby
+// /**
+//  * @brief createChatLobby create a new chat lobby
+//  * @jsonapi{development}
+//  * @param[in] lobby_name lobby name
+//  * @param[in] lobby_identity chat id to use for new lobby
+//  * @param[in] lobby_topic lobby toic
+//  * @param[in] invited_friends list of friends to invite
+//  * @param[in] lobby_privacy_type flag for new chat lobby
+//  * @return chat id of new lobby
+//  */
+// virtual ChatLobbyId createChatLobby(const std::string &lobby_name, const RsGxsId &lobby_identity, const std::string &lobby_topic, const std::set<RsPeerId> &invited_friends, ChatLobbyFlags lobby_privacy_type) = 0 ;
+gen_webui_param_type!(
+    CreateChatLobby,
+    lobby_name: String,
+    lobby_identity: GxsIdHex,
+    lobby_topic: String,
+    invited_friends: Vec<SslIdHex>,
+    lobby_privacy_type: ChatLobbyFlags
+);
+#[post("/createChatLobby")]
+pub async fn rs_msgs_create_chat_lobby(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<CreateChatLobby>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let (tx, rx) = oneshot::channel();
+
+    {
+        let lock = state.get_service_data().chat().cmd.read().await;
+        match &*lock {
+            Some(cmd) => {
+                _ = cmd
+                    .send(ChatCmd::CreateLobby {
+                        name: params.lobby_name,
+                        topic: params.lobby_topic,
+                        flags: params.lobby_privacy_type,
+                        gxs_id: *params.lobby_identity,
+                        invited: params.invited_friends.iter().map(|peer| **peer).collect(),
+                        tx,
+                    })
+                    .await
+            }
+            None => return Ok(web::Json(RetVal { retval: 0.into() })),
+        }
+    }
+
+    let lobby_id: XInt64<u64> = rx.await.unwrap_or_default().into();
+    Ok(web::Json(RetVal { retval: lobby_id }))
+}
+
+// rsMsgs/invitePeerToLobby
+// /**
+//  * @brief invitePeerToLobby invite a peer to join a lobby
+//  * @jsonapi{development}
+//  * @param[in] lobby_id lobby it to invite into
+//  * @param[in] peer_id peer to invite
+//  */
+// virtual void invitePeerToLobby(const ChatLobbyId &lobby_id, const RsPeerId &peer_id) = 0;
+gen_webui_param_type!(
+    InvitePeerToLobby,
+    lobby_id: ChatLobbyId,
+    peer_id: SslIdHex
+);
+#[post("/invitePeerToLobby")]
+pub async fn rs_msgs_invite_peer_to_lobby(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<InvitePeerToLobby>,
+) -> Result<impl Responder> {
+    let lobby = params.0.lobby_id;
+    let peer_id = *params.0.peer_id;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::InviteToLobby(lobby, peer_id)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/getPendingChatLobbyInvites
+// /**
+//  * @brief getPendingChatLobbyInvites get a list of all pending chat lobby invites
+//  * @jsonapi{development}
+//  * @param[out] invites list of all pending chat lobby invites
+//  */
+// virtual void getPendingChatLobbyInvites(std::list<ChatLobbyInvite> &invites) = 0;
+gen_webui_return_type!(GetPendingChatLobbyInvites, invites, Vec<ChatLobbyInvite>);
+#[post("/getPendingChatLobbyInvites")]
+pub async fn rs_msgs_get_pending_chat_lobby_invites(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let invites = state
+        .get_service_data()
+        .chat()
+        .invites
+        .read()
+        .await
+        .values()
+        .map(|(peer_id, invite)| to_chat_lobby_invite(peer_id, invite))
+        .collect();
+
+    Ok(web::Json(GetPendingChatLobbyInvites {
+        retval: true,
+        invites,
+    }))
+}
+
+// rsMsgs/acceptLobbyInvite
+// /**
+//  * @brief acceptLobbyInvite accept a chat invite
+//  * @jsonapi{development}
+//  * @param[in] id chat lobby id you were invited into and you want to join
+//  * @param[in] identity chat identity to use
+//  * @return true on success
+//  */
+// virtual bool acceptLobbyInvite(const ChatLobbyId &id, const RsGxsId &identity) = 0 ;
+gen_webui_param_type!(AcceptLobbyInvite, id: ChatLobbyId, identity: GxsIdHex);
+#[post("/acceptLobbyInvite")]
+pub async fn rs_msgs_accept_lobby_invite(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<AcceptLobbyInvite>,
+) -> Result<impl Responder> {
+    let lobby = params.0.id;
+    let gxs_id = *params.0.identity;
+
+    let data = state.get_service_data().chat();
+    if !data.invites.read().await.contains_key(&lobby) {
+        return Ok(web::Json(RetVal { retval: false }));
+    }
+
+    let lock = data.cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::AcceptLobbyInvite(lobby, gxs_id)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/denyLobbyInvite
+// /**
+//  * @brief denyLobbyInvite deny a chat lobby invite
+//  * @jsonapi{development}
+//  * @param[in] id of the lobby you were invited to
+//  */
+// virtual void denyLobbyInvite(const ChatLobbyId &id) = 0 ;
+gen_webui_param_type!(DenyLobbyInvite, id: ChatLobbyId);
+#[post("/denyLobbyInvite")]
+pub async fn rs_msgs_deny_lobby_invite(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<DenyLobbyInvite>,
+) -> Result<impl Responder> {
+    let lobby = params.0.id;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::DenyLobbyInvite(lobby)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/getAvatarData
+// virtual void getAvatarData(const RsPeerId& pid,unsigned char *& data,int& size) = 0 ;
+// not exposed by RS's json api, the jpeg data is base64 encoded
+// when the avatar is unknown, it is requested from the friend and `retval` is false
+gen_webui_param_type!(GetAvatarData, peer_id: SslIdHex);
+gen_webui_return_type!(GetAvatarDataRet, data, String);
+#[post("/getAvatarData")]
+pub async fn rs_msgs_get_avatar_data(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetAvatarData>,
+) -> Result<impl Responder> {
+    let peer_id = *params.0.peer_id;
+
+    let data = state.get_service_data().chat();
+    let avatar = data
+        .avatars
+        .read()
+        .await
+        .get(&peer_id)
+        .map(|avatar| avatar.data.to_owned())
+        .unwrap_or_default();
+
+    if avatar.is_empty() {
+        if let Some(tx) = &*data.cmd.read().await {
+            _ = tx.send(ChatCmd::RequestAvatar(peer_id)).await;
+        }
+    }
+
+    Ok(web::Json(GetAvatarDataRet {
+        retval: !avatar.is_empty(),
+        data: base64::encode(avatar),
+    }))
+}
+
+// rsMsgs/getOwnAvatarData
+// virtual void getOwnAvatarData(unsigned char *& data,int& size) = 0 ;
+// not exposed by RS's json api, the jpeg data is base64 encoded
+#[post("/getOwnAvatarData")]
+pub async fn rs_msgs_get_own_avatar_data(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let avatar = state
+        .get_service_data()
+        .chat()
+        .own_avatar
+        .read()
+        .await
+        .to_owned();
+
+    Ok(web::Json(GetAvatarDataRet {
+        retval: !avatar.is_empty(),
+        data: base64::encode(avatar),
+    }))
+}
+
+// rsMsgs/setOwnAvatarData
+// virtual void setOwnAvatarData(const unsigned char *data,int size) = 0 ;
+// not exposed by RS's json api, the jpeg data is base64 encoded, an empty string removes the avatar
+gen_webui_param_type!(SetOwnAvatarData, data: String);
+#[post("/setOwnAvatarData")]
+pub async fn rs_msgs_set_own_avatar_data(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetOwnAvatarData>,
+) -> Result<impl Responder> {
+    let avatar = match base64::decode(&params.0.data) {
+        Ok(avatar) if avatar.len() <= MAX_AVATAR_SIZE => avatar,
+        _ => return Ok(web::Json(RetVal { retval: false })),
+    };
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::SetOwnAvatar(avatar)).await,
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/getLobbyModeration
+// not part of RS, returns ban and mute lists as well as the reputation threshold
+gen_webui_return_type!(GetLobbyModeration, moderation, ChatLobbyModeration);
+#[post("/getLobbyModeration")]
+pub async fn rs_msgs_get_lobby_moderation(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let moderation = state
+        .get_service_data()
+        .chat()
+        .moderation
+        .read()
+        .await
+        .to_chat_lobby_moderation();
+
+    Ok(web::Json(GetLobbyModeration {
+        retval: true,
+        moderation,
+    }))
+}
+
+// rsMsgs/setLobbyIdentityBanned
+// not part of RS, bans from all lobbies when `lobby_id` is not set
+gen_webui_param_type!(
+    SetLobbyIdentityBanned,
+    lobby_id: Option<ChatLobbyId>,
+    gxs_id: GxsIdHex,
+    banned: bool
+);
+#[post("/setLobbyIdentityBanned")]
+pub async fn rs_msgs_set_lobby_identity_banned(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetLobbyIdentityBanned>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => {
+            _ = tx
+                .send(ChatCmd::SetBanned {
+                    lobby_id: params.lobby_id,
+                    gxs_id: *params.gxs_id,
+                    banned: params.banned,
+                })
+                .await
+        }
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/setLobbyIdentityMuted
+// not part of RS, messages of muted identities are still relayed but not shown
+gen_webui_param_type!(SetLobbyIdentityMuted, gxs_id: GxsIdHex, muted: bool);
+#[post("/setLobbyIdentityMuted")]
+pub async fn rs_msgs_set_lobby_identity_muted(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetLobbyIdentityMuted>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => {
+            _ = tx
+                .send(ChatCmd::SetMuted(*params.gxs_id, params.muted))
+                .await
+        }
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/setLobbyMinReputation
+// not part of RS, items from identities with a lower overall reputation score are dropped
+gen_webui_param_type!(SetLobbyMinReputation, min_reputation: i32);
+#[post("/setLobbyMinReputation")]
+pub async fn rs_msgs_set_lobby_min_reputation(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetLobbyMinReputation>,
+) -> Result<impl Responder> {
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => {
+            _ = tx
+                .send(ChatCmd::SetMinReputation(params.0.min_reputation))
+                .await
+        }
+        None => return Ok(web::Json(RetVal { retval: false })),
+    }
+
+    Ok(web::Json(RetVal { retval: true }))
+}
+
+// rsMsgs/initiateDistantChatConnexion
+// /**
+//  * @brief initiateDistantChatConnexion initiate a connexion for a distant chat
+//  * @jsonapi{development}
+//  * @param[in] to_pid RsGxsId to start the connection
+//  * @param[in] from_pid owned RsGxsId who start the connection
+//  * @param[out] pid distant chat id
+//  * @param[out] error_code if the connection can't be stablished
+//  * @param[in] notify notify remote that the connection is stablished
+//  * @return true on success. If you try to initate a connection already started it will return the pid of it.
+//  */
+// virtual bool initiateDistantChatConnexion(
+//         const RsGxsId& to_pid, const RsGxsId& from_pid,
+//         DistantChatPeerId& pid, uint32_t& error_code,
+//         bool notify = true ) = 0;
+gen_webui_param_type!(
+    InitiateDistantChatConnexion,
+    to_pid: GxsIdHex,
+    from_pid: GxsIdHex
+);
+gen_webui_return_type!(InitiateDistantChatConnexionRet, pid, DistantChatPeerIdHex);
+#[post("/initiateDistantChatConnexion")]
+pub async fn rs_msgs_initiate_distant_chat_connexion(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<InitiateDistantChatConnexion>,
+) -> Result<impl Responder> {
+    let to_id = *params.0.to_pid;
+    let from_id = *params.0.from_pid;
+
+    // the distant chat id is the tunnel id, which is known upfront
+    let pid = DistantChatPeerId::from(*make_tunnel_id(&from_id, &to_id));
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::InitiateDistantChat(to_id, from_id)).await,
+        None => {
+            return Ok(web::Json(InitiateDistantChatConnexionRet {
+                retval: false,
+                pid: Default::default(),
+            }))
+        }
+    }
+
+    Ok(web::Json(InitiateDistantChatConnexionRet {
+        retval: true,
+        pid: pid.into(),
+    }))
+}
+
+// rsMsgs/closeDistantChatConnexion
+// /**
+//  * @brief closeDistantChatConnexion
+//  * @jsonapi{development}
+//  * @param[in] pid distant chat id
+//  * @return true on success
+//  */
+// virtual bool closeDistantChatConnexion(const DistantChatPeerId& pid) = 0;
+gen_webui_param_type!(CloseDistantChatConnexion, pid: DistantChatPeerIdHex);
+#[post("/closeDistantChatConnexion")]
+pub async fn rs_msgs_close_distant_chat_connexion(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<CloseDistantChatConnexion>,
+) -> Result<impl Responder> {
+    let pid = *params.0.pid;
+
+    let lock = state.get_service_data().chat().cmd.read().await;
+    match &*lock {
+        Some(tx) => _ = tx.send(ChatCmd::CloseDistantChat(pid)).await,
         None => return Ok(web::Json(RetVal { retval: false })),
     }
 
     Ok(web::Json(RetVal { retval: true }))
 }
 
+// rsMsgs/getDistantChatStatus
+// /**
+//  * @brief getDistantChatStatus receives distant chat info to a given distant chat id
+//  * @jsonapi{development}
+//  * @param[in] pid distant chat id
+//  * @param[out] info distant chat info
+//  * @return true on success
+//  */
+// virtual bool getDistantChatStatus(const DistantChatPeerId& pid, DistantChatPeerInfo& info)=0;
+gen_webui_param_type!(GetDistantChatStatus, pid: DistantChatPeerIdHex);
+gen_webui_return_type!(GetDistantChatStatusRet, info, DistantChatPeerInfo);
+#[post("/getDistantChatStatus")]
+pub async fn rs_msgs_get_distant_chat_status(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetDistantChatStatus>,
+) -> Result<impl Responder> {
+    let pid = params.0.pid;
+
+    match state
+        .get_service_data()
+        .gxs_tunnel()
+        .get_tunnel_info(&GxsTunnelId::from(**pid))
+    {
+        Some(info) => Ok(web::Json(GetDistantChatStatusRet {
+            retval: true,
+            info: DistantChatPeerInfo {
+                to_id: info.remote_id.into(),
+                own_id: info.own_id.into(),
+                peer_id: pid,
+                status: match info.status {
+                    GxsTunnelStatus::TunnelDown => DISTANT_CHAT_STATUS_TUNNEL_DN,
+                    GxsTunnelStatus::CanTalk => DISTANT_CHAT_STATUS_CAN_TALK,
+                    GxsTunnelStatus::RemotelyClosed => DISTANT_CHAT_STATUS_REMOTELY_CLOSED,
+                },
+                pending_items: 0,
+            },
+        })),
+        None => Ok(web::Json(GetDistantChatStatusRet {
+            retval: false,
+            info: DistantChatPeerInfo {
+                peer_id: pid,
+                status: DISTANT_CHAT_STATUS_UNKNOWN,
+                ..Default::default()
+            },
+        })),
+    }
+}
+
+/// Runs `f` on the mailbox, `None` when the mailbox is not available or the query failed.
+async fn with_mailbox<T>(
+    state: &DataCore,
+    f: impl FnOnce(&Mailbox) -> rusqlite::Result<T>,
+) -> Option<T> {
+    let lock = state.get_service_data().mail().mailbox.lock().await;
+    lock.as_ref().and_then(|mailbox| f(mailbox).ok())
+}
+
+/// Sets or clears the flags of a message and notifies the web ui.
+async fn set_message_flags(
+    state: &DataCore,
+    msg_id: &str,
+    set: MsgItemFlags,
+    clear: MsgItemFlags,
+) -> bool {
+    let id = match msg_id.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return false,
+    };
+
+    let changed = with_mailbox(state, |mailbox| {
+        Ok(mailbox.set_flags(id, clear, false)? && mailbox.set_flags(id, set, true)?)
+    })
+    .await
+    .unwrap_or_default();
+
+    if changed {
+        state
+            .webui_send(EventType::MailStatus {
+                code: MailStatusEventCode::MessageChanged,
+                msg_ids: vec![msg_id.to_owned()],
+            })
+            .await;
+    }
+    changed
+}
+
+// rsMsgs/getMessageSummaries
+// /**
+//  * @brief getMessageSummaries
+//  * @jsonapi{development}
+//  * @param[in] box
+//  * @param[out] msgList
+//  * @return always true
+//  */
+// virtual bool getMessageSummaries(BoxName box,std::list<MsgInfoSummary> &msgList) = 0;
+// all boxes are returned when `box` is not set
+gen_webui_param_type!(GetMessageSummaries, r#box: Option<BoxName>);
+#[derive(serde::Serialize)]
+pub struct GetMessageSummariesRet {
+    retval: bool,
+    #[serde(rename = "msgList")]
+    msg_list: Vec<MsgInfoSummary>,
+}
+#[post("/getMessageSummaries")]
+pub async fn rs_msgs_get_message_summaries(
+    state: web::Data<Arc<DataCore>>,
+    params: Option<web::Json<GetMessageSummaries>>,
+) -> Result<impl Responder> {
+    let box_name = params.and_then(|params| params.0.r#box).unwrap_or_default();
+
+    let msgs = with_mailbox(&state, |mailbox| mailbox.get_summaries(box_name)).await;
+
+    Ok(web::Json(GetMessageSummariesRet {
+        retval: msgs.is_some(),
+        msg_list: msgs.unwrap_or_default(),
+    }))
+}
+
+// rsMsgs/getMessage
+// /**
+//  * @brief getMessage
+//  * @jsonapi{development}
+//  * @param[in] msgId message ID to lookup
+//  * @param[out] msg
+//  * @return true on success
+//  */
+// virtual bool getMessage(const std::string &msgId, MessageInfo &msg) = 0;
+gen_webui_param_type!(
+    MsgIdParam,
+    #[serde(rename = "msgId")]
+    msg_id: String
+);
+gen_webui_return_type!(GetMessageRet, msg, MessageInfo);
+#[post("/getMessage")]
+pub async fn rs_msgs_get_message(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MsgIdParam>,
+) -> Result<impl Responder> {
+    let msg = match params.0.msg_id.parse::<i64>() {
+        Ok(id) => with_mailbox(&state, |mailbox| mailbox.get_message(id))
+            .await
+            .flatten()
+            .map(|(peer_id, item)| MessageInfo::new(id, peer_id, item)),
+        Err(_) => None,
+    };
+
+    Ok(web::Json(GetMessageRet {
+        retval: msg.is_some(),
+        msg: msg.unwrap_or_default(),
+    }))
+}
+
+// rsMsgs/getMessageCount
+// /**
+//  * @brief getMessageCount
+//  * @jsonapi{development}
+//  * @param[out] nInbox
+//  * @param[out] nInboxNew
+//  * @param[out] nOutbox
+//  * @param[out] nDraftbox
+//  * @param[out] nSentbox
+//  * @param[out] nTrashbox
+//  */
+// virtual void getMessageCount(uint32_t &nInbox, uint32_t &nInboxNew, uint32_t &nOutbox, uint32_t &nDraftbox, uint32_t &nSentbox, uint32_t &nTrashbox) = 0;
+#[derive(serde::Serialize)]
+pub struct GetMessageCountRet {
+    retval: bool,
+    #[serde(flatten)]
+    count: MessageCount,
+}
+#[post("/getMessageCount")]
+pub async fn rs_msgs_get_message_count(state: web::Data<Arc<DataCore>>) -> Result<impl Responder> {
+    let count = with_mailbox(&state, |mailbox| mailbox.get_message_count()).await;
+
+    Ok(web::Json(GetMessageCountRet {
+        retval: count.is_some(),
+        count: count.unwrap_or_default(),
+    }))
+}
+
+// rsMsgs/MessageSend
+// /**
+//  * @brief MessageSend
+//  * @jsonapi{development}
+//  * @param[in] info
+//  * @return always true
+//  */
+// virtual bool MessageSend(MessageInfo &info) = 0;
+gen_webui_param_type!(MessageSend, info: MessageInfo);
+#[post("/MessageSend")]
+pub async fn rs_msgs_message_send(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MessageSend>,
+) -> Result<impl Responder> {
+    let (tx, rx) = oneshot::channel();
+    let info = params.0.info;
+    let from = (!info.rsgxsid_srcId.is_default()).then(|| *info.rsgxsid_srcId);
+
+    {
+        let lock = state.get_service_data().mail().cmd.read().await;
+        match &*lock {
+            Some(cmd) => {
+                _ = cmd
+                    .send(MailCmd::Send {
+                        item: info.into(),
+                        from,
+                        tx,
+                    })
+                    .await
+            }
+            None => return Ok(web::Json(RetVal { retval: false })),
+        }
+    }
+
+    let msg_id = rx.await.ok().flatten();
+    Ok(web::Json(RetVal {
+        retval: msg_id.is_some(),
+    }))
+}
+
+// rsMsgs/MessageToDraft
+// /**
+//  * @brief MessageToDraft
+//  * @jsonapi{development}
+//  * @param[in] info
+//  * @param[in] msgParentId
+//  * @return true on success
+//  */
+// virtual bool MessageToDraft(MessageInfo &info, const std::string &msgParentId) = 0;
+// an existing draft with the same `msgId` is replaced, `msgParentId` is ignored
+gen_webui_param_type!(MessageToDraft, info: MessageInfo);
+#[post("/MessageToDraft")]
+pub async fn rs_msgs_message_to_draft(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MessageToDraft>,
+) -> Result<impl Responder> {
+    let info = params.0.info;
+    let old_id = info.msgId.parse::<i64>().ok();
+    let own_id = *state.get_own_location().get_location_id();
+
+    let mut item: MsgItem = info.into();
+    item.msg_flags = MsgItemFlags::OUTGOING | MsgItemFlags::DRAFT;
+    item.send_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+    item.recv_time = item.send_time;
+
+    let msg_id = with_mailbox(&state, |mailbox| {
+        if let Some(old_id) = old_id {
+            if let Some((_, old)) = mailbox.get_message(old_id)? {
+                if old.msg_flags.contains(MsgItemFlags::DRAFT) {
+                    mailbox.remove_message(old_id)?;
+                }
+            }
+        }
+        mailbox.add_message(&own_id, &item)
+    })
+    .await;
+
+    if let Some(msg_id) = msg_id {
+        state
+            .webui_send(EventType::MailStatus {
+                code: MailStatusEventCode::MessageChanged,
+                msg_ids: old_id
+                    .into_iter()
+                    .chain([msg_id])
+                    .map(|id| id.to_string())
+                    .collect(),
+            })
+            .await;
+    }
+
+    Ok(web::Json(RetVal {
+        retval: msg_id.is_some(),
+    }))
+}
+
+// rsMsgs/MessageToTrash
+// /**
+//  * @brief MessageToTrash
+//  * @jsonapi{development}
+//  * @param[in] msgId
+//  * @param[in] bTrash
+//  * @return true on success
+//  */
+// virtual bool MessageToTrash(const std::string &msgId, bool bTrash) = 0;
+gen_webui_param_type!(
+    MessageToTrash,
+    #[serde(rename = "msgId")]
+    msg_id: String,
+    #[serde(rename = "bTrash")]
+    trash: bool
+);
+#[post("/MessageToTrash")]
+pub async fn rs_msgs_message_to_trash(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MessageToTrash>,
+) -> Result<impl Responder> {
+    let (set, clear) = if params.0.trash {
+        (MsgItemFlags::TRASH, MsgItemFlags::empty())
+    } else {
+        (MsgItemFlags::empty(), MsgItemFlags::TRASH)
+    };
+    let retval = set_message_flags(&state, &params.0.msg_id, set, clear).await;
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsMsgs/MessageDelete
+// /**
+//  * @brief MessageDelete
+//  * @jsonapi{development}
+//  * @param[in] msgId
+//  * @return true on success
+//  */
+// virtual bool MessageDelete(const std::string &msgId) = 0;
+#[post("/MessageDelete")]
+pub async fn rs_msgs_message_delete(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MsgIdParam>,
+) -> Result<impl Responder> {
+    let msg_id = params.0.msg_id;
+    let retval = match msg_id.parse::<i64>() {
+        Ok(id) => with_mailbox(&state, |mailbox| mailbox.remove_message(id))
+            .await
+            .unwrap_or_default(),
+        Err(_) => false,
+    };
+
+    if retval {
+        state
+            .webui_send(EventType::MailStatus {
+                code: MailStatusEventCode::MessageRemoved,
+                msg_ids: vec![msg_id],
+            })
+            .await;
+    }
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsMsgs/MessageRead
+// /**
+//  * @brief MessageRead
+//  * @jsonapi{development}
+//  * @param[in] msgId
+//  * @param[in] unreadByUser
+//  * @return true on success
+//  */
+// virtual bool MessageRead(const std::string &msgId, bool unreadByUser) = 0;
+gen_webui_param_type!(
+    MessageRead,
+    #[serde(rename = "msgId")]
+    msg_id: String,
+    #[serde(rename = "unreadByUser")]
+    unread_by_user: bool
+);
+#[post("/MessageRead")]
+pub async fn rs_msgs_message_read(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MessageRead>,
+) -> Result<impl Responder> {
+    // any change by the user makes the message "not new" anymore
+    let (set, clear) = if params.0.unread_by_user {
+        (MsgItemFlags::UNREAD_BY_USER, MsgItemFlags::NEW)
+    } else {
+        (
+            MsgItemFlags::empty(),
+            MsgItemFlags::NEW | MsgItemFlags::UNREAD_BY_USER,
+        )
+    };
+    let retval = set_message_flags(&state, &params.0.msg_id, set, clear).await;
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+macro_rules! gen_msg_flag_endpoint {
+    ($fn:ident, $path:literal, $param:ident, $field:ident, $flag:expr) => {
+        gen_webui_param_type!(
+            $param,
+            #[serde(rename = "msgId")]
+            msg_id: String,
+            $field: bool
+        );
+        #[post($path)]
+        pub async fn $fn(
+            state: web::Data<Arc<DataCore>>,
+            params: web::Json<$param>,
+        ) -> Result<impl Responder> {
+            let (set, clear) = if params.0.$field {
+                ($flag, MsgItemFlags::empty())
+            } else {
+                (MsgItemFlags::empty(), $flag)
+            };
+            let retval = set_message_flags(&state, &params.0.msg_id, set, clear).await;
+
+            Ok(web::Json(RetVal { retval }))
+        }
+    };
+}
+
+// rsMsgs/MessageReplied
+// virtual bool MessageReplied(const std::string &msgId, bool replied) = 0;
+gen_msg_flag_endpoint!(
+    rs_msgs_message_replied,
+    "/MessageReplied",
+    MessageReplied,
+    replied,
+    MsgItemFlags::REPLIED
+);
+
+// rsMsgs/MessageForwarded
+// virtual bool MessageForwarded(const std::string &msgId, bool forwarded) = 0;
+gen_msg_flag_endpoint!(
+    rs_msgs_message_forwarded,
+    "/MessageForwarded",
+    MessageForwarded,
+    forwarded,
+    MsgItemFlags::FORWARDED
+);
+
+// rsMsgs/MessageStar
+// virtual bool MessageStar(const std::string &msgId, bool mark) = 0;
+gen_msg_flag_endpoint!(
+    rs_msgs_message_star,
+    "/MessageStar",
+    MessageStar,
+    mark,
+    MsgItemFlags::STAR
+);
+
+// rsMsgs/getMessageTagTypes
+// /**
+//  * @brief getMessageTagTypes
+//  * @jsonapi{development}
+//  * @param[out] tags
+//  * @return always true
+//  */
+// virtual bool getMessageTagTypes(MsgTagType& tags) = 0;
+gen_webui_return_type!(GetMessageTagTypes, tags, MsgTagType);
+#[post("/getMessageTagTypes")]
+pub async fn rs_msgs_get_message_tag_types(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let types = with_mailbox(&state, |mailbox| mailbox.get_tag_types()).await;
+
+    Ok(web::Json(GetMessageTagTypes {
+        retval: types.is_some(),
+        tags: MsgTagType {
+            types: types
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(key, first, second)| MsgTagTypeEntry {
+                    key,
+                    value: MsgTagTypeValue { first, second },
+                })
+                .collect(),
+        },
+    }))
+}
+
+// rsMsgs/setMessageTagType
+// /**
+//  * @brief setMessageTagType
+//  * @jsonapi{development}
+//  * @param[in] tagId
+//  * @param[in] text
+//  * @param[in] rgb_color
+//  * @return true on success
+//  */
+// virtual bool setMessageTagType(uint32_t tagId, std::string& text, uint32_t rgb_color) = 0;
+gen_webui_param_type!(
+    SetMessageTagType,
+    #[serde(rename = "tagId")]
+    tag_id: u32,
+    text: String,
+    rgb_color: u32
+);
+#[post("/setMessageTagType")]
+pub async fn rs_msgs_set_message_tag_type(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetMessageTagType>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let added = with_mailbox(&state, |mailbox| {
+        mailbox.set_tag_type(params.tag_id, &params.text, params.rgb_color)
+    })
+    .await;
+
+    if let Some(added) = added {
+        state
+            .webui_send(EventType::MailTag {
+                code: if added {
+                    MailTagEventCode::TagAdded
+                } else {
+                    MailTagEventCode::TagChanged
+                },
+                tag_ids: vec![params.tag_id.to_string()],
+            })
+            .await;
+    }
+
+    Ok(web::Json(RetVal {
+        retval: added.is_some(),
+    }))
+}
+
+// rsMsgs/removeMessageTagType
+// /**
+//  * @brief removeMessageTagType
+//  * @jsonapi{development}
+//  * @param[in] tagId
+//  * @return true on success
+//  */
+// virtual bool removeMessageTagType(uint32_t tagId) = 0;
+gen_webui_param_type!(
+    RemoveMessageTagType,
+    #[serde(rename = "tagId")]
+    tag_id: u32
+);
+#[post("/removeMessageTagType")]
+pub async fn rs_msgs_remove_message_tag_type(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<RemoveMessageTagType>,
+) -> Result<impl Responder> {
+    let tag_id = params.0.tag_id;
+
+    let retval = with_mailbox(&state, |mailbox| mailbox.remove_tag_type(tag_id))
+        .await
+        .unwrap_or_default();
+
+    if retval {
+        state
+            .webui_send(EventType::MailTag {
+                code: MailTagEventCode::TagRemoved,
+                tag_ids: vec![tag_id.to_string()],
+            })
+            .await;
+    }
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsMsgs/getMessageTag
+// /**
+//  * @brief getMessageTag
+//  * @jsonapi{development}
+//  * @param[in] msgId
+//  * @param[out] info
+//  * @return true on success
+//  */
+// virtual bool getMessageTag(const std::string &msgId, MsgTagInfo& info) = 0;
+gen_webui_return_type!(GetMessageTag, info, MsgTagInfo);
+#[post("/getMessageTag")]
+pub async fn rs_msgs_get_message_tag(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<MsgIdParam>,
+) -> Result<impl Responder> {
+    let msg_id = params.0.msg_id;
+    let tags = match msg_id.parse::<i64>() {
+        Ok(id) => with_mailbox(&state, |mailbox| mailbox.get_tags(id)).await,
+        Err(_) => None,
+    };
+
+    Ok(web::Json(GetMessageTag {
+        retval: tags.is_some(),
+        info: MsgTagInfo {
+            msgId: msg_id,
+            tagIds: tags.unwrap_or_default(),
+        },
+    }))
+}
+
+// rsMsgs/setMessageTag
+// /**
+//  * @brief setMessageTag
+//  *  set == false && tagId == 0 --> remove all
+//  * @jsonapi{development}
+//  * @param[in] msgId
+//  * @param[in] tagId
+//  * @param[in] set
+//  * @return true on success
+//  */
+// virtual bool setMessageTag(const std::string &msgId, uint32_t tagId, bool set) = 0;
+gen_webui_param_type!(
+    SetMessageTag,
+    #[serde(rename = "msgId")]
+    msg_id: String,
+    #[serde(rename = "tagId")]
+    tag_id: u32,
+    set: bool
+);
+#[post("/setMessageTag")]
+pub async fn rs_msgs_set_message_tag(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetMessageTag>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let id = match params.msg_id.parse::<i64>() {
+        Ok(id) => id,
+        Err(_) => return Ok(web::Json(RetVal { retval: false })),
+    };
+
+    let changed = with_mailbox(&state, |mailbox| {
+        if mailbox.get_message(id)?.is_none() {
+            return Ok(false);
+        }
+        if params.tag_id == 0 && !params.set {
+            let mut changed = false;
+            for tag_id in mailbox.get_tags(id)? {
+                changed |= mailbox.set_tag(id, tag_id, false)?;
+            }
+            return Ok(changed);
+        }
+        mailbox.set_tag(id, params.tag_id, params.set)
+    })
+    .await;
+
+    if changed == Some(true) {
+        state
+            .webui_send(EventType::MailStatus {
+                code: MailStatusEventCode::TagChanged,
+                msg_ids: vec![params.msg_id],
+            })
+            .await;
+    }
+
+    Ok(web::Json(RetVal {
+        retval: changed.is_some(),
+    }))
+}
+
 pub fn get_entry_points() -> actix_web::Scope {
     web::scope("/rsMsgs")
         .service(rs_msgs_get_chat_lobby_list)
         .service(rs_msgs_get_list_of_nearby_chat_lobbies)
         .service(rs_msgs_get_chat_lobby_info)
         .service(rs_msgs_send_chat)
+        .service(rs_msgs_send_status_string)
         .service(rs_msgs_join_visible_chat_lobby)
         .service(rs_msgs_unsubscribe_chat_lobby)
+        .service(rs_msgs_set_lobby_auto_subscribe)
+        .service(rs_msgs_get_lobby_auto_subscribe)
+        .service(rs_msgs_get_chat_lobby_subscriptions)
+        .service(rs_msgs_set_chat_lobby_notify)
+        .service(rs_msgs_create_chat_lobby)
+        .service(rs_msgs_invite_peer_to_lobby)
+        .service(rs_msgs_get_pending_chat_lobby_invites)
+        .service(rs_msgs_accept_lobby_invite)
+        .service(rs_msgs_deny_lobby_invite)
+        .service(rs_msgs_get_avatar_data)
+        .service(rs_msgs_get_own_avatar_data)
+        .service(rs_msgs_set_own_avatar_data)
+        .service(rs_msgs_get_lobby_moderation)
+        .service(rs_msgs_set_lobby_identity_banned)
+        .service(rs_msgs_set_lobby_identity_muted)
+        .service(rs_msgs_set_lobby_min_reputation)
+        .service(rs_msgs_initiate_distant_chat_connexion)
+        .service(rs_msgs_close_distant_chat_connexion)
+        .service(rs_msgs_get_distant_chat_status)
+        .service(rs_msgs_get_message_summaries)
+        .service(rs_msgs_get_message)
+        .service(rs_msgs_get_message_count)
+        .service(rs_msgs_message_send)
+        .service(rs_msgs_message_to_draft)
+        .service(rs_msgs_message_to_trash)
+        .service(rs_msgs_message_delete)
+        .service(rs_msgs_message_read)
+        .service(rs_msgs_message_replied)
+        .service(rs_msgs_message_forwarded)
+        .service(rs_msgs_message_star)
+        .service(rs_msgs_get_message_tag_types)
+        .service(rs_msgs_set_message_tag_type)
+        .service(rs_msgs_remove_message_tag_type)
+        .service(rs_msgs_get_message_tag)
+        .service(rs_msgs_set_message_tag)
 }
@@ -1,14 +1,26 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc};
 
 use actix_web::{
     post,
     web::{self},
     Responder, Result,
 };
-use retroshare_compat::basics::{SslIdHex, SslIdWrapped};
+use retroshare_compat::{
+    basics::{PgpId, PgpIdHex, SslId, SslIdHex, SslIdWrapped},
+    config::PeerBandwidthLimits,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::{model::DataCore, webui::RetVal};
+use crate::{
+    gen_webui_param_type, gen_webui_return_type,
+    model::{
+        net::{FriendProxy, HiddenType, NET_CONFIG_FILE},
+        services::bwctrl::BwCtrlCmd,
+        DataCore,
+    },
+    transport_ng::proxy::Proxy,
+    webui::RetVal,
+};
 
 // rsPeers/getRetroshareInvite
 #[post("/GetRetroshareInvite")]
@@ -63,6 +75,31 @@ pub async fn rs_peers_get_friend_list(state: web::Data<Arc<DataCore>>) -> Result
     }))
 }
 
+// rsPeers/getGPGAllList
+// virtual bool getGPGAllList(std::list<RsPgpId> &gpg_ids) = 0;
+#[derive(Serialize)]
+pub struct GpgAllList {
+    retval: bool,
+    gpg_ids: Vec<PgpIdHex>,
+}
+#[post("/getGPGAllList")]
+pub async fn rs_peers_get_gpg_all_list(state: web::Data<Arc<DataCore>>) -> Result<impl Responder> {
+    // friends and the friends of friends discovery got the keys of
+    let mut gpg_ids: Vec<PgpId> = state
+        .get_persons()
+        .iter()
+        .map(|peer| *peer.get_pgp_id())
+        .collect();
+    gpg_ids.extend(state.get_service_data().discovery().get_key_ids());
+    gpg_ids.sort();
+    gpg_ids.dedup();
+
+    Ok(web::Json(GpgAllList {
+        retval: true,
+        gpg_ids: gpg_ids.into_iter().map(|id| id.into()).collect(),
+    }))
+}
+
 // rsPeers/getPeerDetails
 #[derive(Serialize)]
 pub struct PeerDetails {
@@ -97,11 +134,345 @@ pub async fn rs_peers_is_online(
     Ok(web::Json(RetVal { retval: online }))
 }
 
+// rsPeers/getPeerMaximumRates
+// virtual bool getPeerMaximumRates(const RsPgpId& pid, uint32_t& maxUploadRate, uint32_t& maxDownloadRate) = 0;
+gen_webui_param_type!(GetPeerMaximumRates, pid: PgpIdHex);
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct PeerMaximumRates {
+    retval: bool,
+    maxUploadRate: u32,
+    maxDownloadRate: u32,
+}
+#[post("/getPeerMaximumRates")]
+pub async fn rs_peers_get_peer_maximum_rates(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetPeerMaximumRates>,
+) -> Result<impl Responder> {
+    let pgp_id: PgpId = params.0.pid.into();
+    let limits = state
+        .get_persons()
+        .into_iter()
+        .find(|peer| peer.get_pgp_id() == &pgp_id)
+        .map(|peer| peer.get_bandwidth_limits());
+
+    Ok(web::Json(PeerMaximumRates {
+        retval: limits.is_some(),
+        maxUploadRate: limits.unwrap_or_default().max_up_rate_kbs,
+        maxDownloadRate: limits.unwrap_or_default().max_dl_rate_kbs,
+    }))
+}
+
+// rsPeers/setPeerMaximumRates
+// virtual bool setPeerMaximumRates(const RsPgpId& pid, uint32_t maxUploadRate, uint32_t maxDownloadRate) = 0;
+// rates are in kB/s, 0 means unlimited
+gen_webui_param_type!(
+    SetPeerMaximumRates,
+    pid: PgpIdHex,
+    #[serde(rename = "maxUploadRate")]
+    max_upload_rate: u32,
+    #[serde(rename = "maxDownloadRate")]
+    max_download_rate: u32
+);
+#[post("/setPeerMaximumRates")]
+pub async fn rs_peers_set_peer_maximum_rates(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetPeerMaximumRates>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let retval = state
+        .get_service_data()
+        .bwctrl()
+        .send_cmd(BwCtrlCmd::SetPeerMaxRates(
+            params.pid.into(),
+            PeerBandwidthLimits {
+                max_up_rate_kbs: params.max_upload_rate,
+                max_dl_rate_kbs: params.max_download_rate,
+            },
+        ));
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// RS_HIDDEN_TYPE_TOR = 0x0002, RS_HIDDEN_TYPE_I2P = 0x0004
+fn to_hidden_type(ty: u32) -> Option<HiddenType> {
+    match ty {
+        0x0002 => Some(HiddenType::Tor),
+        0x0004 => Some(HiddenType::I2p),
+        _ => None,
+    }
+}
+
+// rsPeers/getProxyServer
+// virtual bool getProxyServer(const uint32_t type, std::string &addr, uint16_t &port,uint32_t& status_flags) = 0;
+// status flags: RS_NET_PROXY_STATUS_UNKNOWN = 0x0000, RS_NET_PROXY_STATUS_OK = 0x0001 (only the Tor proxy is probed)
+gen_webui_param_type!(GetProxyServer, r#type: u32);
+#[derive(Serialize)]
+pub struct ProxyServer {
+    retval: bool,
+    addr: String,
+    port: u16,
+    status_flags: u32,
+}
+#[post("/getProxyServer")]
+pub async fn rs_peers_get_proxy_server(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetProxyServer>,
+) -> Result<impl Responder> {
+    let ty = to_hidden_type(params.0.r#type);
+    let net = state.get_net();
+    let proxy = ty.map(|ty| net.get_proxy(ty));
+    let reachable = ty == Some(HiddenType::Tor) && net.get_tor_reachable() == Some(true);
+
+    Ok(web::Json(ProxyServer {
+        retval: proxy.is_some(),
+        addr: proxy
+            .map(|proxy| proxy.ip().to_string())
+            .unwrap_or_default(),
+        port: proxy.map(|proxy| proxy.port()).unwrap_or_default(),
+        status_flags: reachable as u32,
+    }))
+}
+
+// rsPeers/setProxyServer
+// virtual bool setProxyServer(const uint32_t type, const std::string &addr, const uint16_t port) = 0;
+gen_webui_param_type!(SetProxyServer, r#type: u32, addr: String, port: u16);
+#[post("/setProxyServer")]
+pub async fn rs_peers_set_proxy_server(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetProxyServer>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let retval = match (to_hidden_type(params.r#type), params.addr.parse::<IpAddr>()) {
+        (Some(ty), Ok(ip)) => {
+            let net = state.get_net();
+            net.set_proxy(ty, (ip, params.port).into());
+            state.save_config(NET_CONFIG_FILE, &net.save_config())
+        }
+        _ => false,
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getHiddenOnly
+// not part of RS, whether clear-net addresses are neither used nor advertised
+#[post("/getHiddenOnly")]
+pub async fn rs_peers_get_hidden_only(state: web::Data<Arc<DataCore>>) -> Result<impl Responder> {
+    Ok(web::Json(RetVal {
+        retval: state.get_net().is_hidden_only(),
+    }))
+}
+
+// rsPeers/setHiddenOnly
+// not part of RS, takes effect on the next connection attempts (advertised addresses after a restart)
+gen_webui_param_type!(SetHiddenOnly, enabled: bool);
+#[post("/setHiddenOnly")]
+pub async fn rs_peers_set_hidden_only(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetHiddenOnly>,
+) -> Result<impl Responder> {
+    let net = state.get_net();
+    net.set_hidden_only(params.0.enabled);
+    let retval = state.save_config(NET_CONFIG_FILE, &net.save_config());
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getOutgoingProxy
+// not part of RS, proxy for clear-net connections (`socks5://[user:password@]host:port` or `http://..`), empty for none
+#[derive(Serialize)]
+pub struct OutgoingProxy {
+    retval: bool,
+    proxy: String,
+}
+#[post("/getOutgoingProxy")]
+pub async fn rs_peers_get_outgoing_proxy(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let proxy = state.get_net().get_outgoing_proxy();
+
+    Ok(web::Json(OutgoingProxy {
+        retval: true,
+        proxy: proxy.map(|proxy| proxy.to_string()).unwrap_or_default(),
+    }))
+}
+
+// rsPeers/setOutgoingProxy
+// not part of RS, an empty proxy connects directly
+gen_webui_param_type!(SetOutgoingProxy, proxy: String);
+#[post("/setOutgoingProxy")]
+pub async fn rs_peers_set_outgoing_proxy(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetOutgoingProxy>,
+) -> Result<impl Responder> {
+    let proxy = match params.0.proxy.as_str() {
+        "" => Ok(None),
+        proxy => proxy.parse::<Proxy>().map(Some),
+    };
+    let retval = match proxy {
+        Ok(proxy) => {
+            let net = state.get_net();
+            net.set_outgoing_proxy(proxy);
+            state.save_config(NET_CONFIG_FILE, &net.save_config())
+        }
+        Err(_) => false,
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getFriendProxy
+// not part of RS, a friend's proxy: empty for the global one, `DIRECT` or a proxy
+gen_webui_param_type!(GetFriendProxy, pid: PgpIdHex);
+#[post("/getFriendProxy")]
+pub async fn rs_peers_get_friend_proxy(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetFriendProxy>,
+) -> Result<impl Responder> {
+    let pgp_id: PgpId = params.0.pid.into();
+    let proxy = state.get_net().get_friend_proxy(&pgp_id);
+
+    Ok(web::Json(OutgoingProxy {
+        retval: true,
+        proxy: proxy.map(|proxy| proxy.to_string()).unwrap_or_default(),
+    }))
+}
+
+// rsPeers/setFriendProxy
+// not part of RS, see getFriendProxy
+gen_webui_param_type!(SetFriendProxy, pid: PgpIdHex, proxy: String);
+#[post("/setFriendProxy")]
+pub async fn rs_peers_set_friend_proxy(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetFriendProxy>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let proxy = match params.proxy.as_str() {
+        "" => Ok(None),
+        proxy => proxy.parse::<FriendProxy>().map(Some),
+    };
+    let retval = match proxy {
+        Ok(proxy) => {
+            let net = state.get_net();
+            net.set_friend_proxy(params.pid.into(), proxy);
+            state.save_config(NET_CONFIG_FILE, &net.save_config())
+        }
+        Err(_) => false,
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getDynDNS
+// not part of RS (RS returns it with the peer details), our own DynDNS host name, empty for none
+gen_webui_return_type!(DynDns, addr, String);
+#[post("/getDynDNS")]
+pub async fn rs_peers_get_dyndns(state: web::Data<Arc<DataCore>>) -> Result<impl Responder> {
+    Ok(web::Json(DynDns {
+        retval: true,
+        addr: state.get_net().get_dyndns().unwrap_or_default(),
+    }))
+}
+
+// rsPeers/setDynDNS
+// virtual bool setDynDNS(const RsPeerId &id, const std::string &addr) = 0;
+// only our own location is supported
+gen_webui_param_type!(SetDynDns, id: SslIdHex, addr: String);
+#[post("/setDynDNS")]
+pub async fn rs_peers_set_dyndns(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetDynDns>,
+) -> Result<impl Responder> {
+    let params = params.0;
+    let ssl_id: SslId = params.id.into();
+    let retval = if ssl_id == *state.get_own_location().get_location_id() {
+        let net = state.get_net();
+        let addr = params.addr.trim();
+        net.set_dyndns(Some(addr.to_owned()).filter(|addr| !addr.is_empty()));
+        state.announce_own_addresses().await;
+        state.save_config(NET_CONFIG_FILE, &net.save_config())
+    } else {
+        false
+    };
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getBroadcastDiscovery
+// not part of RS, whether we announce ourself (and look for friends) in the local network
+#[post("/getBroadcastDiscovery")]
+pub async fn rs_peers_get_broadcast_discovery(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    Ok(web::Json(RetVal {
+        retval: state.get_net().is_broadcast_discovery(),
+    }))
+}
+
+// rsPeers/setBroadcastDiscovery
+// not part of RS, hidden nodes never use the broadcast discovery
+gen_webui_param_type!(SetBroadcastDiscovery, enabled: bool);
+#[post("/setBroadcastDiscovery")]
+pub async fn rs_peers_set_broadcast_discovery(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetBroadcastDiscovery>,
+) -> Result<impl Responder> {
+    let net = state.get_net();
+    net.set_broadcast_discovery(params.0.enabled);
+    let retval = state.save_config(NET_CONFIG_FILE, &net.save_config());
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// rsPeers/getExperimentalUdp
+// not part of RS, whether friends are also tried over TOU
+#[post("/getExperimentalUdp")]
+pub async fn rs_peers_get_experimental_udp(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    Ok(web::Json(RetVal {
+        retval: state.get_net().is_experimental_udp(),
+    }))
+}
+
+// rsPeers/setExperimentalUdp
+// not part of RS, without DHT only friends that answer on their UDP port directly are reached
+gen_webui_param_type!(SetExperimentalUdp, enabled: bool);
+#[post("/setExperimentalUdp")]
+pub async fn rs_peers_set_experimental_udp(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<SetExperimentalUdp>,
+) -> Result<impl Responder> {
+    let net = state.get_net();
+    net.set_experimental_udp(params.0.enabled);
+    let retval = state.save_config(NET_CONFIG_FILE, &net.save_config());
+
+    Ok(web::Json(RetVal { retval }))
+}
+
 pub fn get_entry_points() -> actix_web::Scope {
     web::scope("/rsPeers")
         .service(rs_peers_get_peer_details)
         .service(rs_peers_is_online)
         .service(rs_peers_get_friend_list)
+        .service(rs_peers_get_gpg_all_list)
         .service(rs_peers_get_rs_invite)
         .service(rs_peers_get_short_invite)
+        .service(rs_peers_get_peer_maximum_rates)
+        .service(rs_peers_set_peer_maximum_rates)
+        .service(rs_peers_get_proxy_server)
+        .service(rs_peers_set_proxy_server)
+        .service(rs_peers_get_hidden_only)
+        .service(rs_peers_set_hidden_only)
+        .service(rs_peers_get_outgoing_proxy)
+        .service(rs_peers_set_outgoing_proxy)
+        .service(rs_peers_get_friend_proxy)
+        .service(rs_peers_set_friend_proxy)
+        .service(rs_peers_get_dyndns)
+        .service(rs_peers_set_dyndns)
+        .service(rs_peers_get_broadcast_discovery)
+        .service(rs_peers_set_broadcast_discovery)
+        .service(rs_peers_get_experimental_udp)
+        .service(rs_peers_set_experimental_udp)
 }
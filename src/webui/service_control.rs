@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use actix_web::{post, web, Responder, Result};
+use retroshare_compat::{
+    basics::{NodeGroupIdHex, SslId, SslIdHex},
+    services::service_info::RsServiceInfo,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gen_webui_param_type, gen_webui_return_type,
+    model::{
+        services::service_control::{from_full_service_id, to_full_service_id, ServicePermissions},
+        DataCore,
+    },
+    webui::RetVal,
+};
+
+// struct RsServicePermissions : RsSerializable
+// {
+// 	uint32_t mServiceId;
+// 	std::string mServiceName;
+// 	bool mDefaultAllowed;
+// 	std::set<RsPeerId> mPeersAllowed;
+// 	std::set<RsPeerId> mPeersDenied;
+// };
+// `mGroupsAllowed` and `mGroupsDenied` are not part of RS
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct RsServicePermissions {
+    mServiceId: u32,
+    mServiceName: String,
+    mDefaultAllowed: bool,
+    mPeersAllowed: Vec<SslIdHex>,
+    mPeersDenied: Vec<SslIdHex>,
+    #[serde(default)]
+    mGroupsAllowed: Vec<NodeGroupIdHex>,
+    #[serde(default)]
+    mGroupsDenied: Vec<NodeGroupIdHex>,
+}
+
+impl RsServicePermissions {
+    fn new(service_id: u32, permissions: ServicePermissions) -> Self {
+        RsServicePermissions {
+            mServiceId: service_id,
+            mServiceName: permissions.service_name,
+            mDefaultAllowed: permissions.default_allowed,
+            mPeersAllowed: permissions
+                .peers_allowed
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            mPeersDenied: permissions
+                .peers_denied
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            mGroupsAllowed: permissions
+                .groups_allowed
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            mGroupsDenied: permissions
+                .groups_denied
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+impl From<RsServicePermissions> for ServicePermissions {
+    fn from(permissions: RsServicePermissions) -> Self {
+        ServicePermissions {
+            service_name: permissions.mServiceName,
+            default_allowed: permissions.mDefaultAllowed,
+            peers_allowed: permissions
+                .mPeersAllowed
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            peers_denied: permissions
+                .mPeersDenied
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            groups_allowed: permissions
+                .mGroupsAllowed
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            groups_denied: permissions
+                .mGroupsDenied
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GetServicePermissionsRet {
+    retval: bool,
+    permissions: RsServicePermissions,
+}
+
+// rsServiceControl/getServicePermissions
+// virtual bool getServicePermissions(uint32_t serviceId, RsServicePermissions &permissions) = 0;
+// services without permissions are allowed for everyone
+gen_webui_param_type!(
+    GetServicePermissions,
+    #[serde(rename = "serviceId")]
+    service_id: u32
+);
+#[post("/getServicePermissions")]
+pub async fn rs_service_control_get_service_permissions(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetServicePermissions>,
+) -> Result<impl Responder> {
+    let service = from_full_service_id(params.0.service_id);
+    let (retval, permissions) = match state
+        .get_service_data()
+        .service_control()
+        .get_permissions(&service)
+    {
+        Some(permissions) => (true, permissions),
+        None => (false, ServicePermissions::default()),
+    };
+
+    Ok(web::Json(GetServicePermissionsRet {
+        retval,
+        permissions: RsServicePermissions::new(to_full_service_id(service), permissions),
+    }))
+}
+
+// rsServiceControl/updateServicePermissions
+// virtual bool updateServicePermissions(uint32_t serviceId, const RsServicePermissions &permissions) = 0;
+gen_webui_param_type!(
+    UpdateServicePermissions,
+    #[serde(rename = "serviceId")]
+    service_id: u32,
+    permissions: RsServicePermissions
+);
+#[post("/updateServicePermissions")]
+pub async fn rs_service_control_update_service_permissions(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<UpdateServicePermissions>,
+) -> Result<impl Responder> {
+    let params = params.0;
+
+    let retval = state
+        .update_service_permissions(
+            from_full_service_id(params.service_id),
+            params.permissions.into(),
+        )
+        .await;
+
+    Ok(web::Json(RetVal { retval }))
+}
+
+// class RsPeerServiceInfo : RsSerializable
+// {
+// 	RsPeerId mPeerId;
+// 	std::map<uint32_t, RsServiceInfo> mServiceList;
+// };
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct RsPeerServiceInfo {
+    mPeerId: SslIdHex,
+    mServiceList: Vec<ServiceListEntry>,
+}
+
+#[derive(Serialize)]
+pub struct ServiceListEntry {
+    key: u32,
+    value: RsServiceInfo,
+}
+
+impl RsPeerServiceInfo {
+    fn new(peer_id: SslId, services: impl Iterator<Item = RsServiceInfo>) -> Self {
+        RsPeerServiceInfo {
+            mPeerId: peer_id.into(),
+            mServiceList: services
+                .map(|info| ServiceListEntry {
+                    key: info.m_service_type,
+                    value: info,
+                })
+                .collect(),
+        }
+    }
+}
+
+gen_webui_return_type!(GetServicesRet, info, RsPeerServiceInfo);
+
+// rsServiceControl/getServicesProvided
+// virtual bool getServicesProvided(const RsPeerId &peerId, RsPeerServiceInfo &info) = 0;
+// only known for connected peers
+gen_webui_param_type!(
+    GetServices,
+    #[serde(rename = "peerId")]
+    peer_id: SslIdHex
+);
+#[post("/getServicesProvided")]
+pub async fn rs_service_control_get_services_provided(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetServices>,
+) -> Result<impl Responder> {
+    let peer_id: SslId = params.0.peer_id.into();
+    let services = state
+        .get_service_data()
+        .service_control()
+        .get_peer_services(&peer_id);
+
+    Ok(web::Json(GetServicesRet {
+        retval: services.is_some(),
+        info: RsPeerServiceInfo::new(peer_id, services.unwrap_or_default().provided.into_iter()),
+    }))
+}
+
+// rsServiceControl/getServicesAllowed
+// virtual bool getServicesAllowed(const RsPeerId &peerId, RsPeerServiceInfo &info) = 0;
+// services that the peer runs in a compatible version and is allowed to use
+#[post("/getServicesAllowed")]
+pub async fn rs_service_control_get_services_allowed(
+    state: web::Data<Arc<DataCore>>,
+    params: web::Json<GetServices>,
+) -> Result<impl Responder> {
+    let peer_id: SslId = params.0.peer_id.into();
+    let service_control = state.get_service_data().service_control();
+    let services = service_control.get_peer_services(&peer_id);
+
+    let info = RsPeerServiceInfo::new(
+        peer_id,
+        services
+            .to_owned()
+            .unwrap_or_default()
+            .provided
+            .into_iter()
+            .filter(|info| {
+                let service = from_full_service_id(info.m_service_type);
+                service_control.is_active(service, &peer_id)
+                    && service_control.is_allowed(service, &peer_id)
+            }),
+    );
+
+    Ok(web::Json(GetServicesRet {
+        retval: services.is_some(),
+        info,
+    }))
+}
+
+// rsServiceControl/getAllServicesProvided
+// not part of RS, returns the service lists of all connected peers
+gen_webui_return_type!(GetAllServicesRet, infos, Vec<RsPeerServiceInfo>);
+#[post("/getAllServicesProvided")]
+pub async fn rs_service_control_get_all_services_provided(
+    state: web::Data<Arc<DataCore>>,
+) -> Result<impl Responder> {
+    let infos = state
+        .get_service_data()
+        .service_control()
+        .get_all_peer_services()
+        .into_iter()
+        .map(|(peer_id, services)| RsPeerServiceInfo::new(peer_id, services.provided.into_iter()))
+        .collect();
+
+    Ok(web::Json(GetAllServicesRet {
+        retval: true,
+        infos,
+    }))
+}
+
+pub fn get_entry_points() -> actix_web::Scope {
+    web::scope("/rsServiceControl")
+        .service(rs_service_control_get_service_permissions)
+        .service(rs_service_control_update_service_permissions)
+        .service(rs_service_control_get_services_provided)
+        .service(rs_service_control_get_services_allowed)
+        .service(rs_service_control_get_all_services_provided)
+}